@@ -0,0 +1,36 @@
+/* The C interface to nfhl_util, from `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`).
+ * See src/ffi.rs. Arguments and results are UTF-8 JSON; free returned strings with nfhl_string_free. A NULL
+ * result means the call failed, and nfhl_last_error says why. */
+
+#ifndef NFHL_UTIL_H
+#define NFHL_UTIL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct NfhlHandle NfhlHandle;
+
+/* options_json: NULL for the defaults, or
+ * {"timeout_secs", "connect_timeout_secs", "user_agent", "proxy", "headers", "rate_limit_ms",
+ *  "connection": {"dns_cache_secs", "pool_idle_secs", "pool_max_idle_per_host", "tcp_keepalive_secs", "http2"}} */
+NfhlHandle *nfhl_new(const char *options_json);
+void nfhl_free(NfhlHandle *handle);
+/* Cancels the calls running on the handle; safe from any thread. */
+void nfhl_cancel(const NfhlHandle *handle);
+
+char *nfhl_counties_inventory(const NfhlHandle *handle);
+char *nfhl_states_inventory(const NfhlHandle *handle);
+char *nfhl_diff(const char *old_json, const char *new_json);
+/* request_json: {"inventory", "cache_dir", "old_inventory", "delete", "politeness", "concurrency"} */
+char *nfhl_download(const NfhlHandle *handle, const char *request_json);
+
+/* {"message", "exit_code", "retryable"} for the thread's last failed call, or NULL; owned by the library */
+const char *nfhl_last_error(void);
+void nfhl_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
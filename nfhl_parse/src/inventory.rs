@@ -0,0 +1,327 @@
+//! The inventory: each state's or county's current NFHL product urls and dates, keyed by 2-digit state or 5-digit
+//! county fips, as nfhl_util's `states_inventory` and `counties_inventory` save it and everything else reads it. The
+//! types here are the typed model; `format` has what's on disk.
+
+pub mod format;
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use url::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Inventory entries by fips code.
+pub type Inventory = HashMap<Fips, InventoryEntry>;
+
+/// A 2-digit state or 5-digit county fips code. It derefs to, and looks up by, a `&str`, so `inv.get("48201")`
+/// works.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct Fips(String);
+
+impl Fips {
+    pub fn new(fips: impl Into<String>) -> Result<Fips> {
+        let fips = fips.into();
+        if !matches!(fips.len(), 2 | 5) || !fips.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::Validation(format!("'{}' isn't a 2-digit state or 5-digit county fips code", fips)));
+        }
+        Ok(Fips(fips))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_state(&self) -> bool {
+        self.0.len() == 2
+    }
+
+    /// The state part: all of a state's fips, the first 2 digits of a county's.
+    pub fn state(&self) -> &str {
+        &self.0[..2]
+    }
+}
+
+impl TryFrom<String> for Fips {
+    type Error = Error;
+
+    fn try_from(fips: String) -> Result<Fips> {
+        Fips::new(fips)
+    }
+}
+
+impl FromStr for Fips {
+    type Err = Error;
+
+    fn from_str(fips: &str) -> Result<Fips> {
+        Fips::new(fips)
+    }
+}
+
+impl From<Fips> for String {
+    fn from(fips: Fips) -> String {
+        fips.0
+    }
+}
+
+impl Deref for Fips {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Fips {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Fips {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fips {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Fips {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Fips {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+/// A state's or county's current products. Each kind has a url and a date or neither (the date can be missing on
+/// its own if FEMA didn't give one). A product split into several zips, or with supplementary archives, has the rest
+/// in `files`. It's (de)serialized as `format::v2::Entry`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(into = "format::v2::Entry", try_from = "format::v2::Entry")]
+pub struct InventoryEntry {
+    pub effective_file_url: Option<Url>,
+    pub effective_file_date: Option<NaiveDate>,
+    pub preliminary_file_url: Option<Url>,
+    pub preliminary_file_date: Option<NaiveDate>,
+    /// The product's files besides its main one, above.
+    pub files: Vec<InventoryFile>,
+    /// The county's name as the NFHL portal lists it, e.g. `HARRIS COUNTY`; None for states, and counties only MSC
+    /// listed.
+    pub county_name: Option<String>,
+    /// The state's postal code, e.g. `TX`.
+    pub state_abbrev: Option<String>,
+    /// The 2-digit fips of the state, or the county's state.
+    pub state_fips: Option<String>,
+}
+
+/// One of an entry's other files.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InventoryFile {
+    /// The product it's part of.
+    pub kind: ProductKind,
+    pub role: FileRole,
+    pub url: Url,
+    pub date: Option<NaiveDate>,
+}
+
+/// What one of an entry's other files is to its product.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FileRole {
+    /// One of the zips the product is split into, after the first (the main file).
+    Part,
+    /// An archive published alongside the product, like supplementary data.
+    Supplement,
+}
+
+impl FileRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileRole::Part => "part",
+            FileRole::Supplement => "supplement",
+        }
+    }
+}
+
+/// The two kinds of product an entry has a file for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductKind {
+    Effective,
+    Preliminary,
+}
+
+impl InventoryEntry {
+    /// The url of the kind of product, if the entry has one.
+    pub fn url(&self, kind: ProductKind) -> Option<&Url> {
+        match kind {
+            ProductKind::Effective => self.effective_file_url.as_ref(),
+            ProductKind::Preliminary => self.preliminary_file_url.as_ref(),
+        }
+    }
+
+    pub fn date(&self, kind: ProductKind) -> Option<NaiveDate> {
+        match kind {
+            ProductKind::Effective => self.effective_file_date,
+            ProductKind::Preliminary => self.preliminary_file_date,
+        }
+    }
+
+    /// The effective file date, which FEMA encodes as YYYYMMDD in the file name.
+    pub fn effective_date(&self) -> Option<NaiveDate> {
+        self.effective_file_date
+    }
+
+    pub fn has(&self, kind: ProductKind) -> bool {
+        self.url(kind).is_some()
+    }
+
+    /// Fills in `state_fips` and `state_abbrev` for the entry at `fips`, from `STATES`.
+    pub fn locate(&mut self, fips: &Fips) {
+        self.state_fips = Some(fips.state().to_string());
+        self.state_abbrev = STATES.iter()
+            .find(|(code, _, _)| *code == fips.state())
+            .map(|(_, postal, _)| postal.to_string());
+    }
+
+    /// The other files of the kind of product, in the order the inventory lists them.
+    pub fn files(&self, kind: ProductKind) -> impl Iterator<Item = &InventoryFile> {
+        self.files.iter().filter(move |file| file.kind == kind)
+    }
+}
+
+/// (fips, postal code, name) of every state, DC and the territories FEMA maps.
+pub const STATES: [(&str, &str, &str); 58] = [
+    ("01", "AL", "Alabama"),
+    ("02", "AK", "Alaska"),
+    ("04", "AZ", "Arizona"),
+    ("05", "AR", "Arkansas"),
+    ("06", "CA", "California"),
+    ("08", "CO", "Colorado"),
+    ("09", "CT", "Connecticut"),
+    ("10", "DE", "Delaware"),
+    ("11", "DC", "District of Columbia"),
+    ("12", "FL", "Florida"),
+    ("13", "GA", "Georgia"),
+    ("15", "HI", "Hawaii"),
+    ("16", "ID", "Idaho"),
+    ("17", "IL", "Illinois"),
+    ("18", "IN", "Indiana"),
+    ("19", "IA", "Iowa"),
+    ("20", "KS", "Kansas"),
+    ("21", "KY", "Kentucky"),
+    ("22", "LA", "Louisiana"),
+    ("23", "ME", "Maine"),
+    ("24", "MD", "Maryland"),
+    ("25", "MA", "Massachusetts"),
+    ("26", "MI", "Michigan"),
+    ("27", "MN", "Minnesota"),
+    ("28", "MS", "Mississippi"),
+    ("29", "MO", "Missouri"),
+    ("30", "MT", "Montana"),
+    ("31", "NE", "Nebraska"),
+    ("32", "NV", "Nevada"),
+    ("33", "NH", "New Hampshire"),
+    ("34", "NJ", "New Jersey"),
+    ("35", "NM", "New Mexico"),
+    ("36", "NY", "New York"),
+    ("37", "NC", "North Carolina"),
+    ("38", "ND", "North Dakota"),
+    ("39", "OH", "Ohio"),
+    ("40", "OK", "Oklahoma"),
+    ("41", "OR", "Oregon"),
+    ("42", "PA", "Pennsylvania"),
+    ("44", "RI", "Rhode Island"),
+    ("45", "SC", "South Carolina"),
+    ("46", "SD", "South Dakota"),
+    ("47", "TN", "Tennessee"),
+    ("48", "TX", "Texas"),
+    ("49", "UT", "Utah"),
+    ("50", "VT", "Vermont"),
+    ("51", "VA", "Virginia"),
+    ("53", "WA", "Washington"),
+    ("54", "WV", "West Virginia"),
+    ("55", "WI", "Wisconsin"),
+    ("56", "WY", "Wyoming"),
+    ("60", "AS", "American Samoa"),
+    ("64", "FM", "Federated States of Micronesia"),
+    ("66", "GU", "Guam"),
+    ("68", "MH", "Marshall Islands"),
+    ("69", "MP", "Northern Mariana Islands"),
+    ("72", "PR", "Puerto Rico"),
+    ("78", "VI", "Virgin Islands"),
+];
+
+/// `InventoryEntry::locate` for every entry.
+pub fn locate_all(inv: &mut Inventory) {
+    for (fips, entry) in inv.iter_mut() {
+        entry.locate(fips);
+    }
+}
+
+/// The county equivalents FEMA maps community by community rather than countywide, as (community id, county fips).
+/// Their downloads are named by the 6-digit community id (`515531_20150116.zip` for Virginia Beach) instead of
+/// `{fips}C_`. They're Virginia's independent cities, DC and Alaska's consolidated city-boroughs; cities mapped with a
+/// neighbouring county (Manassas with Prince William) are in that county's file, and have no download of their own.
+pub const COUNTY_EQUIVALENT_CIDS: [(&str, &str); 13] = [
+    ("020005", "02020"), // Anchorage
+    ("020009", "02110"), // Juneau
+    ("110001", "11001"), // District of Columbia
+    ("510034", "51550"), // Chesapeake
+    ("510103", "51700"), // Newport News
+    ("510104", "51710"), // Norfolk
+    ("510129", "51760"), // Richmond
+    ("510130", "51770"), // Roanoke
+    ("510156", "51800"), // Suffolk
+    ("515519", "51510"), // Alexandria
+    ("515527", "51650"), // Hampton
+    ("515529", "51740"), // Portsmouth
+    ("515531", "51810"), // Virginia Beach
+];
+
+/// The county a download is for, from the DFIRM id its file name starts with: `48201C` (either case) for a
+/// countywide one, or a community id in `COUNTY_EQUIVALENT_CIDS`. None for anything else.
+pub fn dfirm_id_fips(dfirm_id: &str) -> Option<Fips> {
+    if let Some(fips) = dfirm_id.strip_suffix(['C', 'c']).filter(|fips| fips.len() == 5) {
+        return Fips::new(fips).ok();
+    }
+    COUNTY_EQUIVALENT_CIDS.iter()
+        .find(|(cid, _)| *cid == dfirm_id)
+        .and_then(|(_, fips)| Fips::new(*fips).ok())
+}
+
+/// The county a cached or downloaded file is for, from the DFIRM id before the first `_` of its name; see
+/// `dfirm_id_fips`.
+pub fn file_name_fips(file_name: &str) -> Option<Fips> {
+    dfirm_id_fips(file_name.split_once('_')?.0)
+}
+
+/// Parses a YYYYMMDD inventory date; the empty string (no product) and anything malformed give None.
+pub fn parse_file_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y%m%d").ok()
+}
+
+/// A date as YYYYMMDD, the way the inventory, plans and manifests write it, or the empty string for none.
+pub fn format_file_date(date: Option<NaiveDate>) -> String {
+    date.map(|date| date.format("%Y%m%d").to_string()).unwrap_or_default()
+}
+
+/// The inventory's files use empty strings for "no product"; tables and typed exports want a missing value instead.
+pub fn non_empty(s: &str) -> Option<&str> {
+    if s.is_empty() { None } else { Some(s) }
+}
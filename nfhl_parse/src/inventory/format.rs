@@ -0,0 +1,228 @@
+//! The inventory's JSON, by version, so the typed model in `inventory` can change without breaking the files already
+//! saved or the scripts that read them. Each version so far only adds fields to the one before, so every older file
+//! is also a valid newer one and `read` reads them all the same way.
+//!
+//! Version 1 is an object of entries by fips, each with four strings that are empty when there's no product, and
+//! dates as YYYYMMDD:
+//!
+//! ```json
+//! {"48201": {"effective_file_url": "https://hazards.fema.gov/...", "effective_file_date": "20220915",
+//!            "preliminary_file_url": "", "preliminary_file_date": ""}}
+//! ```
+//!
+//! Version 2, what's written now, lets an entry list more files for a product split into several zips or with
+//! supplementary archives, each with the product `kind` it belongs to and its `role`. An inventory with any such
+//! entry says it's version 2, so a reader that doesn't know `files` fails on it instead of quietly leaving out parts
+//! of a county's database:
+//!
+//! ```json
+//! {"version": 2,
+//!  "48201": {"effective_file_url": "https://...", "effective_file_date": "20220915", "preliminary_file_url": "",
+//!            "preliminary_file_date": "", "files": [{"kind": "effective", "role": "part", "url": "https://...",
+//!            "date": "20220915"}]}}
+//! ```
+//!
+//! Entries also say where they are, for whoever reads the file without a fips table of their own: `state_fips`,
+//! `state_abbrev` (`TX`) and, for counties read from the portal, `county_name` as it lists them (`HARRIS COUNTY`).
+//!
+//! `files` and the location fields are left out of entries without them. An inventory with no `files` anywhere has
+//! no `version` either, so older readers still read it, ignoring the location fields. A version 1 file reads as
+//! version 2 with no `files`, and `read` fills in its entries' states. `read` refuses a version it doesn't know.
+//!
+//! `serialize` and `deserialize` are the format for serde, e.g. `#[serde(with = "format")]` on an inventory field.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserializer, Serializer};
+
+use super::{Fips, Inventory};
+
+/// The version written into an inventory that needs it: one with an entry that lists `files`.
+pub const CURRENT: u32 = 2;
+
+/// Reads an inventory in any known version, with each entry's state filled in if the file didn't have it.
+pub fn read<R: Read>(reader: R) -> serde_json::Result<Inventory> {
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let inv = deserialize(&mut de)?;
+    de.end()?;
+    Ok(inv)
+}
+
+/// Writes `inv` as JSON, with its `version` if it has one.
+pub fn write<W: Write>(writer: W, inv: &Inventory) -> serde_json::Result<()> {
+    serialize(inv, &mut serde_json::Serializer::new(writer))
+}
+
+/// `inv` as JSON text, as `write` writes it.
+pub fn to_string(inv: &Inventory) -> String {
+    let mut json = Vec::new();
+    write(&mut json, inv).expect("an inventory always serializes");
+    String::from_utf8(json).expect("serde_json writes utf-8")
+}
+
+pub fn serialize<S: Serializer>(inv: &Inventory, serializer: S) -> Result<S::Ok, S::Error> {
+    let versioned = inv.values().any(|entry| !entry.files.is_empty());
+    let mut map = serializer.serialize_map(Some(inv.len() + usize::from(versioned)))?;
+    if versioned {
+        map.serialize_entry("version", &CURRENT)?;
+    }
+    for (fips, entry) in inv {
+        map.serialize_entry(fips, entry)?;
+    }
+    map.end()
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Inventory, D::Error> {
+    deserializer.deserialize_map(InventoryVisitor)
+}
+
+struct InventoryVisitor;
+
+impl<'de> Visitor<'de> for InventoryVisitor {
+    type Value = Inventory;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an inventory of entries by fips")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Inventory, A::Error> {
+        let mut inv = Inventory::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "version" {
+                let version: u32 = map.next_value()?;
+                if !(1..=CURRENT).contains(&version) {
+                    return Err(de::Error::custom(format!(
+                        "inventory version {} isn't one this reads (1 to {}); it may need a newer nfhl_util",
+                        version, CURRENT
+                    )));
+                }
+            } else {
+                let fips = Fips::new(key).map_err(de::Error::custom)?;
+                inv.insert(fips, map.next_value()?);
+            }
+        }
+        super::locate_all(&mut inv);
+        Ok(inv)
+    }
+}
+
+pub mod v1 {
+    use chrono::NaiveDate;
+    use url::Url;
+    use serde::{Deserialize, Serialize};
+
+    use crate::inventory::{format_file_date, non_empty, parse_file_date, InventoryEntry};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct Entry {
+        pub effective_file_url: String,
+        /// YYYYMMDD, or empty when there's no effective product.
+        pub effective_file_date: String,
+        pub preliminary_file_url: String,
+        /// YYYYMMDD, or empty when there's no preliminary product.
+        pub preliminary_file_date: String,
+    }
+
+    pub(super) fn url(s: &str) -> Result<Option<Url>, String> {
+        non_empty(s).map(|s| s.parse().map_err(|e| format!("'{}' isn't a url: {}", s, e))).transpose()
+    }
+
+    pub(super) fn date(s: &str) -> Result<Option<NaiveDate>, String> {
+        non_empty(s).map(|s| parse_file_date(s).ok_or_else(|| format!("'{}' isn't a YYYYMMDD date", s))).transpose()
+    }
+
+    /// Drops the entry's other `files`, which version 1 can't say.
+    impl From<InventoryEntry> for Entry {
+        fn from(entry: InventoryEntry) -> Entry {
+            Entry {
+                effective_file_url: entry.effective_file_url.map(String::from).unwrap_or_default(),
+                effective_file_date: format_file_date(entry.effective_file_date),
+                preliminary_file_url: entry.preliminary_file_url.map(String::from).unwrap_or_default(),
+                preliminary_file_date: format_file_date(entry.preliminary_file_date),
+            }
+        }
+    }
+
+    impl TryFrom<Entry> for InventoryEntry {
+        type Error = String;
+
+        fn try_from(entry: Entry) -> Result<InventoryEntry, String> {
+            Ok(InventoryEntry {
+                effective_file_url: url(&entry.effective_file_url)?,
+                effective_file_date: date(&entry.effective_file_date)?,
+                preliminary_file_url: url(&entry.preliminary_file_url)?,
+                preliminary_file_date: date(&entry.preliminary_file_date)?,
+                ..Default::default()
+            })
+        }
+    }
+}
+
+pub mod v2 {
+    use serde::{Deserialize, Serialize};
+
+    use super::v1;
+    use crate::inventory::{format_file_date, FileRole, InventoryEntry, InventoryFile, ProductKind};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct Entry {
+        #[serde(flatten)]
+        pub v1: v1::Entry,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub files: Vec<File>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub county_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub state_abbrev: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub state_fips: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct File {
+        pub kind: ProductKind,
+        pub role: FileRole,
+        pub url: String,
+        /// YYYYMMDD, or empty when it has none.
+        #[serde(default)]
+        pub date: String,
+    }
+
+    impl From<InventoryEntry> for Entry {
+        fn from(mut entry: InventoryEntry) -> Entry {
+            let files = std::mem::take(&mut entry.files).into_iter()
+                .map(|file| File {
+                    kind: file.kind,
+                    role: file.role,
+                    url: file.url.into(),
+                    date: format_file_date(file.date),
+                })
+                .collect();
+            let (county_name, state_abbrev, state_fips) =
+                (entry.county_name.take(), entry.state_abbrev.take(), entry.state_fips.take());
+            Entry { v1: entry.into(), files, county_name, state_abbrev, state_fips }
+        }
+    }
+
+    impl TryFrom<Entry> for InventoryEntry {
+        type Error = String;
+
+        fn try_from(entry: Entry) -> Result<InventoryEntry, String> {
+            let mut inventory_entry = InventoryEntry {
+                county_name: entry.county_name,
+                state_abbrev: entry.state_abbrev,
+                state_fips: entry.state_fips,
+                ..InventoryEntry::try_from(entry.v1)?
+            };
+            for file in entry.files {
+                let url = v1::url(&file.url)?.ok_or_else(|| format!("a {} file has no url", file.role.as_str()))?;
+                let date = v1::date(&file.date)?;
+                inventory_entry.files.push(InventoryFile { kind: file.kind, role: file.role, url, date });
+            }
+            Ok(inventory_entry)
+        }
+    }
+}
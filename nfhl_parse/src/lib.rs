@@ -0,0 +1,22 @@
+//! The parsing behind nfhl_util, with no IO, so it also builds for wasm32: the inventory model and its files, the
+//! NFHL portal's search results page, and the Map Service Center's search results. nfhl_util fetches the pages and
+//! re-exports what's here; a browser dashboard can parse saved responses with the same code through the `wasm`
+//! feature's bindings.
+
+pub mod inventory;
+pub mod msc;
+pub mod portal;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A FEMA site's page or JSON isn't in the shape it's parsed in.
+    #[error("{site} has changed its format: {detail}")]
+    PortalFormat { site: &'static str, detail: String },
+    /// Input that can't be used, like a fips code that isn't one.
+    #[error("{0}")]
+    Validation(String),
+}
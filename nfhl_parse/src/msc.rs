@@ -0,0 +1,136 @@
+//! The Map Service Center's (msc.fema.gov) advanced search results, the JSON listing a county's products along with
+//! its state's.
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+use url::Url;
+
+use crate::inventory::{parse_file_date, InventoryEntry};
+use crate::{Error, Result};
+
+pub const SITE: &str = "the Map Service Center";
+
+#[derive(Deserialize, Debug)]
+pub struct SearchResults {
+    #[serde(rename(deserialize = "EFFECTIVE"))]
+    pub effective: SearchResultEffective,
+    #[serde(rename(deserialize = "PRELIM_FIRM_DB"))]
+    pub preliminary: Option<Vec<SearchResultProductEntry>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchResultEffective {
+    #[serde(rename(deserialize = "NFHL_COUNTY_DATA"))]
+    pub county: Option<Vec<SearchResultProductEntry>>,
+    #[serde(rename(deserialize = "NFHL_STATE_DATA"))]
+    pub state: Option<Vec<SearchResultProductEntry>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchResultProductEntry {
+    #[serde(rename(deserialize = "product_TYPE_ID"))]
+    pub type_id: String,
+    #[serde(rename(deserialize = "product_SUBTYPE_ID"))]
+    pub subtype_id: String,
+    #[serde(rename(deserialize = "product_NAME"))]
+    pub name: String,
+    #[serde(rename(deserialize = "product_ID"))]
+    pub id: usize, // so far as I can tell, these ids are useless. Use "name" instead.
+    #[serde(rename(deserialize = "product_EFFECTIVE_DATE_STRING"))]
+    pub effective_date: Option<String>,
+    #[serde(rename(deserialize = "product_FILE_PATH"))]
+    pub filename: Option<String>,
+    #[serde(rename(deserialize = "product_FILE_SIZE"))]
+    pub filesize: Option<String>
+}
+
+/// A product from anywhere in an advanced search's results, with the group it was listed under (`EFFECTIVE`,
+/// `HISTORIC`, `PRELIM_FIRM_DB`...).
+#[derive(Debug)]
+pub struct ListedProduct {
+    pub group: String,
+    pub product: SearchResultProductEntry,
+}
+
+/// Every product an advanced search listed, whatever kind: FIRM panels, FIS reports, LOMCs, Risk MAP products and
+/// the rest, which `SearchResults` skips. They're found by shape (an object with a `product_TYPE_ID`) wherever they
+/// are, so a kind MSC adds or moves is still listed.
+pub fn parse_listed_products(body: &str) -> Result<Vec<ListedProduct>> {
+    let format = |detail: String| Error::PortalFormat { site: SITE, detail };
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|e| format(e.to_string()))?;
+    let groups = value.as_object().ok_or_else(|| format("the search results aren't an object".to_string()))?;
+    let mut listed = Vec::new();
+    for (group, value) in groups {
+        let mut stack = vec![value];
+        while let Some(value) = stack.pop() {
+            match value {
+                serde_json::Value::Object(object) if object.contains_key("product_TYPE_ID") => {
+                    let product = SearchResultProductEntry::deserialize(value).map_err(|e| format(e.to_string()))?;
+                    listed.push(ListedProduct { group: group.clone(), product });
+                }
+                serde_json::Value::Object(object) => stack.extend(object.values().rev()),
+                serde_json::Value::Array(values) => stack.extend(values.iter().rev()),
+                _ => {}
+            }
+        }
+    }
+    Ok(listed)
+}
+
+/// An advanced search's JSON results.
+pub fn parse_search_results(body: &str) -> Result<SearchResults> {
+    serde_json::from_str(body).map_err(|e| Error::PortalFormat { site: SITE, detail: e.to_string() })
+}
+
+impl SearchResults {
+    /// The searched county's entry: its newest effective county data and preliminary database.
+    pub fn county_entry(&self) -> Result<Option<InventoryEntry>> {
+        entry(self.effective.county.as_deref().unwrap_or_default(), self.preliminary.as_deref().unwrap_or_default())
+    }
+
+    /// The searched county's state's entry: its newest effective statewide data.
+    pub fn state_entry(&self) -> Result<Option<InventoryEntry>> {
+        entry(self.effective.state.as_deref().unwrap_or_default(), &[])
+    }
+}
+
+impl SearchResultProductEntry {
+    /// The url behind the product's download button.
+    pub fn download_url(&self) -> Result<Url> {
+        let filename = self.filename.as_deref().filter(|f| !f.is_empty())
+            .ok_or_else(|| Error::PortalFormat { site: SITE, detail: format!("product {} has no file path", self.name) })?;
+        let mut url = Url::parse("https://msc.fema.gov/portal/downloadProduct").expect("a valid url");
+        url.query_pairs_mut()
+            .append_pair("filepath", filename)
+            .append_pair("productTypeID", &self.type_id)
+            .append_pair("productSubTypeID", &self.subtype_id)
+            .append_pair("productID", &self.name);
+        Ok(url)
+    }
+
+    /// The effective date: from the file name where it ends in a YYYYMMDD one, otherwise the MM/DD/YYYY date MSC
+    /// shows.
+    pub fn file_date(&self) -> Option<NaiveDate> {
+        let name = self.filename.as_deref()?.rsplit('/').next()?;
+        let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+        stem.get(stem.len().saturating_sub(8)..)
+            .and_then(parse_file_date)
+            .or_else(|| NaiveDate::parse_from_str(self.effective_date.as_deref()?.trim(), "%m/%d/%Y").ok())
+    }
+}
+
+/// An inventory entry from the newest of each kind of product, leaving out what MSC doesn't have.
+pub fn entry(effective: &[SearchResultProductEntry], preliminary: &[SearchResultProductEntry]) -> Result<Option<InventoryEntry>> {
+    let newest = |products: &[SearchResultProductEntry]| -> Result<(Option<Url>, Option<NaiveDate>)> {
+        match products.iter().filter(|p| p.filename.is_some()).max_by_key(|p| p.file_date()) {
+            Some(product) => Ok((Some(product.download_url()?), product.file_date())),
+            None => Ok((None, None)),
+        }
+    };
+    let (effective_file_url, effective_file_date) = newest(effective)?;
+    let (preliminary_file_url, preliminary_file_date) = newest(preliminary)?;
+    if effective_file_url.is_none() && preliminary_file_url.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(InventoryEntry { effective_file_url, effective_file_date, preliminary_file_url, preliminary_file_date, ..Default::default() }))
+}
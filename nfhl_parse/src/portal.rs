@@ -0,0 +1,185 @@
+//! The NFHL portal's search results page (hazards.fema.gov/femaportal/NFHL/searchResult), which lists every
+//! county's effective NFHL download.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use lol_html::element;
+use lol_html::errors::RewritingError;
+use lol_html::send::{HtmlRewriter, Settings};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::inventory::{dfirm_id_fips, parse_file_date, Fips, Inventory, InventoryEntry};
+use crate::{Error, Result};
+
+pub const SITE: &str = "the NFHL portal";
+
+/// What the page's relative download links are relative to.
+pub const PORTAL_URL: &str = "https://hazards.fema.gov/femaportal/NFHL/";
+
+/// The inventory in a search results page. A page that no longer has the table of downloads is a `PortalFormat`
+/// error rather than an empty inventory, which `--delete` would take at its word.
+pub fn parse_search_results(body_response: &str) -> Result<Inventory> {
+    let mut parser = SearchResultsParser::new();
+    parser.write(body_response.as_bytes())?;
+    parser.finish()
+}
+
+/// The rows read so far, shared with the rewriter's handlers.
+#[derive(Default)]
+struct Rows {
+    inv: Inventory,
+    /// In a row whose first link hasn't come yet; only that one is the download.
+    awaiting_link: bool,
+    /// The file each county's entry came from, to say which was kept when another row has the same county.
+    file_names: HashMap<Fips, String>,
+    collisions: BTreeMap<Fips, Collision>,
+    /// Downloads whose file names don't say which county they're for.
+    unmatched: BTreeSet<String>,
+}
+
+/// Everything read from a search results page.
+#[derive(Debug, Clone, Default)]
+pub struct SearchResultsPage {
+    pub inventory: Inventory,
+    /// The counties with more than one row, by fips.
+    pub collisions: Vec<Collision>,
+    /// The file names of downloads left out because they're for no known county, like a community-based DFIRM that
+    /// isn't in `inventory::COUNTY_EQUIVALENT_CIDS`.
+    pub unmatched: BTreeSet<String>,
+}
+
+/// Rows for the same county, as when FEMA reissues a county's file and the table keeps a row for the old one too.
+/// The newest effective date wins, whichever order the rows came in; on a tie, the file name that sorts last.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub fips: Fips,
+    pub kept: String,
+    pub dropped: Vec<String>,
+}
+
+impl Rows {
+    fn insert(&mut self, fips: Fips, file_name: String, entry: InventoryEntry) {
+        let Some(existing) = self.inv.get(&fips) else {
+            self.file_names.insert(fips.clone(), file_name);
+            self.inv.insert(fips, entry);
+            return;
+        };
+        let existing_name = &self.file_names[&fips];
+        let newer = (entry.effective_file_date, &file_name) > (existing.effective_file_date, existing_name);
+        let (kept, dropped) = match newer {
+            true => (file_name, existing_name.clone()),
+            false => (existing_name.clone(), file_name),
+        };
+        let collision = self.collisions.entry(fips.clone())
+            .or_insert_with(|| Collision { fips: fips.clone(), kept: String::new(), dropped: Vec::new() });
+        collision.kept = kept.clone();
+        collision.dropped.push(dropped);
+        collision.dropped.sort();
+        if newer {
+            self.file_names.insert(fips.clone(), kept);
+            self.inv.insert(fips, entry);
+        }
+    }
+}
+
+/// `parse_search_results` a chunk at a time, as the page arrives. The page is several MB and its DOM several times
+/// that, so this streams it through lol_html's tokenizer instead and keeps nothing but the inventory.
+pub struct SearchResultsParser {
+    rewriter: HtmlRewriter<'static, fn(&[u8])>,
+    rows: Arc<Mutex<Rows>>,
+}
+
+impl Default for SearchResultsParser {
+    fn default() -> Self {
+        SearchResultsParser::new()
+    }
+}
+
+impl SearchResultsParser {
+    pub fn new() -> SearchResultsParser {
+        let rows = Arc::new(Mutex::new(Rows::default()));
+        // `{fips}C_{date}.zip`, or `{cid}_{date}.zip` for a county equivalent mapped by community
+        let re = Regex::new(r"fileName=(([^&_]+)_([^&]*?)\.(?i:zip))").unwrap();
+        let (row_start, link) = (rows.clone(), rows.clone());
+        let settings = Settings::new_send()
+            // not building the DOM, it can't always tell where it is, as in `<select>`s; the table is plain enough
+            .with_strict(false)
+            .append_element_content_handler(element!("tbody tr", move |_| {
+                row_start.lock().unwrap().awaiting_link = true;
+                Ok(())
+            }))
+            .append_element_content_handler(element!("tbody tr a", move |a| {
+                let mut rows = link.lock().unwrap();
+                if !std::mem::take(&mut rows.awaiting_link) {
+                    return Ok(());
+                }
+                let href = a.get_attribute("href")
+                    .ok_or_else(|| Error::PortalFormat { site: SITE, detail: "a download link has no href".to_string() })?;
+                // attributes come as written, `&amp;`s and all
+                let file_url = html_escape::decode_html_entities(&href);
+                if let Some(caps) = re.captures(&file_url) {
+                    let Some(county_fips) = dfirm_id_fips(&caps[2]) else {
+                        rows.unmatched.insert(caps[1].to_string());
+                        return Ok(());
+                    };
+                    let url = Url::parse(PORTAL_URL).and_then(|portal| portal.join(&file_url))
+                        .map_err(|e| Error::PortalFormat { site: SITE, detail: format!("'{}' isn't a usable download link: {}", file_url, e) })?;
+                    // the link names the county as the table does, `HARRIS COUNTY`, `FALLS CHURCH, CITY OF`
+                    let county_name = url.query_pairs()
+                        .find(|(key, _)| key == "county")
+                        .map(|(_, name)| name.trim().to_string())
+                        .filter(|name| !name.is_empty());
+                    let mut entry = InventoryEntry {
+                        effective_file_url: Some(url),
+                        effective_file_date: parse_file_date(&caps[3]),
+                        county_name,
+                        ..Default::default()
+                    };
+                    entry.locate(&county_fips);
+                    rows.insert(county_fips, caps[1].to_string(), entry);
+                }
+                Ok(())
+            }));
+        let discard: fn(&[u8]) = |_| {};
+        SearchResultsParser { rewriter: HtmlRewriter::new(settings, discard), rows }
+    }
+
+    /// The next chunk of the page.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.rewriter.write(chunk).map_err(rewriting_error)
+    }
+
+    /// The inventory, once the whole page has been written.
+    pub fn finish(self) -> Result<Inventory> {
+        Ok(self.finish_page()?.inventory)
+    }
+
+    /// `finish`, along with what else the page said about how it was read.
+    pub fn finish_page(self) -> Result<SearchResultsPage> {
+        self.rewriter.end().map_err(rewriting_error)?;
+        let mut rows = self.rows.lock().unwrap();
+        let inventory = std::mem::take(&mut rows.inv);
+        if inventory.is_empty() {
+            return Err(Error::PortalFormat { site: SITE, detail: "no county downloads on the search results page".to_string() });
+        }
+        Ok(SearchResultsPage {
+            inventory,
+            collisions: std::mem::take(&mut rows.collisions).into_values().collect(),
+            unmatched: std::mem::take(&mut rows.unmatched),
+        })
+    }
+}
+
+/// The handlers' own errors back out of lol_html's, and anything else from it as a page that can't be read.
+fn rewriting_error(e: RewritingError) -> Error {
+    match e {
+        RewritingError::ContentHandlerError(e) => match e.downcast::<Error>() {
+            Ok(e) => *e,
+            Err(e) => Error::PortalFormat { site: SITE, detail: e.to_string() },
+        },
+        e => Error::PortalFormat { site: SITE, detail: e.to_string() },
+    }
+}
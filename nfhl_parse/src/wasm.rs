@@ -0,0 +1,45 @@
+//! JavaScript bindings, behind the `wasm` feature: `wasm-pack build nfhl_parse --features wasm`. Each takes a saved
+//! response or file's text and returns an inventory as JSON text, in the format the CLI writes (see
+//! `inventory::format`), or throws with the same message the CLI would print:
+//!
+//! ```js
+//! import init, { parsePortalSearchResults, parseMscSearchResults, readInventory } from "./pkg/nfhl_parse.js";
+//!
+//! await init();
+//! const counties = JSON.parse(parsePortalSearchResults(await (await fetch("searchResult.html")).text()));
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::inventory::{format, Fips, Inventory};
+use crate::{msc, portal};
+
+fn to_json(inv: &Inventory) -> Result<String, JsError> {
+    Ok(format::to_string(inv))
+}
+
+/// The inventory in a saved NFHL portal search results page.
+#[wasm_bindgen(js_name = parsePortalSearchResults)]
+pub fn parse_portal_search_results(html: &str) -> Result<String, JsError> {
+    to_json(&portal::parse_search_results(html)?)
+}
+
+/// The inventory in a saved MSC advanced search for the county `fips`: that county's entry, or its state's if
+/// `fips` is the 2-digit state code. Empty if MSC had nothing for it.
+#[wasm_bindgen(js_name = parseMscSearchResults)]
+pub fn parse_msc_search_results(json: &str, fips: &str) -> Result<String, JsError> {
+    let fips = Fips::new(fips)?;
+    let results = msc::parse_search_results(json)?;
+    let entry = if fips.is_state() { results.state_entry()? } else { results.county_entry()? };
+    let entry = entry.map(|mut entry| {
+        entry.locate(&fips);
+        (fips, entry)
+    });
+    to_json(&entry.into_iter().collect())
+}
+
+/// An inventory file's text, checked and rewritten in the current format.
+#[wasm_bindgen(js_name = readInventory)]
+pub fn read_inventory(json: &str) -> Result<String, JsError> {
+    to_json(&format::read(json.as_bytes())?)
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::blocking::{multipart, Client};
+use reqwest::Url;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::diff::Change;
+use crate::inventory::{non_empty, parse_file_date, Fips, Inventory};
+
+/// An OAuth access token to use as-is, e.g. from `gcloud auth print-access-token`. Otherwise the service account key
+/// in `GOOGLE_APPLICATION_CREDENTIALS` is used, and failing that the GCE/Cloud Run metadata server.
+pub const ACCESS_TOKEN_ENV: &str = "GOOGLE_OAUTH_ACCESS_TOKEN";
+
+const SCOPE: &str = "https://www.googleapis.com/auth/bigquery";
+const METADATA_TOKEN_URL: &str = "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const METADATA_PROJECT_URL: &str = "http://metadata.google.internal/computeMetadata/v1/project/project-id";
+
+/// A `[project.]dataset.table` destination. Without a project, the credentials' project is used.
+#[derive(Debug, Clone)]
+pub struct TableRef {
+    pub project: Option<String>,
+    pub dataset: String,
+    pub table: String,
+}
+
+impl std::str::FromStr for TableRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('.').collect();
+        match parts[..] {
+            [dataset, table] if !dataset.is_empty() && !table.is_empty() =>
+                Ok(TableRef { project: None, dataset: dataset.to_string(), table: table.to_string() }),
+            [project, dataset, table] if !project.is_empty() && !dataset.is_empty() && !table.is_empty() =>
+                Ok(TableRef { project: Some(project.to_string()), dataset: dataset.to_string(), table: table.to_string() }),
+            _ => Err(format!("'{}' should look like dataset.table or project.dataset.table", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotRow<'a> {
+    snapshot_at: DateTime<Utc>,
+    kind: &'a str,
+    fips: &'a str,
+    effective_file_url: Option<&'a str>,
+    effective_file_date: Option<NaiveDate>,
+    preliminary_file_url: Option<&'a str>,
+    preliminary_file_date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct ChangeRow<'a> {
+    observed_at: DateTime<Utc>,
+    fips: &'a str,
+    kind: &'a str,
+    old_date: Option<NaiveDate>,
+    new_date: Option<NaiveDate>,
+    url: Option<&'a str>,
+}
+
+/// Appends an inventory snapshot to `table` (created if need be), one row per fips, all sharing a `snapshot_at`.
+pub fn export_inventory(table: &TableRef, kind: &str, inv: &Inventory) -> Result<usize, Box<dyn std::error::Error>> {
+    let snapshot_at = Utc::now();
+    let mut fips_codes: Vec<&Fips> = inv.keys().collect();
+    fips_codes.sort();
+    let rows: Vec<SnapshotRow> = fips_codes.into_iter()
+        .map(|fips| {
+            let e = &inv[fips];
+            SnapshotRow {
+                snapshot_at,
+                kind,
+                fips,
+                effective_file_url: e.effective_file_url.as_ref().map(Url::as_str),
+                effective_file_date: e.effective_file_date,
+                preliminary_file_url: e.preliminary_file_url.as_ref().map(Url::as_str),
+                preliminary_file_date: e.preliminary_file_date,
+            }
+        })
+        .collect();
+    let schema = json!([
+        {"name": "snapshot_at", "type": "TIMESTAMP", "mode": "REQUIRED"},
+        {"name": "kind", "type": "STRING", "mode": "REQUIRED"},
+        {"name": "fips", "type": "STRING", "mode": "REQUIRED"},
+        {"name": "effective_file_url", "type": "STRING"},
+        {"name": "effective_file_date", "type": "DATE"},
+        {"name": "preliminary_file_url", "type": "STRING"},
+        {"name": "preliminary_file_date", "type": "DATE"},
+    ]);
+    load(table, schema, &rows)
+}
+
+/// Appends changes to `table` (created if need be).
+pub fn export_changes(table: &TableRef, changes: &[Change], observed_at: DateTime<Utc>) -> Result<usize, Box<dyn std::error::Error>> {
+    let rows: Vec<ChangeRow> = changes.iter()
+        .map(|c| ChangeRow {
+            observed_at,
+            fips: &c.fips,
+            kind: c.kind.as_str(),
+            old_date: parse_file_date(&c.old_date),
+            new_date: parse_file_date(&c.new_date),
+            url: non_empty(&c.url),
+        })
+        .collect();
+    let schema = json!([
+        {"name": "observed_at", "type": "TIMESTAMP", "mode": "REQUIRED"},
+        {"name": "fips", "type": "STRING", "mode": "REQUIRED"},
+        {"name": "kind", "type": "STRING", "mode": "REQUIRED"},
+        {"name": "old_date", "type": "DATE"},
+        {"name": "new_date", "type": "DATE"},
+        {"name": "url", "type": "STRING"},
+    ]);
+    load(table, schema, &rows)
+}
+
+/// Runs a single load job appending `rows` as newline-delimited JSON, and waits for it to finish. Load jobs (unlike
+/// streaming inserts) are free and atomic: either the whole snapshot lands or none of it does.
+fn load<T: Serialize>(table: &TableRef, schema: Value, rows: &[T]) -> Result<usize, Box<dyn std::error::Error>> {
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let client = Client::builder().timeout(Duration::from_secs(300)).build()?;
+    let credentials = Credentials::discover(&client)?;
+    let project = match &table.project {
+        Some(project) => project.clone(),
+        None => credentials.project_id.clone()
+            .ok_or("no project in the table name or the credentials; use project.dataset.table")?,
+    };
+
+    let mut data = Vec::new();
+    for row in rows {
+        serde_json::to_writer(&mut data, row)?;
+        data.push(b'\n');
+    }
+    let job = json!({
+        "configuration": {
+            "load": {
+                "destinationTable": {"projectId": project, "datasetId": table.dataset, "tableId": table.table},
+                "schema": {"fields": schema},
+                "sourceFormat": "NEWLINE_DELIMITED_JSON",
+                "writeDisposition": "WRITE_APPEND",
+                "createDisposition": "CREATE_IF_NEEDED",
+            }
+        }
+    });
+    let form = multipart::Form::new()
+        .part("metadata", multipart::Part::text(job.to_string()).mime_str("application/json")?)
+        .part("data", multipart::Part::bytes(data).mime_str("application/octet-stream")?);
+    let response = client
+        .post(format!("https://bigquery.googleapis.com/upload/bigquery/v2/projects/{}/jobs?uploadType=multipart", project))
+        .bearer_auth(&credentials.access_token)
+        .multipart(form)
+        .send()?;
+    let mut job = api_response(response)?;
+
+    let job_id = job["jobReference"]["jobId"].as_str().ok_or("load job response has no job id")?.to_string();
+    let location = job["jobReference"]["location"].as_str().map(String::from);
+    while job["status"]["state"] != "DONE" {
+        std::thread::sleep(Duration::from_secs(2));
+        let mut request = client
+            .get(format!("https://bigquery.googleapis.com/bigquery/v2/projects/{}/jobs/{}", project, job_id))
+            .bearer_auth(&credentials.access_token);
+        if let Some(location) = &location {
+            request = request.query(&[("location", location)]);
+        }
+        job = api_response(request.send()?)?;
+    }
+    if let Some(error) = job["status"].get("errorResult") {
+        return Err(format!("BigQuery load job {} failed: {}", job_id, error["message"].as_str().unwrap_or("unknown error")).into());
+    }
+    Ok(rows.len())
+}
+
+/// The JSON body of a successful API call, or an error carrying BigQuery's own message (which says far more than
+/// the status code, e.g. which field of which row didn't match the schema).
+fn api_response(response: reqwest::blocking::Response) -> Result<Value, Box<dyn std::error::Error>> {
+    let status = response.status();
+    let body: Value = response.json().unwrap_or_default();
+    if !status.is_success() {
+        let message = body["error"]["message"].as_str().unwrap_or_else(|| status.canonical_reason().unwrap_or("request failed"));
+        return Err(format!("BigQuery API error {}: {}", status.as_u16(), message).into());
+    }
+    Ok(body)
+}
+
+struct Credentials {
+    access_token: String,
+    project_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+    project_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+impl Credentials {
+    fn discover(client: &Client) -> Result<Credentials, Box<dyn std::error::Error>> {
+        if let Ok(access_token) = std::env::var(ACCESS_TOKEN_ENV) {
+            return Ok(Credentials { access_token, project_id: std::env::var("GOOGLE_CLOUD_PROJECT").ok() });
+        }
+        if let Ok(key_path) = std::env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            let key: ServiceAccountKey = serde_json::from_reader(std::fs::File::open(&key_path)?)
+                .map_err(|e| format!("{} isn't a service account key: {}", key_path, e))?;
+            return Credentials::from_service_account(client, key);
+        }
+        Credentials::from_metadata_server(client)
+            .map_err(|e| format!("no Google credentials: set {} or GOOGLE_APPLICATION_CREDENTIALS ({})", ACCESS_TOKEN_ENV, e).into())
+    }
+
+    /// Exchanges a self-signed JWT for an access token, per Google's server-to-server OAuth flow.
+    fn from_service_account(client: &Client, key: ServiceAccountKey) -> Result<Credentials, Box<dyn std::error::Error>> {
+        let now = Utc::now().timestamp();
+        let claims = json!({
+            "iss": key.client_email,
+            "scope": SCOPE,
+            "aud": key.token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        let assertion = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key.as_bytes())?,
+        )?;
+        let token: TokenResponse = client.post(&key.token_uri)
+            .form(&[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", assertion.as_str())])
+            .send()?
+            .error_for_status()?
+            .json()?;
+        Ok(Credentials { access_token: token.access_token, project_id: key.project_id })
+    }
+
+    fn from_metadata_server(client: &Client) -> Result<Credentials, Box<dyn std::error::Error>> {
+        let get = |url: &str| client.get(url).header("Metadata-Flavor", "Google").timeout(Duration::from_secs(5)).send();
+        let token: TokenResponse = get(METADATA_TOKEN_URL)?.error_for_status()?.json()?;
+        let project_id = get(METADATA_PROJECT_URL).ok().and_then(|r| r.error_for_status().ok()).and_then(|r| r.text().ok());
+        Ok(Credentials { access_token: token.access_token, project_id })
+    }
+}
@@ -0,0 +1,140 @@
+//! Synchronous wrappers over the async core, for the CLI and embedders without a runtime of their own. They share one
+//! tokio runtime and one client, so connections are reused from call to call; `set_client` swaps in one of the
+//! embedder's own. Like `reqwest::blocking`, these panic
+//! if called from inside an async runtime; async code should call the async functions directly.
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+
+use crate::cancel::CancellationToken;
+use crate::client::{Client, ConnectionOptions};
+use crate::download::{self, Downloaded, RunReport};
+use crate::error::Result;
+use crate::inventory::Inventory;
+use crate::msc::StateProducts;
+use crate::nfhl_portal::Scrape;
+use crate::plan::{ListedFile, Plan, Product, Republished};
+use crate::publish::Publishers;
+use crate::shard::Shard;
+use crate::{msc, nfhl_portal, robots};
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("can't start the tokio runtime"))
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// The shared client, built with `ClientBuilder`'s defaults, the environment's `ConnectionOptions` and
+/// `NFHL_UTIL_RESPECT_ROBOTS` on first use unless `set_client` came first.
+pub fn client() -> Result<Client> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client.clone());
+    }
+    let client = Client::builder()
+        .connection(ConnectionOptions::from_env()?)
+        .respect_robots(robots::from_env())
+        .build()?;
+    Ok(CLIENT.get_or_init(|| client).clone())
+}
+
+/// Makes `client` the one these functions use. Fails, handing it back, once a client is in use.
+pub fn set_client(client: Client) -> std::result::Result<(), Client> {
+    CLIENT.set(client)
+}
+
+pub fn get_effective_county_products() -> Result<Inventory> {
+    let client = client()?;
+    block_on(nfhl_portal::get_effective_county_products(&client))
+}
+
+/// `nfhl_portal::scrape_county_products`.
+pub fn scrape_county_products() -> Result<Scrape> {
+    let client = client()?;
+    block_on(nfhl_portal::scrape_county_products(&client))
+}
+
+pub fn get_effective_state_products() -> Result<Inventory> {
+    let client = client()?;
+    block_on(msc::get_effective_state_products(&client))
+}
+
+/// `msc::get_state_products`.
+pub fn get_state_products(
+    states: &[&str],
+    region_concurrency: usize,
+    cancel: &CancellationToken,
+    diagnostics: &Path,
+) -> Result<StateProducts> {
+    let client = client()?;
+    block_on(msc::get_state_products(&client, states, region_concurrency, cancel, diagnostics))
+}
+
+/// `msc::get_checkpointed_state_products`.
+pub fn get_checkpointed_state_products(
+    checkpoint: &Path,
+    resume: bool,
+    region_concurrency: usize,
+    cancel: &CancellationToken,
+    diagnostics: &Path,
+) -> Result<StateProducts> {
+    let client = client()?;
+    block_on(msc::get_checkpointed_state_products(&client, checkpoint, resume, region_concurrency, cancel, diagnostics))
+}
+
+/// `msc::list_product_files`.
+pub fn list_product_files(counties: &[&str], product: Product, delay: Duration, cancel: &CancellationToken) -> Result<Vec<ListedFile>> {
+    let client = client()?;
+    block_on(msc::list_product_files(&client, counties, product, delay, cancel))
+}
+
+pub fn download_file(url: &str, path: &Path) -> Result<Downloaded> {
+    let client = client()?;
+    block_on(download::download_file(&client, url, path))
+}
+
+pub fn download_archive(url: &str, path: &Path) -> Result<Downloaded> {
+    let client = client()?;
+    block_on(download::download_archive(&client, url, path))
+}
+
+/// `download::find_republished`.
+pub fn find_republished(plan: &Plan, inv: &Inventory, delay: Duration, cancel: &CancellationToken) -> Result<Vec<Republished>> {
+    let client = client()?;
+    block_on(download::find_republished(&client, plan, inv, delay, cancel))
+}
+
+/// `download::apply_plan`, one download at a time. `cancel` can be cancelled from another thread.
+pub fn apply_plan(
+    plan: &Plan,
+    politeness: u8,
+    publishers: &mut Publishers,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let client = client()?;
+    block_on(download::apply_plan(&client, plan, politeness, 1, publishers, cancel))
+}
+
+/// `download::download_all`, one download at a time.
+#[allow(clippy::too_many_arguments)]
+pub fn download_all(
+    inv: &Inventory,
+    old_inv: Option<&Inventory>,
+    cache_dir: &Path,
+    delete: bool,
+    politeness: u8,
+    shard: Option<Shard>,
+    publishers: &mut Publishers,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let client = client()?;
+    block_on(download::download_all(&client, inv, old_inv, cache_dir, delete, politeness, 1, shard, publishers, cancel))
+}
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::Url;
+use serde::{Serialize, Deserialize};
+
+use crate::error::{NfhlError, Result};
+use crate::inventory::{InventoryEntry, InventoryFile, ProductKind};
+use crate::recompress::Recompressed;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Where files that failed verification are moved, inside the cache directory.
+pub const QUARANTINE_DIR_NAME: &str = ".quarantine";
+
+/// What we know about a file we downloaded into the cache.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub file_name: String,
+    pub url: String,
+    pub effective_date: String,
+    pub size: u64,
+    /// The file's SHA-256 as it was downloaded; missing for files downloaded before they were hashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub downloaded_at: DateTime<Utc>,
+    /// When the file was moved to the quarantine for failing verification. The next plan downloads it again, and
+    /// the entry that download writes doesn't have this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantined_at: Option<DateTime<Utc>>,
+    /// Set if `--recompress` rewrote the file; `size` and `sha256` are still those of the file as downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recompressed: Option<Recompressed>,
+}
+
+impl CacheEntry {
+    /// The file's size as the cache holds it, recompressed or not (but before any encryption).
+    pub fn stored_size(&self) -> u64 {
+        self.recompressed.as_ref().map_or(self.size, |recompressed| recompressed.size)
+    }
+
+    /// The SHA-256 of the file as the cache holds it, like `stored_size`.
+    pub fn stored_sha256(&self) -> Option<&str> {
+        match &self.recompressed {
+            Some(recompressed) => Some(&recompressed.sha256),
+            None => self.sha256.as_deref(),
+        }
+    }
+}
+
+/// How a cache directory arranges its county files. Recorded in the manifest; `migrate-cache` moves a cache from
+/// one to another.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheLayout {
+    /// Every file in the cache directory itself. Caches from before layouts were recorded are laid out like this.
+    #[default]
+    Legacy,
+    /// A directory per state, `{state fips}/{file name}`, so no one directory holds thousands of files.
+    V2,
+}
+
+impl CacheLayout {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheLayout::Legacy => "legacy",
+            CacheLayout::V2 => "v2",
+        }
+    }
+
+    /// Where a county's file named `file_name` goes, relative to the cache directory, as manifests and plans record
+    /// it.
+    pub fn relative_path(&self, fips: &str, file_name: &str) -> String {
+        match self {
+            CacheLayout::Legacy => file_name.to_string(),
+            CacheLayout::V2 => format!("{}/{}", fips.get(..2).unwrap_or(fips), file_name),
+        }
+    }
+}
+
+/// The cache's record of downloaded files, keyed by fips. Lives at `<cache_dir>/manifest.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheManifest {
+    /// Missing from manifests written before there was a choice, which were all `Legacy`.
+    #[serde(default)]
+    pub layout: CacheLayout,
+    pub entries: BTreeMap<String, CacheEntry>,
+    /// The inventory products' other files (see `InventoryEntry::files`), keyed by file name, so `entries` stays
+    /// one file per county.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, CacheEntry>,
+}
+
+impl CacheManifest {
+    /// Loads the manifest from `cache_dir`, or an empty one if the cache has never been written to.
+    pub fn load(cache_dir: &Path) -> Result<CacheManifest> {
+        let path = cache_dir.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(CacheManifest::default());
+        }
+        let f = File::open(&path).map_err(NfhlError::io(&path))?;
+        serde_json::from_reader(BufReader::new(f)).map_err(NfhlError::parse(&path))
+    }
+
+    /// Saves the manifest to `cache_dir`. The file is replaced atomically so an interrupted run can't corrupt it.
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        let path = cache_dir.join(MANIFEST_FILE_NAME);
+        let tmp_path = cache_dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        let f = File::create(&tmp_path).map_err(NfhlError::io(&tmp_path))?;
+        serde_json::to_writer_pretty(BufWriter::new(f), self).map_err(|e| NfhlError::io(&tmp_path)(e.into()))?;
+        std::fs::rename(&tmp_path, &path).map_err(NfhlError::io(&path))?;
+        Ok(())
+    }
+}
+
+/// The name a county's effective file is cached under. This is FEMA's own file name (e.g. `48201C_20220915.zip`)
+/// when the url carries one, so a new effective date naturally gets a new file.
+pub fn cache_file_name(fips: &str, entry: &InventoryEntry) -> String {
+    entry.effective_file_url.as_ref().and_then(fema_file_name)
+        .unwrap_or_else(|| format!("{}C_{}.zip", fips, crate::inventory::format_file_date(entry.effective_file_date)))
+}
+
+/// The name one of a county's other files (see `InventoryEntry::files`) is cached under: FEMA's own, as for
+/// `cache_file_name`, or the last part of the url's path if it names a zip, or failing both
+/// `{fips}C_{role}{n}_{YYYYMMDD}.zip`, `n` counting the product's other files from 1.
+pub fn inventory_file_name(fips: &str, file: &InventoryFile, n: usize) -> String {
+    fema_file_name(&file.url)
+        .or_else(|| file.url.path_segments()?.last()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".zip"))
+            .map(String::from))
+        .unwrap_or_else(|| format!("{}C_{}{}_{}.zip", fips, file.role.as_str(), n, crate::inventory::format_file_date(file.date)))
+}
+
+/// The `fileName` FEMA's download links carry.
+fn fema_file_name(url: &Url) -> Option<String> {
+    url.query_pairs()
+        .find(|(k, _)| k == "fileName")
+        .map(|(_, file_name)| file_name.into_owned())
+        .filter(|file_name| !file_name.is_empty() && !file_name.contains(['/', '\\']))
+}
+
+/// The name a county's file of `kind` is cached under in that product's directory (see `plan::Product::cache_dir`).
+/// Effective files keep `cache_file_name`; preliminary databases are `{fips}C_PRELIM_{YYYYMMDD}.zip`, since MSC's
+/// own name for them (`48201C_PRELIM_FIRM_DB.zip`) stays the same from one preliminary release to the next.
+pub fn product_file_name(fips: &str, entry: &InventoryEntry, kind: ProductKind) -> String {
+    match kind {
+        ProductKind::Effective => cache_file_name(fips, entry),
+        ProductKind::Preliminary => {
+            format!("{}C_PRELIM_{}.zip", fips, crate::inventory::format_file_date(entry.preliminary_file_date))
+        }
+    }
+}
+
+/// Why a file was quarantined, saved beside it as `.quarantine/{file_name}.reason.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuarantineReason {
+    pub fips: String,
+    pub file_name: String,
+    pub quarantined_at: DateTime<Utc>,
+    pub problems: Vec<String>,
+}
+
+/// Moves a cached file that failed verification to `.quarantine/` with a reason file, replacing one quarantined
+/// there before, and marks its manifest entry (if it has one, in `entries` or `files`). The caller saves the
+/// manifest. With the file gone, the next plan downloads it again rather than counting it as cached.
+pub fn quarantine_file(
+    cache_dir: &Path,
+    manifest: &mut CacheManifest,
+    fips: &str,
+    file_name: &str,
+    problems: &[String],
+) -> Result<PathBuf> {
+    let dir = cache_dir.join(QUARANTINE_DIR_NAME);
+    let quarantined = dir.join(file_name);
+    // `file_name` is the path in the cache, which for `CacheLayout::V2` is in a state's directory
+    let quarantined_dir = quarantined.parent().unwrap_or(&dir);
+    std::fs::create_dir_all(quarantined_dir).map_err(NfhlError::io(quarantined_dir))?;
+    let from = cache_dir.join(file_name);
+    std::fs::rename(&from, &quarantined).map_err(NfhlError::io(&from))?;
+
+    let reason = QuarantineReason {
+        fips: fips.to_string(),
+        file_name: file_name.to_string(),
+        quarantined_at: Utc::now(),
+        problems: problems.to_vec(),
+    };
+    let reason_path = dir.join(format!("{}.reason.json", file_name));
+    let f = File::create(&reason_path).map_err(NfhlError::io(&reason_path))?;
+    serde_json::to_writer_pretty(BufWriter::new(f), &reason).map_err(|e| NfhlError::io(&reason_path)(e.into()))?;
+
+    let entry = match manifest.files.get_mut(file_name) {
+        Some(entry) => Some(entry),
+        None => manifest.entries.get_mut(fips).filter(|entry| entry.file_name == file_name),
+    };
+    if let Some(entry) = entry {
+        entry.quarantined_at = Some(reason.quarantined_at);
+    }
+    Ok(quarantined)
+}
+
+/// A path in the cache as manifests and plans record it: relative to `cache_dir`, with `/` between directories.
+pub fn relative_name(cache_dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(cache_dir).unwrap_or(path);
+    let parts: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+    parts.join("/")
+}
+
+/// The zip files in the cache: in the directory itself, and in the state directories of `CacheLayout::V2`. Both
+/// are looked at whatever the manifest says, so a cache part way through `migrate-cache` is still read whole.
+pub fn zip_files(cache_dir: &Path) -> Result<Vec<PathBuf>> {
+    let is_zip = |path: &Path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    let mut files = Vec::new();
+    for dir_entry in std::fs::read_dir(cache_dir).map_err(NfhlError::io(cache_dir))? {
+        let path = dir_entry.map_err(NfhlError::io(cache_dir))?.path();
+        let is_state_dir = path.is_dir() && path.file_name().and_then(|f| f.to_str())
+            .is_some_and(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_digit()));
+        if is_state_dir {
+            for dir_entry in std::fs::read_dir(&path).map_err(NfhlError::io(&path))? {
+                let path = dir_entry.map_err(NfhlError::io(&path))?.path();
+                if is_zip(&path) {
+                    files.push(path);
+                }
+            }
+        } else if is_zip(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Finds the cached archive for a county: the manifest's file if it's still there, otherwise the newest
+/// `{fips}C_*.zip` (or `{cid}_*.zip`, for a county equivalent mapped by community) in the cache (e.g. for a cache
+/// populated by hand).
+pub fn cached_archive(cache_dir: &Path, fips: &str) -> Result<PathBuf> {
+    let manifest = CacheManifest::load(cache_dir)?;
+    if let Some(cached) = manifest.entries.get(fips) {
+        let path = cache_dir.join(&cached.file_name);
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+    let mut candidates: Vec<PathBuf> = zip_files(cache_dir)?.into_iter()
+        .filter(|path| {
+            let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+            crate::inventory::file_name_fips(file_name).is_some_and(|named| named == fips)
+                && !manifest.files.contains_key(&relative_name(cache_dir, path))
+        })
+        .collect();
+    // the names end in YYYYMMDD, so the newest sorts last
+    candidates.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    candidates.pop().ok_or_else(|| NfhlError::NotCached { fips: fips.to_string(), cache_dir: cache_dir.to_path_buf() })
+}
+
+/// Every county archive in the cache, sorted by fips. The county is taken from the manifest where it's recorded
+/// there, otherwise from the DFIRM id FEMA's file names start with (see `inventory::file_name_fips`). Other zips are
+/// ignored, and so are the counties' other files the manifest has in `files`.
+pub fn cached_archives(cache_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let manifest = CacheManifest::load(cache_dir)?;
+    let by_file_name: HashMap<&str, &str> = manifest.entries.iter()
+        .map(|(fips, cached)| (cached.file_name.as_str(), fips.as_str()))
+        .collect();
+
+    let mut archives = Vec::new();
+    for path in zip_files(cache_dir)? {
+        let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+        let relative = relative_name(cache_dir, &path);
+        // a county's other files aren't another version of its database
+        if manifest.files.contains_key(&relative) {
+            continue;
+        }
+        let fips = match by_file_name.get(relative.as_str()) {
+            Some(fips) => fips.to_string(),
+            None => match crate::inventory::file_name_fips(file_name) {
+                Some(fips) => fips.into(),
+                None => continue,
+            },
+        };
+        archives.push((fips, path));
+    }
+    archives.sort();
+    Ok(archives)
+}
+
+/// The effective date of a cached archive: the manifest's, if it downloaded the file, otherwise the `YYYYMMDD` its
+/// name ends with.
+pub fn archive_effective_date(manifest: &CacheManifest, fips: &str, archive: &Path) -> Option<NaiveDate> {
+    if let Some(cached) = manifest.entries.get(fips).filter(|cached| archive.ends_with(&cached.file_name)) {
+        return crate::inventory::parse_file_date(&cached.effective_date);
+    }
+    let stem = archive.file_stem().and_then(|f| f.to_str())?;
+    crate::inventory::parse_file_date(stem.get(stem.len().checked_sub(8)?..)?)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub files: usize,
+    pub total_bytes: u64,
+}
+
+/// Counts the zip files actually present in `cache_dir`.
+pub fn cache_stats(cache_dir: &Path) -> Result<CacheStats> {
+    let mut stats = CacheStats::default();
+    for path in zip_files(cache_dir)? {
+        stats.files += 1;
+        stats.total_bytes += std::fs::metadata(&path).map_err(NfhlError::io(&path))?.len();
+    }
+    Ok(stats)
+}
+
+/// `cache_stats` for the products MSC lists, whose files are in a `{fips}/` directory per county.
+pub fn county_dirs_stats(cache_dir: &Path) -> Result<CacheStats> {
+    let mut stats = CacheStats::default();
+    for dir_entry in std::fs::read_dir(cache_dir).map_err(NfhlError::io(cache_dir))? {
+        let dir = dir_entry.map_err(NfhlError::io(cache_dir))?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for dir_entry in std::fs::read_dir(&dir).map_err(NfhlError::io(&dir))? {
+            let dir_entry = dir_entry.map_err(NfhlError::io(&dir))?;
+            let path = dir_entry.path();
+            if path.is_file() && !path.extension().is_some_and(|ext| ext == "part") {
+                stats.files += 1;
+                stats.total_bytes += dir_entry.metadata().map_err(NfhlError::io(&path))?.len();
+            }
+        }
+    }
+    Ok(stats)
+}
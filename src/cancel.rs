@@ -0,0 +1,29 @@
+//! Stopping long operations part way. Scraping MSC, applying a plan and converting take a `CancellationToken`
+//! (tokio-util's) and, once it's cancelled, stop at the next county or file, returning what they finished and what's
+//! left, in a form that can be handed back to finish the job. A GUI or server cancels the token; the CLI's
+//! SIGTERM/SIGINT handling (see `systemd`) counts as cancelling every token.
+
+use std::future::Future;
+
+pub use tokio_util::sync::CancellationToken;
+
+use crate::error::{NfhlError, Result};
+use crate::systemd;
+
+/// Whether to stop: the token is cancelled, or the process was asked to shut down.
+pub fn requested(token: &CancellationToken) -> bool {
+    token.is_cancelled() || systemd::shutdown_requested()
+}
+
+/// Runs `future` unless stopping is already `requested` or the token is cancelled first, in which case the future is
+/// dropped and it's `NfhlError::Interrupted`. Good for single requests like
+/// `nfhl_portal::get_effective_county_products`, which have no partial result.
+pub async fn or_cancelled<T>(token: &CancellationToken, future: impl Future<Output = Result<T>>) -> Result<T> {
+    if requested(token) {
+        return Err(NfhlError::Interrupted);
+    }
+    tokio::select! {
+        result = future => result,
+        _ = token.cancelled() => Err(NfhlError::Interrupted),
+    }
+}
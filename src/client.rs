@@ -0,0 +1,419 @@
+//! The HTTP client the scrapers and downloads go through. `ClientBuilder` sets its timeouts, user agent, proxy, extra
+//! headers, rate limit, `ConnectionOptions` and response archive, and takes `Middleware` that sees every request
+//! before it's sent, e.g. to add the auth headers an internal caching proxy wants. Embedders build one and hand it to
+//! the async functions (or `blocking::set_client`) instead of each function making its own.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{IntoUrl, Method, Request, RequestBuilder, Response};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::error::{NfhlError, Result};
+use crate::response_archive::ResponseArchive;
+use crate::robots::{self, Robots};
+
+/// Sees each request after it's built and before it's sent, and can change it or refuse to send it. Closures taking
+/// a `&mut reqwest::Request` are middleware.
+pub trait Middleware: Send + Sync {
+    fn on_request(&self, request: &mut Request) -> Result<()>;
+}
+
+impl<F: Fn(&mut Request) -> Result<()> + Send + Sync> Middleware for F {
+    fn on_request(&self, request: &mut Request) -> Result<()> {
+        self(request)
+    }
+}
+
+/// A `reqwest::Client` with middleware and a rate limit. Cheap to clone; clones share the connection pool, cookies
+/// and rate limit.
+#[derive(Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    rate_limit: Option<Arc<RateLimit>>,
+    archive: Option<Arc<ResponseArchive>>,
+    robots: Option<Arc<RobotsCache>>,
+}
+
+/// One request per `interval`, across every clone of the client.
+struct RateLimit {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimit {
+    async fn wait(&self) {
+        // held through the sleep, so waiters go one at a time
+        let mut next = self.next.lock().await;
+        if let Some(at) = *next {
+            tokio::time::sleep_until(at).await;
+        }
+        *next = Some(Instant::now() + self.interval);
+    }
+}
+
+/// Each host's robots.txt, fetched on the first request there, and when its `Crawl-delay` next allows one.
+#[derive(Default)]
+struct RobotsCache {
+    hosts: Mutex<HashMap<String, Arc<HostRobots>>>,
+}
+
+struct HostRobots {
+    robots: Robots,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RobotsCache {
+    /// Fails a request robots.txt disallows, and otherwise waits out the host's crawl delay.
+    async fn check(&self, client: &reqwest::Client, request: &Request) -> Result<()> {
+        let url = request.url();
+        let origin = url.origin().ascii_serialization();
+        let host = {
+            let mut hosts = self.hosts.lock().await;
+            match hosts.get(&origin) {
+                Some(host) => host.clone(),
+                None => {
+                    let host = Arc::new(HostRobots { robots: fetch_robots(client, &origin).await?, next: Mutex::new(None) });
+                    hosts.insert(origin.clone(), host.clone());
+                    host
+                }
+            }
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        if !host.robots.allows(&path) {
+            return Err(NfhlError::Validation(format!("{}/robots.txt disallows {}; it's only fetched without --respect-robots", origin, path)));
+        }
+        if let Some(delay) = host.robots.crawl_delay {
+            let mut next = host.next.lock().await;
+            if let Some(at) = *next {
+                tokio::time::sleep_until(at).await;
+            }
+            let now = Instant::now();
+            *next = Some(now.checked_add(delay).unwrap_or(now));
+        }
+        Ok(())
+    }
+}
+
+/// A host's robots.txt. A missing one (any 4xx) allows everything; a server error is a network error, so nothing is
+/// fetched until it can be read.
+async fn fetch_robots(client: &reqwest::Client, origin: &str) -> Result<Robots> {
+    let response = client.get(format!("{}/robots.txt", origin)).send().await?;
+    let robots = if response.status().is_client_error() {
+        Robots::default()
+    } else {
+        Robots::parse(&response.error_for_status()?.text().await?, robots::USER_AGENT_TOKEN)
+    };
+    // for the record, since respecting it is the point
+    eprintln!("{}/robots.txt: {} disallowed, {} allowed paths, crawl delay {}", origin, robots.disallow.len(),
+        robots.allow.len(), robots.crawl_delay.map_or("none".to_string(), |delay| format!("{:.1}s", delay.as_secs_f64())));
+    Ok(robots)
+}
+
+impl Client {
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.inner.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.inner.post(url)
+    }
+
+    pub fn head<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.inner.head(url)
+    }
+
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.inner.request(method, url)
+    }
+
+    /// Builds the request and runs it past the middleware, without sending it.
+    pub fn build(&self, request: RequestBuilder) -> Result<Request> {
+        let mut request = request.build()?;
+        for middleware in self.middleware.iter() {
+            middleware.on_request(&mut request)?;
+        }
+        Ok(request)
+    }
+
+    /// Sends a request from `build` once the rate limit allows, whatever the response's status.
+    pub async fn execute(&self, request: Request) -> Result<Response> {
+        if let Some(robots) = &self.robots {
+            robots.check(&self.inner, &request).await?;
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.wait().await;
+        }
+        Ok(self.inner.execute(request).await?)
+    }
+
+    /// Where the scrapers' responses are being archived, if they are.
+    pub fn response_archive(&self) -> Option<&ResponseArchive> {
+        self.archive.as_deref()
+    }
+
+    /// `build` and `execute`, with an unsuccessful status as an error.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let request = self.build(request)?;
+        Ok(self.execute(request).await?.error_for_status()?)
+    }
+}
+
+/// A plain reqwest client, with no middleware or rate limit.
+impl From<reqwest::Client> for Client {
+    fn from(inner: reqwest::Client) -> Client {
+        Client { inner, middleware: Arc::new([]), rate_limit: None, archive: None, robots: None }
+    }
+}
+
+/// How the client makes and keeps connections. A long run makes tens of thousands of requests to the same two
+/// hosts, so the defaults lean towards reuse: lookups are cached, idle connections kept, quiet ones kept alive, and
+/// HTTP/2 used where the server offers it.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionOptions {
+    /// How long a host's looked-up addresses are reused; 0 looks it up for every new connection. A lookup that fails
+    /// falls back on the last answer, however old.
+    pub dns_cache_secs: u64,
+    /// How long an idle connection is kept for the next request.
+    pub pool_idle_secs: u64,
+    /// The most idle connections kept per host.
+    pub pool_max_idle_per_host: usize,
+    /// How often TCP keepalives are sent on a quiet connection; 0 for never.
+    pub tcp_keepalive_secs: u64,
+    /// Offer HTTP/2 when connecting over TLS, falling back to HTTP/1.1 for servers that don't take it. False sticks
+    /// to HTTP/1.1.
+    pub http2: bool,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            dns_cache_secs: 300,
+            pool_idle_secs: 90,
+            pool_max_idle_per_host: 16,
+            tcp_keepalive_secs: 60,
+            http2: true,
+        }
+    }
+}
+
+impl ConnectionOptions {
+    /// The defaults, with whatever `NFHL_UTIL_DNS_CACHE_SECS`, `NFHL_UTIL_POOL_IDLE_SECS`,
+    /// `NFHL_UTIL_POOL_MAX_IDLE_PER_HOST`, `NFHL_UTIL_TCP_KEEPALIVE_SECS` and `NFHL_UTIL_HTTP2` say instead.
+    pub fn from_env() -> Result<ConnectionOptions> {
+        fn var<T: std::str::FromStr>(name: &str, value: &mut T) -> Result<()> {
+            if let Ok(s) = std::env::var(name) {
+                *value = s.trim().parse()
+                    .map_err(|_| NfhlError::Validation(format!("{}='{}' isn't usable", name, s)))?;
+            }
+            Ok(())
+        }
+        let mut opts = ConnectionOptions::default();
+        var("NFHL_UTIL_DNS_CACHE_SECS", &mut opts.dns_cache_secs)?;
+        var("NFHL_UTIL_POOL_IDLE_SECS", &mut opts.pool_idle_secs)?;
+        var("NFHL_UTIL_POOL_MAX_IDLE_PER_HOST", &mut opts.pool_max_idle_per_host)?;
+        var("NFHL_UTIL_TCP_KEEPALIVE_SECS", &mut opts.tcp_keepalive_secs)?;
+        var("NFHL_UTIL_HTTP2", &mut opts.http2)?;
+        Ok(opts)
+    }
+}
+
+/// The system resolver, remembering answers for `ttl`. Every download is a new connection to one of two hosts, and
+/// a lookup that flakes fails the download with it.
+struct DnsCache {
+    ttl: Duration,
+    entries: Arc<std::sync::Mutex<LookedUp>>,
+}
+
+/// host -> (when it was looked up, its addresses)
+type LookedUp = HashMap<String, (Instant, Vec<SocketAddr>)>;
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (ttl, entries, host) = (self.ttl, self.entries.clone(), name.as_str().to_string());
+        Box::pin(async move {
+            let cached = entries.lock().unwrap().get(&host).cloned();
+            if let Some((at, addrs)) = &cached {
+                if at.elapsed() < ttl {
+                    return Ok(Box::new(addrs.clone().into_iter()) as Addrs);
+                }
+            }
+            // the port is filled in by the connector
+            let looked_up = tokio::net::lookup_host((host.as_str(), 0)).await.map(|addrs| addrs.collect::<Vec<_>>());
+            match looked_up {
+                Ok(addrs) => {
+                    entries.lock().unwrap().insert(host, (Instant::now(), addrs.clone()));
+                    Ok(Box::new(addrs.into_iter()) as Addrs)
+                }
+                Err(e) => match cached {
+                    Some((_, addrs)) => {
+                        eprintln!("couldn't look up {} ({}), using its last addresses", host, e);
+                        Ok(Box::new(addrs.into_iter()) as Addrs)
+                    }
+                    None => Err(e.into()),
+                },
+            }
+        })
+    }
+}
+
+/// Settings for a `Client`. The defaults are what the CLI uses: cookies on (the portal's sessions need them), a
+/// timeout long enough for the biggest state-sized files, reqwest's user agent (none) and the proxy from
+/// `HTTPS_PROXY`/`HTTP_PROXY`, no rate limit beyond the politeness delay between downloads, and the default
+/// `ConnectionOptions` (the CLI's come from `ConnectionOptions::from_env`).
+pub struct ClientBuilder {
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxy: Option<String>,
+    headers: Vec<(String, String)>,
+    rate_limit: Option<Duration>,
+    connection: ConnectionOptions,
+    archive_dir: Option<PathBuf>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    respect_robots: bool,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder::new()
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> ClientBuilder {
+        ClientBuilder {
+            timeout: Some(Duration::from_secs(60 * 60)),
+            connect_timeout: None,
+            user_agent: None,
+            proxy: None,
+            headers: Vec::new(),
+            rate_limit: None,
+            connection: ConnectionOptions::default(),
+            archive_dir: None,
+            middleware: Vec::new(),
+            respect_robots: false,
+        }
+    }
+
+    /// The longest a whole request may take, body included; None for no limit.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> ClientBuilder {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// A proxy for every request, in place of the environment's.
+    pub fn proxy(mut self, url: impl Into<String>) -> ClientBuilder {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// A header sent with every request.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> ClientBuilder {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// At most one request per `interval`, downloads included.
+    pub fn rate_limit(mut self, interval: Duration) -> ClientBuilder {
+        self.rate_limit = Some(interval);
+        self
+    }
+
+    pub fn connection(mut self, connection: ConnectionOptions) -> ClientBuilder {
+        self.connection = connection;
+        self
+    }
+
+    /// Keeps a copy of every scraper response in `dir`; see `response_archive`.
+    pub fn archive_responses(mut self, dir: impl Into<PathBuf>) -> ClientBuilder {
+        self.archive_dir = Some(dir.into());
+        self
+    }
+
+    /// Honors each host's robots.txt; see `robots`.
+    pub fn respect_robots(mut self, respect_robots: bool) -> ClientBuilder {
+        self.respect_robots = respect_robots;
+        self
+    }
+
+    /// Adds middleware, which runs after any added before it.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> ClientBuilder {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let connection = &self.connection;
+        let mut builder = reqwest::Client::builder()
+            .cookie_store(true)
+            .pool_idle_timeout(Duration::from_secs(connection.pool_idle_secs))
+            .pool_max_idle_per_host(connection.pool_max_idle_per_host)
+            .tcp_keepalive(Some(Duration::from_secs(connection.tcp_keepalive_secs)).filter(|d| !d.is_zero()));
+        if connection.dns_cache_secs > 0 {
+            let ttl = Duration::from_secs(connection.dns_cache_secs);
+            builder = builder.dns_resolver(Arc::new(DnsCache { ttl, entries: Default::default() }));
+        }
+        builder = if connection.http2 {
+            // big downloads want a bigger window than h2's default 64KB
+            builder.http2_adaptive_window(true)
+        } else {
+            builder.http1_only()
+        };
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy.as_str())
+                .map_err(|e| NfhlError::Validation(format!("'{}' isn't a usable proxy: {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| NfhlError::Validation(format!("'{}' isn't a header name", name)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| NfhlError::Validation(format!("the {} header's value isn't a valid one", name)))?;
+            headers.append(name, value);
+        }
+        Ok(Client {
+            inner: builder.default_headers(headers).build()?,
+            middleware: self.middleware.into(),
+            rate_limit: self.rate_limit.map(|interval| Arc::new(RateLimit { interval, next: Mutex::new(None) })),
+            archive: self.archive_dir.as_deref().map(ResponseArchive::open).transpose()?.map(Arc::new),
+            robots: self.respect_robots.then(|| Arc::new(RobotsCache::default())),
+        })
+    }
+}
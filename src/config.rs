@@ -0,0 +1,261 @@
+//! Defaults for the CLI from a config file, `~/.config/nfhl_util/config.toml` (or `$XDG_CONFIG_HOME/nfhl_util/`, or
+//! wherever `NFHL_UTIL_CONFIG` points), e.g.
+//!
+//! ```toml
+//! cache_dir = "/srv/nfhl/cache"
+//! politeness = 100
+//! proxy = "http://proxy.internal:3128"
+//! notify_urls = ["https://hooks.example.com/nfhl"]
+//! ```
+//!
+//! `[profile.NAME]` tables hold settings for `--profile NAME` (or `NFHL_UTIL_PROFILE`), which override the top-level
+//! ones, so one install can drive e.g. a production mirror and a scratch cache:
+//!
+//! ```toml
+//! [profile.prod-mirror]
+//! cache_dir = "/srv/nfhl/cache"
+//! publish = ["kafka://broker:9092/nfhl-events"]
+//!
+//! [profile.dev]
+//! cache_dir = "/tmp/nfhl"
+//! politeness = 255
+//! ```
+//!
+//! Each setting has an environment variable (`NFHL_UTIL_CACHE_DIR` and so on, see `Config::vars`) which the flags
+//! read, so the config file only fills in variables that aren't already set: flags win over the environment, which
+//! wins over the file.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+pub const CONFIG_ENV: &str = "NFHL_UTIL_CONFIG";
+pub const PROFILE_ENV: &str = "NFHL_UTIL_PROFILE";
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub cache_dir: Option<PathBuf>,
+    pub politeness: Option<u8>,
+    /// An http(s) proxy for every request, unless `HTTPS_PROXY`/`HTTP_PROXY` say otherwise.
+    pub proxy: Option<String>,
+    /// `watch`'s `--notify-url`s.
+    pub notify_urls: Option<Vec<String>>,
+    /// `--publish` message buses.
+    pub publish: Option<Vec<String>>,
+    pub report_postgres: Option<String>,
+    pub postgis_dsn: Option<String>,
+    /// `serve`'s `--refresh-token`.
+    pub refresh_token: Option<String>,
+    pub sign_key_password: Option<String>,
+    /// Encrypts cached files at rest; see `encryption`.
+    pub cache_key: Option<String>,
+    /// A service account key file for BigQuery.
+    pub bigquery_credentials: Option<PathBuf>,
+    /// Where MSC responses that can't be read are saved.
+    pub diagnostics_dir: Option<PathBuf>,
+    /// `--respect-robots`.
+    pub respect_robots: Option<bool>,
+    /// Connection tuning; see `client::ConnectionOptions`.
+    pub dns_cache_secs: Option<u64>,
+    pub pool_idle_secs: Option<u64>,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub tcp_keepalive_secs: Option<u64>,
+    pub http2: Option<bool>,
+    /// Named sets of settings, by profile name.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Config>,
+}
+
+/// Where the config file is looked for: `NFHL_UTIL_CONFIG`, otherwise under `XDG_CONFIG_HOME` or `~/.config`.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("nfhl_util").join("config.toml"))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// The config at `config_path()`. A missing file is an empty config, unless `NFHL_UTIL_CONFIG` named it.
+    pub fn load_default() -> Result<Config, Box<dyn std::error::Error>> {
+        match config_path() {
+            Some(path) if path.exists() || std::env::var_os(CONFIG_ENV).is_some() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// The top-level settings with the profile's laid over them.
+    pub fn with_profile(mut self, name: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        let profile = match self.profile.remove(name) {
+            Some(profile) => profile,
+            None => {
+                let names: Vec<&str> = self.profile.keys().map(|name| name.as_str()).collect();
+                let names = if names.is_empty() { "none".to_string() } else { names.join(", ") };
+                return Err(format!("there's no profile '{}'; the config has {}", name, names).into());
+            }
+        };
+        if !profile.profile.is_empty() {
+            return Err(format!("profile '{}' has profiles of its own", name).into());
+        }
+        Ok(Config {
+            cache_dir: profile.cache_dir.or(self.cache_dir),
+            politeness: profile.politeness.or(self.politeness),
+            proxy: profile.proxy.or(self.proxy),
+            notify_urls: profile.notify_urls.or(self.notify_urls),
+            publish: profile.publish.or(self.publish),
+            report_postgres: profile.report_postgres.or(self.report_postgres),
+            postgis_dsn: profile.postgis_dsn.or(self.postgis_dsn),
+            refresh_token: profile.refresh_token.or(self.refresh_token),
+            sign_key_password: profile.sign_key_password.or(self.sign_key_password),
+            cache_key: profile.cache_key.or(self.cache_key),
+            bigquery_credentials: profile.bigquery_credentials.or(self.bigquery_credentials),
+            diagnostics_dir: profile.diagnostics_dir.or(self.diagnostics_dir),
+            respect_robots: profile.respect_robots.or(self.respect_robots),
+            dns_cache_secs: profile.dns_cache_secs.or(self.dns_cache_secs),
+            pool_idle_secs: profile.pool_idle_secs.or(self.pool_idle_secs),
+            pool_max_idle_per_host: profile.pool_max_idle_per_host.or(self.pool_max_idle_per_host),
+            tcp_keepalive_secs: profile.tcp_keepalive_secs.or(self.tcp_keepalive_secs),
+            http2: profile.http2.or(self.http2),
+            profile: BTreeMap::new(),
+        })
+    }
+
+    /// The environment variable for each setting that's set. Lists are comma-separated.
+    pub fn vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        let mut var = |name: &'static str, value: Option<String>| {
+            if let Some(value) = value {
+                vars.push((name, value));
+            }
+        };
+        var("NFHL_UTIL_CACHE_DIR", self.cache_dir.as_ref().map(|dir| dir.display().to_string()));
+        var("NFHL_UTIL_POLITENESS", self.politeness.map(|p| p.to_string()));
+        var("NFHL_UTIL_PROXY", self.proxy.clone());
+        var("NFHL_UTIL_NOTIFY_URL", self.notify_urls.as_ref().map(|urls| urls.join(",")));
+        var("NFHL_UTIL_PUBLISH", self.publish.as_ref().map(|urls| urls.join(",")));
+        var("NFHL_UTIL_REPORT_POSTGRES", self.report_postgres.clone());
+        var("NFHL_POSTGIS_DSN", self.postgis_dsn.clone());
+        var("NFHL_UTIL_REFRESH_TOKEN", self.refresh_token.clone());
+        var(crate::signing::SIGN_KEY_PASSWORD_ENV, self.sign_key_password.clone());
+        var(crate::encryption::CACHE_KEY_ENV, self.cache_key.clone());
+        var("GOOGLE_APPLICATION_CREDENTIALS", self.bigquery_credentials.as_ref().map(|path| path.display().to_string()));
+        var("NFHL_UTIL_DIAGNOSTICS_DIR", self.diagnostics_dir.as_ref().map(|dir| dir.display().to_string()));
+        var(crate::robots::RESPECT_ROBOTS_ENV, self.respect_robots.map(|respect| respect.to_string()));
+        var("NFHL_UTIL_DNS_CACHE_SECS", self.dns_cache_secs.map(|secs| secs.to_string()));
+        var("NFHL_UTIL_POOL_IDLE_SECS", self.pool_idle_secs.map(|secs| secs.to_string()));
+        var("NFHL_UTIL_POOL_MAX_IDLE_PER_HOST", self.pool_max_idle_per_host.map(|n| n.to_string()));
+        var("NFHL_UTIL_TCP_KEEPALIVE_SECS", self.tcp_keepalive_secs.map(|secs| secs.to_string()));
+        var("NFHL_UTIL_HTTP2", self.http2.map(|http2| http2.to_string()));
+        vars
+    }
+
+    /// Sets the environment variables the environment doesn't already have. Call before parsing the command line,
+    /// and before any threads start.
+    pub fn apply_to_env(&self) {
+        for (name, value) in self.vars() {
+            if std::env::var_os(name).is_none() {
+                std::env::set_var(name, value);
+            }
+        }
+        // reqwest takes its proxy from the standard variables
+        if let Some(proxy) = std::env::var_os("NFHL_UTIL_PROXY") {
+            for name in ["HTTPS_PROXY", "HTTP_PROXY"] {
+                if std::env::var_os(name).is_none() && std::env::var_os(name.to_lowercase()).is_none() {
+                    std::env::set_var(name, &proxy);
+                }
+            }
+        }
+    }
+}
+
+/// The `--profile` on a command line, which is needed before the command line can be parsed properly (its settings
+/// become the flags' defaults), otherwise `NFHL_UTIL_PROFILE`.
+pub fn profile_arg(args: &[OsString]) -> Option<String> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--profile" {
+            return args.next().map(|name| name.into_owned());
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_string());
+        }
+    }
+    std::env::var(PROFILE_ENV).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn comments_and_multi_line_arrays() {
+        let config = parse(r#"
+            # the mirror
+            cache_dir = "/srv/nfhl # not a comment"  # a comment
+            politeness = 100
+            notify_urls = [
+                "https://hooks.example.com/a",  # first
+                "https://hooks.example.com/b",
+            ]
+        "#);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/srv/nfhl # not a comment")));
+        assert_eq!(config.politeness, Some(100));
+        assert_eq!(config.notify_urls.unwrap(), ["https://hooks.example.com/a", "https://hooks.example.com/b"]);
+    }
+
+    #[test]
+    fn quoted_profile_names() {
+        let config = parse(r#"
+            [profile."prod.mirror"]
+            cache_dir = "/srv/nfhl"
+
+            [profile.'dev']
+            politeness = 255
+        "#);
+        assert_eq!(config.profile["prod.mirror"].cache_dir, Some(PathBuf::from("/srv/nfhl")));
+        assert_eq!(config.profile["dev"].politeness, Some(255));
+    }
+
+    #[test]
+    fn profiles_lay_over_the_top_level() {
+        let config = parse(r#"
+            cache_dir = "/srv/nfhl"
+            politeness = 100
+            http2 = true
+
+            [profile.dev]
+            cache_dir = "/tmp/nfhl"
+            http2 = false
+        "#);
+        let dev = config.clone().with_profile("dev").unwrap();
+        assert_eq!(dev.cache_dir, Some(PathBuf::from("/tmp/nfhl")));
+        assert_eq!(dev.politeness, Some(100));
+        assert_eq!(dev.http2, Some(false));
+        assert!(dev.profile.is_empty());
+        let e = config.with_profile("prod").unwrap_err();
+        assert_eq!(e.to_string(), "there's no profile 'prod'; the config has dev");
+    }
+
+    #[test]
+    fn unknown_or_mistyped_settings_are_errors() {
+        assert!(toml::from_str::<Config>("cache_directory = \"/srv/nfhl\"").is_err());
+        assert!(toml::from_str::<Config>("[profile.dev]\npoliteness = \"fast\"").is_err());
+    }
+}
@@ -0,0 +1,432 @@
+use std::path::{Path, PathBuf};
+
+use crate::cancel::{self, CancellationToken};
+
+/// The formats `convert` can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum ConvertFormat {
+    /// GeoPackage: one `.gpkg` per county holding all of its layers.
+    Gpkg,
+    /// GeoParquet: one `.parquet` per layer holding every county converted (or one per layer and state with
+    /// `--partition-by-state`).
+    Geoparquet,
+    /// FlatGeobuf: a `{fips}/` folder per county with one spatially indexed `.fgb` per layer, which web maps can
+    /// read with HTTP range requests straight from a bucket.
+    Fgb,
+    /// Shapefile: a `{fips}/{layer}/` folder per county layer, for software that takes nothing else. Field names
+    /// longer than the format's 10 characters get shortened, and the renames are listed beside each layer.
+    Shp,
+}
+
+impl ConvertFormat {
+    fn driver(&self) -> &'static str {
+        match self {
+            ConvertFormat::Gpkg => "GPKG",
+            ConvertFormat::Geoparquet => "Parquet",
+            ConvertFormat::Fgb => "FlatGeobuf",
+            ConvertFormat::Shp => "ESRI Shapefile",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ConvertFormat::Gpkg => "gpkg",
+            ConvertFormat::Geoparquet => "parquet",
+            ConvertFormat::Fgb => "fgb",
+            ConvertFormat::Shp => "shp",
+        }
+    }
+
+    /// Whether a file holds a single layer, so each county gets a folder of them.
+    fn is_single_layer(&self) -> bool {
+        matches!(self, ConvertFormat::Fgb | ConvertFormat::Shp)
+    }
+
+    /// Where a single-layer format puts a county's layer.
+    fn layer_path(&self, out_dir: &Path, fips: &str, layer: &str) -> PathBuf {
+        match self {
+            // a shapefile is several files, so each gets a folder that can be zipped up and handed over as is
+            ConvertFormat::Shp => out_dir.join(fips).join(layer).join(format!("{}.shp", layer)),
+            _ => out_dir.join(fips).join(format!("{}.{}", layer, self.extension())),
+        }
+    }
+
+    /// Extra `ogr2ogr` arguments for the format.
+    fn options(&self) -> &'static [&'static str] {
+        match self {
+            ConvertFormat::Fgb => &["-lco", "SPATIAL_INDEX=YES"],
+            ConvertFormat::Shp => &["-lco", "ENCODING=UTF-8"],
+            _ => &[],
+        }
+    }
+
+    /// Whether outputs are per layer, combining counties, rather than per county.
+    pub fn is_per_layer(&self) -> bool {
+        matches!(self, ConvertFormat::Geoparquet)
+    }
+}
+
+/// `ogr2ogr` options shared by the commands that write converted data.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct TranslateOptions {
+    /// Reproject to this CRS, given as anything GDAL accepts: `EPSG:5070` (CONUS Albers, for areas), a PROJ string,
+    /// WKT. Defaults to keeping the source CRS.
+    #[clap(long)]
+    pub t_srs: Option<String>,
+    /// Only keep what's inside this box, `min_lon,min_lat,max_lon,max_lat`; features crossing it are cut at its
+    /// edges.
+    #[clap(long, allow_hyphen_values = true, conflicts_with = "clip-geojson")]
+    pub clip_bbox: Option<ClipBox>,
+    /// Only keep what's inside the polygons of this GeoJSON file (a watershed, a metro area...); features crossing
+    /// them are cut at their edges.
+    #[clap(long, parse(from_os_str))]
+    pub clip_geojson: Option<PathBuf>,
+    /// Only keep features matching this OGR SQL condition, e.g. `"FLD_ZONE IN ('AE','VE','A')"`. Every layer
+    /// converted needs the fields it names, so it has to come with `--layers`.
+    #[clap(long = "where", requires = "layers")]
+    pub where_clause: Option<String>,
+}
+
+/// A `--clip-bbox`, in longitude and latitude.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl std::str::FromStr for ClipBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("'{}' should look like min_lon,min_lat,max_lon,max_lat, e.g. -95.8,29.5,-95.0,30.1", s);
+        let values = s.split(',').map(|v| v.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>().map_err(|_| err())?;
+        match values[..] {
+            [min_x, min_y, max_x, max_y] if min_x < max_x && min_y < max_y => Ok(ClipBox { min_x, min_y, max_x, max_y }),
+            [_, _, _, _] => Err(format!("'{}' is empty: the minimums have to be less than the maximums", s)),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl TranslateOptions {
+    /// Fails early on options GDAL would only reject after the first county.
+    #[cfg(feature = "gdal")]
+    pub fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(srs) = &self.t_srs {
+            gdal::spatial_ref::SpatialRef::from_definition(srs).map_err(|e| format!("--t-srs {}: {}", srs, e))?;
+        }
+        if let Some(aoi) = &self.clip_geojson {
+            use gdal::vector::LayerAccess;
+
+            let polygons: u64 = gdal::Dataset::open(aoi)
+                .map_err(|e| format!("--clip-geojson {}: {}", aoi.display(), e))?
+                .layers().map(|layer| layer.feature_count()).sum();
+            if polygons == 0 {
+                return Err(format!("--clip-geojson {} has nothing in it to clip to", aoi.display()).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The options as `ogr2ogr` arguments.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(srs) = &self.t_srs {
+            args.extend(["-t_srs".to_string(), srs.clone()]);
+        }
+        // the clip is applied in the source CRS, which for FEMA's data is NAD83 longitude and latitude; a GeoJSON file
+        // is reprojected to it by GDAL
+        if let Some(b) = &self.clip_bbox {
+            args.push("-clipsrc".to_string());
+            args.extend([b.min_x, b.min_y, b.max_x, b.max_y].map(|v| v.to_string()));
+        }
+        if let Some(aoi) = &self.clip_geojson {
+            // absolute, since the merge manifest compares these across runs
+            let aoi = std::fs::canonicalize(aoi).unwrap_or_else(|_| aoi.clone());
+            args.extend(["-clipsrc".to_string(), aoi.to_string_lossy().into_owned()]);
+        }
+        if let Some(condition) = &self.where_clause {
+            args.extend(["-where".to_string(), condition.clone()]);
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    pub format: ConvertFormat,
+    /// Only these layers (e.g. `S_Fld_Haz_Ar`); all of them if empty.
+    pub layers: Vec<String>,
+    /// For per-layer formats, write a hive-style `{layer}/state_fips={ss}/` partition per state instead of a single
+    /// file per layer.
+    pub partition_by_state: bool,
+    pub translate: TranslateOptions,
+}
+
+/// What a cancelled `convert` didn't get to: converting these archives with these options finishes the job. For
+/// per-county formats it's the counties not yet converted; for per-layer ones, the layers not yet written.
+#[derive(Debug, Clone)]
+pub struct ConvertRemaining {
+    pub archives: Vec<(String, PathBuf)>,
+    pub opts: ConvertOptions,
+}
+
+/// Converts the given cached county archives into `out_dir`. Per-county formats get a file per county, and a
+/// county that fails is reported and skipped; per-layer formats combine all the counties into each layer's output.
+/// Once `cancel` is cancelled it stops before the next county or layer, returning what's left.
+#[cfg(feature = "gdal")]
+pub fn convert(
+    archives: &[(String, PathBuf)],
+    out_dir: &Path,
+    opts: &ConvertOptions,
+    cancel: &CancellationToken,
+) -> Result<Option<ConvertRemaining>, Box<dyn std::error::Error>> {
+    if opts.partition_by_state && !opts.format.is_per_layer() {
+        return Err("--partition-by-state only applies to per-layer formats like geoparquet".into());
+    }
+    opts.translate.check()?;
+    let mut failed = 0;
+    let mut gdbs = Vec::new();
+    let mut sources = Vec::new();
+    for source @ (fips, archive) in archives {
+        match crate::extract::require_archive_gdb_path(archive) {
+            Ok(gdb) => {
+                gdbs.push((fips.clone(), gdb));
+                sources.push(source.clone());
+            }
+            Err(e) => {
+                eprintln!("{}: {}", fips, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if opts.format.is_per_layer() {
+        let (written, layers_left) = convert_layers(&gdbs, out_dir, opts, cancel)?;
+        for dest in written {
+            eprintln!("wrote {}", dest.display());
+        }
+        if !layers_left.is_empty() {
+            eprintln!("cancelled, stopped converting with {} left", layers_left.join(", "));
+            return Ok(Some(ConvertRemaining { archives: sources, opts: ConvertOptions { layers: layers_left, ..opts.clone() } }));
+        }
+    } else {
+        for (i, (fips, gdb)) in gdbs.iter().enumerate() {
+            if cancel::requested(cancel) {
+                eprintln!("cancelled, stopped converting with {} counties left", gdbs.len() - i);
+                return Ok(Some(ConvertRemaining { archives: sources[i..].to_vec(), opts: opts.clone() }));
+            }
+            match convert_gdb(gdb, fips, out_dir, opts) {
+                Ok(written) => {
+                    for dest in written {
+                        eprintln!("{}: wrote {}", fips, dest.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", fips, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} counties failed to convert", failed, archives.len()).into());
+    }
+    Ok(None)
+}
+
+/// Combines each layer across counties (through an OGR VRT union, so nothing is staged on disk) and writes it out,
+/// tagging every feature with its `county_fips`. Counties missing a layer are left out of that layer. Returns the
+/// files written, and the layers not written because `cancel` was cancelled.
+#[cfg(feature = "gdal")]
+fn convert_layers(
+    gdbs: &[(String, String)],
+    out_dir: &Path,
+    opts: &ConvertOptions,
+    cancel: &CancellationToken,
+) -> Result<(Vec<PathBuf>, Vec<String>), Box<dyn std::error::Error>> {
+    use std::collections::BTreeMap;
+    use gdal::vector::LayerAccess;
+
+    // partition (a state, if partitioning) -> [(fips, gdb)]
+    type Partitions<'a> = BTreeMap<Option<&'a str>, Vec<(&'a str, &'a str)>>;
+    let mut sources: BTreeMap<String, Partitions> = BTreeMap::new();
+    for (fips, gdb) in gdbs {
+        let dataset = gdal::Dataset::open(gdb)?;
+        let partition = if opts.partition_by_state { fips.get(..2) } else { None };
+        for layer in dataset.layers() {
+            let name = layer.name();
+            let wanted = opts.layers.is_empty() || opts.layers.iter().any(|l| l.eq_ignore_ascii_case(&name));
+            if wanted {
+                sources.entry(name).or_default().entry(partition).or_default().push((fips.as_str(), gdb.as_str()));
+            }
+        }
+    }
+    for wanted in &opts.layers {
+        if !sources.keys().any(|name| name.eq_ignore_ascii_case(wanted)) {
+            eprintln!("warning: no county has a {} layer", wanted);
+        }
+    }
+
+    let mut written = Vec::new();
+    for (i, (layer, partitions)) in sources.iter().enumerate() {
+        if cancel::requested(cancel) {
+            return Ok((written, sources.keys().skip(i).cloned().collect()));
+        }
+        for (partition, counties) in partitions {
+            let vrt = format!("<OGRVRTDataSource>{}</OGRVRTDataSource>", union_layer_vrt(layer, counties));
+
+            let dest = match partition {
+                Some(state) => out_dir.join(layer).join(format!("state_fips={}", state)).join(format!("part.{}", opts.format.extension())),
+                None => out_dir.join(format!("{}.{}", layer, opts.format.extension())),
+            };
+            let src = gdal::Dataset::open(&vrt)?;
+            write_layers(&src, std::slice::from_ref(layer), &dest, opts)?;
+            written.push(dest);
+        }
+    }
+    Ok((written, Vec::new()))
+}
+
+/// An OGR VRT union layer named `layer` over that layer of each of the given `(fips, gdb)` counties, adding a
+/// `county_fips` field saying where each feature came from. Goes inside an `<OGRVRTDataSource>`.
+pub fn union_layer_vrt(layer: &str, counties: &[(&str, &str)]) -> String {
+    use crate::html_report::escape;
+
+    let mut vrt = format!("<OGRVRTUnionLayer name=\"{}\">", escape(layer));
+    vrt.push_str("<SourceLayerFieldName>county_fips</SourceLayerFieldName>");
+    for (fips, gdb) in counties {
+        vrt.push_str(&format!(
+            "<OGRVRTLayer name=\"{}\"><SrcDataSource>{}</SrcDataSource><SrcLayer>{}</SrcLayer></OGRVRTLayer>",
+            escape(fips), escape(gdb), escape(layer)));
+    }
+    vrt.push_str("</OGRVRTUnionLayer>");
+    vrt
+}
+
+/// Converts a county geodatabase (an `archive_gdb_path` or an extracted `.gdb`) into `out_dir`, as `{fips}.{ext}`
+/// or, for single-layer formats, `{fips}/{layer}.{ext}`, replacing anything already there. Requested layers the
+/// county doesn't have are skipped with a warning, since small counties often ship partial layer sets. Returns the
+/// files written.
+#[cfg(feature = "gdal")]
+fn convert_gdb(gdb: &str, fips: &str, out_dir: &Path, opts: &ConvertOptions) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let src = gdal::Dataset::open(gdb)?;
+    let available: Vec<String> = src.layers().map(|layer| layer.name()).collect();
+    let layers: Vec<String> = if opts.layers.is_empty() {
+        available
+    } else {
+        let mut layers = Vec::new();
+        for wanted in &opts.layers {
+            match available.iter().find(|name| name.eq_ignore_ascii_case(wanted)) {
+                Some(name) => layers.push(name.clone()),
+                None => eprintln!("warning: {} has no {} layer", gdb, wanted),
+            }
+        }
+        layers
+    };
+    if layers.is_empty() {
+        return Err(format!("{} has none of the requested layers", gdb).into());
+    }
+
+    let ext = opts.format.extension();
+    if !opts.format.is_single_layer() {
+        let dest = out_dir.join(format!("{}.{}", fips, ext));
+        write_layers(&src, &layers, &dest, opts)?;
+        return Ok(vec![dest]);
+    }
+    let mut written = Vec::new();
+    for layer in &layers {
+        let dest = opts.format.layer_path(out_dir, fips, layer);
+        if let (ConvertFormat::Shp, Some(dir)) = (opts.format, dest.parent()) {
+            // the .dbf, .shx, .prj and so on would otherwise be left over from last time
+            if dir.exists() {
+                std::fs::remove_dir_all(dir)?;
+            }
+        }
+        write_layers(&src, std::slice::from_ref(layer), &dest, opts)?;
+        if opts.format == ConvertFormat::Shp {
+            report_renamed_fields(&src, layer, &dest)?;
+        }
+        written.push(dest);
+    }
+    Ok(written)
+}
+
+/// Compares the fields of a layer with those of the shapefile written from it, and for any that the driver had to
+/// shorten, warns and writes a `field_names.csv` (original, shapefile) beside the shapefile so its readers can map
+/// them back.
+#[cfg(feature = "gdal")]
+fn report_renamed_fields(src: &gdal::Dataset, layer: &str, shp: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let original: Vec<String> = src.layer_by_name(layer)?.defn().fields().map(|f| f.name()).collect();
+    let written = gdal::Dataset::open(shp)?;
+    let shortened: Vec<String> = written.layer(0)?.defn().fields().map(|f| f.name()).collect();
+    let renamed: Vec<(&String, &String)> = original.iter().zip(&shortened).filter(|(a, b)| a != b).collect();
+    if renamed.is_empty() {
+        return Ok(());
+    }
+
+    let headers = vec!["original".to_string(), "shapefile".to_string()];
+    let rows: Vec<Vec<String>> = renamed.iter().map(|(a, b)| vec![a.to_string(), b.to_string()]).collect();
+    let csv = shp.with_file_name("field_names.csv");
+    crate::report::write_csv(&mut std::fs::File::create(&csv)?, &headers, &rows)?;
+    eprintln!("warning: {} had {} field names shortened for the shapefile ({}); see {}",
+        layer, renamed.len(),
+        renamed.iter().map(|(a, b)| format!("{} -> {}", a, b)).collect::<Vec<_>>().join(", "),
+        csv.display());
+    Ok(())
+}
+
+/// Writes `layers` of `src` to a new `dest` in the requested format, replacing anything already there.
+#[cfg(feature = "gdal")]
+fn write_layers(src: &gdal::Dataset, layers: &[String], dest: &Path, opts: &ConvertOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if dest.exists() {
+        std::fs::remove_file(dest)?;
+    }
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut args = vec!["-f".to_string(), opts.format.driver().to_string()];
+    args.extend(opts.format.options().iter().map(|arg| arg.to_string()));
+    args.extend(opts.translate.args());
+    args.extend(layers.iter().cloned());
+    vector_translate(src, dest, &args)
+}
+
+/// GDAL's `ogr2ogr` as a library call: translates `src` to a new dataset at `dest`, with `args` as they would be
+/// given on the `ogr2ogr` command line (minus the source and destination). Bare arguments are layer names.
+#[cfg(feature = "gdal")]
+pub fn vector_translate(src: &gdal::Dataset, dest: &Path, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int};
+    use std::ptr::null_mut;
+
+    let c_args = args.iter().map(|arg| CString::new(arg.as_str())).collect::<Result<Vec<_>, _>>()?;
+    let mut argv: Vec<*mut c_char> = c_args.iter().map(|arg| arg.as_ptr() as *mut c_char).collect();
+    argv.push(null_mut());
+    let c_dest = CString::new(dest.to_string_lossy().as_bytes())?;
+
+    // SAFETY: argv is null-terminated and its strings outlive the options, which only copy them; the source handle
+    // stays owned by `src`, and the returned dataset is closed here.
+    unsafe {
+        let options = gdal_sys::GDALVectorTranslateOptionsNew(argv.as_mut_ptr(), null_mut());
+        if options.is_null() {
+            return Err(format!("invalid ogr2ogr options: {}", args.join(" ")).into());
+        }
+        let mut src_handle = src.c_dataset();
+        let mut usage_error: c_int = 0;
+        let out = gdal_sys::GDALVectorTranslate(c_dest.as_ptr(), null_mut(), 1, &mut src_handle, options, &mut usage_error);
+        gdal_sys::GDALVectorTranslateOptionsFree(options);
+        if out.is_null() {
+            let message = CStr::from_ptr(gdal_sys::CPLGetLastErrorMsg()).to_string_lossy().into_owned();
+            return Err(format!("converting to {} failed: {}", dest.display(), message).into());
+        }
+        gdal_sys::GDALClose(out);
+    }
+    Ok(())
+}
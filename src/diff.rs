@@ -0,0 +1,142 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+use reqwest::Url;
+use serde::{Serialize, Deserialize};
+
+use crate::inventory::{format_file_date, Fips, Inventory};
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// The jurisdiction is new to the inventory.
+    Added,
+    /// The jurisdiction is no longer in the inventory.
+    Removed,
+    /// The effective file's url or date changed.
+    Effective,
+    /// The preliminary file's url or date changed.
+    Preliminary,
+    /// The url and date are the same, but FEMA is serving a different file than the cached one.
+    Republished,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Effective => "effective",
+            ChangeKind::Preliminary => "preliminary",
+            ChangeKind::Republished => "republished",
+        }
+    }
+}
+
+/// A single detected difference between two inventories. Dates are left blank where the product doesn't exist,
+/// matching the inventory's own convention.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    pub fips: String,
+    pub kind: ChangeKind,
+    pub old_date: String,
+    pub new_date: String,
+    /// The product's new url, or its old one if it was removed.
+    pub url: String,
+}
+
+fn url_string(url: &Option<Url>) -> String {
+    url.as_ref().map(Url::to_string).unwrap_or_default()
+}
+
+/// Lists every change from `old` to `new`, ordered by fips. A county whose effective and preliminary files both
+/// changed yields one change for each.
+pub fn diff_inventories(old: &Inventory, new: &Inventory) -> Vec<Change> {
+    let fips_codes: BTreeSet<&Fips> = old.keys().chain(new.keys()).collect();
+    let mut changes = Vec::new();
+    for fips in fips_codes {
+        match (old.get(fips), new.get(fips)) {
+            (None, Some(n)) => changes.push(Change {
+                fips: fips.to_string(),
+                kind: ChangeKind::Added,
+                old_date: "".to_string(),
+                new_date: format_file_date(n.effective_file_date),
+                url: url_string(&n.effective_file_url),
+            }),
+            (Some(o), None) => changes.push(Change {
+                fips: fips.to_string(),
+                kind: ChangeKind::Removed,
+                old_date: format_file_date(o.effective_file_date),
+                new_date: "".to_string(),
+                url: url_string(&o.effective_file_url),
+            }),
+            (Some(o), Some(n)) => {
+                if o.effective_file_url != n.effective_file_url || o.effective_file_date != n.effective_file_date {
+                    changes.push(Change {
+                        fips: fips.to_string(),
+                        kind: ChangeKind::Effective,
+                        old_date: format_file_date(o.effective_file_date),
+                        new_date: format_file_date(n.effective_file_date),
+                        url: url_string(&n.effective_file_url),
+                    });
+                }
+                if o.preliminary_file_url != n.preliminary_file_url || o.preliminary_file_date != n.preliminary_file_date {
+                    changes.push(Change {
+                        fips: fips.to_string(),
+                        kind: ChangeKind::Preliminary,
+                        old_date: format_file_date(o.preliminary_file_date),
+                        new_date: format_file_date(n.preliminary_file_date),
+                        url: url_string(&n.preliminary_file_url),
+                    });
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    changes
+}
+
+/// A one-line tally of changes by kind, e.g. "3 changes (1 added, 2 effective)".
+pub fn summarize_changes(changes: &[Change]) -> String {
+    let kinds = [ChangeKind::Added, ChangeKind::Removed, ChangeKind::Effective, ChangeKind::Preliminary, ChangeKind::Republished];
+    let counts: Vec<String> = kinds.iter()
+        .map(|&kind| (kind, changes.iter().filter(|c| c.kind == kind).count()))
+        .filter(|&(_, n)| n > 0)
+        .map(|(kind, n)| format!("{} {}", n, kind.as_str()))
+        .collect();
+    match changes.len() {
+        0 => "no changes".to_string(),
+        1 => format!("1 change ({})", counts.join(", ")),
+        n => format!("{} changes ({})", n, counts.join(", ")),
+    }
+}
+
+pub fn write_changes(out: &mut dyn Write, changes: &[Change], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, changes)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["fips", "change", "old_date", "new_date"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = changes.iter()
+        .map(|c| vec![c.fips.clone(), c.kind.as_str().to_string(), c.old_date.clone(), c.new_date.clone()])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => {
+            writeln!(out, "**NFHL inventory: {}**", summarize_changes(changes))?;
+            if !changes.is_empty() {
+                writeln!(out)?;
+                report::write_markdown_table(out, &headers, &rows)?;
+            }
+            Ok(())
+        }
+        _ => {
+            report::write_table(out, &headers, &rows)?;
+            writeln!(out, "{}", summarize_changes(changes))?;
+            Ok(())
+        }
+    }
+}
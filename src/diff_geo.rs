@@ -0,0 +1,211 @@
+//! `diff-geo`: what a map revision changed on the ground. The previous and current versions of a county (kept by
+//! `--keep-history`) have their `S_Fld_Haz_Ar` layers overlaid, and the area is totalled by flood zone transition:
+//! how many acres went from X to AE, which were newly mapped, and which dropped out.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::merge_geo::Source;
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    Unchanged,
+    Rezoned,
+    /// Mapped now but not before, e.g. a study extended into unmapped land.
+    Added,
+    /// Mapped before but not now.
+    Removed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub from_zone: Option<String>,
+    pub to_zone: Option<String>,
+    pub acres: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GeoDiff {
+    pub fips: String,
+    pub old_file: String,
+    pub old_effective_date: Option<NaiveDate>,
+    pub new_file: String,
+    pub new_effective_date: Option<NaiveDate>,
+    /// Largest first.
+    pub transitions: Vec<Transition>,
+    /// Land brought into the Special Flood Hazard Area, and taken out of it.
+    pub sfha_added_acres: f64,
+    pub sfha_removed_acres: f64,
+}
+
+/// The previous and current cached versions of a county, by effective date.
+pub fn versions(cache_dir: &Path, fips: &str) -> Result<(Source, Source), Box<dyn std::error::Error>> {
+    let mut versions = crate::merge_geo::county_versions(cache_dir, fips)?;
+    match (versions.pop(), versions.pop()) {
+        (Some(new), Some(old)) => Ok((old, new)),
+        _ => Err(format!(
+            "{} doesn't have two versions of {} to compare; `--delete` removes the previous one unless `--keep-history` is given too",
+            cache_dir.display(), fips).into()),
+    }
+}
+
+/// Overlays the flood hazard polygons of two versions of a county, in the same equal-area projection `stats` uses.
+/// Slivers under a square metre, from boundaries being redrawn a hair apart, are dropped.
+#[cfg(feature = "gdal")]
+pub fn diff_county(old: &Source, new: &Source) -> Result<GeoDiff, Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::{Envelope, Geometry, LayerAccess};
+    use crate::stats::{equal_area_srs, layer_srs, SQ_M_PER_ACRE};
+
+    const SLIVER_AREA: f64 = 1.0;
+
+    struct Zoned {
+        zone: String,
+        sfha: bool,
+        envelope: Envelope,
+        geometry: Geometry,
+        area: f64,
+        /// How much of it the other version covers.
+        matched: f64,
+    }
+
+    fn read(source: &Source, equal_area: Option<&SpatialRef>) -> Result<(Vec<Zoned>, SpatialRef), Box<dyn std::error::Error>> {
+        let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+        let dataset = gdal::Dataset::open(&gdb)?;
+        let mut layer = dataset.layer_by_name("S_Fld_Haz_Ar")
+            .map_err(|_| format!("{} has no S_Fld_Haz_Ar layer", gdb))?;
+        let equal_area = match equal_area {
+            Some(srs) => srs.clone(),
+            None => equal_area_srs(&layer)?,
+        };
+        let to_equal_area = CoordTransform::new(&layer_srs(&layer)?, &equal_area)?;
+        let mut polygons = Vec::new();
+        for feature in layer.features() {
+            let geometry = match feature.geometry() {
+                Some(geometry) if !geometry.is_empty() => geometry.transform(&to_equal_area)?,
+                _ => continue,
+            };
+            // a polygon GEOS can't intersect as is usually just needs a zero-width buffer to become valid
+            let geometry = if geometry.is_valid() { geometry } else { geometry.buffer(0.0, 8)? };
+            polygons.push(Zoned {
+                zone: feature.field_as_string_by_name("FLD_ZONE")?.unwrap_or_default(),
+                sfha: feature.field_as_string_by_name("SFHA_TF")?.is_some_and(|tf| tf.eq_ignore_ascii_case("T")),
+                envelope: geometry.envelope(),
+                area: geometry.area(),
+                geometry,
+                matched: 0.0,
+            });
+        }
+        Ok((polygons, equal_area))
+    }
+
+    let (mut new_polygons, equal_area) = read(new, None)?;
+    let (mut old_polygons, _) = read(old, Some(&equal_area))?;
+
+    let mut areas: BTreeMap<(Option<String>, Option<String>), f64> = BTreeMap::new();
+    let (mut sfha_added, mut sfha_removed) = (0.0, 0.0);
+    for before in &mut old_polygons {
+        for after in &mut new_polygons {
+            let (a, b) = (&before.envelope, &after.envelope);
+            if a.MinX > b.MaxX || a.MaxX < b.MinX || a.MinY > b.MaxY || a.MaxY < b.MinY
+                || !before.geometry.intersects(&after.geometry) {
+                continue;
+            }
+            let area = match before.geometry.intersection(&after.geometry) {
+                Some(piece) if piece.area() > SLIVER_AREA => piece.area(),
+                _ => continue,
+            };
+            before.matched += area;
+            after.matched += area;
+            *areas.entry((Some(before.zone.clone()), Some(after.zone.clone()))).or_default() += area;
+            match (before.sfha, after.sfha) {
+                (false, true) => sfha_added += area,
+                (true, false) => sfha_removed += area,
+                _ => {}
+            }
+        }
+    }
+    for after in &new_polygons {
+        let unmatched = after.area - after.matched;
+        if unmatched > SLIVER_AREA {
+            *areas.entry((None, Some(after.zone.clone()))).or_default() += unmatched;
+            if after.sfha {
+                sfha_added += unmatched;
+            }
+        }
+    }
+    for before in &old_polygons {
+        let unmatched = before.area - before.matched;
+        if unmatched > SLIVER_AREA {
+            *areas.entry((Some(before.zone.clone()), None)).or_default() += unmatched;
+            if before.sfha {
+                sfha_removed += unmatched;
+            }
+        }
+    }
+
+    let mut transitions: Vec<Transition> = areas.into_iter()
+        .map(|((from_zone, to_zone), area)| Transition {
+            kind: match (&from_zone, &to_zone) {
+                (None, _) => TransitionKind::Added,
+                (_, None) => TransitionKind::Removed,
+                (from, to) if from == to => TransitionKind::Unchanged,
+                _ => TransitionKind::Rezoned,
+            },
+            from_zone,
+            to_zone,
+            acres: area / SQ_M_PER_ACRE,
+        })
+        .collect();
+    transitions.sort_by(|a, b| b.acres.total_cmp(&a.acres));
+
+    let file_name = |source: &Source| source.archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(GeoDiff {
+        fips: new.fips.clone(),
+        old_file: file_name(old),
+        old_effective_date: old.effective_date,
+        new_file: file_name(new),
+        new_effective_date: new.effective_date,
+        transitions,
+        sfha_added_acres: sfha_added / SQ_M_PER_ACRE,
+        sfha_removed_acres: sfha_removed / SQ_M_PER_ACRE,
+    })
+}
+
+/// Writes a row per transition, then (for a table) the net change to the SFHA.
+pub fn write_geo_diff(out: &mut dyn Write, diff: &GeoDiff, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, diff)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["change", "from_zone", "to_zone", "acres"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = diff.transitions.iter()
+        .map(|t| vec![
+            format!("{:?}", t.kind).to_lowercase(),
+            t.from_zone.clone().unwrap_or_default(),
+            t.to_zone.clone().unwrap_or_default(),
+            format!("{:.2}", t.acres),
+        ])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows)?,
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+    if let ReportFormat::Table = format {
+        let date = |date: Option<NaiveDate>| date.map(|d| d.to_string()).unwrap_or_else(|| "undated".to_string());
+        writeln!(out, "\n{} ({}) -> {} ({}): {:.2} acres added to the SFHA, {:.2} removed",
+            diff.old_file, date(diff.old_effective_date), diff.new_file, date(diff.new_effective_date),
+            diff.sfha_added_acres, diff.sfha_removed_acres)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! `export-domains`: the coded-value domains of a county's database (flood zones, zone subtypes, study types and the
+//! rest), with the fields that use each, so attribute values can be decoded without FEMA's technical references.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CodedValue {
+    pub code: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Domain {
+    pub name: String,
+    pub description: String,
+    pub codes: Vec<CodedValue>,
+    /// `layer.field` of each field the domain is assigned to.
+    pub fields: Vec<String>,
+}
+
+/// The coded-value domains of the archive's geodatabase, by name. Older databases without domains ship them as `D_*`
+/// lookup tables instead, which are read (code, then description) when there are no domains.
+#[cfg(feature = "gdal")]
+pub fn read_domains(archive: &std::path::Path) -> Result<Vec<Domain>, Box<dyn std::error::Error>> {
+    use std::ffi::CStr;
+    use gdal::vector::LayerAccess;
+
+    let gdb = crate::extract::require_archive_gdb_path(archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let text = |ptr: *const std::os::raw::c_char| if ptr.is_null() {
+        String::new()
+    } else {
+        // SAFETY: non-null strings from GDAL are nul terminated, and owned by the dataset, which outlives this
+        unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+    };
+
+    let mut domains: BTreeMap<String, Domain> = BTreeMap::new();
+    // SAFETY: the name list is ours to free, and each domain handle is owned by the dataset
+    unsafe {
+        let names = gdal_sys::GDALDatasetGetFieldDomainNames(dataset.c_dataset(), std::ptr::null_mut());
+        if !names.is_null() {
+            let mut i = 0;
+            while !(*names.add(i)).is_null() {
+                let name = text(*names.add(i));
+                let c_domain = gdal_sys::GDALDatasetGetFieldDomain(dataset.c_dataset(), *names.add(i));
+                i += 1;
+                if c_domain.is_null() || gdal_sys::OGR_FldDomain_GetDomainType(c_domain) != gdal_sys::OGRFieldDomainType::OFDT_CODED {
+                    continue;
+                }
+                let mut codes = Vec::new();
+                let mut value = gdal_sys::OGR_CodedFldDomain_GetEnumeration(c_domain);
+                while !value.is_null() && !(*value).pszCode.is_null() {
+                    codes.push(CodedValue { code: text((*value).pszCode), value: text((*value).pszValue) });
+                    value = value.add(1);
+                }
+                let description = text(gdal_sys::OGR_FldDomain_GetDescription(c_domain));
+                domains.insert(name.clone(), Domain { name, description, codes, fields: Vec::new() });
+            }
+            gdal_sys::CSLDestroy(names);
+        }
+    }
+
+    if domains.is_empty() {
+        for mut layer in dataset.layers() {
+            let name = layer.name();
+            if !name.starts_with("D_") {
+                continue;
+            }
+            let mut codes = Vec::new();
+            for feature in layer.features() {
+                let code = feature.field_as_string(0)?.unwrap_or_default();
+                let value = if feature.field_count() > 1 { feature.field_as_string(1)?.unwrap_or_default() } else { String::new() };
+                if !code.is_empty() {
+                    codes.push(CodedValue { code, value });
+                }
+            }
+            domains.insert(name.clone(), Domain { name, description: String::new(), codes, fields: Vec::new() });
+        }
+        return Ok(domains.into_values().collect());
+    }
+
+    for layer in dataset.layers() {
+        let layer_name = layer.name();
+        // SAFETY: the definition belongs to the layer, which is alive for the loop
+        unsafe {
+            let c_defn = gdal_sys::OGR_L_GetLayerDefn(layer.c_layer());
+            for i in 0..gdal_sys::OGR_FD_GetFieldCount(c_defn) {
+                let c_field = gdal_sys::OGR_FD_GetFieldDefn(c_defn, i);
+                let domain_name = text(gdal_sys::OGR_Fld_GetDomainName(c_field));
+                if let Some(domain) = domains.get_mut(&domain_name) {
+                    domain.fields.push(format!("{}.{}", layer_name, text(gdal_sys::OGR_Fld_GetNameRef(c_field))));
+                }
+            }
+        }
+    }
+    Ok(domains.into_values().collect())
+}
+
+/// A row per code, with its domain and the fields using it, or for JSON the domains as read.
+pub fn write_domains(out: &mut dyn Write, domains: &[Domain], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, domains)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["domain", "code", "value", "fields"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = domains.iter()
+        .flat_map(|domain| domain.codes.iter().map(move |code| vec![
+            domain.name.clone(),
+            code.code.clone(),
+            code.value.clone(),
+            domain.fields.join(" "),
+        ]))
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
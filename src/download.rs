@@ -0,0 +1,569 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::task::JoinSet;
+
+use crate::cache::{self, CacheEntry, CacheManifest, CacheStats};
+use crate::cancel::{self, CancellationToken};
+use crate::client::Client;
+use crate::diff::Change;
+use crate::encryption::{self, CacheKey};
+use crate::html_report::{format_bytes, format_duration};
+use crate::error::{NfhlError, Result};
+use crate::inventory::Inventory;
+use crate::pipeline::ExtractReport;
+use crate::plan::{self, Plan, Product};
+use crate::politeness::AdaptiveDelay;
+use crate::publish::{Event, Publishers};
+use crate::recompress::{self, Recompressed, Recompression};
+use crate::shard::Shard;
+use crate::systemd;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadRecord {
+    pub fips: String,
+    pub file_name: String,
+    pub url: String,
+    pub bytes: u64,
+    /// Missing from reports written before downloads were hashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// How long the transfer that succeeded took, not counting earlier attempts.
+    pub seconds: f64,
+    /// `bytes` over `seconds`. Missing (0) from reports written before it was recorded.
+    #[serde(default)]
+    pub bytes_per_second: f64,
+    /// How many times the download was tried again after failing on the network. Missing (0) from reports written
+    /// before downloads were retried.
+    #[serde(default)]
+    pub retries: u32,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadFailure {
+    pub fips: String,
+    pub url: String,
+    pub error: String,
+    /// How many times it was tried again before giving up.
+    #[serde(default)]
+    pub retries: u32,
+}
+
+/// A run's transfer totals, for telling from one run's report to the next when a FEMA endpoint is getting slower or
+/// flakier.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RunSummary {
+    pub files: usize,
+    pub total_bytes: u64,
+    pub wall_seconds: f64,
+    /// `total_bytes` over `wall_seconds`, so pauses and everything else the run did count against it.
+    pub bytes_per_second: f64,
+    pub retries: u32,
+    /// Retries per download attempted, downloaded or failed.
+    pub retry_rate: f64,
+}
+
+impl RunSummary {
+    pub fn of(report: &RunReport) -> RunSummary {
+        let total_bytes = report.downloads.iter().map(|d| d.bytes).sum();
+        let wall_seconds = (report.finished_at - report.started_at).num_milliseconds() as f64 / 1000.0;
+        let retries = report.downloads.iter().map(|d| d.retries).chain(report.failures.iter().map(|f| f.retries)).sum();
+        let attempted = report.downloads.len() + report.failures.len();
+        RunSummary {
+            files: report.downloads.len(),
+            total_bytes,
+            wall_seconds,
+            bytes_per_second: rate(total_bytes, wall_seconds),
+            retries,
+            retry_rate: if attempted > 0 { retries as f64 / attempted as f64 } else { 0.0 },
+        }
+    }
+
+    /// One line for the end of a run, e.g. `12.4 GB in 1h 2m (3.3 MB/s), 4 retries (0.05 per file)`.
+    pub fn line(&self) -> String {
+        format!("{} in {} ({}/s), {} retries ({:.2} per file)", format_bytes(self.total_bytes),
+            format_duration(self.wall_seconds), format_bytes(self.bytes_per_second as u64), self.retries, self.retry_rate)
+    }
+}
+
+fn rate(bytes: u64, seconds: f64) -> f64 {
+    if seconds > 0.0 { bytes as f64 / seconds } else { 0.0 }
+}
+
+/// Everything that happened during one `download_all` run.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Changes relative to the old inventory, if one was given.
+    pub changes: Vec<Change>,
+    pub downloads: Vec<DownloadRecord>,
+    pub failures: Vec<DownloadFailure>,
+    /// How many files were already in the cache and left alone.
+    pub skipped: usize,
+    /// File names removed from the cache because they're no longer in the inventory.
+    pub deleted: Vec<String>,
+    /// The state of the cache at the end of the run.
+    pub cache: CacheStats,
+    /// The slice of the inventory this run was limited to, if it was one of several workers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+    /// What's left of the plan when the run was cancelled part way: the downloads it didn't finish and the deletions
+    /// it didn't get to. Applying it finishes the job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<Plan>,
+    /// What `--extract` did with the downloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extraction: Option<ExtractReport>,
+    /// Missing (all 0) from reports written before it was added.
+    #[serde(default)]
+    pub summary: RunSummary,
+}
+
+/// How many times a download that failed on the network is tried again, the politeness delay times the attempt
+/// apart, before it counts as failed.
+pub const DOWNLOAD_RETRIES: u32 = 2;
+
+/// The delay between consecutive requests for a given politeness coefficient. The default of 255 is ~2.5s.
+pub fn politeness_delay(politeness: u8) -> Duration {
+    Duration::from_millis(politeness as u64 * 10)
+}
+
+/// Downloads the effective file of every county in `inv` that isn't already in `cache_dir`, plus any which changed
+/// since `old_inv`. With a `shard`, only that shard's counties are downloaded and reported on. This is `make_plan`
+/// followed immediately by `apply_plan`.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_all(
+    client: &Client,
+    inv: &Inventory,
+    old_inv: Option<&Inventory>,
+    cache_dir: &Path,
+    delete: bool,
+    politeness: u8,
+    concurrency: usize,
+    shard: Option<Shard>,
+    publishers: &mut Publishers,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let plan = plan::make_plan(inv, old_inv, cache_dir, delete, false, shard)?;
+    apply_plan(client, &plan, politeness, concurrency, publishers, cancel).await
+}
+
+/// Carries out a plan against its cache directory, with up to `concurrency` downloads in flight and the politeness
+/// delay between starting each, longer while FEMA pushes back (see `politeness`). Individual failures are recorded in
+/// the report rather than aborting the run. Once `cancel` is cancelled (or a shutdown requested, by SIGTERM), no more
+/// downloads are started, the ones in flight are dropped along with their partial files, nothing is deleted, and the
+/// report's `remaining` has the rest of the plan. Every change and completed download is also sent to `publishers`.
+pub async fn apply_plan(
+    client: &Client,
+    plan: &Plan,
+    politeness: u8,
+    concurrency: usize,
+    publishers: &mut Publishers,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let started_at = Utc::now();
+    let cache_dir = plan.cache_dir.as_path();
+    std::fs::create_dir_all(cache_dir).map_err(NfhlError::io(cache_dir))?;
+    let mut manifest = CacheManifest::load(cache_dir)?;
+
+    for change in &plan.changes {
+        publishers.publish(&Event::Change(change.clone()));
+    }
+    for kept in &plan.keep {
+        let (from, to) = (cache_dir.join(&kept.file_name), cache_dir.join(&kept.kept_as));
+        match std::fs::rename(&from, &to) {
+            Ok(()) => {}
+            // already set aside by an earlier, interrupted run of the plan
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(NfhlError::io(&from)(e)),
+        }
+    }
+
+    // slower than `politeness` if the last run here was backing off
+    let mut adaptive = AdaptiveDelay::restore(cache_dir, politeness_delay(politeness));
+    let (key, recompress) = (CacheKey::from_env()?, plan.recompress);
+    let mut downloads = Vec::new();
+    let mut failures = Vec::new();
+    let mut in_flight = JoinSet::new();
+    // the downloads started and not yet finished, and those interrupted part way
+    let mut started = BTreeSet::new();
+    let mut unfinished = Vec::new();
+    let mut planned = plan.downloads.iter().enumerate().peekable();
+    loop {
+        // start downloads until there are enough in flight
+        while in_flight.len() < concurrency.max(1) && !cancel::requested(cancel) {
+            let Some(&(i, next)) = planned.peek() else {
+                break;
+            };
+            let delay = adaptive.delay();
+            if (i > 0 || adaptive.level() > 0) && !pause(delay, cancel).await {
+                break;
+            }
+            planned.next();
+            started.insert(i);
+            eprintln!("downloading {} ({})", next.fips, next.file_name);
+            systemd::notify_status(&format!("downloading {} ({} done, {} failed)", next.fips, downloads.len(), failures.len()));
+            let (client, url, path) = (client.clone(), next.url.clone(), cache_dir.join(&next.file_name));
+            let (expected, token) = (Expected::for_product(plan.product), cancel.clone());
+            in_flight.spawn(async move {
+                // a file that's stored is fetched beside its path and moved there once it is, so one that fails to
+                // store isn't left at its path for the next plan to take as cached
+                let needs_storing = recompress.is_some() || key.is_some();
+                let mut unstored = needs_storing.then(|| PartFile::beside(&path, ".store.part"));
+                let fetch_path = unstored.as_ref().map_or_else(|| path.clone(), |part| part.path.clone());
+                let mut retries = 0;
+                loop {
+                    let start = Instant::now();
+                    match fetch(&client, &url, &fetch_path, expected).await {
+                        Err(e) if e.is_pushback() && retries < DOWNLOAD_RETRIES => {
+                            retries += 1;
+                            eprintln!("retrying {} ({} of {}): {}", url, retries, DOWNLOAD_RETRIES, e);
+                            if !pause(delay * retries, &token).await {
+                                return (i, Err(NfhlError::Interrupted), start.elapsed(), retries);
+                            }
+                        }
+                        // stored before it's recorded, so the manifest never describes a file that isn't there yet
+                        Ok(downloaded) => {
+                            let stored = match unstored.take() {
+                                Some(unstored) => {
+                                    let path = path.clone();
+                                    let storing = move || store(unstored, &path, recompress, key.as_ref());
+                                    tokio::task::spawn_blocking(storing).await
+                                        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+                                }
+                                None => Ok(None),
+                            };
+                            return (i, stored.map(|recompressed| (downloaded, recompressed)), start.elapsed(), retries);
+                        }
+                        Err(e) => return (i, Err(e), start.elapsed(), retries),
+                    }
+                }
+            });
+        }
+
+        let joined = tokio::select! {
+            joined = in_flight.join_next() => joined,
+            _ = cancel.cancelled() => {
+                // dropping the downloads removes their partial files
+                in_flight.abort_all();
+                while in_flight.join_next().await.is_some() {}
+                break;
+            }
+        };
+        let (i, result, elapsed, retries) = match joined {
+            Some(joined) => joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())),
+            None => break,
+        };
+        started.remove(&i);
+        let planned = &plan.downloads[i];
+        let fips = &planned.fips;
+        // a county has one main file of the inventory's products, and any number of those MSC lists; the main file
+        // is kept by fips, the rest by name
+        let by_fips = plan.product.inventory_kind().is_some() && planned.role.is_none();
+        let key = if by_fips { fips } else { &planned.file_name };
+        match &result {
+            Ok(_) if retries == 0 => adaptive.succeeded(),
+            Ok(_) => adaptive.pushed_back(),
+            Err(e) if e.is_pushback() => adaptive.pushed_back(),
+            Err(_) => {}
+        }
+        adaptive.save(cache_dir)?;
+        match result {
+            Ok((Downloaded { bytes, sha256 }, recompressed)) => {
+                let finished_at = Utc::now();
+                let entries = if planned.role.is_some() { &mut manifest.files } else { &mut manifest.entries };
+                entries.insert(key.clone(), CacheEntry {
+                    file_name: planned.file_name.clone(),
+                    url: planned.url.clone(),
+                    effective_date: planned.effective_date.clone(),
+                    size: bytes,
+                    sha256: Some(sha256.clone()),
+                    downloaded_at: finished_at,
+                    quarantined_at: None,
+                    recompressed,
+                });
+                // saved after every file so an interrupted run keeps what it got
+                manifest.save(cache_dir)?;
+                let record = DownloadRecord {
+                    fips: fips.clone(),
+                    file_name: planned.file_name.clone(),
+                    url: planned.url.clone(),
+                    bytes,
+                    sha256: Some(sha256),
+                    seconds: elapsed.as_secs_f64(),
+                    bytes_per_second: rate(bytes, elapsed.as_secs_f64()),
+                    retries,
+                    finished_at,
+                };
+                publishers.publish(&Event::Download(record.clone()));
+                downloads.push(record);
+            }
+            Err(NfhlError::Interrupted) => unfinished.push(i),
+            Err(e) => {
+                eprintln!("failed to download {}: {}", fips, e);
+                failures.push(DownloadFailure {
+                    fips: fips.clone(),
+                    url: planned.url.clone(),
+                    error: e.to_string(),
+                    retries,
+                });
+            }
+        }
+    }
+    let mut deleted = Vec::new();
+    let remaining = if cancel::requested(cancel) {
+        unfinished.extend(started);
+        unfinished.extend(planned.map(|(i, _)| i));
+        unfinished.sort_unstable();
+        eprintln!("cancelled, stopped downloading with {} left", unfinished.len());
+        Some(Plan {
+            created_at: Utc::now(),
+            // already reported and set aside by this run
+            changes: Vec::new(),
+            keep: Vec::new(),
+            downloads: unfinished.iter().map(|&i| plan.downloads[i].clone()).collect(),
+            skipped: plan.skipped + downloads.len(),
+            ..plan.clone()
+        })
+    } else {
+        for file_name in &plan.deletions {
+            let path = cache_dir.join(file_name);
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                // another shard got there first, or someone tidied up since the plan was made
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(NfhlError::io(&path)(e)),
+            }
+            deleted.push(file_name.clone());
+        }
+        for key in &plan.forget {
+            manifest.entries.remove(key);
+            manifest.files.remove(key);
+        }
+        None
+    };
+    manifest.save(cache_dir)?;
+
+    let mut report = RunReport {
+        started_at,
+        finished_at: Utc::now(),
+        changes: plan.changes.clone(),
+        downloads,
+        failures,
+        skipped: plan.skipped,
+        deleted,
+        cache: match plan.product.inventory_kind() {
+            Some(_) => cache::cache_stats(cache_dir)?,
+            None => cache::county_dirs_stats(cache_dir)?,
+        },
+        shard: plan.shard,
+        remaining,
+        extraction: None,
+        summary: RunSummary::default(),
+    };
+    report.summary = RunSummary::of(&report);
+    Ok(report)
+}
+
+/// HEADs the url of every file `plan` leaves alone as cached, `delay` apart, and lists those FEMA now serves at a
+/// different size than the manifest recorded: the same url and date, but a silently republished file. Files the
+/// manifest has no size for (not downloaded by nfhl_util) and responses without a `Content-Length` can't be told
+/// apart and are passed over. Only the inventory's products have a url to check. Stops with `Interrupted` once
+/// `cancel` is.
+pub async fn find_republished(
+    client: &Client,
+    plan: &Plan,
+    inv: &Inventory,
+    delay: Duration,
+    cancel: &CancellationToken,
+) -> Result<Vec<plan::Republished>> {
+    let Some(kind) = plan.product.inventory_kind() else {
+        return Ok(Vec::new());
+    };
+    let manifest = CacheManifest::load(&plan.cache_dir)?;
+    let unchanged: Vec<(&String, &CacheEntry)> = manifest.entries.iter()
+        .filter(|(fips, _)| plan.shard.is_none_or(|shard| shard.contains(fips)))
+        .filter(|(fips, _)| !plan.downloads.iter().any(|d| d.fips == **fips))
+        .filter(|(fips, cached)| inv.get(fips.as_str())
+            .and_then(|entry| entry.url(kind))
+            .is_some_and(|url| url.as_str() == cached.url))
+        .filter(|(_, cached)| cached.size > 0 && plan.cache_dir.join(&cached.file_name).exists())
+        .collect();
+
+    let mut republished = Vec::new();
+    for (i, (fips, cached)) in unchanged.into_iter().enumerate() {
+        if i > 0 && !pause(delay, cancel).await {
+            return Err(NfhlError::Interrupted);
+        }
+        let headers = cancel::or_cancelled(cancel, crate::http::headers(client, client.head(&cached.url))).await?;
+        let served_size = headers.get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+        let Some(served_size) = served_size.filter(|&size| size != cached.size) else {
+            continue;
+        };
+        eprintln!("{} was republished: {} is {} bytes now, but {} were downloaded", fips, cached.file_name,
+            served_size, cached.size);
+        republished.push(plan::Republished {
+            fips: fips.clone(),
+            file_name: cached.file_name.clone(),
+            url: cached.url.clone(),
+            effective_date: cached.effective_date.clone(),
+            cached_size: cached.size,
+            served_size,
+        });
+    }
+    Ok(republished)
+}
+
+/// The politeness delay, cut short by cancelling: false if it was.
+pub async fn pause(delay: Duration, cancel: &CancellationToken) -> bool {
+    tokio::select! {
+        slept = systemd::sleep_async(delay) => slept,
+        _ = cancel.cancelled() => false,
+    }
+}
+
+/// A download's `.part` file, removed when it's dropped unless the download finished. That includes the download's
+/// future being dropped part way through, so cancelling one leaves nothing behind.
+struct PartFile {
+    path: PathBuf,
+    done: bool,
+}
+
+impl PartFile {
+    /// `path` with `suffix` added, e.g. `48201C_20220915.zip.part`.
+    fn beside(path: &Path, suffix: &str) -> PartFile {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(suffix);
+        PartFile { path: PathBuf::from(part_path), done: false }
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Rewrites a file downloaded to `part` the way the cache keeps it, recompressed, then encrypted, each if asked for,
+/// and only then moves it to `path`. If anything fails, `part` is removed and `path` is left as it was.
+fn store(
+    mut part: PartFile,
+    path: &Path,
+    recompress: Option<Recompression>,
+    key: Option<&CacheKey>,
+) -> Result<Option<Recompressed>> {
+    let recompressed = match recompress {
+        Some(method) => recompress::recompress_file(&part.path, method)?,
+        None => None,
+    };
+    if let Some(key) = key {
+        encryption::encrypt_file(&part.path, key)?;
+    }
+    std::fs::rename(&part.path, path).map_err(NfhlError::io(path))?;
+    part.done = true;
+    Ok(recompressed)
+}
+
+/// A finished download's size and SHA-256, both worked out as it was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downloaded {
+    pub bytes: u64,
+    /// Lowercase hex, as `sha256sum` prints it.
+    pub sha256: String,
+}
+
+/// The first bytes of a zip archive: a local file header, or the end of central directory record of an empty one.
+const ZIP_MAGIC: [&[u8; 4]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+
+/// The first bytes of a scanned FIRM panel: a TIFF, either byte order, or a PDF.
+const SCAN_MAGIC: [&[u8; 4]; 3] = [b"II*\0", b"MM\0*", b"%PDF"];
+
+/// What a download has to start like to be kept, and what to call it when it doesn't.
+#[derive(Debug, Clone, Copy)]
+struct Expected {
+    magic: &'static [&'static [u8; 4]],
+    what: &'static str,
+}
+
+impl Expected {
+    const ZIP: Expected = Expected { magic: &ZIP_MAGIC, what: "a zip archive" };
+    const SCAN: Expected = Expected { magic: &SCAN_MAGIC, what: "a TIFF or PDF scan" };
+
+    fn for_product(product: Product) -> Option<Expected> {
+        if product.is_archive() {
+            Some(Expected::ZIP)
+        } else if product.is_scan() {
+            Some(Expected::SCAN)
+        } else {
+            None
+        }
+    }
+}
+
+/// Streams `url` to `path` via a temporary `.part` file, hashing it on the way so checking it later needs no
+/// second read. The systemd watchdog is fed as data arrives, so only a stalled transfer (not merely a big one) trips
+/// it.
+pub async fn download_file(client: &Client, url: &str, path: &Path) -> Result<Downloaded> {
+    fetch(client, url, path, None).await
+}
+
+/// `download_file` for a zip archive. A body that doesn't start like one, such as a maintenance page served with a
+/// 200, fails as soon as its first bytes arrive, as a `PortalFormat` error, instead of being cached.
+pub async fn download_archive(client: &Client, url: &str, path: &Path) -> Result<Downloaded> {
+    fetch(client, url, path, Some(Expected::ZIP)).await
+}
+
+async fn fetch(client: &Client, url: &str, path: &Path, expected: Option<Expected>) -> Result<Downloaded> {
+    let mut part = PartFile::beside(path, ".part");
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(NfhlError::io(dir))?;
+    }
+    let mut response = client.send(client.get(url)).await?;
+    let io = || NfhlError::io(&part.path);
+    let mut f = BufWriter::new(tokio::fs::File::create(&part.path).await.map_err(io())?);
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(4);
+    let mut bytes = 0;
+    // a chunk failing part way through is the connection's fault, not the disk's
+    while let Some(chunk) = response.chunk().await? {
+        if let Some(expected) = expected.filter(|_| head.len() < 4) {
+            head.extend(chunk.iter().take(4 - head.len()));
+            if head.len() == 4 && !expected.magic.iter().any(|magic| head == magic[..]) {
+                return Err(unexpected(url, expected, &head));
+            }
+        }
+        hasher.update(&chunk);
+        f.write_all(&chunk).await.map_err(io())?;
+        bytes += chunk.len() as u64;
+        systemd::watchdog_ping();
+        if systemd::shutdown_requested() {
+            return Err(NfhlError::Interrupted);
+        }
+    }
+    if let Some(expected) = expected.filter(|_| head.len() < 4) {
+        return Err(unexpected(url, expected, &head));
+    }
+    f.flush().await.map_err(io())?;
+    f.into_inner().sync_all().await.map_err(io())?;
+    tokio::fs::rename(&part.path, path).await.map_err(NfhlError::io(path))?;
+    part.done = true;
+    Ok(Downloaded { bytes, sha256: format!("{:x}", hasher.finalize()) })
+}
+
+fn unexpected(url: &str, expected: Expected, head: &[u8]) -> NfhlError {
+    let host = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(String::from));
+    NfhlError::PortalFormat {
+        site: crate::error::site(host.as_deref()),
+        detail: format!("{} isn't {}; it starts {:?}", url, expected.what, String::from_utf8_lossy(head)),
+    }
+}
@@ -0,0 +1,300 @@
+//! Encrypting cached files at rest, for hosts that require it without full-disk encryption. With a key set
+//! (`NFHL_UTIL_CACHE_KEY`, or `cache_key` in the config file) each file is encrypted as soon as it's downloaded, and
+//! everything that reads the cache (extracting, converting, `serve` and the geo commands) decrypts it on the way.
+//!
+//! Files are AES-256-GCM in 64 KiB chunks (the STREAM construction), so a truncated, reordered or altered file fails
+//! to decrypt rather than yielding bad data, and a multi-GB file never has to fit in memory. An encrypted file
+//! keeps its name and starts with `NFHLENC1`, so a cache can hold both kinds, e.g. one that turned encryption on part
+//! way through. The manifest's sizes and SHA-256s are the plain file's, as FEMA served it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{KeyInit, OsRng};
+use aes_gcm::Aes256Gcm;
+
+use crate::error::{NfhlError, Result};
+use crate::recompress;
+
+pub const CACHE_KEY_ENV: &str = "NFHL_UTIL_CACHE_KEY";
+/// Where encrypted files are decrypted to for the tools that need a plain file, GDAL above all. Defaults to the
+/// system's temporary directory; a tmpfs keeps the plain copies off disk.
+pub const DECRYPT_DIR_ENV: &str = "NFHL_UTIL_DECRYPT_DIR";
+
+const MAGIC: &[u8; 8] = b"NFHLENC1";
+/// The STREAM nonce prefix: AES-GCM's 12 bytes less the 5 of the chunk counter.
+const NONCE_LEN: usize = 7;
+const CHUNK: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// A 256-bit key, given as 64 hex digits.
+#[derive(Clone, Copy)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    pub fn parse(hex: &str) -> Result<CacheKey> {
+        let hex = hex.trim();
+        let invalid = || NfhlError::Validation(format!("{} should be 64 hex digits (a 256-bit key)", CACHE_KEY_ENV));
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(CacheKey(key))
+    }
+
+    /// The key in `NFHL_UTIL_CACHE_KEY`, which the config file's `cache_key` fills in; None leaves files plain.
+    pub fn from_env() -> Result<Option<CacheKey>> {
+        match std::env::var(CACHE_KEY_ENV) {
+            Ok(hex) if !hex.trim().is_empty() => Ok(Some(CacheKey::parse(&hex)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.0).expect("a 256-bit key")
+    }
+}
+
+/// Never prints the key itself.
+impl fmt::Debug for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CacheKey(..)")
+    }
+}
+
+/// Whether `path` is an encrypted file.
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut head = [0u8; MAGIC.len()];
+    let n = read_full(&mut File::open(path).map_err(NfhlError::io(path))?, &mut head).map_err(NfhlError::io(path))?;
+    Ok(n == MAGIC.len() && &head == MAGIC)
+}
+
+/// The size `plain_size` bytes take up encrypted.
+pub fn encrypted_size(plain_size: u64) -> u64 {
+    let chunks = plain_size.div_ceil(CHUNK as u64).max(1);
+    (MAGIC.len() + NONCE_LEN) as u64 + plain_size + chunks * TAG_LEN as u64
+}
+
+/// Encrypts the plain file at `path` in place. The encrypted copy is written beside it and renamed over it, so an
+/// interruption leaves the plain file.
+pub fn encrypt_file(path: &Path, key: &CacheKey) -> Result<()> {
+    let part = PathBuf::from(format!("{}.enc.part", path.display()));
+    let written = (|| -> std::io::Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut out = BufWriter::new(File::create(&part)?);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        out.write_all(MAGIC)?;
+        out.write_all(&nonce)?;
+
+        let mut encryptor = EncryptorBE32::from_aead(key.cipher(), nonce.as_slice().into());
+        let mut current = vec![0u8; CHUNK];
+        let mut n = read_full(&mut reader, &mut current)?;
+        loop {
+            // a chunk is the last one when there's nothing after it
+            let mut next = vec![0u8; CHUNK];
+            let m = if n == CHUNK { read_full(&mut reader, &mut next)? } else { 0 };
+            if m == 0 {
+                out.write_all(&encryptor.encrypt_last(&current[..n]).map_err(|_| crypto_error())?)?;
+                break;
+            }
+            out.write_all(&encryptor.encrypt_next(&current[..n]).map_err(|_| crypto_error())?)?;
+            (current, n) = (next, m);
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&part);
+        return Err(NfhlError::io(&part)(e));
+    }
+    std::fs::rename(&part, path).map_err(NfhlError::io(path))
+}
+
+/// Reads an encrypted file as its plain contents.
+pub struct DecryptingReader<R> {
+    inner: R,
+    /// None once the last chunk is decrypted.
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    next: Vec<u8>,
+    plain: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(mut inner: R, key: &CacheKey) -> std::io::Result<DecryptingReader<R>> {
+        let mut header = [0u8; MAGIC.len() + NONCE_LEN];
+        if read_full(&mut inner, &mut header)? != header.len() || &header[..MAGIC.len()] != MAGIC {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not an encrypted file"));
+        }
+        let decryptor = DecryptorBE32::from_aead(key.cipher(), header[MAGIC.len()..].into());
+        let next = read_chunk(&mut inner)?;
+        Ok(DecryptingReader { inner, decryptor: Some(decryptor), next, plain: Vec::new(), pos: 0 })
+    }
+
+    fn fill(&mut self) -> std::io::Result<()> {
+        let current = std::mem::take(&mut self.next);
+        let following = if current.len() == CHUNK + TAG_LEN { read_chunk(&mut self.inner)? } else { Vec::new() };
+        self.plain = if following.is_empty() {
+            let decryptor = self.decryptor.take().expect("fill() after the last chunk");
+            decryptor.decrypt_last(current.as_slice()).map_err(|_| crypto_error())?
+        } else {
+            let decryptor = self.decryptor.as_mut().expect("fill() after the last chunk");
+            decryptor.decrypt_next(current.as_slice()).map_err(|_| crypto_error())?
+        };
+        self.next = following;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.plain.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Opens a cached file for reading its plain contents, whether it's encrypted or not.
+pub fn open(path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(path).map_err(NfhlError::io(path))?;
+    if !is_encrypted(path)? {
+        return Ok(Box::new(file));
+    }
+    let key = require_key(path)?;
+    Ok(Box::new(DecryptingReader::new(BufReader::new(file), &key).map_err(NfhlError::io(path))?))
+}
+
+/// A cached file as a plain file on disk: the file itself, or a decrypted copy that's removed when this is dropped.
+#[derive(Debug)]
+pub struct Plain {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl Plain {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Plain {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// `path` as a plain file, decrypting it to `NFHL_UTIL_DECRYPT_DIR` if it's encrypted.
+pub fn plain(path: &Path) -> Result<Plain> {
+    if !is_encrypted(path)? {
+        return Ok(Plain { path: path.to_path_buf(), temporary: false });
+    }
+    let plain = temporary(path)?;
+    let mut out = BufWriter::new(File::create(&plain.path).map_err(NfhlError::io(&plain.path))?);
+    std::io::copy(&mut open(path)?, &mut out)
+        .and_then(|_| out.flush())
+        .map_err(|e| NfhlError::Validation(format!("can't decrypt {}: {}", path.display(), e)))?;
+    Ok(plain)
+}
+
+/// Like `plain`, but with a zip's members deflated again if they were recompressed, as FEMA served it, for readers
+/// that don't know zstd: GDAL, and whoever downloads it from `serve`.
+pub fn deflated(path: &Path) -> Result<Plain> {
+    let plain = plain(path)?;
+    if !recompress::is_recompressed(plain.path())? {
+        return Ok(plain);
+    }
+    let deflated = temporary(path)?;
+    recompress::deflate_file(plain.path(), deflated.path())?;
+    Ok(deflated)
+}
+
+/// A new, empty file in `NFHL_UTIL_DECRYPT_DIR` for a plain copy of `path`. The directory is usually shared, so the
+/// file is created afresh (never one someone else put there) and, on unix, readable only by this user.
+fn temporary(path: &Path) -> Result<Plain> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::var_os(DECRYPT_DIR_ENV).map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    std::fs::create_dir_all(&dir).map_err(NfhlError::io(&dir))?;
+    let file_name = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+    let path = dir.join(format!("nfhl_util-{}-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed), file_name));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(&path).map_err(NfhlError::io(&path))?;
+    Ok(Plain { path, temporary: true })
+}
+
+fn decrypted() -> &'static Mutex<HashMap<PathBuf, Plain>> {
+    static DECRYPTED: OnceLock<Mutex<HashMap<PathBuf, Plain>>> = OnceLock::new();
+    DECRYPTED.get_or_init(Default::default)
+}
+
+/// Like `deflated`, but the copy is kept, and shared, until `remove_decrypted`. For paths handed on to GDAL, which
+/// opens them again and again.
+pub fn plain_path(path: &Path) -> Result<PathBuf> {
+    let mut decrypted = decrypted().lock().unwrap();
+    if let Some(plain) = decrypted.get(path) {
+        return Ok(plain.path.clone());
+    }
+    let plain = deflated(path)?;
+    let plain_path = plain.path.clone();
+    if plain.temporary {
+        decrypted.insert(path.to_path_buf(), plain);
+    }
+    Ok(plain_path)
+}
+
+/// Removes the decrypted copies `plain_path` made. Call before exiting.
+pub fn remove_decrypted() {
+    decrypted().lock().unwrap().clear();
+}
+
+fn require_key(path: &Path) -> Result<CacheKey> {
+    CacheKey::from_env()?.ok_or_else(|| NfhlError::Validation(format!(
+        "{} is encrypted; set {} (or cache_key in the config file) to read it", path.display(), CACHE_KEY_ENV)))
+}
+
+fn crypto_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "wrong cache key, or the file has been altered")
+}
+
+/// Reads until `buf` is full or the end, returning how much was read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            read => n += read,
+        }
+    }
+    Ok(n)
+}
+
+fn read_chunk(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut chunk = vec![0u8; CHUNK + TAG_LEN];
+    let n = read_full(reader, &mut chunk)?;
+    chunk.truncate(n);
+    Ok(chunk)
+}
@@ -0,0 +1,133 @@
+//! The library's error type. The inventory, cache, plan and download functions return `NfhlError` so callers can
+//! tell a flaky network (retry) from a FEMA site that changed under the scrapers (don't) from a bad cache or input
+//! file (fix it). The rest of the crate still uses `Box<dyn Error>`, which an `NfhlError` converts into with `?`.
+
+use std::path::{Path, PathBuf};
+
+/// Exit codes, from sysexits.h, the same family as `task::EXIT_WORK_REMAINS`.
+pub const EXIT_DATA: i32 = 65;
+pub const EXIT_IO: i32 = 74;
+pub const EXIT_TEMPORARY: i32 = 75;
+pub const EXIT_PROTOCOL: i32 = 76;
+
+pub type Result<T> = std::result::Result<T, NfhlError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NfhlError {
+    /// FEMA couldn't be reached, answered with a server error or 429, or dropped the connection part way through.
+    #[error("network error: {0}")]
+    Network(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// FEMA answered with a client error other than 429, like a 404 for a dead link: asking again won't help.
+    #[error("{url} answered with HTTP status {status}")]
+    Rejected { url: String, status: u16 },
+    /// A FEMA site answered, but not in the shape it's scraped in.
+    #[error("{site} has changed its format: {detail}")]
+    PortalFormat { site: &'static str, detail: String },
+    /// An inventory, plan or manifest that isn't what it should be.
+    #[error("can't parse {}: {source}", path.display())]
+    Parse { path: PathBuf, source: serde_json::Error },
+    /// Reading or writing the cache directory, or an inventory or plan file.
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("no cached archive for {fips} in {}", cache_dir.display())]
+    NotCached { fips: String, cache_dir: PathBuf },
+    /// Input that parsed but can't be used, like a plan from another version.
+    #[error("{0}")]
+    Validation(String),
+    /// A shutdown (SIGTERM) was requested part way through.
+    #[error("interrupted by shutdown")]
+    Interrupted,
+}
+
+impl NfhlError {
+    /// For `map_err` on IO against `path`.
+    pub fn io(path: &Path) -> impl FnOnce(std::io::Error) -> NfhlError + '_ {
+        move |source| NfhlError::Io { path: path.to_path_buf(), source }
+    }
+
+    /// For `map_err` on parsing `path`.
+    pub fn parse(path: &Path) -> impl FnOnce(serde_json::Error) -> NfhlError + '_ {
+        move |source| NfhlError::Parse { path: path.to_path_buf(), source }
+    }
+
+    /// Whether trying again later might work.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, NfhlError::Network(_) | NfhlError::Interrupted)
+    }
+
+    /// Whether this is FEMA pushing back, as opposed to failing: rate limiting (429), being overloaded (503), a
+    /// timeout or a refused connection. Only these are worth slowing down for, or trying the same request again.
+    pub fn is_pushback(&self) -> bool {
+        let NfhlError::Network(source) = self else {
+            return false;
+        };
+        match source.downcast_ref::<reqwest::Error>() {
+            Some(e) => e.is_timeout() || e.is_connect() || e.status().is_some_and(is_pushback_status),
+            // the replayed responses of tests
+            None => source.downcast_ref::<StatusError>().is_some_and(|e| is_pushback_status(e.status)),
+        }
+    }
+
+    /// The error for an unsuccessful `status` from `url`: a client error other than 429 is `Rejected`, anything
+    /// else `Network`.
+    pub fn status(url: &str, status: reqwest::StatusCode) -> NfhlError {
+        match status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            true => NfhlError::Rejected { url: url.to_string(), status: status.as_u16() },
+            false => NfhlError::Network(Box::new(StatusError { url: url.to_string(), status })),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NfhlError::Network(_) | NfhlError::Interrupted => EXIT_TEMPORARY,
+            NfhlError::PortalFormat { .. } | NfhlError::Rejected { .. } => EXIT_PROTOCOL,
+            NfhlError::Parse { .. } | NfhlError::Validation(_) => EXIT_DATA,
+            NfhlError::Io { .. } | NfhlError::NotCached { .. } => EXIT_IO,
+        }
+    }
+}
+
+impl From<nfhl_parse::Error> for NfhlError {
+    fn from(e: nfhl_parse::Error) -> Self {
+        match e {
+            nfhl_parse::Error::PortalFormat { site, detail } => NfhlError::PortalFormat { site, detail },
+            nfhl_parse::Error::Validation(message) => NfhlError::Validation(message),
+        }
+    }
+}
+
+fn is_pushback_status(status: reqwest::StatusCode) -> bool {
+    matches!(status, reqwest::StatusCode::TOO_MANY_REQUESTS | reqwest::StatusCode::SERVICE_UNAVAILABLE)
+}
+
+/// An unsuccessful status that didn't come with a `reqwest::Error`.
+#[derive(Debug, thiserror::Error)]
+#[error("HTTP status {status} for url ({url})")]
+pub struct StatusError {
+    pub url: String,
+    pub status: reqwest::StatusCode,
+}
+
+/// A response that arrived but didn't decode means the site changed, not that the network failed. A client error
+/// status is `Rejected`.
+impl From<reqwest::Error> for NfhlError {
+    fn from(e: reqwest::Error) -> Self {
+        let rejected = e.status().filter(|status| status.is_client_error() && *status != reqwest::StatusCode::TOO_MANY_REQUESTS);
+        if let Some(status) = rejected {
+            NfhlError::Rejected { url: e.url().map(|url| url.to_string()).unwrap_or_default(), status: status.as_u16() }
+        } else if e.is_decode() {
+            NfhlError::PortalFormat { site: site(e.url().and_then(|url| url.host_str())), detail: e.to_string() }
+        } else {
+            NfhlError::Network(e.into())
+        }
+    }
+}
+
+/// The FEMA site a host is, for `PortalFormat`'s `site`.
+pub fn site(host: Option<&str>) -> &'static str {
+    match host {
+        Some("msc.fema.gov") => "the Map Service Center",
+        Some("hazards.fema.gov") => "the NFHL portal",
+        _ => "FEMA",
+    }
+}
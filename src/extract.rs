@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+#[cfg(feature = "gdal")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+#[cfg(feature = "gdal")]
+use crate::report::{self, ReportFormat};
+#[cfg(feature = "gdal")]
+use crate::shard;
+use crate::encryption;
+
+/// What unzipping a county archive produced.
+#[derive(Debug)]
+pub struct Extracted {
+    pub files: usize,
+    /// The file geodatabase inside, if there is one. Older products are shapefiles only.
+    pub gdb: Option<PathBuf>,
+}
+
+/// Unzips `archive` into `out_dir`. Entries that would land outside `out_dir` (`../` and absolute paths) are
+/// refused rather than trusted.
+pub fn extract_archive(archive: &Path, out_dir: &Path) -> Result<Extracted, Box<dyn std::error::Error>> {
+    let plain = encryption::plain(archive)?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(plain.path())?))
+        .map_err(|e| format!("{} isn't a readable zip: {}", archive.display(), e))?;
+    std::fs::create_dir_all(out_dir)?;
+
+    let mut files = 0;
+    let mut gdb: Option<PathBuf> = None;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let relative = entry.enclosed_name()
+            .ok_or_else(|| format!("{} has an unsafe entry '{}'", archive.display(), entry.name()))?
+            .to_path_buf();
+        let path = out_dir.join(&relative);
+
+        if gdb.is_none() {
+            gdb = gdb_prefix(&relative).map(|prefix| out_dir.join(prefix));
+        }
+
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::io::copy(&mut entry, &mut File::create(&path)?)?;
+        files += 1;
+    }
+    Ok(Extracted { files, gdb })
+}
+
+/// The raster grids in a Risk MAP Flood Risk Database that `--frd-grids` pulls out of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum FrdGrid {
+    /// Flood depth, e.g. `Depth_01pct` for the 1% annual chance flood.
+    Depth,
+    /// Water surface elevation, e.g. `WSE_01pct`.
+    Wse,
+    /// The percent annual chance of flooding, `PctAnnChance`.
+    PctAnnualChance,
+}
+
+impl FrdGrid {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrdGrid::Depth => "depth",
+            FrdGrid::Wse => "wse",
+            FrdGrid::PctAnnualChance => "pct-annual-chance",
+        }
+    }
+
+    /// Whether an archive entry's file or directory name is one of this grid's: GeoTIFFs are single files, and Esri
+    /// grids are directories of `.adf` files named for the grid. Studies name them inconsistently, so this goes by
+    /// prefix and ignores case and underscores.
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase().replace('_', "");
+        match self {
+            FrdGrid::Depth => name.starts_with("depth"),
+            FrdGrid::Wse => name.starts_with("wse"),
+            FrdGrid::PctAnnualChance => name.starts_with("pctannchance") || name.starts_with("pctannualchance"),
+        }
+    }
+}
+
+/// Unzips only the `grids` from a Flood Risk Database `archive`, each into `out_dir/{grid}/` under the name it has
+/// in the archive, and leaves the rest (the geodatabase, the reports). The archive having none of them isn't an
+/// error, since not every study produced every grid; `files` is 0.
+pub fn extract_frd_grids(archive: &Path, out_dir: &Path, grids: &[FrdGrid]) -> Result<Extracted, Box<dyn std::error::Error>> {
+    let plain = encryption::plain(archive)?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(plain.path())?))
+        .map_err(|e| format!("{} isn't a readable zip: {}", archive.display(), e))?;
+
+    let mut files = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let relative = entry.enclosed_name()
+            .ok_or_else(|| format!("{} has an unsafe entry '{}'", archive.display(), entry.name()))?
+            .to_path_buf();
+        // the grid's file, or its directory and everything under it
+        let components: Vec<_> = relative.components().collect();
+        let found = components.iter().enumerate().find_map(|(i, component)| {
+            let name = component.as_os_str().to_string_lossy();
+            grids.iter().find(|grid| grid.matches(&name)).map(|grid| (i, *grid))
+        });
+        let Some((start, grid)) = found else {
+            continue;
+        };
+        let path = components[start..].iter()
+            .fold(out_dir.join(grid.as_str()), |path, component| path.join(component));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::io::copy(&mut entry, &mut File::create(&path)?)?;
+        files += 1;
+    }
+    Ok(Extracted { files, gdb: None })
+}
+
+/// The `.gdb` directory an archive entry lives in, i.e. the shallowest leading part of its path ending in `.gdb`.
+fn gdb_prefix(relative: &Path) -> Option<PathBuf> {
+    let mut prefix = PathBuf::new();
+    for component in relative.components() {
+        prefix.push(component);
+        if prefix.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gdb")) {
+            return Some(prefix);
+        }
+    }
+    None
+}
+
+/// Finds the geodatabase inside an archive without unzipping it, returning a path GDAL can open in place via its
+/// `/vsizip/` virtual file system. An encrypted archive is decrypted for GDAL, into a copy that lasts until
+/// `encryption::remove_decrypted`.
+pub fn archive_gdb_path(archive: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let plain = encryption::plain_path(archive)?;
+    let zip = zip::ZipArchive::new(BufReader::new(File::open(&plain)?))
+        .map_err(|e| format!("{} isn't a readable zip: {}", archive.display(), e))?;
+    let gdb = zip.file_names()
+        .filter_map(|name| gdb_prefix(Path::new(name)))
+        .min_by_key(|prefix| prefix.components().count());
+    let archive = std::fs::canonicalize(&plain)?;
+    Ok(gdb.map(|gdb| format!("/vsizip/{}/{}", archive.display(), gdb.to_string_lossy().replace('\\', "/"))))
+}
+
+/// Like `archive_gdb_path`, but an archive without a geodatabase is an error.
+pub fn require_archive_gdb_path(archive: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    archive_gdb_path(archive)?.ok_or_else(|| format!("{} has no .gdb in it", archive.display()).into())
+}
+
+/// The publication date in the FGDC metadata (`<pubdate>`) shipped next to the geodatabase, if the archive has any.
+pub fn archive_metadata_date(archive: &Path) -> Result<Option<NaiveDate>, Box<dyn std::error::Error>> {
+    let plain = encryption::plain(archive)?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(plain.path())?))
+        .map_err(|e| format!("{} isn't a readable zip: {}", archive.display(), e))?;
+    let pubdate = Regex::new(r"<pubdate>\s*(\d{8})\s*</pubdate>").unwrap();
+    let names: Vec<String> = zip.file_names()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".xml") && gdb_prefix(Path::new(name)).is_none())
+        .map(|name| name.to_string())
+        .collect();
+    for name in names {
+        let mut xml = String::new();
+        zip.by_name(&name)?.read_to_string(&mut xml)?;
+        if let Some(date) = pubdate.captures(&xml).and_then(|c| crate::inventory::parse_file_date(&c[1])) {
+            return Ok(Some(date));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LayerInfo {
+    pub name: String,
+    pub geometry_type: String,
+    pub feature_count: u64,
+    /// `AUTHORITY:CODE` (e.g. `EPSG:4269`) when known, otherwise the CRS's name.
+    pub crs: Option<String>,
+    pub field_count: usize,
+    /// A fingerprint of the layer's field names and types. Counties built against different revisions of FEMA's
+    /// FIRM database schema get different fingerprints for the same layer.
+    pub schema: String,
+}
+
+/// Lists the layers of a vector dataset (the county `.gdb`, extracted or as an `archive_gdb_path`), with their
+/// feature counts, CRS and schema.
+#[cfg(feature = "gdal")]
+pub fn inspect_layers(path: &Path) -> Result<Vec<LayerInfo>, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let dataset = gdal::Dataset::open(path)?;
+    let mut layers = Vec::new();
+    for layer in dataset.layers() {
+        let geometry_type = layer.defn().geom_fields().next()
+            .map(|field| gdal::vector::geometry_type_to_name(field.field_type()))
+            .unwrap_or_else(|| "None".to_string());
+        let crs = layer.spatial_ref().map(|srs| match (srs.auth_name(), srs.auth_code()) {
+            (Ok(name), Ok(code)) => format!("{}:{}", name, code),
+            _ => srs.name().unwrap_or_else(|_| "unknown".to_string()),
+        });
+        let mut fields: Vec<String> = layer.defn().fields()
+            .map(|field| format!("{}:{}", field.name().to_ascii_uppercase(), gdal::vector::field_type_to_name(field.field_type())))
+            .collect();
+        fields.sort();
+        layers.push(LayerInfo {
+            name: layer.name(),
+            geometry_type,
+            feature_count: layer.feature_count(),
+            crs,
+            field_count: fields.len(),
+            schema: format!("{:016x}", shard::fnv1a(fields.join(",").as_bytes())),
+        });
+    }
+    Ok(layers)
+}
+
+#[cfg(feature = "gdal")]
+pub fn write_layers(out: &mut dyn Write, layers: &[LayerInfo], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, layers)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["layer", "geometry", "features", "crs"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = layers.iter()
+        .map(|l| vec![l.name.clone(), l.geometry_type.clone(), l.feature_count.to_string(), l.crs.clone().unwrap_or_default()])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
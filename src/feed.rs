@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use atom_syndication::{Category, Entry, Feed, FixedDateTime, Link, Text};
+use chrono::{NaiveDate, Utc};
+
+use crate::diff::{Change, ChangeKind};
+
+/// Adds an entry for each change to the Atom feed at `feed_path` (creating it if needed), newest first, keeping
+/// at most `max_entries`. Entry ids are derived from the change itself, so publishing the same diff twice is a no-op.
+/// Returns the number of entries added.
+pub fn publish_changes(
+    feed_path: &Path,
+    changes: &[Change],
+    title: &str,
+    link: Option<&str>,
+    max_entries: usize,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut feed = if feed_path.exists() {
+        Feed::read_from(BufReader::new(File::open(feed_path)?))?
+    } else {
+        let mut feed = Feed::default();
+        feed.set_id(link.unwrap_or("urn:nfhl_util:changes"));
+        feed
+    };
+    feed.set_title(title);
+    if let Some(link) = link {
+        feed.set_links(vec![Link { href: link.to_string(), rel: "self".to_string(), ..Default::default() }]);
+    }
+
+    let now: FixedDateTime = Utc::now().into();
+    let existing_ids: HashSet<String> = feed.entries().iter().map(|e| e.id().to_string()).collect();
+    let mut new_entries: Vec<Entry> = changes.iter()
+        .map(|c| change_entry(c, now))
+        .filter(|e| !existing_ids.contains(e.id()))
+        .collect();
+    let added = new_entries.len();
+
+    if added > 0 {
+        new_entries.extend(feed.entries().iter().cloned());
+        new_entries.truncate(max_entries);
+        feed.set_entries(new_entries);
+        feed.set_updated(now);
+    }
+
+    if let Some(feed_dir) = feed_path.parent() {
+        std::fs::create_dir_all(feed_dir)?;
+    }
+    let tmp_path = feed_path.with_extension("tmp");
+    feed.write_to(BufWriter::new(File::create(&tmp_path)?))?;
+    std::fs::rename(tmp_path, feed_path)?;
+    Ok(added)
+}
+
+fn change_entry(change: &Change, updated: FixedDateTime) -> Entry {
+    let (title, summary) = match change.kind {
+        ChangeKind::Added => (
+            format!("{}: added with effective date {}", change.fips, display_date(&change.new_date)),
+            format!("County {} appeared in the NFHL inventory with an effective date of {}.", change.fips, display_date(&change.new_date)),
+        ),
+        ChangeKind::Removed => (
+            format!("{}: removed", change.fips),
+            format!("County {} (effective {}) is no longer in the NFHL inventory.", change.fips, display_date(&change.old_date)),
+        ),
+        ChangeKind::Effective => (
+            format!("{}: new effective date {}", change.fips, display_date(&change.new_date)),
+            format!("The effective NFHL data for county {} changed from {} to {}.", change.fips, display_date(&change.old_date), display_date(&change.new_date)),
+        ),
+        ChangeKind::Preliminary if change.new_date.is_empty() && change.url.is_empty() => (
+            format!("{}: preliminary data withdrawn", change.fips),
+            format!("The preliminary data for county {} (dated {}) is no longer available.", change.fips, display_date(&change.old_date)),
+        ),
+        ChangeKind::Preliminary => (
+            format!("{}: new preliminary data {}", change.fips, display_date(&change.new_date)),
+            format!("New preliminary FIRM data is available for county {}, dated {}.", change.fips, display_date(&change.new_date)),
+        ),
+        ChangeKind::Republished => (
+            format!("{}: republished {}", change.fips, display_date(&change.new_date)),
+            format!("FEMA replaced the file for county {} dated {} with a different one, at the same url and date.",
+                change.fips, display_date(&change.new_date)),
+        ),
+    };
+
+    let mut entry = Entry::default();
+    entry.set_id(format!("urn:nfhl_util:change:{}:{}:{}:{}", change.fips, change.kind.as_str(), change.old_date, change.new_date));
+    entry.set_title(title);
+    entry.set_summary(Some(Text::plain(summary)));
+    entry.set_updated(updated);
+    // feed readers can filter on these to follow individual counties or states
+    entry.set_categories(vec![fips_category(&change.fips), fips_category(change.fips.get(..2).unwrap_or(&change.fips))]);
+    if !change.url.is_empty() {
+        entry.set_links(vec![Link { href: change.url.clone(), ..Default::default() }]);
+    }
+    entry
+}
+
+fn fips_category(fips: &str) -> Category {
+    Category {
+        term: fips.to_string(),
+        scheme: Some("urn:nfhl_util:fips".to_string()),
+        label: None,
+    }
+}
+
+/// Formats FEMA's YYYYMMDD dates as YYYY-MM-DD, leaving anything else (including blanks) readable as-is.
+fn display_date(date: &str) -> String {
+    match NaiveDate::parse_from_str(date, "%Y%m%d") {
+        Ok(d) => d.format("%Y-%m-%d").to_string(),
+        Err(_) if date.is_empty() => "none".to_string(),
+        Err(_) => date.to_string(),
+    }
+}
@@ -0,0 +1,329 @@
+//! A C interface to the inventory and download functions, behind the `ffi` feature, for desktop GIS plugins (.NET,
+//! C++) that want them in-process. Build it as a shared or static library with
+//! `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`); `include/nfhl_util.h` declares it.
+//!
+//! Everything crosses as JSON in UTF-8, NUL-terminated strings, in the same shapes as the CLI's files and output
+//! (inventories, changes, run reports). A `NfhlHandle` holds the runtime and HTTP client, and can be shared between
+//! threads. Strings returned to the caller are freed with `nfhl_string_free`. A NULL return means the call failed,
+//! and `nfhl_last_error` says why.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+
+use crate::cancel::CancellationToken;
+use crate::client::{Client, ConnectionOptions};
+use crate::inventory::{format, Inventory};
+use crate::publish::Publishers;
+use crate::{diff, download, msc, nfhl_portal};
+
+type Error = Box<dyn std::error::Error>;
+
+/// The runtime and client calls on a handle share, and the token `nfhl_cancel` cancels.
+pub struct NfhlHandle {
+    runtime: Runtime,
+    client: Client,
+    cancel: Mutex<CancellationToken>,
+}
+
+impl NfhlHandle {
+    /// The token for a call starting now.
+    fn cancel_token(&self) -> CancellationToken {
+        self.cancel.lock().unwrap().clone()
+    }
+}
+
+/// `nfhl_new`'s options, all optional, mapping onto `client::ClientBuilder`.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Options {
+    timeout_secs: Option<u64>,
+    connect_timeout_secs: Option<u64>,
+    user_agent: Option<String>,
+    proxy: Option<String>,
+    headers: BTreeMap<String, String>,
+    rate_limit_ms: Option<u64>,
+    connection: ConnectionOptions,
+}
+
+/// `nfhl_download`'s request.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DownloadRequest {
+    #[serde(deserialize_with = "format::deserialize")]
+    inventory: Inventory,
+    cache_dir: PathBuf,
+    #[serde(default, deserialize_with = "optional_inventory")]
+    old_inventory: Option<Inventory>,
+    #[serde(default)]
+    delete: bool,
+    #[serde(default = "default_politeness")]
+    politeness: u8,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+}
+
+fn optional_inventory<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Inventory>, D::Error> {
+    Option::<serde_json::Value>::deserialize(deserializer)?
+        .map(|value| format::deserialize(value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn default_politeness() -> u8 {
+    255
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+/// What `nfhl_last_error` describes.
+#[derive(Serialize)]
+struct LastError {
+    message: String,
+    /// The CLI's exit code for the error: 75 network, 76 format change, 65 bad input, 74 files, 1 anything else.
+    exit_code: i32,
+    retryable: bool,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(e: Error) {
+    let nfhl = e.downcast_ref::<crate::error::NfhlError>();
+    let error = LastError {
+        message: e.to_string(),
+        exit_code: nfhl.map_or(1, |e| e.exit_code()),
+        retryable: nfhl.is_some_and(|e| e.is_retryable()),
+    };
+    let json = serde_json::to_string(&error).expect("an error always serializes");
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(json).ok());
+}
+
+/// Runs `f`, turning its error (or panic) into a NULL return and `nfhl_last_error`.
+fn call<T>(f: impl FnOnce() -> Result<T, Error>, fail: T) -> T {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e);
+            fail
+        }
+        Err(panic) => {
+            let message = panic.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panicked".to_string());
+            set_last_error(format!("nfhl_util panicked: {}", message).into());
+            fail
+        }
+    }
+}
+
+/// Calls returning JSON.
+fn call_json<T: Serialize>(f: impl FnOnce() -> Result<T, Error>) -> *mut c_char {
+    call(|| Ok(CString::new(serde_json::to_string(&f()?)?)?.into_raw()), ptr::null_mut())
+}
+
+/// An inventory as `format` writes it, `version` and all, for callers that save what they get.
+struct InventoryJson(Inventory);
+
+impl Serialize for InventoryJson {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        format::serialize(&self.0, serializer)
+    }
+}
+
+/// # Safety
+/// `s` is NULL or a NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, Error> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name).into());
+    }
+    Ok(CStr::from_ptr(s).to_str().map_err(|_| format!("{} isn't UTF-8", name))?)
+}
+
+/// # Safety
+/// `s` is NULL or a NUL-terminated string of JSON.
+unsafe fn json_arg<T: for<'de> Deserialize<'de>>(s: *const c_char, name: &str) -> Result<T, Error> {
+    serde_json::from_str(str_arg(s, name)?).map_err(|e| format!("{} isn't usable: {}", name, e).into())
+}
+
+/// # Safety
+/// `s` is NULL or a NUL-terminated string.
+unsafe fn inventory_arg(s: *const c_char, name: &str) -> Result<Inventory, Error> {
+    format::read(str_arg(s, name)?.as_bytes()).map_err(|e| format!("{} isn't usable: {}", name, e).into())
+}
+
+/// # Safety
+/// `handle` is NULL or from `nfhl_new` and not yet freed.
+unsafe fn handle_arg<'a>(handle: *const NfhlHandle) -> Result<&'a NfhlHandle, Error> {
+    handle.as_ref().ok_or_else(|| "the handle is NULL".into())
+}
+
+/// A handle with a client made from `options_json` (`timeout_secs`, `connect_timeout_secs`, `user_agent`, `proxy`,
+/// `headers`, `rate_limit_ms`, and a `connection` object of `client::ConnectionOptions`), or the CLI's defaults if
+/// it's NULL. NULL on failure.
+///
+/// # Safety
+/// `options_json` is NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_new(options_json: *const c_char) -> *mut NfhlHandle {
+    call(|| {
+        let options: Options =
+            if options_json.is_null() { Options::default() } else { json_arg(options_json, "options_json")? };
+        let mut builder = Client::builder();
+        if let Some(secs) = options.timeout_secs {
+            builder = builder.timeout(Some(Duration::from_secs(secs)));
+        }
+        if let Some(secs) = options.connect_timeout_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(user_agent) = options.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = options.proxy {
+            builder = builder.proxy(proxy);
+        }
+        for (name, value) in options.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(ms) = options.rate_limit_ms {
+            builder = builder.rate_limit(Duration::from_millis(ms));
+        }
+        builder = builder.connection(options.connection);
+        let handle = NfhlHandle {
+            runtime: Runtime::new()?,
+            client: builder.build()?,
+            cancel: Mutex::new(CancellationToken::new()),
+        };
+        Ok(Box::into_raw(Box::new(handle)))
+    }, ptr::null_mut())
+}
+
+/// Frees a handle. Calls still running on it must have returned first.
+///
+/// # Safety
+/// `handle` is NULL or from `nfhl_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_free(handle: *mut NfhlHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Cancels the calls running on the handle, from any thread. They return what they'd finished: `nfhl_download`'s
+/// report then has a `remaining` plan. Calls made afterwards aren't affected.
+///
+/// # Safety
+/// `handle` is NULL or from `nfhl_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_cancel(handle: *const NfhlHandle) {
+    if let Some(handle) = handle.as_ref() {
+        let mut cancel = handle.cancel.lock().unwrap();
+        cancel.cancel();
+        *cancel = CancellationToken::new();
+    }
+}
+
+/// The current county inventory from the NFHL portal, as JSON.
+///
+/// # Safety
+/// `handle` is NULL or from `nfhl_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_counties_inventory(handle: *const NfhlHandle) -> *mut c_char {
+    call_json(|| {
+        let handle = handle_arg(handle)?;
+        let cancel = handle.cancel_token();
+        let inv = handle.runtime.block_on(crate::cancel::or_cancelled(&cancel,
+            nfhl_portal::get_effective_county_products(&handle.client)))?;
+        Ok(InventoryJson(inv))
+    })
+}
+
+/// The current state inventory from the Map Service Center, as JSON.
+///
+/// # Safety
+/// `handle` is NULL or from `nfhl_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_states_inventory(handle: *const NfhlHandle) -> *mut c_char {
+    call_json(|| {
+        let handle = handle_arg(handle)?;
+        let cancel = handle.cancel_token();
+        let states = msc::states();
+        let region_concurrency = msc::DEFAULT_REGION_CONCURRENCY;
+        let diagnostics = msc::diagnostics_dir();
+        let products = handle.runtime.block_on(msc::get_state_products(&handle.client, &states, region_concurrency,
+            &cancel, &diagnostics))?;
+        products.finished()?;
+        Ok(InventoryJson(products.inventory))
+    })
+}
+
+/// The changes from one inventory's JSON to another's, as a JSON list like `nfhl_util diff --format json` prints.
+///
+/// # Safety
+/// Both are NULL or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_diff(old_json: *const c_char, new_json: *const c_char) -> *mut c_char {
+    call_json(|| {
+        let old = inventory_arg(old_json, "old_json")?;
+        let new = inventory_arg(new_json, "new_json")?;
+        Ok(diff::diff_inventories(&old, &new))
+    })
+}
+
+/// Brings a cache up to date, like `nfhl_util download_all`, returning the run report's JSON. The request is an
+/// object with `inventory` and `cache_dir`, and optionally `old_inventory`, `delete` (false), `politeness` (255) and
+/// `concurrency` (1).
+///
+/// # Safety
+/// `handle` is NULL or from `nfhl_new` and not yet freed, and `request_json` is NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_download(handle: *const NfhlHandle, request_json: *const c_char) -> *mut c_char {
+    call_json(|| {
+        let handle = handle_arg(handle)?;
+        let request: DownloadRequest = json_arg(request_json, "request_json")?;
+        let cancel = handle.cancel_token();
+        let mut publishers = Publishers::default();
+        let report = handle.runtime.block_on(download::download_all(
+            &handle.client,
+            &request.inventory,
+            request.old_inventory.as_ref(),
+            &request.cache_dir,
+            request.delete,
+            request.politeness,
+            request.concurrency,
+            None,
+            &mut publishers,
+            &cancel,
+        ))?;
+        Ok(report)
+    })
+}
+
+/// Why the calling thread's last call failed, as JSON with `message`, `exit_code` and `retryable`; NULL if it
+/// didn't. Owned by the library and valid until the thread's next call.
+#[no_mangle]
+pub extern "C" fn nfhl_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Frees a string the library returned.
+///
+/// # Safety
+/// `s` is NULL or from one of these functions and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn nfhl_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -0,0 +1,172 @@
+//! Reading the fips codes people type. Every command taking one normalizes it first: leading zeros dropped by a
+//! spreadsheet are put back (`1001` is `01001`), a state can be given by its postal code or name (`TX`, `texas`), and
+//! a code for no state is refused with the nearest ones suggested, rather than matching nothing and quietly doing
+//! nothing. The parsers have the `Result<String, String>` clap's `try_from_str` wants.
+
+pub use crate::inventory::STATES;
+
+/// How many suggestions an unknown code gets.
+const SUGGESTIONS: usize = 3;
+
+/// The state with fips `fips`, as (fips, postal code, name).
+pub fn state(fips: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    STATES.iter().find(|(code, _, _)| *code == fips).copied()
+}
+
+/// A 2-digit state fips code, from 1 or 2 digits, a postal code or a name.
+pub fn parse_state(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return state_by_name(s);
+    }
+    if s.len() > 2 {
+        return Err(format!("'{}' isn't a 2-digit state fips code", s));
+    }
+    known_state(&format!("{:0>2}", s))
+}
+
+/// A 5-digit county fips code, from 4 or 5 digits.
+pub fn parse_county(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if !matches!(s.len(), 4 | 5) || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("'{}' isn't a 5-digit county fips code", s));
+    }
+    let fips = format!("{:0>5}", s);
+    known_state(&fips[..2])?;
+    Ok(fips)
+}
+
+/// A state's fips code (as `parse_state` reads it) or a county's (as `parse_county` does), told apart by length.
+pub fn parse_fips(s: &str) -> Result<String, String> {
+    match s.trim() {
+        digits if matches!(digits.len(), 4 | 5) && digits.bytes().all(|b| b.is_ascii_digit()) => parse_county(digits),
+        other => parse_state(other)
+            .map_err(|_| format!("'{}' isn't a state (e.g. 48 or TX) or a 5-digit county fips code", other)),
+    }
+}
+
+/// Like `parse_fips`, for the options matching every code starting with it, so 3 digits are taken as they are once
+/// their state is checked.
+pub fn parse_fips_prefix(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.len() == 3 && s.bytes().all(|b| b.is_ascii_digit()) {
+        known_state(&s[..2])?;
+        return Ok(s.to_string());
+    }
+    parse_fips(s)
+}
+
+/// Checks that each of `wanted` (codes or prefixes) matches at least one of `known`, so a typo'd county isn't
+/// silently left out; the error suggests the nearest known codes.
+pub fn check_known<'a>(wanted: &[String], known: impl IntoIterator<Item = &'a str> + Clone) -> Result<(), String> {
+    for fips in wanted {
+        if known.clone().into_iter().any(|known| known.starts_with(fips.as_str())) {
+            continue;
+        }
+        let candidates = known.clone().into_iter().filter(|known| known.len() == fips.len());
+        return Err(match suggest(fips, candidates) {
+            suggestions if suggestions.is_empty() => format!("nothing has the fips code {}", fips),
+            suggestions => format!("nothing has the fips code {}; did you mean {}?", fips, suggestions.join(", ")),
+        });
+    }
+    Ok(())
+}
+
+fn known_state(fips: &str) -> Result<String, String> {
+    if state(fips).is_some() {
+        return Ok(fips.to_string());
+    }
+    let suggestions: Vec<String> = suggest(fips, STATES.iter().map(|(code, _, _)| *code)).iter()
+        .map(|code| {
+            let (_, postal, _) = state(code).expect("suggested from STATES");
+            format!("{} ({})", code, postal)
+        })
+        .collect();
+    match suggestions.is_empty() {
+        true => Err(format!("no state has the fips code {}", fips)),
+        false => Err(format!("no state has the fips code {}; did you mean {}?", fips, suggestions.join(", "))),
+    }
+}
+
+fn state_by_name(s: &str) -> Result<String, String> {
+    let found = STATES.iter()
+        .find(|(_, postal, name)| postal.eq_ignore_ascii_case(s) || name.eq_ignore_ascii_case(s));
+    if let Some((fips, _, _)) = found {
+        return Ok(fips.to_string());
+    }
+    let lower = s.to_ascii_lowercase();
+    let names: Vec<String> = STATES.iter().map(|(_, _, name)| name.to_ascii_lowercase()).collect();
+    let postals: Vec<String> = STATES.iter().map(|(_, postal, _)| postal.to_ascii_lowercase()).collect();
+    let candidates = if s.len() <= 2 { &postals } else { &names };
+    let suggestions: Vec<String> = suggest(&lower, candidates.iter().map(String::as_str)).iter()
+        .filter_map(|candidate| STATES.iter().find(|(_, postal, name)| {
+            postal.eq_ignore_ascii_case(candidate) || name.eq_ignore_ascii_case(candidate)
+        }))
+        .map(|(fips, postal, name)| format!("{} ({}, {})", postal, name, fips))
+        .collect();
+    match suggestions.is_empty() {
+        true => Err(format!("'{}' isn't a state's fips code, postal code or name", s)),
+        false => Err(format!("'{}' isn't a state's fips code, postal code or name; did you mean {}?", s, suggestions.join(", "))),
+    }
+}
+
+/// The candidates within a couple of edits of `s`, nearest first.
+fn suggest<'a>(s: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let most = (s.len() / 3).max(1);
+    let mut near: Vec<(usize, &str)> = candidates.into_iter()
+        .map(|candidate| (strsim::levenshtein(s, candidate), candidate))
+        .filter(|(distance, _)| *distance <= most)
+        .collect();
+    near.sort();
+    near.dedup();
+    near.into_iter().take(SUGGESTIONS).map(|(_, candidate)| candidate.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn county_codes_get_their_leading_zero_back() {
+        assert_eq!(parse_county("1001").unwrap(), "01001");
+        assert_eq!(parse_county(" 48201 ").unwrap(), "48201");
+        assert_eq!(parse_fips("1001").unwrap(), "01001");
+        assert_eq!(parse_state("6").unwrap(), "06");
+        assert!(parse_county("482010").is_err());
+        assert!(parse_county("48a01").is_err());
+    }
+
+    #[test]
+    fn states_by_postal_code_or_name() {
+        assert_eq!(parse_state("tx").unwrap(), "48");
+        assert_eq!(parse_state("Texas").unwrap(), "48");
+        assert_eq!(parse_fips("TX").unwrap(), "48");
+        assert_eq!(parse_state("district of columbia").unwrap(), "11");
+    }
+
+    #[test]
+    fn unknown_codes_are_refused_with_suggestions() {
+        let e = parse_state("03").unwrap_err();
+        assert!(e.starts_with("no state has the fips code 03; did you mean "), "{}", e);
+        let e = parse_county("03001").unwrap_err();
+        assert!(e.contains("did you mean"), "{}", e);
+        let e = parse_state("texs").unwrap_err();
+        assert!(e.contains("TX (Texas, 48)"), "{}", e);
+        assert_eq!(parse_state("zzzzzzzz").unwrap_err(), "'zzzzzzzz' isn't a state's fips code, postal code or name");
+
+        let known = ["48201", "48203"];
+        assert!(check_known(&["48201".to_string(), "482".to_string()], known).is_ok());
+        let e = check_known(&["48202".to_string()], known).unwrap_err();
+        assert_eq!(e, "nothing has the fips code 48202; did you mean 48201, 48203?");
+    }
+
+    #[test]
+    fn every_territory_is_a_state() {
+        for (fips, postal) in [("60", "AS"), ("64", "FM"), ("66", "GU"), ("68", "MH"), ("69", "MP"), ("72", "PR"), ("78", "VI")] {
+            assert_eq!(parse_state(fips).unwrap(), fips);
+            assert_eq!(parse_state(postal).unwrap(), fips);
+            assert_eq!(parse_county(&format!("{}010", fips)).unwrap(), format!("{}010", fips));
+        }
+        assert_eq!(parse_state("Virgin Islands").unwrap(), "78");
+    }
+}
@@ -0,0 +1,125 @@
+//! `firmette`: the FIRMette (an official one-page excerpt of the FIRM) at a point, from the print service behind the
+//! Map Service Center's "Create FIRMette" button. The service is an ArcGIS geoprocessing task: a job is submitted,
+//! polled until it's done, and its output file downloaded.
+
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+pub const FIRMETTE_SERVICE: &str =
+    "https://msc.fema.gov/arcgis/rest/services/NFHL_Print/MSCPrintB/GPServer/Print%20FIRM%20or%20FIRMette";
+
+/// How often the job is polled, and how long it may take. FIRMettes usually take well under a minute.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+const JOB_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum FirmetteFormat {
+    Pdf,
+    Png,
+}
+
+impl FirmetteFormat {
+    fn graphic(&self) -> &'static str {
+        match self {
+            FirmetteFormat::Pdf => "PDF",
+            FirmetteFormat::Png => "PNG",
+        }
+    }
+}
+
+/// Has a FIRMette made for the point and saves it to `out`, returning the url it was downloaded from.
+pub fn create_firmette(lon: f64, lat: f64, format: FirmetteFormat, out: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let client = reqwest::blocking::Client::builder()
+        .cookie_store(true)
+        .timeout(Duration::from_secs(60))
+        .build()?;
+    let submitted: Value = client.get(format!("{}/submitJob", FIRMETTE_SERVICE))
+        .query(&[
+            ("f", "json"),
+            ("PRINT_TYPE", "FIRMETTE"),
+            ("graphic", format.graphic()),
+            ("input_lat", &lat.to_string()),
+            ("input_lon", &lon.to_string()),
+        ])
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let job_id = submitted["jobId"].as_str()
+        .ok_or_else(|| format!("the FIRMette service didn't start a job: {}", submitted))?
+        .to_string();
+    let job_url = format!("{}/jobs/{}", FIRMETTE_SERVICE, job_id);
+
+    let started = Instant::now();
+    loop {
+        let job: Value = client.get(&job_url).query(&[("f", "json")]).send()?.error_for_status()?.json()?;
+        match job["jobStatus"].as_str().unwrap_or_default() {
+            "esriJobSucceeded" => break,
+            "esriJobFailed" | "esriJobCancelled" | "esriJobTimedOut" => {
+                let messages: Vec<&str> = job["messages"].as_array().into_iter().flatten()
+                    .filter_map(|message| message["description"].as_str())
+                    .collect();
+                return Err(format!("the FIRMette job {} failed: {}", job_id, messages.join("; ")).into());
+            }
+            _ if started.elapsed() > JOB_TIMEOUT => {
+                return Err(format!("the FIRMette job {} didn't finish in {}s", job_id, JOB_TIMEOUT.as_secs()).into());
+            }
+            _ => sleep(POLL_INTERVAL),
+        }
+    }
+
+    let result: Value = client.get(format!("{}/results/OutputFile", job_url))
+        .query(&[("f", "json")])
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let url = result["value"]["url"].as_str()
+        .ok_or_else(|| format!("the FIRMette job {} has no output file: {}", job_id, result))?
+        .to_string();
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    crate::blocking::download_file(&url, out)?;
+    Ok(url)
+}
+
+/// Where a panel is, as a point inside it, from the cached `S_FIRM_Pan` of the county its number starts with.
+#[cfg(feature = "gdal")]
+pub fn panel_point(cache_dir: &Path, firm_pan: &str) -> Result<(f64, f64), Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::LayerAccess;
+
+    let fips = firm_pan.get(..5).filter(|fips| fips.bytes().all(|b| b.is_ascii_digit()))
+        .ok_or_else(|| format!("'{}' isn't a countywide panel number; give `--lat` and `--lon` instead", firm_pan))?;
+    let source = crate::merge_geo::sources(cache_dir, |cached| cached == fips)?
+        .pop()
+        .ok_or_else(|| format!("no cached archive for {} in {}", fips, cache_dir.display()))?;
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let mut panels = dataset.layer_by_name("S_FIRM_Pan").map_err(|_| format!("{} has no S_FIRM_Pan layer", gdb))?;
+    let lon_lat = SpatialRef::from_epsg(4326)?;
+    lon_lat.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    let to_lon_lat = CoordTransform::new(&crate::stats::layer_srs(&panels)?, &lon_lat)?;
+    for feature in panels.features() {
+        let matches = feature.field_as_string_by_name("FIRM_PAN")?.is_some_and(|pan| pan.trim().eq_ignore_ascii_case(firm_pan));
+        if let Some(geometry) = feature.geometry().filter(|g| matches && !g.is_empty()) {
+            // a point on the surface rather than the centroid, which can fall outside an irregular panel
+            // SAFETY: the handle stays owned by the geometry for the duration of the call; the result is destroyed
+            let (x, y) = unsafe {
+                let c_point = gdal_sys::OGR_G_PointOnSurface(geometry.c_geometry());
+                if c_point.is_null() {
+                    continue;
+                }
+                let (x, y) = (gdal_sys::OGR_G_GetX(c_point, 0), gdal_sys::OGR_G_GetY(c_point, 0));
+                gdal_sys::OGR_G_DestroyGeometry(c_point);
+                (x, y)
+            };
+            let (mut xs, mut ys) = ([x], [y]);
+            to_lon_lat.transform_coords(&mut xs, &mut ys, &mut [0.0])?;
+            return Ok((xs[0], ys[0]));
+        }
+    }
+    Err(format!("{} has no panel {}", source.archive.display(), firm_pan).into())
+}
@@ -0,0 +1,92 @@
+//! The parts of FEMA's FIRM database specification (the *FIRM Database Technical Reference*, whose schema the NFHL
+//! county downloads follow) that `validate-gdb` checks: which tables a county must have, and per table the fields
+//! that must exist, the ones that must be filled in, and the domains of coded fields.
+
+pub struct TableSpec {
+    pub name: &'static str,
+    /// Every county delivery has to have it. The others are only checked when present, since most depend on what was
+    /// studied (a county without detailed studies has no cross sections).
+    pub required: bool,
+    /// Fields that have to exist.
+    pub fields: &'static [&'static str],
+    /// Of those, the ones no feature may leave null or empty.
+    pub not_null: &'static [&'static str],
+    /// Coded fields and the values allowed in them, when filled in.
+    pub domains: &'static [(&'static str, &'static [&'static str])],
+}
+
+const TRUE_FALSE: &[&str] = &["T", "F"];
+
+/// `D_Zone`.
+pub const FLOOD_ZONES: &[&str] = &[
+    "A", "A99", "AE", "AH", "AO", "AR", "V", "VE", "X", "D", "OPEN WATER", "AREA NOT INCLUDED",
+];
+
+pub const TABLES: &[TableSpec] = &[
+    TableSpec {
+        name: "S_Fld_Haz_Ar",
+        required: true,
+        fields: &[
+            "DFIRM_ID", "VERSION_ID", "FLD_AR_ID", "STUDY_TYP", "FLD_ZONE", "ZONE_SUBTY", "SFHA_TF", "STATIC_BFE",
+            "V_DATUM", "DEPTH", "LEN_UNIT", "VELOCITY", "VEL_UNIT", "AR_REVERT", "AR_SUBTRV", "BFE_REVERT",
+            "DEP_REVERT", "DUAL_ZONE", "SOURCE_CIT",
+        ],
+        not_null: &["DFIRM_ID", "VERSION_ID", "FLD_AR_ID", "STUDY_TYP", "FLD_ZONE", "SFHA_TF", "SOURCE_CIT"],
+        domains: &[("FLD_ZONE", FLOOD_ZONES), ("SFHA_TF", TRUE_FALSE), ("DUAL_ZONE", TRUE_FALSE)],
+    },
+    TableSpec {
+        name: "S_FIRM_Pan",
+        required: true,
+        fields: &[
+            "DFIRM_ID", "VERSION_ID", "FIRM_ID", "ST_FIPS", "PCOMM", "PANEL", "SUFFIX", "FIRM_PAN", "PANEL_TYP",
+            "PRE_DATE", "EFF_DATE", "SCALE", "PNP_REASON", "BASE_TYP", "SOURCE_CIT",
+        ],
+        not_null: &["DFIRM_ID", "VERSION_ID", "FIRM_ID", "ST_FIPS", "PANEL", "FIRM_PAN", "PANEL_TYP", "SOURCE_CIT"],
+        domains: &[],
+    },
+    TableSpec {
+        name: "S_Pol_Ar",
+        required: true,
+        fields: &["DFIRM_ID", "VERSION_ID", "POL_AR_ID", "POL_NAME1", "CO_FIPS", "ST_FIPS", "COMM_NO", "CID", "ANI_TF", "SOURCE_CIT"],
+        not_null: &["DFIRM_ID", "VERSION_ID", "POL_AR_ID", "POL_NAME1", "ST_FIPS", "SOURCE_CIT"],
+        domains: &[("ANI_TF", TRUE_FALSE)],
+    },
+    TableSpec {
+        name: "S_BFE",
+        required: false,
+        fields: &["DFIRM_ID", "VERSION_ID", "BFE_LN_ID", "ELEV", "LEN_UNIT", "V_DATUM", "SOURCE_CIT"],
+        not_null: &["DFIRM_ID", "VERSION_ID", "BFE_LN_ID", "ELEV", "LEN_UNIT", "V_DATUM", "SOURCE_CIT"],
+        domains: &[],
+    },
+    TableSpec {
+        name: "S_XS",
+        required: false,
+        fields: &[
+            "DFIRM_ID", "VERSION_ID", "XS_LN_ID", "WTR_NM", "STREAM_STN", "START_ID", "XS_LTR", "XS_LN_TYP",
+            "WSEL_REG", "STRMBED_EL", "LEN_UNIT", "V_DATUM", "SOURCE_CIT",
+        ],
+        not_null: &["DFIRM_ID", "VERSION_ID", "XS_LN_ID", "XS_LN_TYP", "SOURCE_CIT"],
+        domains: &[],
+    },
+    TableSpec {
+        name: "S_LOMR",
+        required: false,
+        fields: &["DFIRM_ID", "VERSION_ID", "LOMR_ID", "EFF_DATE", "CASE_NO", "SCALE", "STATUS", "SOURCE_CIT"],
+        not_null: &["DFIRM_ID", "VERSION_ID", "LOMR_ID", "EFF_DATE", "CASE_NO", "SOURCE_CIT"],
+        domains: &[],
+    },
+    TableSpec {
+        name: "S_Gen_Struct",
+        required: false,
+        fields: &["DFIRM_ID", "VERSION_ID", "STRUCT_ID", "STRUCT_TYP", "SOURCE_CIT"],
+        not_null: &["DFIRM_ID", "VERSION_ID", "STRUCT_ID", "STRUCT_TYP", "SOURCE_CIT"],
+        domains: &[],
+    },
+    TableSpec {
+        name: "Study_Info",
+        required: false,
+        fields: &["DFIRM_ID", "VERSION_ID", "STUDY_PRE", "STUDY_NM", "STATE_NM", "CNTY_NM", "JURIS_TYP", "H_DATUM", "V_DATUM"],
+        not_null: &["DFIRM_ID", "VERSION_ID", "STUDY_NM", "STATE_NM"],
+        domains: &[],
+    },
+];
@@ -0,0 +1,75 @@
+//! Geocoding for `query address`. Providers implement `Geocoder`; the Census Bureau's free geocoder is the one built in.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GeocodedAddress {
+    /// The address as the provider matched it.
+    pub matched_address: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+pub trait Geocoder {
+    /// The best match for a one-line address, or None if the provider couldn't match it.
+    fn geocode(&self, address: &str) -> Result<Option<GeocodedAddress>, Box<dyn std::error::Error>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum GeocoderKind {
+    /// The U.S. Census Bureau's geocoder. Free and keyless, but US addresses only.
+    Census,
+}
+
+impl GeocoderKind {
+    pub fn geocoder(&self) -> Box<dyn Geocoder> {
+        match self {
+            GeocoderKind::Census => Box::new(CensusGeocoder::default()),
+        }
+    }
+}
+
+pub const CENSUS_GEOCODER_URL: &str = "https://geocoding.geo.census.gov/geocoder/locations/onelineaddress";
+
+pub struct CensusGeocoder {
+    /// The address ranges to match against; `Public_AR_Current` is the latest.
+    pub benchmark: String,
+}
+
+impl Default for CensusGeocoder {
+    fn default() -> Self {
+        CensusGeocoder { benchmark: "Public_AR_Current".to_string() }
+    }
+}
+
+impl Geocoder for CensusGeocoder {
+    fn geocode(&self, address: &str) -> Result<Option<GeocodedAddress>, Box<dyn std::error::Error>> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()?;
+        let response: Value = client.get(CENSUS_GEOCODER_URL)
+            .query(&[("address", address), ("benchmark", &self.benchmark), ("format", "json")])
+            .send()?
+            .error_for_status()?
+            .json()?;
+        if let Some(errors) = response.get("errors") {
+            return Err(format!("the Census geocoder failed: {}", errors).into());
+        }
+        // matches come best first
+        let best = match response["result"]["addressMatches"].as_array().and_then(|matches| matches.first()) {
+            Some(best) => best,
+            None => return Ok(None),
+        };
+        match (best["coordinates"]["y"].as_f64(), best["coordinates"]["x"].as_f64()) {
+            (Some(lat), Some(lon)) => Ok(Some(GeocodedAddress {
+                matched_address: best["matchedAddress"].as_str().unwrap_or(address).to_string(),
+                lat,
+                lon,
+            })),
+            _ => Err(format!("the Census geocoder's match for '{}' has no coordinates", address).into()),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::diff::{Change, ChangeKind};
+use crate::report::{self, ReportFormat};
+
+/// One line of the changelog: a change, and when we first saw it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangelogRecord {
+    pub observed_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Reads every record in the changelog, oldest first. A missing changelog is just an empty history.
+pub fn read_changelog(path: &Path) -> Result<Vec<ChangelogRecord>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+    Ok(records)
+}
+
+/// Appends `changes` to the JSONL changelog at `path`. A change identical to the latest one already recorded for
+/// its county and product is skipped, so re-running the same diff doesn't duplicate history. Returns how many
+/// records were written.
+pub fn append_changes(path: &Path, changes: &[Change], observed_at: DateTime<Utc>) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut latest = HashMap::<(String, ChangeKind), Change>::new();
+    for record in read_changelog(path)? {
+        latest.insert((record.change.fips.clone(), record.change.kind), record.change);
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut written = 0;
+    for change in changes {
+        let already_recorded = latest.get(&(change.fips.clone(), change.kind))
+            .is_some_and(|c| c.old_date == change.old_date && c.new_date == change.new_date && c.url == change.url);
+        if already_recorded {
+            continue;
+        }
+        let record = ChangelogRecord { observed_at, change: change.clone() };
+        writeln!(f, "{}", serde_json::to_string(&record)?)?;
+        written += 1;
+    }
+    f.sync_all()?;
+    Ok(written)
+}
+
+pub fn write_history(out: &mut dyn Write, records: &[ChangelogRecord], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, records)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["observed_at", "fips", "change", "old_date", "new_date", "url"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = records.iter()
+        .map(|r| vec![
+            r.observed_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            r.change.fips.clone(),
+            r.change.kind.as_str().to_string(),
+            r.change.old_date.clone(),
+            r.change.new_date.clone(),
+            r.change.url.clone(),
+        ])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
@@ -0,0 +1,160 @@
+//! `ls`: what the cache holds, a row per county, for a quick look over a mirror. Unlike `verify` nothing is read
+//! or hashed; a file's status is what the manifest recorded for it and whether its size still agrees.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::cache::{self, CacheManifest};
+use crate::encryption;
+use crate::error::{NfhlError, Result};
+use crate::html_report::format_bytes;
+use crate::inventory::Inventory;
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The manifest has the file's SHA-256 and its size still matches.
+    Recorded,
+    /// The manifest has the file, but at a different size than it's now.
+    SizeMismatch,
+    /// The manifest doesn't have the file, or has it from before downloads were hashed.
+    Unrecorded,
+    /// `verify` moved the file to the quarantine, for the next run to fetch again.
+    Quarantined,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Recorded => "recorded",
+            Status::SizeMismatch => "size mismatch",
+            Status::Unrecorded => "unrecorded",
+            Status::Quarantined => "quarantined",
+        }
+    }
+}
+
+/// A county's newest cached file.
+#[derive(Serialize, Debug, Clone)]
+pub struct Holding {
+    pub fips: String,
+    pub file_name: String,
+    pub effective_date: Option<NaiveDate>,
+    pub size: u64,
+    /// When `download_all` fetched it; None for files put in the cache some other way.
+    pub downloaded_at: Option<DateTime<Utc>>,
+    pub status: Status,
+    /// The inventory's effective date, when it has a different file than the cached one.
+    pub newer: Option<NaiveDate>,
+    /// Whether the inventory has a different file; always false without an inventory.
+    pub stale: bool,
+}
+
+/// The newest cached file of each county whose fips starts with `prefix` (all of them for ""), by fips, compared
+/// with `inv` if given.
+pub fn list_holdings(cache_dir: &Path, inv: Option<&Inventory>, prefix: &str) -> Result<Vec<Holding>> {
+    let manifest = CacheManifest::load(cache_dir)?;
+    // sorted by fips then name, so the newest file per county wins
+    let newest: BTreeMap<String, PathBuf> = cache::cached_archives(cache_dir)?.into_iter()
+        .filter(|(fips, _)| fips.starts_with(prefix))
+        .collect();
+
+    let mut holdings = Vec::new();
+    for (fips, archive) in &newest {
+        let file_name = cache::relative_name(cache_dir, archive);
+        let size = std::fs::metadata(archive).map_err(NfhlError::io(archive))?.len();
+        let recorded = manifest.entries.get(fips).filter(|cached| cached.file_name == file_name);
+        let status = match recorded {
+            Some(cached) if ![cached.stored_size(), encryption::encrypted_size(cached.stored_size())].contains(&size) => {
+                Status::SizeMismatch
+            }
+            Some(cached) if cached.sha256.is_some() => Status::Recorded,
+            _ => Status::Unrecorded,
+        };
+        holdings.push(Holding {
+            fips: fips.clone(),
+            effective_date: cache::archive_effective_date(&manifest, fips, archive),
+            size,
+            downloaded_at: recorded.map(|cached| cached.downloaded_at),
+            status,
+            newer: None,
+            stale: false,
+            file_name,
+        });
+    }
+    // quarantined files are no longer in the cache, but the county is still held, if badly
+    for (fips, cached) in &manifest.entries {
+        if cached.quarantined_at.is_some() && fips.starts_with(prefix) && !newest.contains_key(fips) {
+            holdings.push(Holding {
+                fips: fips.clone(),
+                file_name: cached.file_name.clone(),
+                effective_date: crate::inventory::parse_file_date(&cached.effective_date),
+                size: cached.size,
+                downloaded_at: Some(cached.downloaded_at),
+                status: Status::Quarantined,
+                newer: None,
+                stale: false,
+            });
+        }
+    }
+    holdings.sort_by(|a, b| a.fips.cmp(&b.fips));
+
+    if let Some(inv) = inv {
+        for holding in &mut holdings {
+            let Some(entry) = inv.get(holding.fips.as_str()).filter(|entry| entry.effective_file_url.is_some()) else {
+                continue;
+            };
+            if manifest.layout.relative_path(&holding.fips, &cache::cache_file_name(&holding.fips, entry)) != holding.file_name {
+                holding.stale = true;
+                holding.newer = entry.effective_file_date;
+            }
+        }
+    }
+    Ok(holdings)
+}
+
+pub fn write_holdings(out: &mut dyn Write, holdings: &[Holding], format: ReportFormat) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, holdings)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let date = |date: Option<NaiveDate>| date.map(|d| d.to_string()).unwrap_or_default();
+    let headers: Vec<String> = ["fips", "file", "effective", "size", "downloaded", "status", "newer"]
+        .iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = holdings.iter()
+        .map(|holding| vec![
+            holding.fips.clone(),
+            holding.file_name.clone(),
+            date(holding.effective_date),
+            match format {
+                ReportFormat::Csv => holding.size.to_string(),
+                _ => format_bytes(holding.size),
+            },
+            holding.downloaded_at.map(|at| at.format("%Y-%m-%d").to_string()).unwrap_or_default(),
+            holding.status.as_str().to_string(),
+            match (holding.stale, holding.newer) {
+                (true, Some(newer)) => newer.to_string(),
+                (true, None) => "yes".to_string(),
+                (false, _) => String::new(),
+            },
+        ])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows)?,
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+    if let ReportFormat::Table = format {
+        let total: u64 = holdings.iter().map(|holding| holding.size).sum();
+        let stale = holdings.iter().filter(|holding| holding.stale).count();
+        writeln!(out, "\n{} counties, {}, {} stale", holdings.len(), format_bytes(total), stale)?;
+    }
+    Ok(())
+}
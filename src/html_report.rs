@@ -0,0 +1,168 @@
+use std::fmt::Write;
+
+use crate::download::RunReport;
+
+const STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; color: #222; max-width: 960px; margin: 2em auto; padding: 0 1em; }
+h1 { font-size: 1.6em; margin-bottom: 0.2em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ddd; padding-bottom: 0.2em; }
+.muted { color: #777; }
+.cards { display: flex; flex-wrap: wrap; gap: 1em; margin-top: 1em; }
+.card { border: 1px solid #ddd; border-radius: 6px; padding: 0.6em 1em; min-width: 120px; }
+.card .value { font-size: 1.5em; font-weight: bold; }
+.card.bad .value { color: #b00020; }
+table { border-collapse: collapse; width: 100%; font-size: 0.9em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #eee; }
+th { background: #f6f6f6; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+svg { background: #fafafa; border: 1px solid #eee; }
+";
+
+/// Renders the run report as a single HTML document with inline styles and charts, so it can be emailed as-is.
+pub fn render_run_report(report: &RunReport) -> String {
+    let mut html = String::new();
+    let downloaded_bytes: u64 = report.downloads.iter().map(|d| d.bytes).sum();
+    let wall_seconds = (report.finished_at - report.started_at).num_milliseconds() as f64 / 1000.0;
+
+    let _ = write!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>NFHL download report {}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape(&report.started_at.format("%Y-%m-%d").to_string()), STYLE);
+    let _ = write!(html, "<h1>NFHL download report</h1>\n<p class=\"muted\">Run started {} and finished {} ({}).</p>\n",
+        escape(&report.started_at.to_rfc3339()), escape(&report.finished_at.to_rfc3339()), format_duration(wall_seconds));
+
+    html.push_str("<div class=\"cards\">\n");
+    card(&mut html, "Changes", &report.changes.len().to_string(), false);
+    card(&mut html, "Downloaded", &report.downloads.len().to_string(), false);
+    card(&mut html, "Failed", &report.failures.len().to_string(), !report.failures.is_empty());
+    card(&mut html, "Already cached", &report.skipped.to_string(), false);
+    card(&mut html, "Deleted", &report.deleted.len().to_string(), false);
+    card(&mut html, "Transferred", &format_bytes(downloaded_bytes), false);
+    html.push_str("</div>\n");
+
+    html.push_str("<h2>Changes</h2>\n");
+    if report.changes.is_empty() {
+        html.push_str("<p class=\"muted\">No changes detected.</p>\n");
+    } else {
+        html.push_str("<table>\n<tr><th>FIPS</th><th>Change</th><th>Old date</th><th>New date</th></tr>\n");
+        for c in &report.changes {
+            let _ = writeln!(html, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&c.fips), c.kind.as_str(), escape(&c.old_date), escape(&c.new_date));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>Failures</h2>\n");
+    if report.failures.is_empty() {
+        html.push_str("<p class=\"muted\">No failures.</p>\n");
+    } else {
+        html.push_str("<table>\n<tr><th>FIPS</th><th>Error</th><th>URL</th></tr>\n");
+        for f in &report.failures {
+            let _ = writeln!(html, "<tr><td>{}</td><td>{}</td><td><a href=\"{}\">link</a></td></tr>",
+                escape(&f.fips), escape(&f.error), escape(&f.url));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>Throughput</h2>\n");
+    if report.downloads.is_empty() {
+        html.push_str("<p class=\"muted\">Nothing was downloaded.</p>\n");
+    } else {
+        html.push_str("<p class=\"muted\">Per-file throughput, in download order (MB/s).</p>\n");
+        html.push_str(&throughput_chart(report));
+        html.push_str("<p class=\"muted\">Cumulative data transferred over the run (MB).</p>\n");
+        html.push_str(&cumulative_chart(report));
+    }
+
+    html.push_str("<h2>Cache</h2>\n<table>\n");
+    let _ = writeln!(html, "<tr><td>Files in cache</td><td class=\"num\">{}</td></tr>", report.cache.files);
+    let _ = writeln!(html, "<tr><td>Total size</td><td class=\"num\">{}</td></tr>", format_bytes(report.cache.total_bytes));
+    let _ = writeln!(html, "<tr><td>Deleted this run</td><td class=\"num\">{}</td></tr>", report.deleted.len());
+    html.push_str("</table>\n");
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn card(html: &mut String, label: &str, value: &str, bad: bool) {
+    let _ = writeln!(html, "<div class=\"card{}\"><div class=\"value\">{}</div><div class=\"muted\">{}</div></div>",
+        if bad { " bad" } else { "" }, escape(value), escape(label));
+}
+
+const CHART_WIDTH: f64 = 900.0;
+const CHART_HEIGHT: f64 = 200.0;
+
+fn throughput_chart(report: &RunReport) -> String {
+    let rates: Vec<f64> = report.downloads.iter()
+        .map(|d| if d.seconds > 0.0 { d.bytes as f64 / 1_000_000.0 / d.seconds } else { 0.0 })
+        .collect();
+    let max = rates.iter().cloned().fold(0.0, f64::max).max(0.001);
+    let bar_width = CHART_WIDTH / rates.len() as f64;
+
+    let mut svg = format!("<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" xmlns=\"http://www.w3.org/2000/svg\">\n", w = CHART_WIDTH, h = CHART_HEIGHT + 20.0);
+    for (i, (rate, d)) in rates.iter().zip(&report.downloads).enumerate() {
+        let height = rate / max * CHART_HEIGHT;
+        let _ = writeln!(svg, "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"#3b75af\"><title>{}: {:.2} MB/s</title></rect>",
+            i as f64 * bar_width, CHART_HEIGHT - height, (bar_width - 1.0).max(0.5), height, escape(&d.fips), rate);
+    }
+    let _ = writeln!(svg, "<text x=\"4\" y=\"{}\" font-size=\"12\" fill=\"#777\">max {:.2} MB/s</text>", CHART_HEIGHT + 15.0, max);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn cumulative_chart(report: &RunReport) -> String {
+    let total_seconds = ((report.finished_at - report.started_at).num_milliseconds() as f64 / 1000.0).max(0.001);
+    let total_mb = (report.downloads.iter().map(|d| d.bytes).sum::<u64>() as f64 / 1_000_000.0).max(0.001);
+
+    let mut points = vec![format!("0,{}", CHART_HEIGHT)];
+    let mut cumulative = 0.0;
+    for d in &report.downloads {
+        cumulative += d.bytes as f64 / 1_000_000.0;
+        let t = (d.finished_at - report.started_at).num_milliseconds() as f64 / 1000.0;
+        points.push(format!("{:.2},{:.2}", t / total_seconds * CHART_WIDTH, CHART_HEIGHT - cumulative / total_mb * CHART_HEIGHT));
+    }
+
+    let mut svg = format!("<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" xmlns=\"http://www.w3.org/2000/svg\">\n", w = CHART_WIDTH, h = CHART_HEIGHT + 20.0);
+    let _ = writeln!(svg, "<polyline points=\"{}\" fill=\"none\" stroke=\"#3b75af\" stroke-width=\"2\"/>", points.join(" "));
+    let _ = writeln!(svg, "<text x=\"4\" y=\"{}\" font-size=\"12\" fill=\"#777\">{:.1} MB over {}</text>",
+        CHART_HEIGHT + 15.0, total_mb, format_duration(total_seconds));
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    match seconds {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
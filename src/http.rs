@@ -0,0 +1,82 @@
+//! The one place the scrapers' requests go out, so they can be recorded and replayed (see `vcr`) and their responses
+//! archived (see `response_archive`).
+
+use reqwest::header::HeaderMap;
+
+use crate::client::Client;
+use crate::error::Result;
+
+/// Sends the request through `client`, returning the body of a successful response. With the `vcr` feature, inside
+/// `vcr::with_cassette` it goes through the cassette instead (after the client's middleware has seen it).
+pub async fn text(client: &Client, request: reqwest::RequestBuilder) -> Result<String> {
+    let request = client.build(request)?;
+    #[cfg(feature = "vcr")]
+    if let Some(cassette) = crate::vcr::current() {
+        return cassette.send(client, request).await;
+    }
+    let Some(archive) = client.response_archive() else {
+        return Ok(client.execute(request).await?.error_for_status()?.text().await?);
+    };
+    let archived = request.try_clone().expect("scraper requests have no streaming bodies");
+    let response = client.execute(request).await?;
+    let mut body = archive.start(&archived, response.status().as_u16(), response.headers())?;
+    // archived whatever the status, since an error page is part of what the site said
+    let failed = response.error_for_status_ref().err();
+    let text = response.text().await?;
+    body.write(text.as_bytes())?;
+    body.finish()?;
+    match failed {
+        Some(e) => Err(e.into()),
+        None => Ok(text),
+    }
+}
+
+/// `text`, handing the body to `on_chunk` as it arrives instead of collecting it, for pages too big to want whole in
+/// memory, and returning the response's headers. A cassette's recorded body comes as one chunk, with no headers.
+pub async fn stream(
+    client: &Client,
+    request: reqwest::RequestBuilder,
+    mut on_chunk: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<HeaderMap> {
+    let request = client.build(request)?;
+    #[cfg(feature = "vcr")]
+    if let Some(cassette) = crate::vcr::current() {
+        on_chunk(cassette.send(client, request).await?.as_bytes())?;
+        return Ok(HeaderMap::new());
+    }
+    let archived = client.response_archive()
+        .map(|archive| (archive, request.try_clone().expect("scraper requests have no streaming bodies")));
+    let mut response = client.execute(request).await?;
+    let headers = response.headers().clone();
+    let mut body = match &archived {
+        Some((archive, request)) => Some(archive.start(request, response.status().as_u16(), &headers)?),
+        None => None,
+    };
+    let failed = response.error_for_status_ref().err();
+    while let Some(chunk) = response.chunk().await? {
+        if let Some(body) = &mut body {
+            body.write(&chunk)?;
+        }
+        // an error page is only read for the archive
+        if failed.is_none() {
+            on_chunk(&chunk)?;
+        }
+    }
+    if let Some(body) = body {
+        body.finish()?;
+    }
+    match failed {
+        Some(e) => Err(e.into()),
+        None => Ok(headers),
+    }
+}
+
+/// The headers of a successful response to the request, e.g. a HEAD. A cassette has none to give.
+pub async fn headers(client: &Client, request: reqwest::RequestBuilder) -> Result<HeaderMap> {
+    let request = client.build(request)?;
+    #[cfg(feature = "vcr")]
+    if crate::vcr::current().is_some() {
+        return Ok(HeaderMap::new());
+    }
+    Ok(client.execute(request).await?.error_for_status()?.headers().clone())
+}
@@ -0,0 +1,86 @@
+//! `extract-layer`: just the hydraulic layers, the BFE lines (`S_BFE`) and cross sections (`S_XS`), for modelers who
+//! need those and not the whole databases. Per county, or merged per state or nationally with `source_fips`
+//! attribution.
+
+use crate::convert::TranslateOptions;
+use crate::merge_geo::{MergeFormat, Source};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum HydraulicLayer {
+    /// Base flood elevation lines.
+    #[clap(name = "S_BFE")]
+    Bfe,
+    /// Cross sections, with their water surface elevations and stream stations.
+    #[clap(name = "S_XS")]
+    Xs,
+}
+
+impl HydraulicLayer {
+    pub fn layer_name(&self) -> &'static str {
+        match self {
+            HydraulicLayer::Bfe => "S_BFE",
+            HydraulicLayer::Xs => "S_XS",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractLayerOptions {
+    pub layers: Vec<HydraulicLayer>,
+    /// Merge into one dataset in this format, rather than a GeoPackage per county.
+    pub merge: Option<MergeFormat>,
+    pub translate: TranslateOptions,
+}
+
+/// Writes the layers of `sources` to `out`: a directory of `{fips}.gpkg`, or with `merge`, the merged dataset (resumed
+/// if it's there already, as `merge-geo` does).
+#[cfg(feature = "gdal")]
+pub fn extract_layers(sources: &[Source], out: &std::path::Path, opts: &ExtractLayerOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let layers: Vec<String> = opts.layers.iter().map(|layer| layer.layer_name().to_string()).collect();
+    match opts.merge {
+        Some(format) => {
+            let merge_opts = crate::merge_geo::MergeOptions { format, layers, translate: opts.translate.clone() };
+            let summary = crate::merge_geo::merge(sources, out, &merge_opts)?;
+            for (layer, features, duplicates) in &summary.layers {
+                eprintln!("{}: {} features ({} duplicates removed)", layer, features, duplicates);
+            }
+            eprintln!("{}: {} counties merged, {} unchanged, {} removed", out.display(), summary.merged, summary.unchanged, summary.removed);
+            if !summary.failed.is_empty() {
+                return Err(format!("{} counties couldn't be merged: {}", summary.failed.len(), summary.failed.join(", ")).into());
+            }
+        }
+        None => {
+            // most counties without detailed studies have neither layer, which isn't a failure
+            let mut archives: Vec<(String, std::path::PathBuf)> = Vec::new();
+            let mut without = 0;
+            for source in sources {
+                let has_layers = match crate::extract::require_archive_gdb_path(&source.archive).and_then(|gdb| Ok(gdal::Dataset::open(gdb)?)) {
+                    Ok(src) => !crate::merge_geo::wanted_layers(&src, &layers).is_empty(),
+                    // left to `convert` to report
+                    Err(_) => true,
+                };
+                if has_layers {
+                    archives.push((source.fips.clone(), source.archive.clone()));
+                } else {
+                    without += 1;
+                }
+            }
+            if without > 0 {
+                eprintln!("{} counties have none of {} and were skipped", without, layers.join(", "));
+            }
+            if archives.is_empty() {
+                return Err(format!("no cached county has {}", layers.join(" or ")).into());
+            }
+            let convert_opts = crate::convert::ConvertOptions {
+                format: crate::convert::ConvertFormat::Gpkg,
+                layers,
+                partition_by_state: false,
+                translate: opts.translate.clone(),
+            };
+            if crate::convert::convert(&archives, out, &convert_opts, &crate::cancel::CancellationToken::new())?.is_some() {
+                return Err(crate::error::NfhlError::Interrupted.into());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//! `info`: everything known about one county, from whichever of the inventory, cache and changelog are given. The
+//! first stop when a county looks stale.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::cache::{self, CacheManifest};
+use crate::extract::LayerInfo;
+use crate::history::{self, ChangelogRecord};
+use crate::inventory::{Inventory, InventoryEntry, ProductKind};
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Product {
+    pub url: String,
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheState {
+    /// The inventory's effective file is cached.
+    Current,
+    /// An older file is cached.
+    Stale,
+    Missing,
+    /// Without an inventory, there's nothing to compare against.
+    Unknown,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CachedFile {
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub effective_date: Option<NaiveDate>,
+    /// When `download-all` fetched it; None for files put in the cache some other way.
+    pub downloaded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CacheInfo {
+    pub state: CacheState,
+    /// The newest cached file.
+    pub file: Option<CachedFile>,
+    /// Older versions kept by `--keep-history`.
+    pub older_files: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CountyInfo {
+    pub fips: String,
+    pub in_inventory: bool,
+    pub effective: Option<Product>,
+    pub preliminary: Option<Product>,
+    pub cache: Option<CacheInfo>,
+    /// The cached file's layers; None without a cache or the `gdal` feature.
+    pub layers: Option<Vec<LayerInfo>>,
+    pub last_change: Option<ChangelogRecord>,
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn county_info(
+    fips: &str,
+    inv: Option<&Inventory>,
+    cache_dir: Option<&Path>,
+    changelog: Option<&Path>,
+) -> Result<CountyInfo, Box<dyn std::error::Error>> {
+    let entry = inv.and_then(|inv| inv.get(fips));
+    let product = |e: &InventoryEntry, kind| e.url(kind).map(|url| Product { url: url.to_string(), date: e.date(kind) });
+    let mut info = CountyInfo {
+        fips: fips.to_string(),
+        in_inventory: entry.is_some(),
+        effective: entry.and_then(|e| product(e, ProductKind::Effective)),
+        preliminary: entry.and_then(|e| product(e, ProductKind::Preliminary)),
+        cache: None,
+        layers: None,
+        last_change: None,
+    };
+
+    if let Some(cache_dir) = cache_dir {
+        let manifest = CacheManifest::load(cache_dir)?;
+        // oldest first
+        let mut archives: Vec<_> = cache::cached_archives(cache_dir)?.into_iter()
+            .filter(|(cached, _)| cached == fips)
+            .map(|(_, archive)| archive)
+            .collect();
+        let newest = archives.pop();
+        let file_name = |archive: &Path| cache::relative_name(cache_dir, archive);
+        let state = match (entry, &newest) {
+            (None, _) => CacheState::Unknown,
+            (Some(_), None) => CacheState::Missing,
+            (Some(entry), Some(_)) if cache_dir.join(manifest.layout.relative_path(fips, &cache::cache_file_name(fips, entry))).exists() => {
+                CacheState::Current
+            }
+            (Some(_), Some(_)) => CacheState::Stale,
+        };
+        let file = match &newest {
+            Some(archive) => {
+                let name = file_name(archive);
+                Some(CachedFile {
+                    size: std::fs::metadata(archive)?.len(),
+                    sha256: sha256_file(archive)?,
+                    effective_date: cache::archive_effective_date(&manifest, fips, archive),
+                    downloaded_at: manifest.entries.get(fips).filter(|cached| archive.ends_with(&cached.file_name)).map(|cached| cached.downloaded_at),
+                    file_name: name,
+                })
+            }
+            None => None,
+        };
+        #[cfg(feature = "gdal")]
+        if let Some(archive) = &newest {
+            match crate::extract::require_archive_gdb_path(archive).and_then(|gdb| crate::extract::inspect_layers(Path::new(&gdb))) {
+                Ok(layers) => info.layers = Some(layers),
+                Err(e) => eprintln!("{}: can't read its layers: {}", archive.display(), e),
+            }
+        }
+        info.cache = Some(CacheInfo { state, file, older_files: archives.iter().map(|archive| file_name(archive)).collect() });
+    }
+
+    if let Some(changelog) = changelog {
+        info.last_change = history::read_changelog(changelog)?.into_iter().rev().find(|record| record.change.fips == fips);
+    }
+    Ok(info)
+}
+
+/// A row per fact, or for JSON the whole record.
+pub fn write_info(out: &mut dyn Write, info: &CountyInfo, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, info)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let date = |date: Option<NaiveDate>| date.map(|d| d.to_string()).unwrap_or_else(|| "undated".to_string());
+    let mut rows: Vec<(String, String)> = vec![
+        ("fips".to_string(), info.fips.clone()),
+        ("in inventory".to_string(), info.in_inventory.to_string()),
+    ];
+    for (name, product) in [("effective", &info.effective), ("preliminary", &info.preliminary)] {
+        match product {
+            Some(product) => {
+                rows.push((format!("{} date", name), date(product.date)));
+                rows.push((format!("{} url", name), product.url.clone()));
+            }
+            None if info.in_inventory => rows.push((name.to_string(), "none".to_string())),
+            None => {}
+        }
+    }
+    if let Some(cache) = &info.cache {
+        rows.push(("cache".to_string(), format!("{:?}", cache.state).to_lowercase()));
+        if let Some(file) = &cache.file {
+            rows.push(("cached file".to_string(), file.file_name.clone()));
+            rows.push(("cached effective date".to_string(), date(file.effective_date)));
+            rows.push(("size".to_string(), file.size.to_string()));
+            rows.push(("sha256".to_string(), file.sha256.clone()));
+            if let Some(downloaded_at) = file.downloaded_at {
+                rows.push(("downloaded at".to_string(), downloaded_at.to_rfc3339()));
+            }
+        }
+        if !cache.older_files.is_empty() {
+            rows.push(("older files".to_string(), cache.older_files.join(" ")));
+        }
+    }
+    if let Some(layers) = &info.layers {
+        let names: Vec<String> = layers.iter().map(|layer| format!("{} ({})", layer.name, layer.feature_count)).collect();
+        rows.push(("layers".to_string(), names.join(", ")));
+    }
+    if let Some(record) = &info.last_change {
+        rows.push(("last change".to_string(), format!("{:?} {} -> {}, seen {}",
+            record.change.kind, record.change.old_date, record.change.new_date, record.observed_at.to_rfc3339())));
+    }
+
+    let headers: Vec<String> = ["field", "value"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = rows.into_iter().map(|(name, value)| vec![name, value]).collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
@@ -0,0 +1,83 @@
+//! The inventory: each state's or county's current NFHL product urls and dates, keyed by 2-digit state or 5-digit
+//! county fips, as `states_inventory` and `counties_inventory` save it and everything else reads it. The model and
+//! its on-disk `format` are in `nfhl_parse`, which has no IO so a browser can use them too; this adds reading files.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+
+pub use nfhl_parse::inventory::*;
+
+use crate::error::{NfhlError, Result};
+
+pub fn read_inventory(path: &Path) -> Result<Inventory> {
+    let f = File::open(path).map_err(NfhlError::io(path))?;
+    format::read(BufReader::new(f)).map_err(NfhlError::parse(path))
+}
+
+/// `--effective-after` and `--effective-before`, for the commands that write inventories.
+#[derive(Debug, Clone, Copy, Default, clap::Args)]
+pub struct EffectiveDates {
+    /// Only keep entries whose effective file is dated on or after this day, e.g. `2020-01-01`.
+    #[clap(long)]
+    pub effective_after: Option<NaiveDate>,
+    /// Only keep entries whose effective file is dated before this day.
+    #[clap(long)]
+    pub effective_before: Option<NaiveDate>,
+}
+
+impl EffectiveDates {
+    pub fn is_set(&self) -> bool {
+        self.effective_after.is_some() || self.effective_before.is_some()
+    }
+
+    /// Whether `entry` is in the range. Entries with no effective file are only kept if no range was given.
+    pub fn contains(&self, entry: &InventoryEntry) -> bool {
+        if !self.is_set() {
+            return true;
+        }
+        entry.effective_date().is_some_and(|date| {
+            self.effective_after.is_none_or(|after| date >= after)
+                && self.effective_before.is_none_or(|before| date < before)
+        })
+    }
+
+    /// Drops the entries of `inv` outside the range.
+    pub fn retain(&self, inv: &mut Inventory) {
+        inv.retain(|_, entry| self.contains(entry));
+    }
+}
+
+/// `inv` by 2-digit state fips: each state's entry, if it has one, with its counties'.
+pub fn split_by_state(inv: &Inventory) -> BTreeMap<String, Inventory> {
+    let mut states: BTreeMap<String, Inventory> = BTreeMap::new();
+    for (fips, entry) in inv {
+        states.entry(fips.state().to_string()).or_default().insert(fips.clone(), entry.clone());
+    }
+    states
+}
+
+/// Writes `inv` into `dir` as a `{state fips}.json` inventory per state, for jobs that only follow a few states. A
+/// state's file is only rewritten if what it holds changed, and then atomically, so anything watching the directory
+/// sees a change only when there is one and never half a file. Returns the files that were written.
+pub fn write_by_state(dir: &Path, inv: &Inventory) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).map_err(NfhlError::io(dir))?;
+    let mut written = Vec::new();
+    for (state, state_inv) in split_by_state(inv) {
+        let path = dir.join(format!("{}.json", state));
+        if path.exists() && read_inventory(&path).is_ok_and(|old| old == state_inv) {
+            continue;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        let f = File::create(&tmp_path).map_err(NfhlError::io(&tmp_path))?;
+        let mut out = BufWriter::new(f);
+        format::write(&mut out, &state_inv).map_err(|e| NfhlError::io(&tmp_path)(e.into()))?;
+        out.flush().map_err(NfhlError::io(&tmp_path))?;
+        std::fs::rename(&tmp_path, &path).map_err(NfhlError::io(&path))?;
+        written.push(path);
+    }
+    Ok(written)
+}
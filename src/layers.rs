@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+#[cfg(feature = "gdal")]
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+#[cfg(feature = "gdal")]
+use crate::{cache, extract};
+use crate::extract::LayerInfo;
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CountyLayers {
+    pub file_name: String,
+    pub layers: Vec<LayerInfo>,
+}
+
+/// Which layers every cached county's geodatabase has, as written by `layers`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LayerInventory {
+    pub generated_at: DateTime<Utc>,
+    pub counties: BTreeMap<String, CountyLayers>,
+    /// Archives that couldn't be read, by fips, with why.
+    pub failures: BTreeMap<String, String>,
+}
+
+/// Opens every cached archive's geodatabase in place (no unzipping) and records its layers. Archives without a
+/// `.gdb`, or which GDAL can't open, are listed under `failures` rather than stopping the walk.
+#[cfg(feature = "gdal")]
+pub fn layer_inventory(cache_dir: &Path) -> Result<LayerInventory, Box<dyn std::error::Error>> {
+    let mut inventory = LayerInventory { generated_at: Utc::now(), ..Default::default() };
+    for (fips, archive) in cache::cached_archives(cache_dir)? {
+        let file_name = archive.file_name().and_then(|f| f.to_str()).unwrap_or_default().to_string();
+        let layers = extract::require_archive_gdb_path(&archive)
+            .and_then(|gdb| extract::inspect_layers(Path::new(&gdb)));
+        match layers {
+            Ok(layers) => {
+                inventory.counties.insert(fips, CountyLayers { file_name, layers });
+            }
+            Err(e) => {
+                eprintln!("failed to read {}: {}", file_name, e);
+                inventory.failures.insert(fips, e.to_string());
+            }
+        }
+    }
+    Ok(inventory)
+}
+
+/// Summarizes a layer inventory per layer: how many counties have it, and how many features they hold between them.
+pub fn write_layer_summary(out: &mut dyn Write, inventory: &LayerInventory, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let mut per_layer: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for county in inventory.counties.values() {
+        for layer in &county.layers {
+            let (counties, features) = per_layer.entry(layer.name.as_str()).or_default();
+            *counties += 1;
+            *features += layer.feature_count;
+        }
+    }
+    if let ReportFormat::Json = format {
+        let summary: BTreeMap<&str, serde_json::Value> = per_layer.iter()
+            .map(|(name, (counties, features))| (*name, serde_json::json!({"counties": counties, "features": features})))
+            .collect();
+        serde_json::to_writer_pretty(&mut *out, &summary)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let total = inventory.counties.len();
+    let headers: Vec<String> = ["layer", "counties", "missing_from", "features"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = per_layer.iter()
+        .map(|(name, (counties, features))| vec![name.to_string(), counties.to_string(), (total - counties).to_string(), features.to_string()])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
@@ -0,0 +1,83 @@
+//! The library behind the `nfhl_util` binary, for embedding inventory and download logic without shelling out to the
+//! CLI. The core is async, on tokio and a [`client::Client`]; [`blocking`] has synchronous wrappers. The usual
+//! entry points:
+//!
+//! - [`nfhl_portal::get_effective_county_products`] and [`msc::get_effective_state_products`] build an
+//!   [`inventory::Inventory`] from FEMA's sites; [`inventory::read_inventory`] loads a saved one.
+//! - [`plan::make_plan`] compares an inventory with a cache directory, and [`download::apply_plan`] carries the plan
+//!   out, with as many downloads in flight as asked for.
+//! - [`source::ProductSource`] wraps the two sites (and a mock) behind one interface.
+//! - [`cache`] has the cache's file naming and its manifest of what was downloaded when.
+//!
+//! Everything else is public too, since the binary is built on it, but is shaped around the CLI's commands.
+
+#![cfg_attr(debug_assertions, allow(dead_code, unused_imports))]
+
+pub mod bigquery;
+pub mod blocking;
+pub mod cache;
+pub mod cancel;
+pub mod client;
+pub mod config;
+pub mod convert;
+pub mod diff;
+pub mod diff_geo;
+pub mod domains;
+pub mod encryption;
+pub mod error;
+pub mod download;
+pub mod extract;
+pub mod feed;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod firmette;
+pub mod fips;
+pub mod gdb_spec;
+pub mod geocode;
+pub mod history;
+pub mod holdings;
+pub mod html_report;
+pub mod http;
+pub mod hydraulics;
+pub mod info;
+pub mod inventory;
+pub mod layers;
+pub mod map_server;
+pub mod markdown_report;
+pub mod merge_geo;
+pub mod migrate;
+pub mod msc;
+pub mod nfhl_portal;
+pub mod panels;
+pub mod pipeline;
+pub mod plan;
+pub mod politeness;
+pub mod postgis;
+pub mod postgres_sink;
+pub mod prelim;
+pub mod publish;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod query;
+pub mod query_batch;
+pub mod recompress;
+pub mod report;
+pub mod response_archive;
+pub mod robots;
+pub mod search;
+pub mod server;
+pub mod shard;
+pub mod signing;
+pub mod sizes;
+pub mod snapshots;
+pub mod source;
+pub mod stac;
+pub mod stats;
+pub mod systemd;
+pub mod task;
+pub mod tiles;
+pub mod validate;
+pub mod verify;
+#[cfg(feature = "vcr")]
+pub mod vcr;
+pub mod watch;
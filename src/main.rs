@@ -1,21 +1,40 @@
 #![cfg_attr(debug_assertions, allow(dead_code, unused_imports))]
 
-use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use serde_json::{json};
-use serde::{Serialize, Deserialize};
-use regex::Regex;
-use scraper::{Html, Selector};
+
+use nfhl_util::cancel::CancellationToken;
+use nfhl_util::client::{Client, ConnectionOptions};
+use nfhl_util::error::NfhlError;
+use nfhl_util::inventory::{read_inventory, EffectiveDates, Inventory};
+use nfhl_util::{
+    bigquery, blocking, cache, config, convert, diff, diff_geo, domains, download, encryption, extract, feed, firmette,
+    fips, gdb_spec, geocode, history, holdings, html_report, hydraulics, info, layers, map_server, markdown_report, merge_geo, migrate, msc,
+    nfhl_portal, panels, pipeline, plan, postgis, postgres_sink, prelim, publish, query, query_batch, recompress, report, robots,
+    search, server, shard, signing, sizes, snapshots, stac, stats, systemd, task, tiles, validate, verify, watch,
+};
+
+use cache::CacheLayout;
+use report::ReportFormat;
 
 #[derive(Debug, Parser)]
 #[clap(name = "nfhl_util")]
 #[clap(author, version, about = "A tool to inventory FEMA FIRM/NFHL files and layers.", long_about = None)]
 struct Cli {
+    /// A `[profile.NAME]` of the config file to take defaults from.
+    #[clap(long, global = true, env = "NFHL_UTIL_PROFILE")]
+    profile: Option<String>,
+    /// Fetch each FEMA host's robots.txt first, and keep to it: no disallowed paths, and at least its crawl delay
+    /// between requests.
+    #[clap(long, global = true, env = "NFHL_UTIL_RESPECT_ROBOTS")]
+    respect_robots: bool,
     #[clap(subcommand)]
     command: Commands,
 }
@@ -25,22 +44,60 @@ enum Commands {
     /// Lists effective NFHL file urls for all states, keyed by 2-digit fips codes.
     #[clap(name = "states_inventory", arg_required_else_help = true)]
     States {
-        /// Where to save the inventory JSON file.
-        #[clap(long, parse(from_os_str))]
-        outfile: PathBuf,
+        /// Where to save the inventory: a JSON file, with `--format postgres` a `postgresql://` connection url, or with
+        /// `--format bigquery` a `[project.]dataset.table`.
+        #[clap(long, required_unless_present = "split-by-state")]
+        outfile: Option<String>,
+        #[clap(long, arg_enum, default_value = "json")]
+        format: InventoryFormat,
         /// A coefficient used to spread out queries to FEMA's servers. Higher number = fewer threads / longer delay between queries.
-        #[clap(long, default_value_t = u8::MAX)]
+        #[clap(long, default_value_t = u8::MAX, env = "NFHL_UTIL_POLITENESS")]
         politeness: u8,
+        /// A minisign secret key to sign the inventory with. The signature is saved next to it as `<outfile>.minisig`.
+        #[clap(long, parse(from_os_str), requires = "outfile")]
+        sign_key: Option<PathBuf>,
+        /// Also (or without `--outfile`, only) write each state's part of the inventory to `<dir>/<state fips>.json`.
+        #[clap(long, parse(from_os_str))]
+        split_by_state: Option<PathBuf>,
+        #[clap(flatten)]
+        effective: EffectiveDates,
+        /// Pick up a run that failed part way, only searching the states it didn't get to. Every run checkpoints
+        /// after each state to `nfhl_util-states.checkpoint.json` in the temp directory.
+        #[clap(long)]
+        resume: bool,
+        /// How many states of each FEMA region to search at once. The regions are searched side by side, and one
+        /// whose searches fail only leaves its own states for `--resume`.
+        #[clap(long, default_value_t = msc::DEFAULT_REGION_CONCURRENCY)]
+        region_concurrency: usize,
+        /// Keep a gzipped, timestamped copy of every response FEMA's sites gave in this directory, indexed in its
+        /// `index.jsonl`.
+        #[clap(long, parse(from_os_str))]
+        archive_responses: Option<PathBuf>,
     },
     /// Lists effective NFHL file urls for all counties, keyed by 5-digit fips codes.
     #[clap(name = "counties_inventory", arg_required_else_help = true)]
     Counties {
-        /// Where to save the inventory JSON file.
-        #[clap(long, parse(from_os_str))]
-        outfile: PathBuf,
+        /// Where to save the inventory: a JSON file, with `--format postgres` a `postgresql://` connection url, or with
+        /// `--format bigquery` a `[project.]dataset.table`.
+        #[clap(long, required_unless_present = "split-by-state")]
+        outfile: Option<String>,
+        #[clap(long, arg_enum, default_value = "json")]
+        format: InventoryFormat,
         /// A coefficient used to spread out requests to FEMA's servers. Higher number = fewer threads / longer delay between requests.
-        #[clap(long, default_value_t = u8::MAX)]
+        #[clap(long, default_value_t = u8::MAX, env = "NFHL_UTIL_POLITENESS")]
         politeness: u8,
+        /// A minisign secret key to sign the inventory with. The signature is saved next to it as `<outfile>.minisig`.
+        #[clap(long, parse(from_os_str), requires = "outfile")]
+        sign_key: Option<PathBuf>,
+        /// Also (or without `--outfile`, only) write each state's part of the inventory to `<dir>/<state fips>.json`.
+        #[clap(long, parse(from_os_str))]
+        split_by_state: Option<PathBuf>,
+        #[clap(flatten)]
+        effective: EffectiveDates,
+        /// Keep a gzipped, timestamped copy of every response FEMA's sites gave in this directory, indexed in its
+        /// `index.jsonl`.
+        #[clap(long, parse(from_os_str))]
+        archive_responses: Option<PathBuf>,
     },
     /// Downloads effective NFHL file urls for all counties, keyed by 5-digit fips codes.
     #[clap(name = "download_all", arg_required_else_help = true)]
@@ -48,243 +105,1910 @@ enum Commands {
         /// The current inventory JSON file.
         inventory: String,
         /// Where to cache files.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// A previous inventory JSON file. Entries which have changed will be re-downloaded, even if the file was already in the cache, unless the cache manifest shows the new file was already fetched.
         #[clap(long, parse(from_os_str))]
+        old_inventory: Option<PathBuf>,
+        /// Whether to delete files from the cache directory which are no longer in the inventory.
+        #[clap(long)]
+        delete: bool,
+        /// With `--delete`, keep the previous file of each county that has a new one, for `diff-geo`.
+        #[clap(long, requires = "delete")]
+        keep_history: bool,
+        /// A coefficient used to spread out requests to FEMA's servers. Higher number = fewer threads / longer delay between requests.
+        #[clap(long, default_value_t = u8::MAX, env = "NFHL_UTIL_POLITENESS")]
+        politeness: u8,
+        #[clap(flatten)]
+        outputs: RunOutputs,
+        /// Only handle this slice of the inventory, e.g. `2/8` for the second of eight workers. Combine the workers'
+        /// `--report`s afterwards with `merge-reports`.
+        #[clap(long)]
+        shard: Option<shard::Shard>,
+        #[clap(flatten)]
+        product: ProductArgs,
+    },
+    /// Works out what `download_all` would download and delete, and saves it as a plan file for review.
+    #[clap(name = "plan", arg_required_else_help = true)]
+    Plan {
+        /// The current inventory JSON file.
+        #[clap(parse(from_os_str))]
+        inventory: PathBuf,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
         cache_dir: PathBuf,
-        /// A previous inventory JSON file. Entries which have changed will be re-downloaded, even if the file was already in the cache.
+        /// A previous inventory JSON file. Entries which have changed will be re-downloaded, even if the file was already in the cache, unless the cache manifest shows the new file was already fetched.
         #[clap(long, parse(from_os_str))]
         old_inventory: Option<PathBuf>,
+        /// Whether to plan deleting files from the cache directory which are no longer in the inventory.
+        #[clap(long)]
+        delete: bool,
+        /// With `--delete`, keep the previous file of each county that has a new one, for `diff-geo`.
+        #[clap(long, requires = "delete")]
+        keep_history: bool,
+        /// Only plan for this slice of the inventory, e.g. `2/8`.
+        #[clap(long)]
+        shard: Option<shard::Shard>,
+        #[clap(flatten)]
+        product: ProductArgs,
+        /// Where to save the plan file.
+        #[clap(long, parse(from_os_str))]
+        outfile: PathBuf,
+    },
+    /// Carries out a plan file written by `plan`, and nothing more.
+    #[clap(name = "apply", arg_required_else_help = true)]
+    Apply {
+        /// The plan file.
+        #[clap(parse(from_os_str))]
+        plan: PathBuf,
+        /// A coefficient used to spread out requests to FEMA's servers. Higher number = fewer threads / longer delay between requests.
+        #[clap(long, default_value_t = u8::MAX, env = "NFHL_UTIL_POLITENESS")]
+        politeness: u8,
+        #[clap(flatten)]
+        outputs: RunOutputs,
+    },
+    /// Combines the `--report`s of a `download_all --shard` run into one.
+    #[clap(name = "merge-reports", arg_required_else_help = true)]
+    MergeReports {
+        /// The JSON reports, one per shard.
+        #[clap(parse(from_os_str), required = true)]
+        reports: Vec<PathBuf>,
+        /// Where to save the merged JSON report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+        /// Where to save an HTML rendering of the merged report.
+        #[clap(long, parse(from_os_str))]
+        report_html: Option<PathBuf>,
+        /// Where to save a Markdown summary of the merged report.
+        #[clap(long, parse(from_os_str))]
+        report_markdown: Option<PathBuf>,
+    },
+    /// Unzips a county's cached archive and lists the layers of the geodatabase inside.
+    #[clap(name = "extract", arg_required_else_help = true)]
+    Extract {
+        /// The 5-digit county fips code.
+        #[clap(long, parse(try_from_str = fips::parse_county))]
+        fips: String,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Where to unzip the archive to.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        /// How to print the layer list (needs the `gdal` feature).
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+    },
+    /// Records which layers every cached county's geodatabase has, with feature counts and schema fingerprints.
+    #[clap(name = "layers", arg_required_else_help = true)]
+    Layers {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Where to save the per-county layer inventory JSON.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        /// How to print the per-layer summary.
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+    },
+    /// Converts cached county geodatabases to another format, one output per county.
+    #[clap(name = "convert", arg_required_else_help = true)]
+    Convert {
+        #[clap(long, arg_enum)]
+        to: convert::ConvertFormat,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The 5-digit fips code of the county to convert.
+        #[clap(long, parse(try_from_str = fips::parse_county), required_unless_present = "all", conflicts_with = "all")]
+        fips: Option<String>,
+        /// Convert every cached county.
+        #[clap(long)]
+        all: bool,
+        /// The directory to write to.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        /// Only convert these layers, e.g. `S_Fld_Haz_Ar,S_BFE`. Defaults to all of them.
+        #[clap(long, use_value_delimiter = true)]
+        layers: Vec<String>,
+        /// With `--to geoparquet`, write each layer as a hive-style dataset partitioned by state
+        /// (`S_Fld_Haz_Ar/state_fips=29/part.parquet`) instead of one file.
+        #[clap(long)]
+        partition_by_state: bool,
+        #[clap(flatten)]
+        translate: convert::TranslateOptions,
+    },
+    /// Merges the cached counties of a state, or the whole cache, into a single dataset.
+    #[clap(name = "merge-geo", arg_required_else_help = true)]
+    MergeGeo {
+        /// The state: its 2-digit fips code or postal code, e.g. 48 or TX for Texas.
+        #[clap(long, parse(try_from_str = fips::parse_state), required_unless_present = "national", conflicts_with = "national")]
+        state: Option<String>,
+        /// Merge every cached county.
+        #[clap(long)]
+        national: bool,
+        #[clap(long, arg_enum, default_value = "gpkg")]
+        to: merge_geo::MergeFormat,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The file to write, e.g. `texas.gpkg`, or for GeoParquet the directory. A merge already there is resumed,
+        /// redoing only the counties whose cached files changed.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        /// Only merge these layers, e.g. `S_Fld_Haz_Ar,S_BFE`. Defaults to all of them.
+        #[clap(long, use_value_delimiter = true)]
+        layers: Vec<String>,
+        #[clap(flatten)]
+        translate: convert::TranslateOptions,
+    },
+    /// Extracts just the BFE lines and/or cross sections of cached counties, per county or merged.
+    #[clap(name = "extract-layer", arg_required_else_help = true)]
+    ExtractLayer {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// `S_BFE`, `S_XS`, or both (comma separated).
+        #[clap(long = "layer", arg_enum, ignore_case = true, use_value_delimiter = true, required = true)]
+        layers: Vec<hydraulics::HydraulicLayer>,
+        /// The state: its 2-digit fips code or postal code.
+        #[clap(long, parse(try_from_str = fips::parse_state), required_unless_present = "national", conflicts_with = "national")]
+        state: Option<String>,
+        /// Every cached county.
+        #[clap(long)]
+        national: bool,
+        /// Merge the counties into one dataset, with a `source_fips` column, instead of a GeoPackage per county.
+        #[clap(long)]
+        merge: bool,
+        /// The format to merge to.
+        #[clap(long, arg_enum, default_value = "gpkg", requires = "merge")]
+        to: merge_geo::MergeFormat,
+        /// The directory for the per-county GeoPackages, or with `--merge`, the file (directory for GeoParquet).
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        #[clap(flatten)]
+        translate: convert::TranslateOptions,
+    },
+    /// Merges every cached county's FIRM panel index (`S_FIRM_Pan`) into one national panel layer.
+    #[clap(name = "panel-index", arg_required_else_help = true)]
+    PanelIndex {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Only the counties of this state (2-digit fips code).
+        #[clap(long, parse(try_from_str = fips::parse_state))]
+        state: Option<String>,
+        /// The GeoPackage to write. One already there is updated, redoing only the counties whose files changed.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Dumps the coded-value domains of a cached county's database (flood zones, zone subtypes, ...) with the fields using them.
+    #[clap(name = "export-domains", arg_required_else_help = true)]
+    ExportDomains {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The 5-digit fips code of the county.
+        #[clap(long, parse(try_from_str = fips::parse_county))]
+        fips: String,
+        #[clap(long, arg_enum, default_value = "csv")]
+        format: ReportFormat,
+        /// Where to write the domains. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Checks cached geodatabases against FEMA's FIRM database specification.
+    #[clap(name = "validate-gdb", arg_required_else_help = true)]
+    ValidateGdb {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The 5-digit fips code of the county, or 2 digits for a state's counties.
+        #[clap(long, parse(try_from_str = fips::parse_fips_prefix), required_unless_present = "all", conflicts_with = "all")]
+        fips: Option<String>,
+        /// Every cached county.
+        #[clap(long)]
+        all: bool,
+        /// Also check geometries: null or invalid ones, overlapping flood zones and gaps inside the county boundary.
+        #[clap(long)]
+        geometry: bool,
+        /// Where to write the features failing the geometry checks, as GeoJSON.
+        #[clap(long, parse(from_os_str), requires = "geometry")]
+        geojson_dir: Option<PathBuf>,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to write the QC report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Checks cached archives against the sizes and SHA-256s they were downloaded with, and that they're intact zips.
+    #[clap(name = "verify", arg_required_else_help = true)]
+    Verify {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Only the counties whose fips starts with this, e.g. 48 for Texas's. Defaults to every cached county.
+        #[clap(long, parse(try_from_str = fips::parse_fips_prefix))]
+        fips: Option<String>,
+        /// How many files to check at once. Defaults to the number of CPUs.
+        #[clap(long)]
+        verify_workers: Option<usize>,
+        /// Also write the files' SHA-256s here, in `sha256sum`'s format, for checking a copy of the cache with
+        /// `sha256sum -c`.
+        #[clap(long, parse(from_os_str))]
+        checksums: Option<PathBuf>,
+        /// Leave files that fail where they are, instead of moving them to `<cache-dir>/.quarantine/` for the next
+        /// `download_all` to fetch again.
+        #[clap(long)]
+        no_quarantine: bool,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to write the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Lists the cached counties: each one's newest file, effective date, size and status, and whether the inventory
+    /// has a newer one.
+    #[clap(name = "ls", arg_required_else_help = true)]
+    Ls {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// A county inventory JSON file, to show which cached files it has a newer version of.
+        #[clap(long, parse(from_os_str))]
+        inventory: Option<PathBuf>,
+        /// Only this state's counties (2-digit fips), or those whose fips starts with this.
+        #[clap(long, parse(try_from_str = fips::parse_fips_prefix))]
+        state: Option<String>,
+        /// Only the counties the inventory has a newer file for.
+        #[clap(long, requires = "inventory")]
+        stale: bool,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to write the listing. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Moves a cache's files to another layout, e.g. from everything in one directory to a directory per state.
+    /// Each file is hardlinked to its new place and checked before the old name is removed, so it's safe to stop
+    /// and run again.
+    #[clap(name = "migrate-cache", arg_required_else_help = true)]
+    MigrateCache {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The layout the cache has now.
+        #[clap(long, arg_enum, default_value = "legacy")]
+        from: CacheLayout,
+        #[clap(long, arg_enum)]
+        to: CacheLayout,
+    },
+    /// Flood zone areas (by zone, SFHA and floodway) of cached counties.
+    #[clap(name = "stats", arg_required_else_help = true)]
+    Stats {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The 5-digit fips code of the county.
+        #[clap(long, parse(try_from_str = fips::parse_county), required_unless_present = "all", conflicts_with = "all")]
+        fips: Option<String>,
+        /// Every cached county.
+        #[clap(long)]
+        all: bool,
+        /// Report the SFHA area per census tract (or block group) instead, from the geographies in `--tiger`.
+        #[clap(long, requires = "tiger")]
+        by_tract: bool,
+        /// A file of census tracts or block groups, e.g. TIGER/Line shapefiles or a GeoPackage of them.
+        #[clap(long, parse(from_os_str))]
+        tiger: Option<PathBuf>,
+        /// The `--tiger` field holding the full GEOID.
+        #[clap(long, default_value = "GEOID")]
+        geoid_field: String,
+        /// A `--tiger` field holding population, for population-weighted exposure.
+        #[clap(long)]
+        population_field: Option<String>,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to write the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Loads layers of the cached counties into PostGIS.
+    #[clap(name = "load-postgis", arg_required_else_help = true)]
+    LoadPostgis {
+        /// Where to load to: a `postgresql://` url or libpq connection string.
+        #[clap(long, env = "NFHL_POSTGIS_DSN")]
+        dsn: String,
+        /// The schema to put the layer tables in; created if need be.
+        #[clap(long, default_value = "nfhl")]
+        schema: String,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Only load this county (5-digit fips), or with two digits, this state's counties.
+        #[clap(long, parse(try_from_str = fips::parse_fips_prefix))]
+        fips: Option<String>,
+        /// Only load these layers, e.g. `S_Fld_Haz_Ar,S_BFE`. Defaults to all of them.
+        #[clap(long, use_value_delimiter = true)]
+        layers: Vec<String>,
+        /// Only (re-)load the counties that are new or were re-issued since they were loaded, replacing their rows,
+        /// instead of reloading the tables from scratch.
+        #[clap(long)]
+        upsert: bool,
+        #[clap(flatten)]
+        translate: convert::TranslateOptions,
+    },
+    /// Makes vector tiles (PMTiles or MBTiles) from the cache or a merged dataset.
+    #[clap(name = "tiles", arg_required_else_help = true)]
+    Tiles {
+        /// Tile every cached county from here.
+        #[clap(long, parse(from_os_str), required_unless_present = "input", conflicts_with = "input")]
+        cache_dir: Option<PathBuf>,
+        /// Tile this dataset instead, e.g. a `merge-geo` GeoPackage.
+        #[clap(long, parse(from_os_str))]
+        input: Option<PathBuf>,
+        /// The layers to tile, e.g. `S_Fld_Haz_Ar`.
+        #[clap(long, use_value_delimiter = true, required = true)]
+        layers: Vec<String>,
+        /// The file to write, ending in `.pmtiles` or `.mbtiles`.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        #[clap(long, default_value = "4")]
+        min_zoom: u8,
+        /// Past this, web maps overzoom the top tiles.
+        #[clap(long, default_value = "14")]
+        max_zoom: u8,
+    },
+    /// Lists the changes between two inventory JSON files.
+    #[clap(name = "diff", arg_required_else_help = true)]
+    Diff {
+        /// The older inventory JSON file.
+        #[clap(parse(from_os_str))]
+        old_inventory: PathBuf,
+        /// The newer inventory JSON file.
+        #[clap(parse(from_os_str))]
+        new_inventory: PathBuf,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to save the list of changes. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+        /// A JSONL changelog to append detected changes to.
+        #[clap(long, parse(from_os_str))]
+        changelog: Option<PathBuf>,
+    },
+    /// Acres of flood zone change between the previous and current cached versions of a county, e.g. X to AE.
+    #[clap(name = "diff-geo", arg_required_else_help = true)]
+    DiffGeo {
+        /// Where files are cached. The previous version is there if downloads ran with `--keep-history`.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The 5-digit fips code of the county.
+        #[clap(long, parse(try_from_str = fips::parse_county))]
+        fips: String,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to write the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Flood zone and BFE changes from a county's effective database to its preliminary one.
+    #[clap(name = "compare-prelim", arg_required_else_help = true)]
+    ComparePrelim {
+        /// Where files are cached. The preliminary file is downloaded into its `preliminary/` directory.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The 5-digit fips code of the county.
+        #[clap(long, parse(try_from_str = fips::parse_county))]
+        fips: String,
+        /// An inventory JSON file with the county's preliminary file url.
+        #[clap(long, parse(from_os_str), required_unless_present = "prelim")]
+        inventory: Option<PathBuf>,
+        /// A preliminary database zip already on disk, instead of the inventory's.
+        #[clap(long, parse(from_os_str))]
+        prelim: Option<PathBuf>,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to write the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Adds an inventory to a snapshot store, keeping only what changed since the store's latest snapshot.
+    #[clap(name = "snapshot", arg_required_else_help = true)]
+    Snapshot {
+        /// The inventory JSON file.
+        #[clap(parse(from_os_str))]
+        inventory: PathBuf,
+        /// The JSONL snapshot store, created by the first snapshot.
+        #[clap(long, parse(from_os_str))]
+        store: PathBuf,
+        /// When the inventory was taken, as an RFC 3339 time, for adding snapshots kept elsewhere. Defaults to now.
+        #[clap(long)]
+        taken_at: Option<chrono::DateTime<chrono::Utc>>,
+    },
+    /// Rebuilds an inventory from a snapshot store as it was at some point.
+    #[clap(name = "materialize", arg_required_else_help = true)]
+    Materialize {
+        /// The JSONL snapshot store written by `snapshot`.
+        #[clap(long, parse(from_os_str))]
+        store: PathBuf,
+        /// The latest snapshot at or before this date (the whole day, in UTC) or RFC 3339 time. Defaults to the
+        /// latest there is.
+        #[clap(long, parse(try_from_str = snapshots::parse_as_of))]
+        as_of: Option<chrono::DateTime<chrono::Utc>>,
+        /// Where to save the inventory JSON. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+        #[clap(flatten)]
+        effective: EffectiveDates,
+    },
+    /// Shows every change to a county's NFHL data recorded in a changelog.
+    #[clap(name = "history", arg_required_else_help = true)]
+    History {
+        /// The JSONL changelog written by `download_all` or `diff`.
+        #[clap(long, parse(from_os_str))]
+        changelog: PathBuf,
+        /// The fips codes to show (5-digit counties or 2-digit states). Shows everything if omitted.
+        #[clap(long, parse(try_from_str = fips::parse_fips), use_value_delimiter = true)]
+        fips: Vec<String>,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to save the history. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// Adds the changes between two inventory JSON files to an Atom feed, one entry per change.
+    #[clap(name = "publish-feed", arg_required_else_help = true)]
+    PublishFeed {
+        /// The older inventory JSON file.
+        #[clap(long, parse(from_os_str))]
+        old_inventory: PathBuf,
+        /// The newer inventory JSON file.
+        #[clap(long, parse(from_os_str))]
+        inventory: PathBuf,
+        /// The Atom feed file to update. Created if it doesn't exist.
+        #[clap(long, parse(from_os_str))]
+        feed: PathBuf,
+        /// Only publish changes for these fips codes (5-digit counties or 2-digit states).
+        #[clap(long, parse(try_from_str = fips::parse_fips), use_value_delimiter = true)]
+        fips: Vec<String>,
+        /// The feed's title.
+        #[clap(long, default_value = "NFHL inventory changes")]
+        title: String,
+        /// The url the feed will be published at.
+        #[clap(long)]
+        link: Option<String>,
+        /// The most entries to keep in the feed; older ones are dropped.
+        #[clap(long, default_value_t = 500)]
+        max_entries: usize,
+    },
+    /// Writes a static STAC catalog of the cache: a collection with an item per cached county file.
+    #[clap(name = "publish-stac", arg_required_else_help = true)]
+    PublishStac {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The directory to write the catalog into.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        /// The url the cache directory is served at, e.g. `https://data.example.org/nfhl/`. Asset hrefs are local
+        /// paths without it.
+        #[clap(long)]
+        base_url: Option<String>,
+        /// GeoParquet from `convert` or `merge-geo` to list as collection assets.
+        #[clap(long, parse(from_os_str))]
+        geoparquet_dir: Option<PathBuf>,
+        /// The url `--geoparquet-dir` is served at.
+        #[clap(long, requires = "geoparquet-dir")]
+        geoparquet_base_url: Option<String>,
+    },
+    /// Periodically refreshes the county inventory, downloads whatever changed, and sends notifications.
+    #[clap(name = "watch", arg_required_else_help = true)]
+    Watch {
+        /// How long between refreshes, e.g. `24h` or `90m`.
+        #[clap(long, parse(try_from_str = humantime::parse_duration), default_value = "24h")]
+        interval: Duration,
+        /// Refresh on a cron schedule instead of an interval, e.g. "0 3 * * SUN".
+        #[clap(long, conflicts_with = "interval")]
+        schedule: Option<String>,
+        /// The IANA time zone the schedule is evaluated in, e.g. America/Chicago.
+        #[clap(long, default_value = "UTC", requires = "schedule")]
+        timezone: String,
+        /// Where to cache files.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Where to keep timestamped inventory snapshots and run reports. The newest is also saved as `latest.json`.
+        #[clap(long, parse(from_os_str))]
+        inventory_dir: PathBuf,
         /// Whether to delete files from the cache directory which are no longer in the inventory.
         #[clap(long)]
         delete: bool,
+        /// With `--delete`, keep the previous file of each county that has a new one, for `diff-geo`.
+        #[clap(long, requires = "delete")]
+        keep_history: bool,
         /// A coefficient used to spread out requests to FEMA's servers. Higher number = fewer threads / longer delay between requests.
-        #[clap(long, default_value_t = u8::MAX)]
+        #[clap(long, default_value_t = u8::MAX, env = "NFHL_UTIL_POLITENESS")]
+        politeness: u8,
+        /// A url to POST a JSON summary to whenever a refresh finds changes or failures. May be repeated.
+        #[clap(long, env = "NFHL_UTIL_NOTIFY_URL", use_value_delimiter = true)]
+        notify_url: Vec<String>,
+        /// A JSONL changelog to append detected changes to.
+        #[clap(long, parse(from_os_str))]
+        changelog: Option<PathBuf>,
+        /// A message bus to emit an event to for every change and completed download: `kafka://host:9092/topic` or
+        /// `nats://host:4222/subject`. May be repeated.
+        #[clap(long, env = "NFHL_UTIL_PUBLISH", use_value_delimiter = true)]
+        publish: Vec<String>,
+        /// A `postgresql://` url to record each cycle's inventory snapshot and run in.
+        #[clap(long, env = "NFHL_UTIL_REPORT_POSTGRES", hide_env_values = true)]
+        report_postgres: Option<String>,
+    },
+    /// Serves the inventory, changelog and cache over a small JSON/HTTP API.
+    #[clap(name = "serve", arg_required_else_help = true)]
+    Serve {
+        /// The port to listen on.
+        #[clap(long, default_value_t = 8080)]
+        port: u16,
+        /// The address to listen on. Use 0.0.0.0 to accept connections from other hosts.
+        #[clap(long, default_value = "127.0.0.1")]
+        bind: String,
+        /// The inventory JSON file to serve, e.g. the `latest.json` kept by `watch`.
+        #[clap(long, parse(from_os_str))]
+        inventory: PathBuf,
+        /// The cache directory to serve files from.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The JSONL changelog backing `/changes`.
+        #[clap(long, parse(from_os_str))]
+        changelog: Option<PathBuf>,
+        /// How many requests to handle at once.
+        #[clap(long, default_value_t = 4)]
+        threads: usize,
+        /// The bearer token `POST /refresh` must present. Refreshing over HTTP is disabled without one.
+        #[clap(long, env = "NFHL_UTIL_REFRESH_TOKEN", hide_env_values = true)]
+        refresh_token: Option<String>,
+        /// A coefficient used to spread out requests to FEMA's servers during refreshes. Higher number = fewer threads / longer delay between requests.
+        #[clap(long, default_value_t = u8::MAX, env = "NFHL_UTIL_POLITENESS")]
+        politeness: u8,
+    },
+    /// Serves PMTiles and GeoPackage layers (as OGC API - Features) for an internal flood map.
+    #[clap(name = "serve-map", arg_required_else_help = true)]
+    ServeMap {
+        /// The port to listen on.
+        #[clap(long, default_value_t = 8081)]
+        port: u16,
+        /// The address to listen on. Use 0.0.0.0 to accept connections from other hosts.
+        #[clap(long, default_value = "127.0.0.1")]
+        bind: String,
+        /// PMTiles files to serve, e.g. from `tiles`.
+        #[clap(long, parse(from_os_str), use_value_delimiter = true, required_unless_present = "gpkg")]
+        tiles: Vec<PathBuf>,
+        /// GeoPackages (e.g. from `merge-geo`) whose layers to serve as OGC API - Features collections.
+        #[clap(long, parse(from_os_str), use_value_delimiter = true)]
+        gpkg: Vec<PathBuf>,
+        /// How many requests to handle at once.
+        #[clap(long, default_value_t = 4)]
+        threads: usize,
+    },
+    /// Checks a file (e.g. an inventory or cache manifest) against its minisign signature.
+    #[clap(name = "verify-signature", arg_required_else_help = true)]
+    VerifySignature {
+        /// The signed file.
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+        /// The minisign public key to check against.
+        #[clap(long, parse(from_os_str))]
+        public_key: PathBuf,
+        /// The signature file. Defaults to `<file>.minisig`.
+        #[clap(long, parse(from_os_str))]
+        signature: Option<PathBuf>,
+    },
+    /// Reports derived from an existing inventory JSON file.
+    #[clap(name = "report", arg_required_else_help = true)]
+    Report {
+        #[clap(subcommand)]
+        command: ReportCommands,
+    },
+    /// Everything known about one county: its products, cached file, layers and last change.
+    #[clap(name = "info", arg_required_else_help = true)]
+    Info {
+        /// The 5-digit fips code of the county.
+        #[clap(parse(try_from_str = fips::parse_county))]
+        fips: String,
+        /// A county inventory JSON file, for its effective and preliminary products.
+        #[clap(long, parse(from_os_str))]
+        inventory: Option<PathBuf>,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
+        /// A JSONL changelog, for its last change.
+        #[clap(long, parse(from_os_str))]
+        changelog: Option<PathBuf>,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+    },
+    /// Finds counties and communities by name, e.g. "St. Louis", or by the start of a fips code or CID.
+    #[clap(name = "search", arg_required_else_help = true)]
+    Search {
+        query: String,
+        /// Where files are cached. The names come from the cached databases.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// A county inventory JSON file, for each match's effective and preliminary dates.
+        #[clap(long, parse(from_os_str))]
+        inventory: Option<PathBuf>,
+        /// At most this many matches, best first.
+        #[clap(long, default_value = "20")]
+        limit: usize,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+    },
+    /// Downloads the FIRMette (MSC's official one-page FIRM excerpt) at a point, address or panel.
+    #[clap(name = "firmette", arg_required_else_help = true)]
+    Firmette {
+        #[clap(long, allow_hyphen_values = true, requires = "lon", required_unless_present_any = &["address", "panel"])]
+        lat: Option<f64>,
+        #[clap(long, allow_hyphen_values = true, requires = "lat")]
+        lon: Option<f64>,
+        /// A one-line address to geocode.
+        #[clap(long, conflicts_with_all = &["lat", "panel"])]
+        address: Option<String>,
+        /// A FIRM panel number, e.g. `22071C0230F`, found in the cached county's panel index.
+        #[clap(long, requires = "cache-dir", conflicts_with = "lat")]
+        panel: Option<String>,
+        /// Where files are cached, for `--panel`.
+        #[clap(long, parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
+        /// Who geocodes `--address`.
+        #[clap(long, arg_enum, default_value = "census")]
+        geocoder: geocode::GeocoderKind,
+        #[clap(long, arg_enum, default_value = "pdf")]
+        format: firmette::FirmetteFormat,
+        /// Where to save the FIRMette.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Flood zone lookups.
+    #[clap(name = "query", arg_required_else_help = true)]
+    Query {
+        #[clap(subcommand)]
+        command: QueryCommands,
+    },
+    /// Prints a completion script for a shell, e.g. `nfhl_util completions bash > /etc/bash_completion.d/nfhl_util`.
+    #[clap(name = "completions", arg_required_else_help = true)]
+    Completions {
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Generates man pages: nfhl_util(1), and one per subcommand like nfhl_util-download_all(1).
+    #[clap(name = "mangen")]
+    Mangen {
+        /// The directory to write them to, e.g. /usr/local/share/man/man1. Defaults to printing nfhl_util(1) to
+        /// stdout.
+        #[clap(long, parse(from_os_str))]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum QueryCommands {
+    /// The flood zone, SFHA flag, static BFE and FIRM panel at a point, from the cached databases or FEMA's map service.
+    #[clap(name = "point", arg_required_else_help = true)]
+    Point {
+        #[clap(long, allow_hyphen_values = true)]
+        lat: f64,
+        #[clap(long, allow_hyphen_values = true)]
+        lon: f64,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), required_unless_present = "online")]
+        cache_dir: Option<PathBuf>,
+        /// The county the point is in (5-digit fips code), if known, to skip finding it.
+        #[clap(long, parse(try_from_str = fips::parse_county), conflicts_with = "online")]
+        fips: Option<String>,
+        /// Ask FEMA's NFHL map service instead of the cache.
+        #[clap(long, conflicts_with = "cache-dir")]
+        online: bool,
+        /// Answer with the fields of a Standard Flood Hazard Determination Form: community, map number, panel,
+        /// suffix, map date, zone and SFHA.
+        #[clap(long)]
+        determination: bool,
+        /// Where to write the JSON answer. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// `query point` at an address, geocoded first.
+    #[clap(name = "address", arg_required_else_help = true)]
+    Address {
+        /// A one-line address, e.g. "123 Main St, Houston TX".
+        address: String,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), required_unless_present = "online")]
+        cache_dir: Option<PathBuf>,
+        /// Ask FEMA's NFHL map service instead of the cache.
+        #[clap(long, conflicts_with = "cache-dir")]
+        online: bool,
+        /// Who geocodes the address.
+        #[clap(long, arg_enum, default_value = "census")]
+        geocoder: geocode::GeocoderKind,
+        /// Where to write the JSON answer. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// The flood zones a polygon (e.g. a parcel) intersects, with the fraction of it in each.
+    #[clap(name = "polygon", arg_required_else_help = true)]
+    Polygon {
+        /// The polygon(s), dissolved into one.
+        #[clap(long, parse(from_os_str))]
+        geojson: PathBuf,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// Only intersect with this county (5-digit fips code), rather than every cached county it overlaps.
+        #[clap(long, parse(try_from_str = fips::parse_county))]
+        fips: Option<String>,
+        #[clap(long, arg_enum, default_value = "json")]
+        format: ReportFormat,
+        /// Where to write the answer. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// The FIRM panel number, suffix and effective date at a point or address, for citing on forms.
+    #[clap(name = "panel", arg_required_else_help = true)]
+    Panel {
+        #[clap(long, allow_hyphen_values = true, requires = "lon", required_unless_present = "address")]
+        lat: Option<f64>,
+        #[clap(long, allow_hyphen_values = true, requires = "lat")]
+        lon: Option<f64>,
+        /// A one-line address to geocode, instead of `--lat` and `--lon`.
+        #[clap(long, conflicts_with = "lat")]
+        address: Option<String>,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), required_unless_present = "online")]
+        cache_dir: Option<PathBuf>,
+        /// The county the point is in (5-digit fips code), if known, to skip finding it.
+        #[clap(long, parse(try_from_str = fips::parse_county), conflicts_with = "online")]
+        fips: Option<String>,
+        /// Ask FEMA's NFHL map service instead of the cache.
+        #[clap(long, conflicts_with = "cache-dir")]
+        online: bool,
+        /// Who geocodes `--address`.
+        #[clap(long, arg_enum, default_value = "census")]
+        geocoder: geocode::GeocoderKind,
+        /// Where to write the JSON answer. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// `query point` as of a past date, in the version of the county then in effect among those `--keep-history`
+    /// kept.
+    #[clap(name = "zone-history", arg_required_else_help = true)]
+    ZoneHistory {
+        #[clap(long, allow_hyphen_values = true)]
+        lat: f64,
+        #[clap(long, allow_hyphen_values = true)]
+        lon: f64,
+        /// The day to answer for, e.g. `2015-06-01`.
+        #[clap(long)]
+        as_of: chrono::NaiveDate,
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The county the point is in (5-digit fips code), if known, to skip finding it.
+        #[clap(long, parse(try_from_str = fips::parse_county))]
+        fips: Option<String>,
+        /// Where to write the JSON answer. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// `query point` for every row of a CSV, adding flood zone, BFE and FIRM effective date columns.
+    #[clap(name = "batch", arg_required_else_help = true)]
+    Batch {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// The CSV of points, with a header row.
+        #[clap(long = "in", parse(from_os_str))]
+        input: PathBuf,
+        /// The CSV to write. If it's there from an interrupted run, only the rows not in it yet are looked up.
+        #[clap(long, parse(from_os_str))]
+        out: PathBuf,
+        /// The latitude column. Defaults to one named `lat` or `latitude`.
+        #[clap(long)]
+        lat_column: Option<String>,
+        /// The longitude column. Defaults to one named `lon`, `lng`, `long` or `longitude`.
+        #[clap(long)]
+        lon_column: Option<String>,
+        /// How many counties to work on at once. Defaults to the number of CPUs.
+        #[clap(long)]
+        jobs: Option<usize>,
+    },
+}
+
+/// Which product `download_all` and `plan` work on.
+#[derive(Debug, Args)]
+struct ProductArgs {
+    /// What to mirror: the inventory's effective or preliminary databases, or the FIS reports, LOMCs, historic
+    /// FIRMs and FIS reports, Flood Risk Databases, or just the historic FIRM panel scans MSC lists for its counties.
+    /// Each is cached in its own
+    /// `<cache-dir>/<product>/`, apart from effective files, which are in the cache directory itself.
+    #[clap(long, arg_enum, default_value = "effective")]
+    product: plan::Product,
+    /// Only search MSC for these counties' files (5-digit counties or 2-digit states), rather than every county in
+    /// the inventory. Only for the products MSC lists.
+    #[clap(long, parse(try_from_str = fips::parse_fips), use_value_delimiter = true)]
+    fips: Vec<String>,
+    /// Also HEAD every cached file the inventory hasn't changed, `--politeness` apart, and download again the ones
+    /// FEMA now serves at a different size, reporting them as `republished` changes. With `--keep-history` the
+    /// replaced copy is kept. Only for the inventory's products.
+    #[clap(long)]
+    check_republished: bool,
+    /// Rewrite each downloaded zip's members with zstd, to take less space. FEMA's checksum stays in the manifest,
+    /// and the geo commands and `serve` read the files as before. Only for the products that are zips.
+    #[clap(long, arg_enum)]
+    recompress: Option<recompress::Recompression>,
+}
+
+impl ProductArgs {
+    /// The plan for the product: from the inventory, or from searching MSC for each of the inventory's counties,
+    /// `politeness` apart.
+    #[allow(clippy::too_many_arguments)]
+    fn plan(
+        &self,
+        inv: &Inventory,
+        old_inv: Option<&Inventory>,
+        cache_dir: &Path,
+        delete: bool,
+        keep_history: bool,
+        shard: Option<shard::Shard>,
         politeness: u8,
+    ) -> Result<plan::Plan, Box<dyn std::error::Error>> {
+        if let Some(recompress) = self.recompress.filter(|_| !self.product.is_archive()) {
+            return Err(format!("--recompress {} only applies to zips, which {} files aren't", recompress.as_str(), self.product.as_str()).into());
+        }
+        if self.product.inventory_kind().is_some() {
+            if !self.fips.is_empty() {
+                return Err("--fips only applies to the products MSC lists; use --shard to split the inventory's".into());
+            }
+            let mut plan = plan::make_product_plan(inv, old_inv, cache_dir, self.product, delete, keep_history, shard)?;
+            if self.check_republished {
+                let delay = download::politeness_delay(politeness);
+                let republished = blocking::find_republished(&plan, inv, delay, &CancellationToken::new())?;
+                plan::add_republished(&mut plan, &republished, keep_history);
+            }
+            plan.recompress = self.recompress;
+            return Ok(plan);
+        }
+        if self.check_republished {
+            return Err(format!("--check-republished only applies to the inventory's products; {} files are \
+                compared with what MSC lists", self.product.as_str()).into());
+        }
+        if old_inv.is_some() || keep_history {
+            return Err(format!("--old-inventory and --keep-history only apply to the inventory's products; {} files \
+                are compared with the cache", self.product.as_str()).into());
+        }
+        fips::check_known(&self.fips, inv.keys().map(|fips| fips.as_str()))?;
+        let wanted = |fips: &str| self.fips.is_empty() || self.fips.iter().any(|prefix| fips.starts_with(prefix.as_str()));
+        let mut counties: Vec<&str> = inv.keys()
+            .filter(|fips| !fips.is_state() && wanted(fips) && shard.is_none_or(|shard| shard.contains(fips)))
+            .map(|fips| fips.as_str())
+            .collect();
+        counties.sort_unstable();
+        let delay = download::politeness_delay(politeness);
+        let files = blocking::list_product_files(&counties, self.product, delay, &CancellationToken::new())?;
+        let mut plan = plan::make_listed_plan(&files, cache_dir, self.product, delete, shard)?;
+        plan.recompress = self.recompress;
+        Ok(plan)
+    }
+}
+
+/// What to do with the results of a `download_all` or `apply` run.
+#[derive(Debug, Args)]
+struct RunOutputs {
+    /// Where to save a JSON report of the run.
+    #[clap(long, parse(from_os_str))]
+    report: Option<PathBuf>,
+    /// Where to save a self-contained HTML report of the run (changes, failures, throughput, cache stats).
+    #[clap(long, parse(from_os_str))]
+    report_html: Option<PathBuf>,
+    /// Where to save a Markdown summary of the run, e.g. for a PR comment.
+    #[clap(long, parse(from_os_str))]
+    report_markdown: Option<PathBuf>,
+    /// A `postgresql://` url to record the run, its downloads and the detected changes in.
+    #[clap(long, env = "NFHL_UTIL_REPORT_POSTGRES", hide_env_values = true)]
+    report_postgres: Option<String>,
+    /// A BigQuery `[project.]dataset.table` to append the detected changes to.
+    #[clap(long)]
+    report_bigquery: Option<bigquery::TableRef>,
+    /// A JSONL changelog to append detected changes to.
+    #[clap(long, parse(from_os_str))]
+    changelog: Option<PathBuf>,
+    /// A minisign secret key to sign the cache manifest with, saved as `manifest.json.minisig` in the cache.
+    #[clap(long, parse(from_os_str))]
+    sign_key: Option<PathBuf>,
+    /// A message bus to emit an event to for every change and completed download: `kafka://host:9092/topic` or
+    /// `nats://host:4222/subject`. May be repeated.
+    #[clap(long, env = "NFHL_UTIL_PUBLISH", use_value_delimiter = true)]
+    publish: Vec<String>,
+    /// For workflow orchestrators: exit 0 only if the cache ends up matching the inventory, and 75 if downloads
+    /// failed or were interrupted so the task should be retried. Also writes a state file (see `--task-state-file`).
+    #[clap(long)]
+    task_mode: bool,
+    /// Where `--task-mode` writes its state summary. Defaults to `task_state.json` in the cache directory.
+    #[clap(long, parse(from_os_str), requires = "task-mode")]
+    task_state_file: Option<PathBuf>,
+    /// Unzip each county's archive into `<dir>/<fips>/` as soon as it's downloaded, alongside the downloads that
+    /// are still going.
+    #[clap(long, parse(from_os_str))]
+    extract: Option<PathBuf>,
+    /// With `--extract`, convert each county to this format (like `convert --to`) instead of unzipping it. Needs
+    /// the `gdal` feature.
+    #[clap(long, arg_enum, requires = "extract")]
+    extract_to: Option<convert::ConvertFormat>,
+    /// How many counties `--extract` works on at once. Defaults to the number of CPUs.
+    #[clap(long, requires = "extract")]
+    extract_workers: Option<usize>,
+    /// With `--extract` of Flood Risk Databases, only unzip these grids, into `<dir>/<fips>/<grid>/`: flood depth,
+    /// water surface elevation, and percent annual chance.
+    #[clap(long, arg_enum, use_value_delimiter = true, requires = "extract")]
+    frd_grids: Vec<extract::FrdGrid>,
+}
+
+#[derive(Debug, Subcommand)]
+enum ReportCommands {
+    /// Buckets counties by the age of their effective NFHL file, per state.
+    #[clap(name = "age", arg_required_else_help = true)]
+    Age {
+        /// The county inventory JSON file.
+        #[clap(long, parse(from_os_str))]
+        inventory: PathBuf,
+        /// Bucket boundaries, in years.
+        #[clap(long, use_value_delimiter = true, default_value = "5,15")]
+        buckets: Vec<u32>,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to save the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
     },
+    /// Counts counties with effective data, preliminary data, both, or neither, per state.
+    #[clap(name = "summary", arg_required_else_help = true)]
+    Summary {
+        /// The county inventory JSON file.
+        #[clap(long, parse(from_os_str))]
+        inventory: PathBuf,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to save the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+    /// How much space the cache takes per state and county, how it's grown since the last run, and how much the
+    /// inventory's pending downloads will add.
+    #[clap(name = "sizes", arg_required_else_help = true)]
+    Sizes {
+        /// Where files are cached.
+        #[clap(long, parse(from_os_str), env = "NFHL_UTIL_CACHE_DIR")]
+        cache_dir: PathBuf,
+        /// A county inventory JSON file, to project the size of the downloads it has pending.
+        #[clap(long, parse(from_os_str))]
+        inventory: Option<PathBuf>,
+        /// The last `download_all` run's `--report`, to show what's changed since.
+        #[clap(long, parse(from_os_str))]
+        last_report: Option<PathBuf>,
+        /// How many of the largest counties to list.
+        #[clap(long, default_value = "10")]
+        largest: usize,
+        #[clap(long, arg_enum, default_value = "table")]
+        format: ReportFormat,
+        /// Where to save the report. Defaults to stdout.
+        #[clap(long, parse(from_os_str))]
+        outfile: Option<PathBuf>,
+    },
+}
+
+/// An exit code `run` has already said why it's exiting with. It's returned rather than exited with there, so `main`
+/// still cleans up (decrypted copies of cached files, above all) before exiting.
+#[derive(Debug)]
+struct QuietExit(i32);
+
+impl std::fmt::Display for QuietExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exiting with {}", self.0)
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::parse();
+impl std::error::Error for QuietExit {}
+
+/// Errors from the library's core exit with a code saying what kind they were (see `NfhlError::exit_code`), anything
+/// else with 1.
+fn main() {
+    // the config file only sets environment variables, which the flags fall back to, so it has to come first
+    let profile = config::profile_arg(&std::env::args_os().collect::<Vec<_>>());
+    let config = config::Config::load_default().and_then(|config| match &profile {
+        Some(profile) => config.with_profile(profile),
+        None => Ok(config),
+    });
+    match config {
+        Ok(config) => config.apply_to_env(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            exit(1);
+        }
+    }
+    let result = run(Cli::parse());
+    encryption::remove_decrypted();
+    if let Err(e) = result {
+        if let Some(QuietExit(code)) = e.downcast_ref::<QuietExit>() {
+            exit(*code);
+        }
+        eprintln!("Error: {}", e);
+        exit(e.downcast_ref::<NfhlError>().map_or(1, NfhlError::exit_code));
+    }
+}
+
+fn run(args: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    // the client is built from the environment on first use
+    if args.respect_robots {
+        std::env::set_var(robots::RESPECT_ROBOTS_ENV, "true");
+    }
 
     match args.command {
-        Commands::States { outfile, politeness } => {
-            if let Some(outfile_dir) = outfile.parent() {
-                std::fs::create_dir_all(outfile_dir)?;
+        Commands::States { outfile, format, politeness, sign_key, split_by_state, effective, resume, region_concurrency, archive_responses } => {
+            archive_responses_to(archive_responses)?;
+            let checkpoint = msc::states_checkpoint_path();
+            let cancel = CancellationToken::new();
+            let products = blocking::get_checkpointed_state_products(&checkpoint, resume, region_concurrency, &cancel, &msc::diagnostics_dir())
+                .and_then(|products| products.finished().map(|()| products))
+                .inspect_err(|_| if checkpoint.exists() {
+                    eprintln!("the states searched so far are saved; `states_inventory --resume` picks up from there");
+                })?;
+            let mut inv = products.inventory;
+            effective.retain(&mut inv);
+
+            save_inventories("states", &inv, format, outfile.as_deref(), split_by_state.as_deref(), sign_key.as_deref())?;
+        }
+        Commands::Counties { outfile, format, politeness, sign_key, split_by_state, effective, archive_responses } => {
+            archive_responses_to(archive_responses)?;
+            let mut scrape = blocking::scrape_county_products()?;
+            effective.retain(&mut scrape.inventory);
+
+            let (inv, split) = (&scrape.inventory, split_by_state.as_deref());
+            save_inventories("counties", inv, format, outfile.as_deref(), split, sign_key.as_deref())?;
+            if let (InventoryFormat::Json, Some(outfile)) = (format, &outfile) {
+                let report = format!("{}.scrape.json", outfile);
+                serde_json::to_writer_pretty(open_output(Some(Path::new(&report)))?, &scrape.report)?;
             }
-            let f = File::create(outfile)?;
+        }
+        Commands::DownloadAll { inventory, cache_dir, old_inventory, delete, keep_history, politeness, outputs, shard, product } => {
+            let inv = read_inventory(Path::new(&inventory))?;
+            let old_inv = match old_inventory {
+                Some(old_inventory) => Some(read_inventory(&old_inventory)?),
+                None => None,
+            };
 
-            let inv = get_effective_state_products().unwrap();
+            let plan = product.plan(&inv, old_inv.as_ref(), &cache_dir, delete, keep_history, shard, politeness)?;
+            outputs.apply(&plan, politeness)?;
+        }
+        Commands::Plan { inventory, cache_dir, old_inventory, delete, keep_history, shard, product, outfile } => {
+            let inv = read_inventory(&inventory)?;
+            let old_inv = match old_inventory {
+                Some(old_inventory) => Some(read_inventory(&old_inventory)?),
+                None => None,
+            };
 
-            serde_json::to_writer(f, &inv)?;
+            // FEMA is asked at the default politeness, for MSC listings and --check-republished
+            let plan = product.plan(&inv, old_inv.as_ref(), &cache_dir, delete, keep_history, shard, u8::MAX)?;
+            serde_json::to_writer_pretty(open_output(Some(&outfile))?, &plan)?;
+            plan::write_plan_summary(&mut std::io::stdout(), &plan)?;
         }
-        Commands::Counties { outfile, politeness } => {
-            if let Some(outfile_dir) = outfile.parent() {
-                std::fs::create_dir_all(outfile_dir)?;
+        Commands::Apply { plan, politeness, outputs } => {
+            let plan = plan::read_plan(&plan)?;
+            outputs.apply(&plan, politeness)?;
+        }
+        Commands::MergeReports { reports, outfile, report_html, report_markdown } => {
+            let mut runs = Vec::new();
+            for report in &reports {
+                let run: download::RunReport = serde_json::from_reader(BufReader::new(File::open(report)?))
+                    .map_err(|e| format!("{} isn't a download_all report: {}", report.display(), e))?;
+                runs.push(run);
             }
-            let f = File::create(outfile)?;
+            let merged = shard::merge_reports(runs)?;
 
-            let inv = get_effective_county_products().unwrap();
+            let mut out = open_output(outfile.as_deref())?;
+            serde_json::to_writer_pretty(&mut out, &merged)?;
+            if let Some(report_html) = report_html {
+                let mut out = open_output(Some(&report_html))?;
+                out.write_all(html_report::render_run_report(&merged).as_bytes())?;
+            }
+            if let Some(report_markdown) = report_markdown {
+                let mut out = open_output(Some(&report_markdown))?;
+                markdown_report::write_run_report(&mut *out, &merged)?;
+            }
+        }
+        Commands::Extract { fips, cache_dir, out, format } => {
+            let archive = cache::cached_archive(&cache_dir, &fips)?;
+            let extracted = extract::extract_archive(&archive, &out)?;
+            eprintln!("extracted {} files from {} to {}", extracted.files, archive.display(), out.display());
+            let gdb = match extracted.gdb {
+                Some(gdb) => gdb,
+                None => return Err(format!("{} has no .gdb in it", archive.display()).into()),
+            };
+            eprintln!("geodatabase: {}", gdb.display());
 
-            serde_json::to_writer(f, &inv)?;
+            #[cfg(feature = "gdal")]
+            extract::write_layers(&mut std::io::stdout(), &extract::inspect_layers(&gdb)?, format)?;
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = format;
+                eprintln!("(build with `--features gdal` to list its layers)");
+            }
+        }
+        Commands::Layers { cache_dir, out, format } => {
+            #[cfg(feature = "gdal")]
+            {
+                let inventory = layers::layer_inventory(&cache_dir)?;
+                serde_json::to_writer_pretty(open_output(Some(&out))?, &inventory)?;
+                layers::write_layer_summary(&mut std::io::stdout(), &inventory, format)?;
+                if !inventory.failures.is_empty() {
+                    eprintln!("{} archives couldn't be read; see `failures` in {}", inventory.failures.len(), out.display());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (cache_dir, out, format);
+                return Err("`layers` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::Convert { to, cache_dir, fips, all, out, layers, partition_by_state, translate } => {
+            let archives = match fips {
+                Some(fips) if !all => vec![(fips.clone(), cache::cached_archive(&cache_dir, &fips)?)],
+                _ => cache::cached_archives(&cache_dir)?,
+            };
+            let opts = convert::ConvertOptions { format: to, layers, partition_by_state, translate };
+            #[cfg(feature = "gdal")]
+            {
+                systemd::install_signal_handlers()?;
+                if convert::convert(&archives, &out, &opts, &CancellationToken::new())?.is_some() {
+                    return Err(NfhlError::Interrupted.into());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (archives, out, opts);
+                return Err("`convert` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::ValidateGdb { cache_dir, fips, all, geometry, geojson_dir, format, outfile } => {
+            let prefix = if all { String::new() } else { fips.unwrap_or_default() };
+            let sources = merge_geo::sources(&cache_dir, |fips| fips.starts_with(prefix.as_str()))?;
+            if sources.is_empty() {
+                return Err(format!("no cached archives for {} in {}", prefix, cache_dir.display()).into());
+            }
+            #[cfg(feature = "gdal")]
+            {
+                let opts = validate::ValidateOptions { geometry, geojson_dir };
+                let mut report = validate::ValidationReport { generated_at: chrono::Utc::now(), counties: Vec::new(), failures: Default::default() };
+                for source in &sources {
+                    match validate::validate_county(source, &opts) {
+                        Ok(county) => report.counties.push(county),
+                        Err(e) => {
+                            report.failures.insert(source.fips.clone(), e.to_string());
+                        }
+                    }
+                }
+                validate::write_validation_report(&mut *open_output(outfile.as_deref())?, &report, format)?;
+                let failed = report.counties.iter().filter(|c| c.errors() > 0).count() + report.failures.len();
+                if failed > 0 {
+                    return Err(format!("{} of {} counties failed validation", failed, sources.len()).into());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (geometry, geojson_dir, format, outfile);
+                return Err("`validate-gdb` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::Verify { cache_dir, fips, verify_workers, checksums, no_quarantine, format, outfile } => {
+            let workers = verify_workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            let mut report = verify::verify_cache(&cache_dir, fips.as_deref().unwrap_or_default(), workers)?;
+            if report.files.is_empty() {
+                return Err(format!("no cached archives for {} in {}", fips.unwrap_or_default(), cache_dir.display()).into());
+            }
+            if !no_quarantine {
+                verify::quarantine_failed(&cache_dir, &mut report)?;
+            }
+            verify::write_verify_report(&mut *open_output(outfile.as_deref())?, &report, format)?;
+            if let Some(checksums) = checksums {
+                verify::write_checksums(&mut *open_output(Some(&checksums))?, &report)?;
+            }
+            let failed = report.files.iter().filter(|file| !file.ok()).count();
+            if failed > 0 {
+                return Err(format!("{} of {} files failed verification", failed, report.files.len()).into());
+            }
+        }
+        Commands::Ls { cache_dir, inventory, state, stale, format, outfile } => {
+            let inv = match inventory {
+                Some(inventory) => Some(read_inventory(&inventory)?),
+                None => None,
+            };
+            let mut holdings = holdings::list_holdings(&cache_dir, inv.as_ref(), state.as_deref().unwrap_or_default())?;
+            if stale {
+                holdings.retain(|holding| holding.stale);
+            }
+            holdings::write_holdings(&mut *open_output(outfile.as_deref())?, &holdings, format)?;
+        }
+        Commands::MigrateCache { cache_dir, from, to } => {
+            let migration = migrate::migrate_cache(&cache_dir, from, to)?;
+            eprintln!("moved {} files ({}) to the {} layout; {} were already there", migration.moved,
+                html_report::format_bytes(migration.bytes), to.as_str(), migration.already_moved);
+        }
+        Commands::Stats { cache_dir, fips, all, by_tract, tiger, geoid_field, population_field, format, outfile } => {
+            let prefix = if all { String::new() } else { fips.unwrap_or_default() };
+            let sources: Vec<merge_geo::Source> = merge_geo::sources(&cache_dir, |fips| fips.starts_with(prefix.as_str()))?
+                .into_iter()
+                .filter(|source| all || source.fips == prefix)
+                .collect();
+            if sources.is_empty() {
+                return Err(format!("no cached archive for {} in {}", prefix, cache_dir.display()).into());
+            }
+            let tracts = match tiger {
+                Some(tiger) if by_tract => Some(stats::TractOptions { tiger, geoid_field, population_field }),
+                _ => None,
+            };
+            #[cfg(feature = "gdal")]
+            if let Some(tracts) = tracts {
+                let mut exposures = Vec::new();
+                let mut failed = 0;
+                for source in &sources {
+                    match stats::tract_exposure(source, &tracts) {
+                        Ok(county) => exposures.extend(county),
+                        Err(e) => {
+                            eprintln!("{}: {}", source.fips, e);
+                            failed += 1;
+                        }
+                    }
+                }
+                stats::write_tract_exposure(&mut *open_output(outfile.as_deref())?, &exposures, format)?;
+                if failed > 0 {
+                    return Err(format!("{} of {} counties failed", failed, sources.len()).into());
+                }
+            } else {
+                let mut results = Vec::new();
+                for source in &sources {
+                    match stats::county_stats(source) {
+                        Ok(county) => results.push(county),
+                        Err(e) => eprintln!("{}: {}", source.fips, e),
+                    }
+                }
+                stats::write_stats(&mut *open_output(outfile.as_deref())?, &results, format)?;
+                if results.len() < sources.len() {
+                    return Err(format!("{} of {} counties failed", sources.len() - results.len(), sources.len()).into());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (tracts, format, outfile);
+                return Err("`stats` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::LoadPostgis { dsn, schema, cache_dir, fips, layers, upsert, translate } => {
+            let prefix = fips.unwrap_or_default();
+            let sources = merge_geo::sources(&cache_dir, |fips| fips.starts_with(prefix.as_str()))?;
+            if sources.is_empty() {
+                return Err(format!("no cached counties to load in {}", cache_dir.display()).into());
+            }
+            let opts = postgis::LoadOptions { schema, layers, upsert, translate };
+            #[cfg(feature = "gdal")]
+            {
+                let summary = postgis::load(&dsn, &sources, &opts)?;
+                eprintln!("{} counties loaded, {} unchanged", summary.loaded, summary.unchanged);
+                if !summary.failed.is_empty() {
+                    return Err(format!("{} counties couldn't be loaded: {}", summary.failed.len(), summary.failed.join(", ")).into());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (dsn, opts);
+                return Err("`load-postgis` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::Tiles { cache_dir, input, layers, out, min_zoom, max_zoom } => {
+            let source = match (cache_dir, input) {
+                (_, Some(input)) => tiles::TileSource::Dataset(input),
+                (Some(cache_dir), None) => tiles::TileSource::Cache(cache_dir),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            tiles::driver(&out)?;
+            let opts = tiles::TileOptions { layers, min_zoom, max_zoom };
+            #[cfg(feature = "gdal")]
+            tiles::write_tiles(&source, &out, &opts)?;
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (source, opts);
+                return Err("`tiles` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::MergeGeo { state, national: _, to, cache_dir, out, layers, translate } => {
+            // already checked by `fips::parse_state`, and None with `--national`
+            let state = state.unwrap_or_default();
+            let sources = merge_geo::sources(&cache_dir, |fips| fips.starts_with(state.as_str()))?;
+            let opts = merge_geo::MergeOptions { format: to, layers, translate };
+            #[cfg(feature = "gdal")]
+            {
+                let summary = merge_geo::merge(&sources, &out, &opts)?;
+                for (layer, features, duplicates) in &summary.layers {
+                    eprintln!("{}: {} features ({} duplicates removed)", layer, features, duplicates);
+                }
+                eprintln!("{}: {} counties merged, {} unchanged, {} removed", out.display(), summary.merged, summary.unchanged, summary.removed);
+                if !summary.failed.is_empty() {
+                    return Err(format!("{} counties couldn't be merged: {}", summary.failed.len(), summary.failed.join(", ")).into());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (sources, out, opts);
+                return Err("`merge-geo` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::ExtractLayer { cache_dir, layers, state, national: _, merge, to, out, translate } => {
+            // already checked by `fips::parse_state`, and None with `--national`
+            let state = state.unwrap_or_default();
+            let sources = merge_geo::sources(&cache_dir, |fips| fips.starts_with(state.as_str()))?;
+            if sources.is_empty() {
+                return Err(format!("no cached archives for {} in {}", state, cache_dir.display()).into());
+            }
+            let opts = hydraulics::ExtractLayerOptions { layers, merge: merge.then_some(to), translate };
+            #[cfg(feature = "gdal")]
+            hydraulics::extract_layers(&sources, &out, &opts)?;
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (sources, out, opts);
+                return Err("`extract-layer` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::ExportDomains { cache_dir, fips, format, outfile } => {
+            let source = merge_geo::sources(&cache_dir, |cached| cached == fips)?
+                .pop()
+                .ok_or_else(|| format!("no cached archive for {} in {}", fips, cache_dir.display()))?;
+            #[cfg(feature = "gdal")]
+            {
+                let domains = domains::read_domains(&source.archive)?;
+                if domains.is_empty() {
+                    return Err(format!("{} has no coded-value domains", source.archive.display()).into());
+                }
+                domains::write_domains(&mut *open_output(outfile.as_deref())?, &domains, format)?;
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (source, format, outfile);
+                return Err("`export-domains` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::PanelIndex { cache_dir, state, out } => {
+            let state = state.unwrap_or_default();
+            let sources = merge_geo::sources(&cache_dir, |fips| fips.starts_with(state.as_str()))?;
+            #[cfg(feature = "gdal")]
+            {
+                let summary = panels::panel_index(&sources, &out)?;
+                eprintln!("{}: {} panels from {} counties merged, {} unchanged, {} removed; {} superseded copies dropped",
+                    out.display(), summary.panels, summary.merge.merged, summary.merge.unchanged, summary.merge.removed, summary.superseded);
+                if !summary.merge.failed.is_empty() {
+                    return Err(format!("{} counties couldn't be merged: {}", summary.merge.failed.len(), summary.merge.failed.join(", ")).into());
+                }
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (sources, out);
+                return Err("`panel-index` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::DiffGeo { cache_dir, fips, format, outfile } => {
+            let (old, new) = diff_geo::versions(&cache_dir, &fips)?;
+            #[cfg(feature = "gdal")]
+            {
+                let diff = diff_geo::diff_county(&old, &new)?;
+                diff_geo::write_geo_diff(&mut *open_output(outfile.as_deref())?, &diff, format)?;
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (old, new, format, outfile);
+                return Err("`diff-geo` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::ComparePrelim { cache_dir, fips, inventory, prelim, format, outfile } => {
+            let effective = merge_geo::sources(&cache_dir, |cached| cached == fips)?
+                .pop()
+                .ok_or_else(|| format!("no cached archive for {} in {}", fips, cache_dir.display()))?;
+            let inv = match inventory {
+                Some(inventory) => Some(read_inventory(&inventory)?),
+                None => None,
+            };
+            let preliminary = prelim::preliminary_source(&cache_dir, &fips, prelim, inv.as_ref())?;
+            #[cfg(feature = "gdal")]
+            {
+                let comparison = prelim::compare(&effective, &preliminary)?;
+                prelim::write_comparison(&mut *open_output(outfile.as_deref())?, &comparison, format)?;
+            }
+            #[cfg(not(feature = "gdal"))]
+            {
+                let _ = (effective, preliminary, format, outfile);
+                return Err("`compare-prelim` needs nfhl_util built with `--features gdal`".into());
+            }
+        }
+        Commands::Diff { old_inventory, new_inventory, format, outfile, changelog } => {
+            let old_inv = read_inventory(&old_inventory)?;
+            let new_inv = read_inventory(&new_inventory)?;
+            let changes = diff::diff_inventories(&old_inv, &new_inv);
+            if let Some(changelog) = changelog {
+                history::append_changes(&changelog, &changes, chrono::Utc::now())?;
+            }
+            let mut out = open_output(outfile.as_deref())?;
+            diff::write_changes(&mut *out, &changes, format)?;
+        }
+        Commands::PublishFeed { old_inventory, inventory, feed, fips, title, link, max_entries } => {
+            let old_inv = read_inventory(&old_inventory)?;
+            let new_inv = read_inventory(&inventory)?;
+            fips::check_known(&fips, old_inv.keys().chain(new_inv.keys()).map(|fips| fips.as_str()))?;
+            let mut changes = diff::diff_inventories(&old_inv, &new_inv);
+            if !fips.is_empty() {
+                changes.retain(|c| fips.iter().any(|f| c.fips.starts_with(f.as_str())));
+            }
+            let added = feed::publish_changes(&feed, &changes, &title, link.as_deref(), max_entries)?;
+            eprintln!("added {} entries to {}", added, feed.display());
+        }
+        Commands::PublishStac { cache_dir, out, base_url, geoparquet_dir, geoparquet_base_url } => {
+            let opts = stac::StacOptions { base_url, geoparquet_dir, geoparquet_base_url };
+            let summary = stac::publish_stac(&cache_dir, &out, &opts)?;
+            eprintln!("wrote {} items to {}", summary.items, out.display());
+            if summary.without_geometry > 0 {
+                eprintln!("{} items have no geometry", summary.without_geometry);
+            }
         }
-        Commands::DownloadAll { inventory, cache_dir, old_inventory, delete, politeness } => {}
+        Commands::Snapshot { inventory, store, taken_at } => {
+            let inv = read_inventory(&inventory)?;
+            let delta = snapshots::append_snapshot(&store, &inv, taken_at.unwrap_or_else(chrono::Utc::now))?;
+            eprintln!("{} entries set and {} removed as of {}", delta.set.len(), delta.removed.len(), delta.taken_at);
+        }
+        Commands::Materialize { store, as_of, outfile, effective } => {
+            let deltas = snapshots::read_store(&store)?;
+            let mut inv = snapshots::materialize(&deltas, as_of).ok_or_else(|| match as_of {
+                Some(as_of) => format!("{} has no snapshot from before {}", store.display(), as_of),
+                None => format!("{} has no snapshots", store.display()),
+            })?;
+            effective.retain(&mut inv);
+            nfhl_util::inventory::format::write(open_output(outfile.as_deref())?, &inv)?;
+        }
+        Commands::History { changelog, fips, format, outfile } => {
+            let mut records = history::read_changelog(&changelog)?;
+            if !fips.is_empty() {
+                records.retain(|r| fips.iter().any(|f| r.change.fips.starts_with(f.as_str())));
+            }
+            let mut out = open_output(outfile.as_deref())?;
+            history::write_history(&mut *out, &records, format)?;
+        }
+        Commands::Watch { interval, schedule, timezone, cache_dir, inventory_dir, delete, keep_history, politeness, notify_url, changelog, publish, report_postgres } => {
+            let cadence = match schedule {
+                Some(schedule) => {
+                    let tz: chrono_tz::Tz = timezone.parse().map_err(|e| format!("invalid timezone '{}': {}", timezone, e))?;
+                    watch::Cadence::Schedule(Box::new(watch::parse_schedule(&schedule)?), tz)
+                }
+                None => watch::Cadence::Interval(interval),
+            };
+            let opts = watch::WatchOptions {
+                cache_dir,
+                inventory_dir,
+                delete,
+                keep_history,
+                politeness,
+                notify_urls: notify_url,
+                changelog,
+                publish_urls: publish,
+                postgres_url: report_postgres,
+            };
+            systemd::install_signal_handlers()?;
+            watch::watch(&opts, &cadence)?;
+        }
+        Commands::Serve { port, bind, inventory, cache_dir, changelog, threads, refresh_token, politeness } => {
+            systemd::install_signal_handlers()?;
+            let opts = server::ServeOptions { inventory, cache_dir, changelog, threads, refresh_token, politeness };
+            server::serve(&format!("{}:{}", bind, port), opts)?;
+        }
+        Commands::ServeMap { port, bind, tiles, gpkg, threads } => {
+            systemd::install_signal_handlers()?;
+            let opts = map_server::MapServeOptions { tiles, gpkgs: gpkg, threads };
+            map_server::serve_map(&format!("{}:{}", bind, port), opts)?;
+        }
+        Commands::VerifySignature { file, public_key, signature } => {
+            match signing::verify_file(&file, &public_key, signature.as_deref()) {
+                Ok(trusted_comment) => println!("Signature and comment signature verified\nTrusted comment: {}", trusted_comment),
+                Err(e) => {
+                    eprintln!("{}: signature verification failed: {}", file.display(), e);
+                    return Err(QuietExit(1).into());
+                }
+            }
+        }
+        Commands::Report { command } => match command {
+            ReportCommands::Age { inventory, buckets, format, outfile } => {
+                let inv = read_inventory(&inventory)?;
+                let today = chrono::Local::now().date_naive();
+                let report = report::age_report(&inv, &buckets, today);
+                let mut out = open_output(outfile.as_deref())?;
+                report::write_age_report(&mut *out, &report, format)?;
+            }
+            ReportCommands::Summary { inventory, format, outfile } => {
+                let inv = read_inventory(&inventory)?;
+                let report = report::summary_report(&inv);
+                let mut out = open_output(outfile.as_deref())?;
+                report::write_summary_report(&mut *out, &report, format)?;
+            }
+            ReportCommands::Sizes { cache_dir, inventory, last_report, largest, format, outfile } => {
+                let inv = match inventory {
+                    Some(inventory) => Some(read_inventory(&inventory)?),
+                    None => None,
+                };
+                let last_run: Option<download::RunReport> = match last_report {
+                    Some(report) => Some(serde_json::from_reader(BufReader::new(File::open(&report)?))
+                        .map_err(|e| format!("{} isn't a download_all report: {}", report.display(), e))?),
+                    None => None,
+                };
+                let report = sizes::sizes_report(&cache_dir, inv.as_ref(), last_run.as_ref(), largest)?;
+                let mut out = open_output(outfile.as_deref())?;
+                sizes::write_sizes_report(&mut *out, &report, format)?;
+            }
+        },
+        Commands::Info { fips, inventory, cache_dir, changelog, format } => {
+            let inv = match inventory {
+                Some(inventory) => Some(read_inventory(&inventory)?),
+                None => None,
+            };
+            let info = info::county_info(&fips, inv.as_ref(), cache_dir.as_deref(), changelog.as_deref())?;
+            info::write_info(&mut std::io::stdout(), &info, format)?;
+        }
+        Commands::Search { query, cache_dir, inventory, limit, format } => {
+            let inv = match inventory {
+                Some(inventory) => Some(read_inventory(&inventory)?),
+                None => None,
+            };
+            let mut matches = search::search(&cache_dir, inv.as_ref(), &query)?;
+            if matches.is_empty() {
+                return Err(format!("nothing in {} matches '{}'", cache_dir.display(), query).into());
+            }
+            matches.truncate(limit);
+            search::write_matches(&mut std::io::stdout(), &matches, format)?;
+        }
+        Commands::Firmette { lat, lon, address, panel, cache_dir, geocoder, format, out } => {
+            let (lon, lat) = match (lat, lon, address, panel) {
+                (Some(lat), Some(lon), _, _) => (lon, lat),
+                (_, _, Some(address), _) => {
+                    let geocoded = geocoder.geocoder().geocode(&address)?
+                        .ok_or_else(|| format!("couldn't find '{}'", address))?;
+                    eprintln!("{}: {}, {}", geocoded.matched_address, geocoded.lat, geocoded.lon);
+                    (geocoded.lon, geocoded.lat)
+                }
+                (_, _, _, Some(panel)) => {
+                    #[cfg(feature = "gdal")]
+                    {
+                        firmette::panel_point(cache_dir.as_deref().ok_or("`--panel` needs `--cache-dir`")?, &panel)?
+                    }
+                    #[cfg(not(feature = "gdal"))]
+                    {
+                        let _ = (panel, cache_dir);
+                        return Err("`firmette --panel` needs nfhl_util built with `--features gdal`".into());
+                    }
+                }
+                _ => return Err("give `--lat` and `--lon`, `--address` or `--panel`".into()),
+            };
+            let url = firmette::create_firmette(lon, lat, format, &out)?;
+            eprintln!("{}: saved from {}", out.display(), url);
+        }
+        Commands::Query { command } => match command {
+            QueryCommands::Point { lat, lon, cache_dir, fips, online, determination, outfile } => {
+                if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                    return Err(format!("{}, {} isn't a latitude and longitude", lat, lon).into());
+                }
+                let cache_dir = cache_dir.as_deref().filter(|_| !online);
+                if determination {
+                    let form = query::determine_form(cache_dir, fips.as_deref(), lon, lat)?;
+                    let mut out = open_output(outfile.as_deref())?;
+                    serde_json::to_writer_pretty(&mut *out, &form)?;
+                    writeln!(out)?;
+                } else {
+                    let determination = query::determine(cache_dir, fips.as_deref(), lon, lat)?;
+                    let mut out = open_output(outfile.as_deref())?;
+                    serde_json::to_writer_pretty(&mut *out, &determination)?;
+                    writeln!(out)?;
+                }
+            }
+            QueryCommands::Address { address, cache_dir, online, geocoder, outfile } => {
+                let geocoded = geocoder.geocoder().geocode(&address)?
+                    .ok_or_else(|| format!("couldn't find '{}'", address))?;
+                let determination = query::determine(cache_dir.as_deref().filter(|_| !online), None, geocoded.lon, geocoded.lat)?;
+                let answer = query::AddressDetermination { address, matched_address: geocoded.matched_address, determination };
+                let mut out = open_output(outfile.as_deref())?;
+                serde_json::to_writer_pretty(&mut *out, &answer)?;
+                writeln!(out)?;
+            }
+            QueryCommands::Panel { lat, lon, address, cache_dir, fips, online, geocoder, outfile } => {
+                let (lat, lon, geocoded) = match (lat, lon, &address) {
+                    (Some(lat), Some(lon), _) => (lat, lon, None),
+                    (_, _, Some(address)) => {
+                        let geocoded = geocoder.geocoder().geocode(address)?
+                            .ok_or_else(|| format!("couldn't find '{}'", address))?;
+                        (geocoded.lat, geocoded.lon, Some(geocoded))
+                    }
+                    _ => return Err("give `--lat` and `--lon`, or `--address`".into()),
+                };
+                if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                    return Err(format!("{}, {} isn't a latitude and longitude", lat, lon).into());
+                }
+                let mut determination = query::determine_panel(cache_dir.as_deref().filter(|_| !online), fips.as_deref(), lon, lat)?;
+                determination.address = address;
+                determination.matched_address = geocoded.map(|geocoded| geocoded.matched_address);
+                let mut out = open_output(outfile.as_deref())?;
+                serde_json::to_writer_pretty(&mut *out, &determination)?;
+                writeln!(out)?;
+            }
+            QueryCommands::Polygon { geojson, cache_dir, fips, format, outfile } => {
+                #[cfg(feature = "gdal")]
+                {
+                    let determination = query::determine_polygon(&cache_dir, &geojson, fips.as_deref())?;
+                    query::write_polygon(&mut *open_output(outfile.as_deref())?, &determination, format)?;
+                }
+                #[cfg(not(feature = "gdal"))]
+                {
+                    let _ = (geojson, cache_dir, fips, format, outfile);
+                    return Err("`query polygon` needs nfhl_util built with `--features gdal`".into());
+                }
+            }
+            QueryCommands::ZoneHistory { lat, lon, as_of, cache_dir, fips, outfile } => {
+                if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                    return Err(format!("{}, {} isn't a latitude and longitude", lat, lon).into());
+                }
+                #[cfg(feature = "gdal")]
+                {
+                    let determination = query::determine_as_of(&cache_dir, fips.as_deref(), lon, lat, as_of)?;
+                    let mut out = open_output(outfile.as_deref())?;
+                    serde_json::to_writer_pretty(&mut *out, &determination)?;
+                    writeln!(out)?;
+                }
+                #[cfg(not(feature = "gdal"))]
+                {
+                    let _ = (as_of, cache_dir, fips, outfile);
+                    return Err("`query zone-history` needs nfhl_util built with `--features gdal`".into());
+                }
+            }
+            QueryCommands::Batch { cache_dir, input, out, lat_column, lon_column, jobs } => {
+                let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+                let opts = query_batch::BatchOptions { lat_column, lon_column, jobs };
+                #[cfg(feature = "gdal")]
+                {
+                    let summary = query_batch::query_batch(&cache_dir, &input, &out, &opts)?;
+                    eprintln!("{}: {} points looked up ({} already done), {} outside the cached counties, {} failed",
+                        out.display(), summary.looked_up, summary.already_done, summary.outside, summary.failed);
+                }
+                #[cfg(not(feature = "gdal"))]
+                {
+                    let _ = (cache_dir, input, out, opts);
+                    return Err("`query batch` needs nfhl_util built with `--features gdal`".into());
+                }
+            }
+        },
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "nfhl_util", &mut std::io::stdout());
+        }
+        Commands::Mangen { out_dir } => match out_dir {
+            Some(out_dir) => {
+                std::fs::create_dir_all(&out_dir)?;
+                let written = write_man_pages(Cli::command(), &out_dir)?;
+                eprintln!("wrote {} man pages to {}", written, out_dir.display());
+            }
+            None => clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?,
+        },
     }
     Ok(())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct InventoryEntry {
-    effective_file_url: String,
-    effective_file_date: String,
-    preliminary_file_url: String,
-    preliminary_file_date: String,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct SearchResults {
-    #[serde(rename(deserialize = "EFFECTIVE"))]
-    effective: SearchResultEffective,
-    #[serde(rename(deserialize = "PRELIM_FIRM_DB"))]
-    preliminary: Option<Vec<SearchResultProductEntry>>,
+/// Writes `command`'s man page to `dir`, then its subcommands', named the way git's are, e.g.
+/// `nfhl_util-query-point.1`. Returns how many it wrote.
+fn write_man_pages(command: clap::Command, dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let name = command.get_name().to_string();
+    let mut page = Vec::new();
+    clap_mangen::Man::new(command.clone()).render(&mut page)?;
+    std::fs::write(dir.join(format!("{}.1", name)), page)?;
+    let mut written = 1;
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        let subcommand = subcommand.clone().name(format!("{}-{}", name, subcommand.get_name()));
+        written += write_man_pages(subcommand, dir)?;
+    }
+    Ok(written)
 }
 
-#[derive(Deserialize, Debug)]
-pub struct SearchResultEffective {
-    #[serde(rename(deserialize = "NFHL_COUNTY_DATA"))]
-    county: Option<Vec<SearchResultProductEntry>>,
-    #[serde(rename(deserialize = "NFHL_STATE_DATA"))]
-    state: Option<Vec<SearchResultProductEntry>>,
+/// Opens `outfile` for writing (creating parent directories as needed), or stdout if there isn't one.
+fn open_output(outfile: Option<&Path>) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+    match outfile {
+        Some(outfile) => {
+            if let Some(outfile_dir) = outfile.parent() {
+                std::fs::create_dir_all(outfile_dir)?;
+            }
+            Ok(Box::new(File::create(outfile)?))
+        }
+        None => Ok(Box::new(std::io::stdout())),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct SearchResultProductEntry {
-    #[serde(rename(deserialize = "product_TYPE_ID"))]
-    type_id: String,
-    #[serde(rename(deserialize = "product_SUBTYPE_ID"))]
-    subtype_id: String,
-    #[serde(rename(deserialize = "product_NAME"))]
-    name: String,
-    #[serde(rename(deserialize = "product_ID"))]
-    id: usize, // so far as I can tell, these ids are useless. Use "name" instead.
-    #[serde(rename(deserialize = "product_EFFECTIVE_DATE_STRING"))]
-    effective_date: Option<String>,
-    #[serde(rename(deserialize = "product_FILE_PATH"))]
-    filename: Option<String>,
-    #[serde(rename(deserialize = "product_FILE_SIZE"))]
-    filesize: Option<String>
-}
+impl RunOutputs {
+    /// Applies `plan`, then signs, records and reports the run as asked.
+    fn apply(self, plan: &plan::Plan, politeness: u8) -> Result<(), Box<dyn std::error::Error>> {
+        // connected before downloading so a bad url doesn't cost a whole run
+        let mut postgres = self.report_postgres.as_deref().map(postgres_sink::PostgresSink::connect).transpose()?;
+        let mut publishers = publish::Publishers::connect_all(&self.publish)?;
+        let pool = match self.extract {
+            Some(_) if !plan.product.is_archive() => {
+                return Err(format!("--extract unzips archives, and {} files aren't", plan.product.as_str()).into());
+            }
+            Some(_) if !self.frd_grids.is_empty() && plan.product != plan::Product::Frd => {
+                return Err(format!("--frd-grids is for Flood Risk Databases, not {} files", plan.product.as_str()).into());
+            }
+            Some(dir) => {
+                let workers = self.extract_workers
+                    .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+                let opts = pipeline::ExtractOptions { dir, convert: self.extract_to, grids: self.frd_grids, workers };
+                let pool = pipeline::ExtractPool::start(&plan.cache_dir, opts)?;
+                publishers.listen(pool.listener());
+                Some(pool)
+            }
+            None => None,
+        };
+        systemd::install_signal_handlers()?;
+        let mut run_report = blocking::apply_plan(plan, politeness, &mut publishers, &CancellationToken::new())?;
+        // the pool's listener goes with the publishers
+        drop(publishers);
+        if let Some(pool) = pool {
+            run_report.extraction = Some(pool.finish());
+        }
+        if let Some(sign_key) = self.sign_key {
+            signing::sign_file(&plan.cache_dir.join(cache::MANIFEST_FILE_NAME), &sign_key)?;
+        }
 
+        if let Some(changelog) = self.changelog {
+            history::append_changes(&changelog, &run_report.changes, run_report.started_at)?;
+        }
 
+        if let Some(report) = self.report {
+            let mut out = open_output(Some(&report))?;
+            serde_json::to_writer_pretty(&mut out, &run_report)?;
+        }
+        if let Some(report_html) = self.report_html {
+            let mut out = open_output(Some(&report_html))?;
+            out.write_all(html_report::render_run_report(&run_report).as_bytes())?;
+        }
+        if let Some(report_markdown) = self.report_markdown {
+            let mut out = open_output(Some(&report_markdown))?;
+            markdown_report::write_run_report(&mut *out, &run_report)?;
+        }
+        if let Some(postgres) = &mut postgres {
+            postgres.write_run(&run_report, None)?;
+        }
+        if let Some(table) = self.report_bigquery {
+            bigquery::export_changes(&table, &run_report.changes, run_report.started_at)?;
+        }
+        eprintln!("{} downloaded, {} failed, {} already cached, {} deleted",
+            run_report.downloads.len(), run_report.failures.len(), run_report.skipped, run_report.deleted.len());
+        eprintln!("{}", run_report.summary.line());
+        if let Some(extraction) = &run_report.extraction {
+            eprintln!("{} extracted, {} failed to extract", extraction.extracted.len(), extraction.failures.len());
+        }
 
-pub fn get_effective_state_products() -> Result<HashMap<String, InventoryEntry>, Box<dyn std::error::Error>> {
-    // let fema_region_states = vec![
-    //     Vec!["ME", "NH", "VT", "MA", "CT", "RI"],
-    //     Vec!["NY", "NJ", "PR", "VI"],
-    //     Vec!["MD", "PA", "WV", "DC", "DE", "VA"],
-    //     Vec!["NC", "SC", "GA", "FL", "AL", "MS", "TN", "KY"],
-    //     Vec!["IL", "IN", "OH", "MI", "WI", "MN"],
-    //     Vec!["NM", "TX", "OK", "LA", "AR"],
-    //     Vec!["NE", "IA", "KS", "MO"],
-    //     Vec!["MT", "ND", "SD", "WY", "UT", "CO"],
-    //     Vec!["NV", "AZ", "CA", "FM", "GU", "HI", "MH", "MP", "AS"],
-    //     Vec!["AK", "WA", "OR", "ID"],
-    // ];
-
-    // in order to query msc.fema.gov, we must look for a specific community. To that end, each state has a county.
-    let state_to_representative_county = HashMap::from([
-        // ("AK", "02"),
-        ("AL", "01101"),
-        ("AR", "05029"),
-        // ("AS", "60"),
-        // ("AZ", "04"),
-        // ("CA", "06"),
-        // ("CO", "08"),
-        // ("CT", "09"),
-        // ("DC", "11"),
-        // ("DE", "10"),
-        // ("FL", "12"),
-        // ("GA", "13"),
-        // ("GU", "66"),
-        // ("HI", "15"),
-        // ("IA", "19"),
-        // ("ID", "16"),
-        // ("IL", "17"),
-        // ("IN", "18"),
-        // ("KS", "20"),
-        // ("KY", "21"),
-        // ("LA", "22"),
-        // ("MA", "25"),
-        // ("MD", "24"),
-        // ("ME", "23"),
-        // ("MI", "26"),
-        // ("MN", "27"),
-        // ("MO", "29"),
-        // ("MS", "28"),
-        // ("MT", "30"),
-        // ("NC", "37"),
-        // ("ND", "38"),
-        // ("NE", "31"),
-        // ("NH", "33"),
-        // ("NJ", "34"),
-        // ("NM", "35"),
-        // ("NV", "32"),
-        // ("NY", "36"),
-        // ("OH", "39"),
-        // ("OK", "40"),
-        // ("OR", "41"),
-        // ("PA", "42"),
-        // ("PR", "72"),
-        // ("RI", "44"),
-        // ("SC", "45"),
-        // ("SD", "46"),
-        // ("TN", "47"),
-        // ("TX", "48"),
-        // ("UT", "49"),
-        // ("VA", "51"),
-        // ("VI", "78"),
-        // ("VT", "50"),
-        // ("WA", "53"),
-        // ("WI", "55"),
-        // ("WV", "54"),
-        // ("WY", "56"),
-        // ("MH", "68"), // may not be available in MSC
-        // ("MP", "69"),
-        // ("FM", "64") // may not be available in MSC
-    ]);
-
-
-
-    let mut inv = HashMap::<String, InventoryEntry>::with_capacity(57);
-
-    let client = reqwest::blocking::Client::builder().cookie_store(true).build()?;
-    // do a search query once just to start a session (sessions are stateful)
-    let a = client.get("https://msc.fema.gov/portal/advanceSearch").send()?;
-
-
-    for (&state, &representative_county) in state_to_representative_county.iter(){
-        let state_code = &representative_county[..2];
-        // https://msc.fema.gov/portal/advanceSearch
-
-        // let b = client.get(format!("https://msc.fema.gov/portal/advanceSearch?getCommunity={}&state={}",representative_county, state_code))
-        //     .send()?;
-
-
-        let b: SearchResults = client.post(format!("https://msc.fema.gov/portal/advanceSearch"))
-            .form(&[
-                ("utf8", "✓"), // I kid you not, this is included in every post to the official site.
-                ("affiliate", "fema"),
-                ("query", ""), // intentionally blank?
-                ("selstate", state_code),
-                ("selcounty", representative_county),
-                ("selcommunity", &format!("{}C", representative_county)),
-                ("jurisdictionkey", ""),
-                ("searchedCid", &format!("{}C", representative_county)),
-                ("searchedDateStart", ""),
-                ("searchedDateEnd", ""),
-                ("txtstartdate", ""),
-                ("txtenddate", ""),
-                ("method", "search")
-            ])
-            .send()?.json()?;
-        dbg!(&b);
+        if self.task_mode {
+            let state = task::TaskState::new(plan, &run_report);
+            let state_file = self.task_state_file.unwrap_or_else(|| plan.cache_dir.join(task::STATE_FILE_NAME));
+            state.save(&state_file)?;
+            if state.exit_code() != 0 {
+                eprintln!("{} downloads remain, exiting with {}", state.remaining.len(), state.exit_code());
+                return Err(QuietExit(state.exit_code()).into());
+            }
+        }
+        if let Some(extraction) = run_report.extraction.filter(|extraction| !extraction.failures.is_empty()) {
+            return Err(format!("{} of {} counties failed to extract", extraction.failures.len(),
+                extraction.failures.len() + extraction.extracted.len()).into());
+        }
+        Ok(())
     }
+}
 
-    Ok(inv)
+/// Where `states_inventory` / `counties_inventory` put what they find.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+enum InventoryFormat {
+    Json,
+    /// A new snapshot in Postgres tables managed by `postgres_sink`.
+    Postgres,
+    /// Rows appended to a `[project.]dataset.table` in BigQuery by a load job.
+    Bigquery,
 }
 
+/// Makes the shared client one that archives responses into `dir`, if there is one.
+fn archive_responses_to(dir: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = dir {
+        let client = Client::builder()
+            .connection(ConnectionOptions::from_env()?)
+            .respect_robots(robots::from_env())
+            .archive_responses(dir)
+            .build()?;
+        if blocking::set_client(client).is_err() {
+            return Err("the client was already in use".into());
+        }
+    }
+    Ok(())
+}
 
-pub fn get_effective_county_products() -> Result<HashMap<String, InventoryEntry>, Box<dyn std::error::Error>> {
-    let client = reqwest::blocking::Client::builder().cookie_store(true).build()?;
-    // client.post("https://www.lycamobile.es/wp-admin/admin-ajax.php")
-    //     .form(&[
-    //         ("action", "lyca_login_ajax"),
-    //         ("method", "login"),
-    //         ("mobile_no", "<MOBILE_PHONE_NUMBER>"),
-    //         ("pass", "<SUPER_SECRET_PASSWORD>")
-    //     ])
-    //     .send()?;
-
-    let response = client.get("https://hazards.fema.gov/femaportal/NFHL/searchResult").send()?;
-    let body_response = response.text()?;
-    let parsed_html = Html::parse_document(&body_response);
-    let tr_selector = &Selector::parse("tbody tr").expect("selector parse error");
-    let a_selector = Selector::parse("a").unwrap();
-
-    let re = Regex::new(r"fileName=(.+?)[cC]_(.+?).zip").unwrap();
-    let mut inv = HashMap::<String, InventoryEntry>::with_capacity(57);
-    for tr in parsed_html.select(&tr_selector) {
-        if let Some(a) = tr.select(&a_selector).next() {
-            let file_url = a.value().attr("href").unwrap();
-            if let Some(caps) = re.captures(file_url) {
-                let county_fips = caps.get(1).map_or("", |m| m.as_str());
-                let date = caps.get(2).map_or("", |m| m.as_str());
-                inv.insert(county_fips.to_string(), InventoryEntry {
-                    effective_file_url: "https://hazards.fema.gov/femaportal/NFHL/".to_string() + file_url,
-                    effective_file_date: date.to_string(),
-                    preliminary_file_url: "".to_string(),
-                    preliminary_file_date: "".to_string(),
-                });
+/// Saves `inv` to `outfile` and, split by state, to `split_dir`, as asked.
+fn save_inventories(
+    kind: &str,
+    inv: &Inventory,
+    format: InventoryFormat,
+    outfile: Option<&str>,
+    split_dir: Option<&Path>,
+    sign_key: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(outfile) = outfile {
+        save_inventory(kind, inv, format, outfile, sign_key)?;
+    }
+    if let Some(split_dir) = split_dir {
+        let written = nfhl_util::inventory::write_by_state(split_dir, inv)?;
+        eprintln!("updated {} state files in {}", written.len(), split_dir.display());
+    }
+    Ok(())
+}
+
+fn save_inventory(
+    kind: &str,
+    inv: &Inventory,
+    format: InventoryFormat,
+    outfile: &str,
+    sign_key: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        InventoryFormat::Json => {
+            let outfile = Path::new(outfile);
+            nfhl_util::inventory::format::write(open_output(Some(outfile))?, inv)?;
+            if let Some(sign_key) = sign_key {
+                signing::sign_file(outfile, sign_key)?;
             }
         }
+        InventoryFormat::Postgres => {
+            if sign_key.is_some() {
+                return Err("--sign-key only applies to JSON inventories".into());
+            }
+            let snapshot_id = postgres_sink::PostgresSink::connect(outfile)?.write_inventory(kind, inv)?;
+            eprintln!("saved {} {} as snapshot {}", inv.len(), kind, snapshot_id);
+        }
+        InventoryFormat::Bigquery => {
+            if sign_key.is_some() {
+                return Err("--sign-key only applies to JSON inventories".into());
+            }
+            let rows = bigquery::export_inventory(&outfile.parse()?, kind, inv)?;
+            eprintln!("loaded {} rows into {}", rows, outfile);
+        }
     }
-    Ok(inv)
+    Ok(())
 }
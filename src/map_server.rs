@@ -0,0 +1,352 @@
+//! `serve-map`: a small map service over converted data. PMTiles files are served with HTTP range requests, which is
+//! all a web map needs to read them, and (with the `gdal` feature) the layers of GeoPackages are served as an
+//! OGC API - Features endpoint that QGIS, ArcGIS and Leaflet plugins can all read.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tiny_http::{Method, Request, Response, ResponseBox, Server, StatusCode};
+
+use crate::server::{error_response, header, json_response, run_workers};
+use crate::systemd;
+
+#[derive(Debug, Clone)]
+pub struct MapServeOptions {
+    /// PMTiles files, served at `/tiles/{file name}`.
+    pub tiles: Vec<PathBuf>,
+    /// GeoPackages whose layers become the OGC API collections, `{file stem}.{layer}`.
+    pub gpkgs: Vec<PathBuf>,
+    pub threads: usize,
+}
+
+/// Items per page when the client doesn't say, and the most it may ask for.
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 10_000;
+
+/// Serves until a shutdown is requested:
+///
+/// - `GET /tiles` and `GET /tiles/{name}.pmtiles`: the tile files, with `Range` support
+/// - `GET /`, `/conformance`, `/collections`, `/collections/{id}`: the OGC API - Features landing page and metadata
+/// - `GET /collections/{id}/items?bbox=&limit=&offset=` and `/collections/{id}/items/{fid}`: features as GeoJSON, in
+///   longitude and latitude
+pub fn serve_map(addr: &str, opts: MapServeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    for path in opts.tiles.iter().chain(&opts.gpkgs) {
+        if !path.is_file() {
+            return Err(format!("{} doesn't exist", path.display()).into());
+        }
+    }
+    let server = Arc::new(Server::http(addr).map_err(|e| format!("couldn't listen on {}: {}", addr, e))?);
+    eprintln!("listening on http://{}", addr);
+    systemd::notify_ready();
+
+    let threads = opts.threads;
+    let opts = Arc::new(opts);
+    run_workers(server, threads, move |request| handle(request, &opts));
+    systemd::notify_stopping();
+    Ok(())
+}
+
+fn handle(request: Request, opts: &MapServeOptions) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    let response = match route(&request, opts) {
+        Ok(response) => response,
+        Err(e) => json_response(500, &json!({ "error": e.to_string() })),
+    };
+    eprintln!("{} {} {}", method, url, response.status_code().0);
+    // web maps are usually served from somewhere else
+    let response = response
+        .with_header(header("Access-Control-Allow-Origin", "*"))
+        .with_header(header("Access-Control-Expose-Headers", "Content-Length, Content-Range, ETag"));
+    if let Err(e) = request.respond(response) {
+        eprintln!("failed to respond to {} {}: {}", method, url, e);
+    }
+}
+
+fn route(request: &Request, opts: &MapServeOptions) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+    if *request.method() == Method::Options {
+        return Ok(Response::empty(204)
+            .with_header(header("Access-Control-Allow-Methods", "GET, HEAD, OPTIONS"))
+            .with_header(header("Access-Control-Allow-Headers", "Range, If-Match"))
+            .boxed());
+    }
+    if *request.method() != Method::Get && *request.method() != Method::Head {
+        return Ok(error_response(405, "method not allowed"));
+    }
+    let parsed = reqwest::Url::parse(&format!("http://localhost{}", request.url()))?;
+    let query: HashMap<String, String> = parsed.query_pairs().into_owned().collect();
+    let segments: Vec<&str> = parsed.path().trim_matches('/').split('/').collect();
+    let base = base_url(request);
+
+    match segments.as_slice() {
+        ["tiles"] => {
+            let tiles: Vec<Value> = opts.tiles.iter()
+                .filter_map(|path| path.file_name().map(|f| f.to_string_lossy().into_owned()))
+                .map(|name| json!({ "name": name, "href": format!("{}/tiles/{}", base, name) }))
+                .collect();
+            Ok(json_response(200, &tiles))
+        }
+        ["tiles", name] => match opts.tiles.iter().find(|path| path.file_name().is_some_and(|f| f == *name)) {
+            Some(path) => serve_file_range(request, path),
+            None => Ok(error_response(404, &format!("no tiles named {}", name))),
+        },
+        [""] => Ok(json_response(200, &json!({
+            "title": "NFHL",
+            "description": "FEMA National Flood Hazard Layer, served by nfhl_util",
+            "links": [
+                { "rel": "self", "type": "application/json", "href": format!("{}/", base) },
+                { "rel": "conformance", "type": "application/json", "href": format!("{}/conformance", base) },
+                { "rel": "data", "type": "application/json", "href": format!("{}/collections", base) },
+                { "rel": "tiles", "type": "application/json", "href": format!("{}/tiles", base) },
+            ],
+        }))),
+        ["conformance"] => Ok(json_response(200, &json!({
+            "conformsTo": [
+                "http://www.opengis.net/spec/ogcapi-features-1/1.0/conf/core",
+                "http://www.opengis.net/spec/ogcapi-features-1/1.0/conf/geojson",
+            ],
+        }))),
+        ["collections", ..] => features::route(&segments[1..], &query, &base, opts),
+        _ => Ok(error_response(404, "not found")),
+    }
+}
+
+/// Where the client reached us, for the links in responses.
+fn base_url(request: &Request) -> String {
+    let host = request.headers().iter()
+        .find(|h| h.field.equiv("Host"))
+        .map(|h| h.value.as_str().to_string())
+        .unwrap_or_else(|| "localhost".to_string());
+    format!("http://{}", host)
+}
+
+/// Serves a file, or the part of it a `Range: bytes=...` header asks for.
+fn serve_file_range(request: &Request, path: &std::path::Path) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let len = metadata.len();
+    let etag = format!("\"{:x}-{:x}\"", len, metadata.modified()?.duration_since(std::time::UNIX_EPOCH)?.as_secs());
+    let range = request.headers().iter()
+        .find(|h| h.field.equiv("Range"))
+        .map(|h| parse_range(h.value.as_str(), len));
+    let content_type = header("Content-Type", "application/vnd.pmtiles");
+
+    let (start, end) = match range {
+        None => {
+            return Ok(Response::from_file(file)
+                .with_header(content_type)
+                .with_header(header("Accept-Ranges", "bytes"))
+                .with_header(header("ETag", &etag))
+                .boxed());
+        }
+        Some(Some(range)) => range,
+        Some(None) => {
+            return Ok(Response::empty(416).with_header(header("Content-Range", &format!("bytes */{}", len))).boxed());
+        }
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let length = end - start + 1;
+    Ok(Response::new(StatusCode(206), vec![], file.take(length), Some(length as usize), None)
+        .with_header(content_type)
+        .with_header(header("Accept-Ranges", "bytes"))
+        .with_header(header("ETag", &etag))
+        .with_header(header("Content-Range", &format!("bytes {}-{}/{}", start, end, len)))
+        .boxed())
+}
+
+/// The inclusive byte range of a single-range `Range` header value, or `None` if it can't be satisfied.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (len.checked_sub(suffix.min(len))?, len.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len.checked_sub(1)?)),
+    };
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start, end))
+}
+
+#[cfg(not(feature = "gdal"))]
+mod features {
+    use super::*;
+
+    pub fn route(_: &[&str], _: &HashMap<String, String>, _: &str, opts: &MapServeOptions) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+        if opts.gpkgs.is_empty() {
+            return Ok(json_response(200, &json!({ "collections": [], "links": [] })));
+        }
+        Ok(error_response(501, "serving features needs nfhl_util built with `--features gdal`"))
+    }
+}
+
+#[cfg(feature = "gdal")]
+mod features {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::{FieldValue, Layer, LayerAccess};
+
+    use super::*;
+
+    pub fn route(segments: &[&str], query: &HashMap<String, String>, base: &str, opts: &MapServeOptions) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+        let collections = collections(opts)?;
+        let id = match segments {
+            [] => {
+                let list: Vec<Value> = collections.iter().map(|(id, _)| describe(id, base)).collect();
+                return Ok(json_response(200, &json!({
+                    "collections": list,
+                    "links": [{ "rel": "self", "type": "application/json", "href": format!("{}/collections", base) }],
+                })));
+            }
+            [id, ..] => *id,
+        };
+        let gpkg = match collections.iter().find(|(candidate, _)| candidate == id) {
+            Some((_, gpkg)) => gpkg,
+            None => return Ok(error_response(404, &format!("no collection {}", id))),
+        };
+        let layer_name = id.split_once('.').map(|(_, layer)| layer).unwrap_or(id);
+        let dataset = gdal::Dataset::open(gpkg)?;
+        let mut layer = dataset.layer_by_name(layer_name)?;
+        let to_lon_lat = lon_lat_transform(&layer)?;
+
+        match segments {
+            [_] => Ok(json_response(200, &describe(id, base))),
+            [_, "items"] => {
+                let limit = match query.get("limit").map(|l| l.parse::<usize>()) {
+                    Some(Ok(limit)) => limit.clamp(1, MAX_LIMIT),
+                    Some(Err(_)) => return Ok(error_response(400, "limit must be a number")),
+                    None => DEFAULT_LIMIT,
+                };
+                let offset = match query.get("offset").map(|o| o.parse::<usize>()) {
+                    Some(Ok(offset)) => offset,
+                    Some(Err(_)) => return Ok(error_response(400, "offset must be a number")),
+                    None => 0,
+                };
+                if let Some(bbox) = query.get("bbox") {
+                    let bbox: Vec<f64> = match bbox.split(',').map(|v| v.trim().parse()).collect() {
+                        Ok(bbox) => bbox,
+                        Err(_) => return Ok(error_response(400, "bbox must be min_lon,min_lat,max_lon,max_lat")),
+                    };
+                    let [min_x, min_y, max_x, max_y] = match bbox[..] {
+                        [a, b, c, d] => [a, b, c, d],
+                        _ => return Ok(error_response(400, "bbox must be min_lon,min_lat,max_lon,max_lat")),
+                    };
+                    let mut filter = gdal::vector::Geometry::from_wkt(&format!(
+                        "POLYGON(({0} {1},{2} {1},{2} {3},{0} {3},{0} {1}))", min_x, min_y, max_x, max_y))?;
+                    if let Some((_, from_lon_lat)) = &to_lon_lat {
+                        filter = filter.transform(from_lon_lat)?;
+                    }
+                    layer.set_spatial_filter(&filter);
+                }
+                let matched = layer.feature_count();
+                let features: Vec<Value> = layer.features()
+                    .skip(offset)
+                    .take(limit)
+                    .map(|feature| to_geojson(&feature, to_lon_lat.as_ref().map(|(to, _)| to)))
+                    .collect::<Result<_, _>>()?;
+                let mut links = vec![json!({
+                    "rel": "self", "type": "application/geo+json",
+                    "href": format!("{}/collections/{}/items?limit={}&offset={}", base, id, limit, offset),
+                })];
+                if (offset + features.len()) < matched as usize {
+                    let bbox = query.get("bbox").map(|b| format!("&bbox={}", b)).unwrap_or_default();
+                    links.push(json!({
+                        "rel": "next", "type": "application/geo+json",
+                        "href": format!("{}/collections/{}/items?limit={}&offset={}{}", base, id, limit, offset + limit, bbox),
+                    }));
+                }
+                Ok(geojson_response(&json!({
+                    "type": "FeatureCollection",
+                    "numberMatched": matched,
+                    "numberReturned": features.len(),
+                    "features": features,
+                    "links": links,
+                })))
+            }
+            [_, "items", fid] => {
+                let feature = fid.parse::<u64>().ok().and_then(|fid| layer.feature(fid));
+                match feature {
+                    Some(feature) => Ok(geojson_response(&to_geojson(&feature, to_lon_lat.as_ref().map(|(to, _)| to))?)),
+                    None => Ok(error_response(404, &format!("no feature {} in {}", fid, id))),
+                }
+            }
+            _ => Ok(error_response(404, "not found")),
+        }
+    }
+
+    /// Every layer of every GeoPackage, as `(collection id, gpkg)`.
+    fn collections(opts: &MapServeOptions) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+        let mut collections = Vec::new();
+        for gpkg in &opts.gpkgs {
+            let stem = gpkg.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            for layer in gdal::Dataset::open(gpkg)?.layers() {
+                collections.push((format!("{}.{}", stem, layer.name()), gpkg.clone()));
+            }
+        }
+        Ok(collections)
+    }
+
+    fn describe(id: &str, base: &str) -> Value {
+        json!({
+            "id": id,
+            "title": id,
+            "crs": ["http://www.opengis.net/def/crs/OGC/1.3/CRS84"],
+            "links": [
+                { "rel": "self", "type": "application/json", "href": format!("{}/collections/{}", base, id) },
+                { "rel": "items", "type": "application/geo+json", "href": format!("{}/collections/{}/items", base, id) },
+            ],
+        })
+    }
+
+    /// Transforms to and from longitude/latitude (WGS 84, which for a web map is the same as NAD83), unless the
+    /// layer already is.
+    fn lon_lat_transform(layer: &Layer) -> Result<Option<(CoordTransform, CoordTransform)>, Box<dyn std::error::Error>> {
+        let srs = match layer.spatial_ref() {
+            Some(srs) if !srs.is_geographic() => srs,
+            _ => return Ok(None),
+        };
+        let lon_lat = SpatialRef::from_epsg(4326)?;
+        lon_lat.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+        srs.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+        Ok(Some((CoordTransform::new(&srs, &lon_lat)?, CoordTransform::new(&lon_lat, &srs)?)))
+    }
+
+    fn to_geojson(feature: &gdal::vector::Feature, to_lon_lat: Option<&CoordTransform>) -> Result<Value, Box<dyn std::error::Error>> {
+        let geometry = match (feature.geometry(), to_lon_lat) {
+            (Some(geometry), Some(transform)) => serde_json::from_str(&geometry.transform(transform)?.json()?)?,
+            (Some(geometry), None) => serde_json::from_str(&geometry.json()?)?,
+            (None, _) => Value::Null,
+        };
+        let properties: serde_json::Map<String, Value> = feature.fields()
+            .map(|(name, value)| (name, value.map(field_json).unwrap_or(Value::Null)))
+            .collect();
+        Ok(json!({ "type": "Feature", "id": feature.fid(), "geometry": geometry, "properties": properties }))
+    }
+
+    fn field_json(value: FieldValue) -> Value {
+        match value {
+            FieldValue::IntegerValue(v) => json!(v),
+            FieldValue::IntegerListValue(v) => json!(v),
+            FieldValue::Integer64Value(v) => json!(v),
+            FieldValue::Integer64ListValue(v) => json!(v),
+            FieldValue::StringValue(v) => json!(v),
+            FieldValue::StringListValue(v) => json!(v),
+            FieldValue::RealValue(v) => json!(v),
+            FieldValue::RealListValue(v) => json!(v),
+            FieldValue::DateValue(v) => json!(v.to_string()),
+            FieldValue::DateTimeValue(v) => json!(v.to_rfc3339()),
+        }
+    }
+
+    fn geojson_response(body: &Value) -> ResponseBox {
+        Response::from_string(body.to_string())
+            .with_header(header("Content-Type", "application/geo+json"))
+            .boxed()
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::Write;
+
+use crate::diff;
+use crate::download::RunReport;
+use crate::html_report::{format_bytes, format_duration};
+use crate::report;
+
+/// Writes a short Markdown summary of the run, meant to be pasted into a PR comment or wiki page.
+pub fn write_run_report(out: &mut dyn Write, report: &RunReport) -> Result<(), Box<dyn std::error::Error>> {
+    let downloaded_bytes: u64 = report.downloads.iter().map(|d| d.bytes).sum();
+    let wall_seconds = (report.finished_at - report.started_at).num_milliseconds() as f64 / 1000.0;
+
+    writeln!(out, "**NFHL download {}: {}**", report.started_at.format("%Y-%m-%d"), diff::summarize_changes(&report.changes))?;
+    writeln!(out)?;
+    writeln!(out, "{} downloaded ({}), {} failed, {} already cached, {} deleted, in {}. Cache now holds {} files ({}).",
+        report.downloads.len(), format_bytes(downloaded_bytes), report.failures.len(), report.skipped,
+        report.deleted.len(), format_duration(wall_seconds), report.cache.files, format_bytes(report.cache.total_bytes))?;
+
+    if !report.changes.is_empty() {
+        writeln!(out)?;
+        let headers: Vec<String> = ["FIPS", "Change", "Old date", "New date"].iter().map(|h| h.to_string()).collect();
+        let rows: Vec<Vec<String>> = report.changes.iter()
+            .map(|c| vec![c.fips.clone(), c.kind.as_str().to_string(), c.old_date.clone(), c.new_date.clone()])
+            .collect();
+        report::write_markdown_table(out, &headers, &rows)?;
+    }
+
+    if !report.failures.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "**Failures**")?;
+        writeln!(out)?;
+        let headers: Vec<String> = ["FIPS", "Error"].iter().map(|h| h.to_string()).collect();
+        let rows: Vec<Vec<String>> = report.failures.iter()
+            .map(|f| vec![f.fips.clone(), f.error.replace('\n', " ")])
+            .collect();
+        report::write_markdown_table(out, &headers, &rows)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,432 @@
+//! `merge-geo`: appends the layers of many cached counties into one dataset, with each feature tagged by the county
+//! it came from. A manifest beside the output records which version of each county went in, so an interrupted merge
+//! picks up where it stopped, and a later one only redoes the counties whose files changed.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+
+/// The formats `merge-geo` can write.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeFormat {
+    /// A single GeoPackage holding every layer.
+    Gpkg,
+    /// A directory with a hive-style GeoParquet dataset per layer, partitioned by state
+    /// (`S_Fld_Haz_Ar/state_fips=48/part.parquet`).
+    Geoparquet,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub format: MergeFormat,
+    /// Only these layers; all of them if empty.
+    pub layers: Vec<String>,
+    pub translate: crate::convert::TranslateOptions,
+}
+
+/// A county going into a merge: its newest cached archive and that file's effective date.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub fips: String,
+    pub archive: PathBuf,
+    pub effective_date: Option<NaiveDate>,
+}
+
+/// The newest cached archive of every county whose fips passes `filter`.
+pub fn sources(cache_dir: &Path, filter: impl Fn(&str) -> bool) -> Result<Vec<Source>, Box<dyn std::error::Error>> {
+    let manifest = crate::cache::CacheManifest::load(cache_dir)?;
+    let mut sources: Vec<Source> = Vec::new();
+    // sorted by fips and then file name, so a county's newest archive comes last
+    for (fips, archive) in crate::cache::cached_archives(cache_dir)? {
+        if !filter(&fips) {
+            continue;
+        }
+        let effective_date = crate::cache::archive_effective_date(&manifest, &fips, &archive);
+        let source = Source { fips, archive, effective_date };
+        match sources.last_mut() {
+            Some(last) if last.fips == source.fips => *last = source,
+            _ => sources.push(source),
+        }
+    }
+    Ok(sources)
+}
+
+/// Every cached version of a county (more than one where `--keep-history` kept them), oldest effective date first.
+pub fn county_versions(cache_dir: &Path, fips: &str) -> Result<Vec<Source>, Box<dyn std::error::Error>> {
+    let manifest = crate::cache::CacheManifest::load(cache_dir)?;
+    let mut versions: Vec<Source> = crate::cache::cached_archives(cache_dir)?
+        .into_iter()
+        .filter(|(cached_fips, _)| cached_fips == fips)
+        .map(|(fips, archive)| {
+            let effective_date = crate::cache::archive_effective_date(&manifest, &fips, &archive);
+            Source { fips, archive, effective_date }
+        })
+        .collect();
+    versions.sort_by(|a, b| (a.effective_date, &a.archive).cmp(&(b.effective_date, &b.archive)));
+    Ok(versions)
+}
+
+/// The version of a county that went into a merge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MergedCounty {
+    pub file_name: String,
+    pub effective_date: Option<NaiveDate>,
+}
+
+impl MergedCounty {
+    fn of(source: &Source) -> MergedCounty {
+        MergedCounty {
+            file_name: source.archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default(),
+            effective_date: source.effective_date,
+        }
+    }
+}
+
+/// Which county versions a merged dataset holds. Lives at `{name}.manifest.json` beside a GeoPackage, or
+/// `manifest.json` in a GeoParquet directory.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MergeManifest {
+    pub format: MergeFormat,
+    pub updated_at: DateTime<Utc>,
+    /// The `ogr2ogr` options the counties were merged with; resuming with different ones would mix, say, CRSs.
+    #[serde(default)]
+    pub translate_args: Vec<String>,
+    pub counties: BTreeMap<String, MergedCounty>,
+}
+
+impl MergeManifest {
+    pub fn path(dest: &Path, format: MergeFormat) -> PathBuf {
+        match format {
+            MergeFormat::Gpkg => dest.with_extension("manifest.json"),
+            MergeFormat::Geoparquet => dest.join("manifest.json"),
+        }
+    }
+
+    /// The manifest of the merge at `dest`, or an empty one if there's no merge there to resume.
+    fn load(dest: &Path, format: MergeFormat, translate_args: Vec<String>) -> Result<MergeManifest, Box<dyn std::error::Error>> {
+        let path = MergeManifest::path(dest, format);
+        if !path.exists() || !dest.exists() {
+            return Ok(MergeManifest { format, updated_at: Utc::now(), translate_args, counties: BTreeMap::new() });
+        }
+        let manifest: MergeManifest = serde_json::from_reader(BufReader::new(File::open(&path)?))
+            .map_err(|e| format!("{} isn't a merge manifest: {}", path.display(), e))?;
+        if manifest.format != format {
+            return Err(format!("{} is from a {:?} merge; remove it or merge to a different place", path.display(), manifest.format).into());
+        }
+        if manifest.translate_args != translate_args {
+            return Err(format!("{} was merged with different options ({}); remove it or merge to a different place",
+                path.display(), manifest.translate_args.join(" ")).into());
+        }
+        Ok(manifest)
+    }
+
+    /// Saved after every county (or state) so an interruption loses at most that one; replaced atomically.
+    fn save(&mut self, dest: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.updated_at = Utc::now();
+        let path = MergeManifest::path(dest, self.format);
+        let tmp_path = path.with_extension("json.tmp");
+        serde_json::to_writer_pretty(BufWriter::new(File::create(&tmp_path)?), self)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// What a merge did.
+#[derive(Debug, Default)]
+pub struct MergeSummary {
+    /// Counties (re-)merged by this run.
+    pub merged: usize,
+    /// Counties already in the output at the same version, and left alone.
+    pub unchanged: usize,
+    /// Counties dropped from the output because they're no longer being merged (e.g. gone from the cache).
+    pub removed: usize,
+    /// Counties left out because their archive couldn't be read.
+    pub failed: Vec<String>,
+    /// Per layer, for GeoPackages: features in the output, and duplicates removed.
+    pub layers: Vec<(String, u64, u64)>,
+}
+
+/// Merges `sources` into `dest`, adding `source_fips` and `effective_date` columns to every layer, and resuming from
+/// (or updating) whatever merge is already there.
+#[cfg(feature = "gdal")]
+pub fn merge(sources: &[Source], dest: &Path, opts: &MergeOptions) -> Result<MergeSummary, Box<dyn std::error::Error>> {
+    if sources.is_empty() {
+        return Err("no cached counties to merge".into());
+    }
+    opts.translate.check()?;
+    let mut manifest = MergeManifest::load(dest, opts.format, opts.translate.args())?;
+    match opts.format {
+        MergeFormat::Gpkg => merge_gpkg(sources, dest, opts, &mut manifest),
+        MergeFormat::Geoparquet => merge_geoparquet(sources, dest, opts, &mut manifest),
+    }
+}
+
+/// Counties are appended one at a time, and a county being redone (or left half done by an interrupted run) has its
+/// features deleted first. Counties of the same study often both ship a feature that straddles their shared
+/// boundary; once everything's in, of features identical in geometry and attributes only the first is kept.
+#[cfg(feature = "gdal")]
+fn merge_gpkg(sources: &[Source], dest: &Path, opts: &MergeOptions, manifest: &mut MergeManifest) -> Result<MergeSummary, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    if manifest.counties.is_empty() && dest.exists() {
+        std::fs::remove_file(dest)?;
+    }
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut summary = MergeSummary::default();
+    let wanted: BTreeMap<&str, &Source> = sources.iter().map(|s| (s.fips.as_str(), s)).collect();
+    let gone: Vec<String> = manifest.counties.keys().filter(|fips| !wanted.contains_key(fips.as_str())).cloned().collect();
+    for fips in gone {
+        delete_county(dest, &fips)?;
+        manifest.counties.remove(&fips);
+        manifest.save(dest)?;
+        summary.removed += 1;
+    }
+
+    for source in sources {
+        if manifest.counties.get(&source.fips) == Some(&MergedCounty::of(source)) {
+            summary.unchanged += 1;
+            continue;
+        }
+        if dest.exists() {
+            delete_county(dest, &source.fips)?;
+        }
+        manifest.counties.remove(&source.fips);
+        if let Err(e) = append_county(source, dest, opts) {
+            eprintln!("{}: {}", source.fips, e);
+            summary.failed.push(source.fips.clone());
+            continue;
+        }
+        manifest.counties.insert(source.fips.clone(), MergedCounty::of(source));
+        manifest.save(dest)?;
+        summary.merged += 1;
+    }
+    if !dest.exists() {
+        return Err("none of the counties could be merged".into());
+    }
+
+    let merged = open_for_update(dest)?;
+    let names: Vec<String> = merged.layers().map(|layer| layer.name()).collect();
+    for name in names {
+        let (features, key) = {
+            let layer = merged.layer_by_name(&name)?;
+            let mut key: Vec<String> = layer.defn().fields()
+                .map(|field| field.name())
+                .filter(|field| field != "source_fips" && field != "effective_date")
+                .collect();
+            key.extend(layer.defn().geom_fields().map(|field| field.name()));
+            (layer.feature_count(), key)
+        };
+        let duplicates = remove_duplicates(&merged, &name, &key)?;
+        summary.layers.push((name, features - duplicates, duplicates));
+    }
+    Ok(summary)
+}
+
+#[cfg(feature = "gdal")]
+fn open_for_update(path: &Path) -> Result<gdal::Dataset, Box<dyn std::error::Error>> {
+    Ok(gdal::Dataset::open_ex(path, gdal::DatasetOptions {
+        open_flags: gdal::GdalOpenFlags::GDAL_OF_UPDATE | gdal::GdalOpenFlags::GDAL_OF_VECTOR,
+        ..Default::default()
+    })?)
+}
+
+/// Deletes a county's features from every layer of a merged GeoPackage.
+#[cfg(feature = "gdal")]
+fn delete_county(dest: &Path, fips: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let merged = open_for_update(dest)?;
+    let names: Vec<String> = merged.layers().map(|layer| layer.name()).collect();
+    for name in names {
+        merged.execute_sql(
+            format!("DELETE FROM {} WHERE source_fips = '{}'", quote(&name), fips.replace('\'', "''")),
+            None,
+            gdal::vector::sql::Dialect::DEFAULT,
+        )?;
+    }
+    Ok(())
+}
+
+/// Appends one county's layers to the GeoPackage at `dest`, creating it if need be.
+#[cfg(feature = "gdal")]
+fn append_county(source: &Source, dest: &Path, opts: &MergeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let src = gdal::Dataset::open(&gdb)?;
+    for layer in wanted_layers(&src, &opts.layers) {
+        let mut args: Vec<String> = ["-f", "GPKG", "-lco", "FID=fid"].map(String::from).to_vec();
+        if dest.exists() {
+            // counties built against older schema revisions lack some fields, and newer ones add some
+            args.push("-addfields".into());
+        }
+        args.extend(translate_args(opts));
+        args.extend([
+            "-nln".to_string(), layer.clone(),
+            "-nlt".to_string(), "PROMOTE_TO_MULTI".to_string(),
+            "-dialect".to_string(), "OGRSQL".to_string(),
+            "-sql".to_string(), tagged_select(source, &layer, opts.translate.where_clause.as_deref()),
+        ]);
+        crate::convert::vector_translate(&src, dest, &args)?;
+    }
+    Ok(())
+}
+
+/// Each state is written as a unit, all of its layers' partitions at once from an OGR VRT union of its counties,
+/// and redone whenever any of its counties changed. Duplicates along county boundaries are kept, since Parquet
+/// files can't be edited in place; `source_fips` says which copy is which.
+#[cfg(feature = "gdal")]
+fn merge_geoparquet(sources: &[Source], dest: &Path, opts: &MergeOptions, manifest: &mut MergeManifest) -> Result<MergeSummary, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    std::fs::create_dir_all(dest)?;
+    let mut states: BTreeMap<&str, Vec<&Source>> = BTreeMap::new();
+    for source in sources {
+        states.entry(source.fips.get(..2).unwrap_or_default()).or_default().push(source);
+    }
+
+    let mut summary = MergeSummary::default();
+    let gone: Vec<String> = manifest.counties.keys()
+        .filter(|fips| !states.contains_key(fips.get(..2).unwrap_or_default()))
+        .cloned()
+        .collect();
+    for state in gone.iter().filter_map(|fips| fips.get(..2)).collect::<std::collections::BTreeSet<_>>() {
+        remove_state_partitions(dest, state)?;
+    }
+    for fips in gone {
+        manifest.counties.remove(&fips);
+        summary.removed += 1;
+    }
+    manifest.save(dest)?;
+
+    for (state, counties) in states {
+        let in_manifest: Vec<&String> = manifest.counties.keys().filter(|fips| fips.starts_with(state)).collect();
+        let up_to_date = in_manifest.len() == counties.len()
+            && counties.iter().all(|source| manifest.counties.get(&source.fips) == Some(&MergedCounty::of(source)));
+        if up_to_date {
+            summary.unchanged += counties.len();
+            continue;
+        }
+
+        // layer -> the counties that have it, with their geodatabases
+        let mut layers: BTreeMap<String, Vec<(&Source, String)>> = BTreeMap::new();
+        let mut merged = Vec::new();
+        for source in counties {
+            let opened = crate::extract::require_archive_gdb_path(&source.archive)
+                .and_then(|gdb| Ok((gdal::Dataset::open(&gdb)?, gdb)));
+            match opened {
+                Ok((src, gdb)) => {
+                    for layer in wanted_layers(&src, &opts.layers) {
+                        layers.entry(layer).or_default().push((source, gdb.clone()));
+                    }
+                    merged.push(source);
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", source.fips, e);
+                    summary.failed.push(source.fips.clone());
+                }
+            }
+        }
+
+        remove_state_partitions(dest, state)?;
+        for (layer, counties) in &layers {
+            use crate::html_report::escape;
+
+            let mut vrt = format!("<OGRVRTDataSource><OGRVRTUnionLayer name=\"{}\">", escape(layer));
+            for (source, gdb) in counties {
+                vrt.push_str(&format!(
+                    "<OGRVRTLayer name=\"{}\"><SrcDataSource>{}</SrcDataSource><SrcSQL dialect=\"OGRSQL\">{}</SrcSQL></OGRVRTLayer>",
+                    escape(&source.fips), escape(gdb), escape(&tagged_select(source, layer, opts.translate.where_clause.as_deref()))));
+            }
+            vrt.push_str("</OGRVRTUnionLayer></OGRVRTDataSource>");
+            let partition = dest.join(layer).join(format!("state_fips={}", state));
+            std::fs::create_dir_all(&partition)?;
+            let src = gdal::Dataset::open(&vrt)?;
+            let mut args: Vec<String> = ["-f", "Parquet", "-nlt", "PROMOTE_TO_MULTI"].map(String::from).to_vec();
+            args.extend(translate_args(opts));
+            args.push(layer.clone());
+            crate::convert::vector_translate(&src, &partition.join("part.parquet"), &args)?;
+        }
+
+        manifest.counties.retain(|fips, _| !fips.starts_with(state));
+        for source in &merged {
+            manifest.counties.insert(source.fips.clone(), MergedCounty::of(source));
+        }
+        manifest.save(dest)?;
+        summary.merged += merged.len();
+        eprintln!("state {}: merged {} counties into {} layers", state, merged.len(), layers.len());
+    }
+    Ok(summary)
+}
+
+/// Removes a state's partition from every layer of a GeoParquet merge.
+fn remove_state_partitions(dest: &Path, state: &str) -> Result<(), Box<dyn std::error::Error>> {
+    for dir_entry in std::fs::read_dir(dest)? {
+        let partition = dir_entry?.path().join(format!("state_fips={}", state));
+        if partition.is_dir() {
+            std::fs::remove_dir_all(partition)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "gdal")]
+pub fn wanted_layers(src: &gdal::Dataset, wanted: &[String]) -> Vec<String> {
+    use gdal::vector::LayerAccess;
+
+    src.layers()
+        .map(|layer| layer.name())
+        .filter(|name| wanted.is_empty() || wanted.iter().any(|l| l.eq_ignore_ascii_case(name)))
+        .collect()
+}
+
+/// The OGR SQL selecting a county's layer with its `source_fips` and `effective_date` added, and only the features
+/// matching `where_clause`.
+pub fn tagged_select(source: &Source, layer: &str, where_clause: Option<&str>) -> String {
+    let effective_date = source.effective_date.map(|d| d.to_string()).unwrap_or_default();
+    let mut sql = format!("SELECT *, '{}' AS source_fips, '{}' AS effective_date FROM {}", source.fips, effective_date, quote(layer));
+    if let Some(condition) = where_clause {
+        sql.push_str(&format!(" WHERE {}", condition));
+    }
+    sql
+}
+
+/// The `ogr2ogr` arguments for the translate options, except `--where`: `ogr2ogr` ignores `-where` next to `-sql`, so
+/// it goes in `tagged_select` instead.
+fn translate_args(opts: &MergeOptions) -> Vec<String> {
+    crate::convert::TranslateOptions { where_clause: None, ..opts.translate.clone() }.args()
+}
+
+/// Quotes an SQL identifier.
+pub fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Deletes all but the first of each set of features of `layer` that agree on every column in `key`, returning how
+/// many went.
+#[cfg(feature = "gdal")]
+fn remove_duplicates(merged: &gdal::Dataset, layer: &str, key: &[String]) -> Result<u64, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    if key.is_empty() {
+        return Ok(0);
+    }
+    let count = |merged: &gdal::Dataset| -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(merged.layer_by_name(layer)?.feature_count())
+    };
+    let before = count(merged)?;
+    let group_by: Vec<String> = key.iter().map(|column| quote(column)).collect();
+    merged.execute_sql(
+        format!("DELETE FROM {table} WHERE fid NOT IN (SELECT MIN(fid) FROM {table} GROUP BY {})",
+            group_by.join(", "), table = quote(layer)),
+        None,
+        gdal::vector::sql::Dialect::DEFAULT,
+    )?;
+    Ok(before - count(merged)?)
+}
@@ -0,0 +1,109 @@
+//! `migrate-cache`: moves a cache's files from one `CacheLayout` to another. Each file is hardlinked (copied, across
+//! filesystems) to its new place, checked against the manifest, and only then is the old name deleted, so a
+//! migration that's interrupted or fails part way leaves every file readable and can simply be run again.
+
+use std::path::{Path, PathBuf};
+
+use crate::cache::{self, CacheLayout, CacheManifest};
+use crate::error::NfhlError;
+use crate::info::sha256_file;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Migration {
+    pub moved: usize,
+    /// Files already in their new place, from an earlier, interrupted migration.
+    pub already_moved: usize,
+    pub bytes: u64,
+}
+
+pub fn migrate_cache(cache_dir: &Path, from: CacheLayout, to: CacheLayout) -> Result<Migration, Box<dyn std::error::Error>> {
+    if from == to {
+        return Err(format!("the cache is already laid out {}", to.as_str()).into());
+    }
+    let mut manifest = CacheManifest::load(cache_dir)?;
+    // an interrupted migration has already switched the manifest
+    if manifest.layout != from && manifest.layout != to {
+        return Err(format!("{} is laid out {}, not {}", cache_dir.display(), manifest.layout.as_str(), from.as_str()).into());
+    }
+
+    let mut migration = Migration::default();
+    let mut archives: Vec<(String, PathBuf)> = cache::cached_archives(cache_dir)?;
+    // the counties' other files, which cached_archives leaves out
+    for file_name in manifest.files.keys() {
+        let path = cache_dir.join(file_name);
+        let base_name = path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+        match crate::inventory::file_name_fips(base_name) {
+            Some(fips) if path.exists() => archives.push((fips.into(), path)),
+            Some(_) => {}
+            None => eprintln!("leaving {} where it is: its name doesn't say which county it's for", file_name),
+        }
+    }
+    for (fips, old_path) in archives {
+        let old_name = cache::relative_name(cache_dir, &old_path);
+        let base_name = old_path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+        let new_name = to.relative_path(&fips, base_name);
+        if new_name == old_name {
+            migration.already_moved += 1;
+            continue;
+        }
+        let new_path = cache_dir.join(&new_name);
+        let size = std::fs::metadata(&old_path).map_err(NfhlError::io(&old_path))?.len();
+
+        if new_path.exists() {
+            // linked by an earlier run that stopped before deleting the old name
+            if std::fs::metadata(&new_path).map_err(NfhlError::io(&new_path))?.len() != size {
+                return Err(format!("both {} and {} exist, at different sizes; remove the wrong one and run again", old_name, new_name).into());
+            }
+        } else {
+            if let Some(parent) = new_path.parent() {
+                std::fs::create_dir_all(parent).map_err(NfhlError::io(parent))?;
+            }
+            if std::fs::hard_link(&old_path, &new_path).is_err() {
+                std::fs::copy(&old_path, &new_path).map_err(NfhlError::io(&new_path))?;
+            }
+        }
+
+        let other_file = manifest.files.contains_key(&old_name);
+        let recorded = match other_file {
+            true => manifest.files.get(&old_name),
+            false => manifest.entries.get(&fips).filter(|cached| cached.file_name == old_name),
+        };
+        let expected_sha256 = recorded.and_then(|cached| cached.sha256.clone());
+        let recorded = recorded.is_some();
+        let check = || -> Result<(), Box<dyn std::error::Error>> {
+            if std::fs::metadata(&new_path)?.len() != size {
+                return Err(format!("{} is {} bytes, not {}", new_name, std::fs::metadata(&new_path)?.len(), size).into());
+            }
+            if let Some(expected) = &expected_sha256 {
+                let actual = sha256_file(&new_path)?;
+                if &actual != expected {
+                    return Err(format!("{} has SHA-256 {}, but {} was downloaded with {}", new_name, actual, old_name, expected).into());
+                }
+            }
+            Ok(())
+        };
+        if let Err(e) = check() {
+            // the old name is untouched; drop the bad copy so a rerun starts this file over
+            let _ = std::fs::remove_file(&new_path);
+            return Err(format!("couldn't move {}: {}", old_name, e).into());
+        }
+
+        if other_file {
+            let mut cached = manifest.files.remove(&old_name).expect("checked above");
+            cached.file_name = new_name.clone();
+            manifest.files.insert(new_name.clone(), cached);
+            manifest.save(cache_dir)?;
+        } else if recorded {
+            manifest.entries.get_mut(&fips).expect("checked above").file_name = new_name.clone();
+            manifest.save(cache_dir)?;
+        }
+        std::fs::remove_file(&old_path).map_err(NfhlError::io(&old_path))?;
+        eprintln!("moved {} to {}", old_name, new_name);
+        migration.moved += 1;
+        migration.bytes += size;
+    }
+
+    manifest.layout = to;
+    manifest.save(cache_dir)?;
+    Ok(migration)
+}
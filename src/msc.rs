@@ -0,0 +1,566 @@
+//! The Map Service Center (msc.fema.gov) and its stateful advanced search, which is where statewide products are
+//! listed. Its JSON is parsed by `nfhl_parse::msc`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::future::Future;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::cancel::{self, CancellationToken};
+use crate::client::Client;
+use crate::error::{NfhlError, Result};
+use crate::inventory::{Fips, Inventory, InventoryEntry};
+use crate::plan::{ListedFile, Product};
+use crate::source::{Jurisdiction, ProductSource};
+
+pub use nfhl_parse::msc::{ListedProduct, SearchResultEffective, SearchResultProductEntry, SearchResults};
+
+pub const ADVANCE_SEARCH_URL: &str = "https://msc.fema.gov/portal/advanceSearch";
+
+/// How many of a FEMA region's states are searched at once, unless told otherwise.
+pub const DEFAULT_REGION_CONCURRENCY: usize = 1;
+
+/// The states and territories of FEMA regions 1 through 10.
+const FEMA_REGIONS: [&[&str]; 10] = [
+    &["ME", "NH", "VT", "MA", "CT", "RI"],
+    &["NY", "NJ", "PR", "VI"],
+    &["MD", "PA", "WV", "DC", "DE", "VA"],
+    &["NC", "SC", "GA", "FL", "AL", "MS", "TN", "KY"],
+    &["IL", "IN", "OH", "MI", "WI", "MN"],
+    &["NM", "TX", "OK", "LA", "AR"],
+    &["NE", "IA", "KS", "MO"],
+    &["MT", "ND", "SD", "WY", "UT", "CO"],
+    &["NV", "AZ", "CA", "FM", "GU", "HI", "MH", "MP", "AS"],
+    &["AK", "WA", "OR", "ID"],
+];
+
+/// The FEMA region (1 to 10) `state`, a postal code, is in.
+pub fn fema_region(state: &str) -> Option<u8> {
+    FEMA_REGIONS.iter().position(|states| states.contains(&state)).map(|i| i as u8 + 1)
+}
+
+fn state_to_representative_county() -> HashMap<&'static str, &'static str> {
+    // in order to query msc.fema.gov, we must look for a specific community. To that end, each state has a county.
+    HashMap::from([
+        // ("AK", "02"),
+        ("AL", "01101"),
+        ("AR", "05029"),
+        // ("AS", "60"),
+        // ("AZ", "04"),
+        // ("CA", "06"),
+        // ("CO", "08"),
+        // ("CT", "09"),
+        // ("DC", "11"),
+        // ("DE", "10"),
+        // ("FL", "12"),
+        // ("GA", "13"),
+        // ("GU", "66"),
+        // ("HI", "15"),
+        // ("IA", "19"),
+        // ("ID", "16"),
+        // ("IL", "17"),
+        // ("IN", "18"),
+        // ("KS", "20"),
+        // ("KY", "21"),
+        // ("LA", "22"),
+        // ("MA", "25"),
+        // ("MD", "24"),
+        // ("ME", "23"),
+        // ("MI", "26"),
+        // ("MN", "27"),
+        // ("MO", "29"),
+        // ("MS", "28"),
+        // ("MT", "30"),
+        // ("NC", "37"),
+        // ("ND", "38"),
+        // ("NE", "31"),
+        // ("NH", "33"),
+        // ("NJ", "34"),
+        // ("NM", "35"),
+        // ("NV", "32"),
+        // ("NY", "36"),
+        // ("OH", "39"),
+        // ("OK", "40"),
+        // ("OR", "41"),
+        // ("PA", "42"),
+        // ("PR", "72"),
+        // ("RI", "44"),
+        // ("SC", "45"),
+        // ("SD", "46"),
+        // ("TN", "47"),
+        // ("TX", "48"),
+        // ("UT", "49"),
+        // ("VA", "51"),
+        // ("VI", "78"),
+        // ("VT", "50"),
+        // ("WA", "53"),
+        // ("WI", "55"),
+        // ("WV", "54"),
+        // ("WY", "56"),
+        // ("MH", "68"), // may not be available in MSC
+        // ("MP", "69"),
+        // ("FM", "64") // may not be available in MSC
+    ])
+}
+
+/// Starts a session; the advanced search is stateful and answers nothing without one.
+pub async fn start_session(client: &Client) -> Result<()> {
+    crate::http::text(client, client.get(ADVANCE_SEARCH_URL)).await?;
+    Ok(())
+}
+
+/// Everything MSC lists for a county: its effective county and state data and any preliminary database.
+pub async fn search(client: &Client, county_fips: &str) -> Result<SearchResults> {
+    parse_search_results(&search_body(client, county_fips).await?)
+}
+
+/// `search`'s response as it came, before it's parsed.
+pub async fn search_body(client: &Client, county_fips: &str) -> Result<String> {
+    let state_code = county_fips.get(..2)
+        .ok_or_else(|| NfhlError::Validation(format!("'{}' isn't a county fips code", county_fips)))?;
+    // let b = client.get(format!("https://msc.fema.gov/portal/advanceSearch?getCommunity={}&state={}",representative_county, state_code))
+    //     .send()?;
+    let cid = format!("{}C", county_fips);
+    let request = client.post(ADVANCE_SEARCH_URL)
+        .form(&[
+            ("utf8", "✓"), // I kid you not, this is included in every post to the official site.
+            ("affiliate", "fema"),
+            ("query", ""), // intentionally blank?
+            ("selstate", state_code),
+            ("selcounty", county_fips),
+            ("selcommunity", &cid),
+            ("jurisdictionkey", ""),
+            ("searchedCid", &cid),
+            ("searchedDateStart", ""),
+            ("searchedDateEnd", ""),
+            ("txtstartdate", ""),
+            ("txtenddate", ""),
+            ("method", "search")
+        ]);
+    crate::http::text(client, request).await
+}
+
+/// Whether MSC's listing is a file of `product`, going by the group it's listed under and its type ids.
+fn is_product(product: Product, listed: &ListedProduct) -> bool {
+    let (group, type_id, subtype_id) = (listed.group.as_str(), &listed.product.type_id, &listed.product.subtype_id);
+    match product {
+        Product::Fis => group == "EFFECTIVE" && type_id == "FIS",
+        Product::Lomc => type_id == "LOMC",
+        Product::Historic => group == "HISTORIC",
+        Product::Frd => type_id.contains("FRD") || subtype_id.contains("FRD"),
+        Product::HistoricPanels => group == "HISTORIC" && type_id == "FIRM",
+        Product::Effective | Product::Preliminary => false,
+    }
+}
+
+/// Where a listed file of `product` is cached in the product's directory: `{fips}/{MSC's file name}`, except for
+/// historic panels, which are `{fips}/{panel}_{YYYYMMDD}.{tif|pdf}` so a panel's scans sort by date whatever MSC
+/// called them. None if MSC didn't give a file name.
+fn listed_file_name(product: Product, county: &str, listed: &SearchResultProductEntry) -> Option<String> {
+    let name = listed.filename.as_deref()?.rsplit(['/', '\\']).next().filter(|name| !name.is_empty())?;
+    if product != Product::HistoricPanels {
+        return Some(format!("{}/{}", county, name));
+    }
+    let ext = match name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()) {
+        Some(ext) if ext != "tiff" => ext,
+        _ => "tif".to_string(),
+    };
+    Some(match listed.file_date() {
+        Some(date) => format!("{}/{}_{}.{}", county, listed.name, date.format("%Y%m%d"), ext),
+        None => format!("{}/{}.{}", county, listed.name, ext),
+    })
+}
+
+/// The files of `product` MSC lists for each of `counties`, searched one at a time `delay` apart, for the products
+/// the inventory doesn't have. Stops with `Interrupted` once `cancel` is.
+pub async fn list_product_files(
+    client: &Client,
+    counties: &[&str],
+    product: Product,
+    delay: Duration,
+    cancel: &CancellationToken,
+) -> Result<Vec<ListedFile>> {
+    if product.inventory_kind().is_some() {
+        return Err(NfhlError::Validation(format!("{} files are listed in the inventory", product.as_str())));
+    }
+    cancel::or_cancelled(cancel, start_session(client)).await?;
+    let mut files: Vec<ListedFile> = Vec::new();
+    for (i, county) in counties.iter().enumerate() {
+        if i > 0 && !crate::download::pause(delay, cancel).await {
+            return Err(NfhlError::Interrupted);
+        }
+        let body = cancel::or_cancelled(cancel, search_body(client, county)).await?;
+        let found = files.len();
+        for listed in nfhl_parse::msc::parse_listed_products(&body)? {
+            if !is_product(product, &listed) {
+                continue;
+            }
+            let Some(file_name) = listed_file_name(product, county, &listed.product) else {
+                continue;
+            };
+            // the same letter or report can be listed under more than one group
+            if files[found..].iter().any(|file| file.file_name == file_name) {
+                continue;
+            }
+            files.push(ListedFile {
+                fips: county.to_string(),
+                file_name,
+                url: listed.product.download_url()?.to_string(),
+                date: listed.product.file_date(),
+            });
+        }
+        eprintln!("{}: {} {} files", county, files.len() - found, product.as_str());
+    }
+    Ok(files)
+}
+
+/// An advanced search's JSON results; see `nfhl_parse::msc`.
+pub fn parse_search_results(body: &str) -> Result<SearchResults> {
+    Ok(nfhl_parse::msc::parse_search_results(body)?)
+}
+
+/// The states MSC can be searched for, by 2-letter abbreviation, in order.
+pub fn states() -> Vec<&'static str> {
+    let mut states: Vec<&str> = state_to_representative_county().into_keys().collect();
+    states.sort_unstable();
+    states
+}
+
+/// What `get_state_products` got through.
+#[derive(Debug, Clone, Default)]
+pub struct StateProducts {
+    /// The statewide products found, keyed by 2-digit fips.
+    pub inventory: Inventory,
+    /// The states not searched because it was cancelled, or their FEMA region couldn't be reached; pass them back in
+    /// to finish, and merge the inventories.
+    pub remaining: Vec<String>,
+    /// The FEMA regions whose searches failed on the network, leaving their states in `remaining`.
+    pub unreachable_regions: BTreeSet<u8>,
+    /// The states whose results couldn't be read, and are missing from the inventory.
+    pub quarantined: Vec<Quarantined>,
+}
+
+impl StateProducts {
+    /// Ok if every state was searched. Otherwise a network error if a region couldn't be reached, since trying
+    /// again later may get through, or `Interrupted`.
+    pub fn finished(&self) -> Result<()> {
+        if self.remaining.is_empty() {
+            return Ok(());
+        }
+        if self.unreachable_regions.is_empty() {
+            return Err(NfhlError::Interrupted);
+        }
+        let regions: Vec<String> = self.unreachable_regions.iter().map(u8::to_string).collect();
+        Err(NfhlError::Network(format!("MSC couldn't be searched for FEMA region {}, leaving {} unsearched",
+            regions.join(", "), self.remaining.join(", ")).into()))
+    }
+}
+
+/// A state whose search results didn't parse, as when MSC adds or renames something.
+#[derive(Serialize, Debug, Clone)]
+pub struct Quarantined {
+    pub state: String,
+    pub county_fips: String,
+    pub error: String,
+    /// Where the response was saved for a look at what changed, unless saving it failed too.
+    pub saved_to: Option<PathBuf>,
+}
+
+/// Where undecodable responses are saved: `NFHL_UTIL_DIAGNOSTICS_DIR`, or `nfhl_util-diagnostics` in the temp
+/// directory.
+pub fn diagnostics_dir() -> PathBuf {
+    std::env::var_os("NFHL_UTIL_DIAGNOSTICS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("nfhl_util-diagnostics"))
+}
+
+/// Saves the body under `dir` and logs a one-line JSON warning saying so, for log pipelines to alert on.
+fn quarantine(dir: &Path, state: &str, county_fips: &str, body: &str, error: &NfhlError) -> Quarantined {
+    let path = dir.join(format!("msc_{}_{}.json", state, Utc::now().format("%Y%m%dT%H%M%SZ")));
+    let saved_to = match std::fs::create_dir_all(dir).and_then(|()| std::fs::write(&path, body)) {
+        Ok(()) => Some(path),
+        Err(e) => {
+            eprintln!("couldn't save MSC's response for {} to {}: {}", state, path.display(), e);
+            None
+        }
+    };
+    let quarantined = Quarantined {
+        state: state.to_string(),
+        county_fips: county_fips.to_string(),
+        error: error.to_string(),
+        saved_to,
+    };
+    eprintln!("{}", serde_json::json!({ "level": "warning", "event": "msc_response_quarantined", "quarantined": &quarantined }));
+    quarantined
+}
+
+/// Each state's effective statewide NFHL product, found by searching one representative county per state, keyed by
+/// 2-digit fips.
+pub async fn get_effective_state_products(client: &Client) -> Result<Inventory> {
+    effective_state_products(client, &diagnostics_dir()).await
+}
+
+/// `get_effective_state_products`, saving responses that can't be read under `diagnostics`.
+async fn effective_state_products(client: &Client, diagnostics: &Path) -> Result<Inventory> {
+    let cancel = CancellationToken::new();
+    let products = get_state_products(client, &states(), DEFAULT_REGION_CONCURRENCY, &cancel, diagnostics).await?;
+    products.finished()?;
+    Ok(products.inventory)
+}
+
+/// `get_effective_state_products` for some of the states, searching up to `region_concurrency` states of each FEMA
+/// region at a time, stopping once `cancel` is. Responses that can't be read are saved under `diagnostics`, e.g.
+/// `diagnostics_dir()`.
+pub async fn get_state_products(
+    client: &Client,
+    states: &[&str],
+    region_concurrency: usize,
+    cancel: &CancellationToken,
+    diagnostics: &Path,
+) -> Result<StateProducts> {
+    let products = search_states(client, states, region_concurrency, cancel, diagnostics, |_, _| Ok(())).await?;
+    let searched = states.len() - products.remaining.len();
+    if searched > 0 && products.quarantined.len() == searched {
+        return Err(all_quarantined(searched, diagnostics));
+    }
+    Ok(products)
+}
+
+/// The states searched so far by `get_checkpointed_state_products`, saved after each one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatesCheckpoint {
+    /// The states whose search was read, whether or not it found a statewide product. Quarantined states aren't,
+    /// so they're tried again.
+    pub searched: BTreeSet<String>,
+    pub inventory: Inventory,
+}
+
+impl StatesCheckpoint {
+    pub fn load(path: &Path) -> Result<StatesCheckpoint> {
+        let f = File::open(path).map_err(NfhlError::io(path))?;
+        serde_json::from_reader(BufReader::new(f)).map_err(NfhlError::parse(path))
+    }
+
+    /// Written atomically, so a run killed while saving leaves the previous checkpoint.
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_vec(self).expect("a checkpoint always serializes");
+        std::fs::write(&tmp_path, json).map_err(NfhlError::io(&tmp_path))?;
+        std::fs::rename(&tmp_path, path).map_err(NfhlError::io(path))
+    }
+}
+
+/// Where `states_inventory` checkpoints: `nfhl_util-states.checkpoint.json` in the temp directory.
+pub fn states_checkpoint_path() -> PathBuf {
+    std::env::temp_dir().join("nfhl_util-states.checkpoint.json")
+}
+
+/// `get_state_products` for every state, saving what's been found to `checkpoint` after each so a run that fails
+/// part way can be picked up again. With `resume`, the states already in `checkpoint` aren't searched again; without
+/// it, any checkpoint there is started over. The checkpoint is removed once every state is searched, and the
+/// inventory returned is all of them, the resumed ones included. Responses that can't be read are saved under
+/// `diagnostics`.
+pub async fn get_checkpointed_state_products(
+    client: &Client,
+    checkpoint: &Path,
+    resume: bool,
+    region_concurrency: usize,
+    cancel: &CancellationToken,
+    diagnostics: &Path,
+) -> Result<StateProducts> {
+    let mut saved = if resume && checkpoint.exists() { StatesCheckpoint::load(checkpoint)? } else { Default::default() };
+    let resumed = saved.searched.len();
+    let states: Vec<&str> = states().into_iter().filter(|state| !saved.searched.contains(*state)).collect();
+    let mut products = search_states(client, &states, region_concurrency, cancel, diagnostics, |state, entry| {
+        saved.searched.insert(state.to_string());
+        if let Some((fips, entry)) = entry {
+            saved.inventory.insert(fips.clone(), entry.clone());
+        }
+        saved.save(checkpoint)
+    }).await?;
+
+    let searched = states.len() - products.remaining.len();
+    if resumed == 0 && searched > 0 && products.quarantined.len() == searched {
+        return Err(all_quarantined(searched, diagnostics));
+    }
+    products.inventory = saved.inventory;
+    if products.remaining.is_empty() {
+        std::fs::remove_file(checkpoint).or_else(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        }).map_err(NfhlError::io(checkpoint))?;
+    }
+    Ok(products)
+}
+
+/// Searches `states`, telling `searched` about each one whose results were read and what it found, and saving the
+/// responses of those that couldn't be read under `diagnostics`. Each FEMA region's
+/// states are queued separately and searched up to `region_concurrency` at a time, so a region whose searches fail on
+/// the network only leaves its own states in `remaining`; the other regions carry on. Only if nothing at all could
+/// be searched is the network error returned.
+async fn search_states(
+    client: &Client,
+    states: &[&str],
+    region_concurrency: usize,
+    cancel: &CancellationToken,
+    diagnostics: &Path,
+    mut searched: impl FnMut(&str, Option<&(Fips, InventoryEntry)>) -> Result<()>,
+) -> Result<StateProducts> {
+    let counties = state_to_representative_county();
+    let mut products = StateProducts::default();
+    if states.is_empty() {
+        return Ok(products);
+    }
+    let mut queues: BTreeMap<u8, VecDeque<(&str, &str)>> = BTreeMap::new();
+    for state in states {
+        let representative_county = counties.get(state)
+            .ok_or_else(|| NfhlError::Validation(format!("MSC can't be searched for the state '{}'", state)))?;
+        let region = fema_region(state)
+            .ok_or_else(|| NfhlError::Validation(format!("'{}' isn't in a FEMA region", state)))?;
+        queues.entry(region).or_default().push_back((state, representative_county));
+    }
+    match cancel::or_cancelled(cancel, start_session(client)).await {
+        Err(NfhlError::Interrupted) => {
+            products.remaining = states.iter().map(|state| state.to_string()).collect();
+            return Ok(products);
+        }
+        result => result?,
+    }
+
+    let mut in_flight: HashMap<u8, usize> = HashMap::new();
+    let mut searches = JoinSet::new();
+    let mut read = 0;
+    let mut unreachable = None;
+    loop {
+        // start searches in each region until it has enough going
+        for (&region, queue) in queues.iter_mut() {
+            let running = in_flight.entry(region).or_default();
+            while *running < region_concurrency.max(1) && !cancel::requested(cancel) {
+                let Some((state, representative_county)) = queue.pop_front() else {
+                    break;
+                };
+                *running += 1;
+                let (client, token) = (client.clone(), cancel.clone());
+                let (state, county) = (state.to_string(), representative_county.to_string());
+                spawn_search(&mut searches, async move {
+                    let body = cancel::or_cancelled(&token, search_body(&client, &county)).await;
+                    (region, state, county, body)
+                });
+            }
+        }
+
+        let (region, state, representative_county, body) = match searches.join_next().await {
+            Some(joined) => joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())),
+            None => break,
+        };
+        *in_flight.get_mut(&region).expect("counted when started") -= 1;
+        let body = match body {
+            Err(NfhlError::Interrupted) => {
+                products.remaining.push(state);
+                continue;
+            }
+            Err(e @ NfhlError::Network(_)) => {
+                let queue = queues.get_mut(&region).expect("queued above");
+                eprintln!("MSC couldn't be searched for {}, leaving FEMA region {}'s {} other unsearched states for later: {}",
+                    state, region, queue.len(), e);
+                products.remaining.push(state);
+                products.remaining.extend(queue.drain(..).map(|(state, _)| state.to_string()));
+                products.unreachable_regions.insert(region);
+                unreachable = Some(e);
+                continue;
+            }
+            body => body?,
+        };
+        read += 1;
+        // one state's results changing shape shouldn't cost every other state's
+        match parse_search_results(&body).and_then(|results| results.state_entry().map_err(NfhlError::from)) {
+            Ok(entry) => {
+                let entry = match entry {
+                    Some(mut entry) => {
+                        let fips = Fips::new(&representative_county[..2])?;
+                        entry.locate(&fips);
+                        Some((fips, entry))
+                    }
+                    None => None,
+                };
+                searched(&state, entry.as_ref())?;
+                products.inventory.extend(entry);
+            }
+            Err(e @ NfhlError::PortalFormat { .. }) => {
+                products.quarantined.push(quarantine(diagnostics, &state, &representative_county, &body, &e));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if let (0, Some(e)) = (read, unreachable) {
+        return Err(e);
+    }
+    // the states not started before it was cancelled
+    for queue in queues.values_mut() {
+        products.remaining.extend(queue.drain(..).map(|(state, _)| state.to_string()));
+    }
+    products.remaining.sort();
+    Ok(products)
+}
+
+/// Spawns a search on `searches`, inside the current task's cassette if it has one, which a spawned task doesn't
+/// inherit.
+fn spawn_search<F>(searches: &mut JoinSet<F::Output>, search: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "vcr")]
+    if let Some(cassette) = crate::vcr::current() {
+        searches.spawn(crate::vcr::with_cassette(cassette, search));
+        return;
+    }
+    searches.spawn(search);
+}
+
+/// Not an empty inventory, which `--delete` would take at its word.
+fn all_quarantined(searched: usize, diagnostics: &Path) -> NfhlError {
+    NfhlError::PortalFormat {
+        site: nfhl_parse::msc::SITE,
+        detail: format!("none of the {} states' search results could be read; the responses are in {}",
+            searched, diagnostics.display()),
+    }
+}
+
+/// MSC's advanced search as a `ProductSource`. For the nation or a state it lists statewide products (keyed by
+/// 2-digit fips); for a county, the county's own effective and preliminary products.
+pub struct Msc {
+    pub client: Client,
+    /// Where responses that can't be read are saved; `diagnostics_dir()` if None.
+    pub diagnostics_dir: Option<PathBuf>,
+}
+
+impl ProductSource for Msc {
+    async fn list_products(&self, jurisdiction: &Jurisdiction) -> Result<Inventory> {
+        match jurisdiction {
+            Jurisdiction::County(fips) => {
+                start_session(&self.client).await?;
+                let results = search(&self.client, fips).await?;
+                let fips = Fips::new(fips.as_str())?;
+                Ok(results.county_entry()?
+                    .map(|mut entry| {
+                        entry.locate(&fips);
+                        (fips, entry)
+                    })
+                    .into_iter().collect())
+            }
+            Jurisdiction::Nation | Jurisdiction::State(_) => {
+                let diagnostics = self.diagnostics_dir.clone().unwrap_or_else(diagnostics_dir);
+                let mut inv = effective_state_products(&self.client, &diagnostics).await?;
+                inv.retain(|fips, _| jurisdiction.contains(fips));
+                Ok(inv)
+            }
+        }
+    }
+}
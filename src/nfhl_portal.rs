@@ -0,0 +1,157 @@
+//! The NFHL portal (hazards.fema.gov/femaportal/NFHL), whose search results page lists every county's effective
+//! NFHL download. The page is parsed by `nfhl_parse::portal`.
+
+use chrono::{DateTime, Utc};
+use nfhl_parse::portal::{Collision, SearchResultsPage, SearchResultsParser};
+use reqwest::header::{HeaderMap, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+
+use crate::client::Client;
+use crate::error::Result;
+use crate::inventory::Inventory;
+use crate::source::{Jurisdiction, ProductSource};
+
+pub const SEARCH_RESULTS_URL: &str = "https://hazards.fema.gov/femaportal/NFHL/searchResult";
+
+/// How many times the page is read before settling for an inventory that may be inconsistent.
+const MAX_PASSES: usize = 3;
+
+/// Every county's effective NFHL download, from one page of the portal; `scrape_county_products` without the
+/// report.
+pub async fn get_effective_county_products(client: &Client) -> Result<Inventory> {
+    Ok(scrape_county_products(client).await?.inventory)
+}
+
+/// How reading the portal went. The table is regenerated as FEMA publishes, and a read that overlaps with that can
+/// repeat some counties and miss others.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScrapeReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// The page's `Last-Modified` (or `ETag`), if the portal sent one.
+    pub last_modified: Option<String>,
+    /// How many times the page was read.
+    pub passes: usize,
+    /// False when the page was still changing after the last pass, so entries may be missing or out of date.
+    pub consistent: bool,
+    /// What made the last pass inconsistent.
+    pub problems: Vec<String>,
+    /// Downloads on the page that aren't for any county it knows of, and so aren't in the inventory.
+    #[serde(default)]
+    pub unmatched_files: Vec<String>,
+    /// Counties the page had more than one row for, and which file was kept.
+    #[serde(default)]
+    pub collisions: Vec<Collision>,
+}
+
+pub struct Scrape {
+    pub inventory: Inventory,
+    pub report: ScrapeReport,
+}
+
+/// The portal's inventory, read until it holds still. When the portal says when the page last changed, that's
+/// checked again once the page is read; otherwise it takes two reads in a row that agree. After `MAX_PASSES` reads
+/// the last is returned anyway, with the report saying so. A county with more than one row gets its newest file, and
+/// the report lists the collision; a stale row that's always there isn't the page changing under the read.
+pub async fn scrape_county_products(client: &Client) -> Result<Scrape> {
+    let started_at = Utc::now();
+    let mut previous: Option<Inventory> = None;
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let (page, last_modified) = read_page(client).await?;
+        let inv = page.inventory;
+        let mut problems = Vec::new();
+        match (&last_modified, previous.take()) {
+            (Some(before), _) => {
+                let after = validator(&crate::http::headers(client, client.head(SEARCH_RESULTS_URL)).await?);
+                if after.as_ref() != Some(before) {
+                    problems.push(format!("the page changed while it was read ({} became {})",
+                        before, after.as_deref().unwrap_or("nothing")));
+                }
+            }
+            (None, None) => {
+                previous = Some(inv);
+                continue;
+            }
+            (None, Some(previous)) => {
+                let changes = crate::diff::diff_inventories(&previous, &inv).len();
+                if changes > 0 {
+                    problems.push(format!("{} counties differed between two reads in a row", changes));
+                }
+            }
+        }
+        if problems.is_empty() || passes >= MAX_PASSES {
+            let consistent = problems.is_empty();
+            if !consistent {
+                eprintln!("warning: the portal's table may be inconsistent: {}", problems.join("; "));
+            }
+            let unmatched_files: Vec<String> = page.unmatched.into_iter().collect();
+            if !unmatched_files.is_empty() {
+                eprintln!("warning: the portal lists downloads for no known county, which are left out: {}", unmatched_files.join(", "));
+            }
+            for collision in &page.collisions {
+                eprintln!("warning: the portal lists {} more than once; kept {}, the newest, over {}",
+                    collision.fips, collision.kept, collision.dropped.join(", "));
+            }
+            let report = ScrapeReport {
+                started_at,
+                finished_at: Utc::now(),
+                last_modified,
+                passes,
+                consistent,
+                problems,
+                unmatched_files,
+                collisions: page.collisions,
+            };
+            return Ok(Scrape { inventory: inv, report });
+        }
+        eprintln!("the portal's table changed while it was read ({}), reading it again", problems.join("; "));
+        if last_modified.is_none() {
+            previous = Some(inv);
+        }
+    }
+}
+
+/// The page's `Last-Modified`, or failing that its `ETag`.
+fn validator(headers: &HeaderMap) -> Option<String> {
+    [LAST_MODIFIED, ETAG].iter()
+        .find_map(|name| headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// One read of the page, and its `validator`.
+async fn read_page(client: &Client) -> Result<(SearchResultsPage, Option<String>)> {
+    // client.post("https://www.lycamobile.es/wp-admin/admin-ajax.php")
+    //     .form(&[
+    //         ("action", "lyca_login_ajax"),
+    //         ("method", "login"),
+    //         ("mobile_no", "<MOBILE_PHONE_NUMBER>"),
+    //         ("pass", "<SUPER_SECRET_PASSWORD>")
+    //     ])
+    //     .send()?;
+
+    let mut parser = SearchResultsParser::new();
+    let headers = crate::http::stream(client, client.get(SEARCH_RESULTS_URL), |chunk| Ok(parser.write(chunk)?)).await?;
+    Ok((parser.finish_page()?, validator(&headers)))
+}
+
+/// The inventory in a search results page; see `nfhl_parse::portal::parse_search_results`.
+pub fn parse_search_results(body_response: &str) -> Result<Inventory> {
+    Ok(nfhl_parse::portal::parse_search_results(body_response)?)
+}
+
+/// The portal as a `ProductSource`. It has county products only, and lists them all at once, so any jurisdiction
+/// costs the same single request.
+pub struct NfhlPortal {
+    pub client: Client,
+}
+
+impl ProductSource for NfhlPortal {
+    async fn list_products(&self, jurisdiction: &Jurisdiction) -> Result<Inventory> {
+        let mut inv = get_effective_county_products(&self.client).await?;
+        inv.retain(|fips, _| jurisdiction.contains(fips));
+        Ok(inv)
+    }
+}
@@ -0,0 +1,65 @@
+//! `panel-index`: every county's FIRM panel index (`S_FIRM_Pan`) merged into one layer, the national panel index
+//! surveyors look panels up in to cite the right panel number, suffix and effective date.
+
+use crate::merge_geo::MergeSummary;
+
+pub const PANEL_LAYER: &str = "S_FIRM_Pan";
+
+/// What `panel_index` did.
+#[derive(Debug, Default)]
+pub struct PanelSummary {
+    pub merge: MergeSummary,
+    pub panels: u64,
+    /// Copies of a panel dropped in favour of a newer one from another county.
+    pub superseded: u64,
+}
+
+/// Merges the `S_FIRM_Pan` of `sources` into the GeoPackage at `dest` (resuming the way `merge-geo` does), then keeps
+/// one row per panel (`PCOMM` and `PANEL`). Panels straddling a county line ship in both counties' files, and when
+/// one of them has been revised since, only it has the current suffix and date; the copy with the latest `EFF_DATE`
+/// (then the newest file) is kept.
+#[cfg(feature = "gdal")]
+pub fn panel_index(sources: &[crate::merge_geo::Source], dest: &std::path::Path) -> Result<PanelSummary, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+    use gdal::vector::sql::Dialect;
+    use crate::merge_geo::{self, MergeFormat, MergeOptions};
+
+    let opts = MergeOptions {
+        format: MergeFormat::Gpkg,
+        layers: vec![PANEL_LAYER.to_string()],
+        translate: Default::default(),
+    };
+    let merge = merge_geo::merge(sources, dest, &opts)?;
+
+    let merged = gdal::Dataset::open_ex(dest, gdal::DatasetOptions {
+        open_flags: gdal::GdalOpenFlags::GDAL_OF_UPDATE | gdal::GdalOpenFlags::GDAL_OF_VECTOR,
+        ..Default::default()
+    })?;
+    let (panel, eff_date, before) = {
+        let layer = merged.layer_by_name(PANEL_LAYER).map_err(|_| format!("none of the counties have a {} layer", PANEL_LAYER))?;
+        let field = |wanted: &str| layer.defn().fields().map(|f| f.name()).find(|name| name.eq_ignore_ascii_case(wanted));
+        // the panel without its suffix, which is the letter a revision bumps
+        let panel = match (field("PCOMM"), field("PANEL"), field("FIRM_PAN")) {
+            (Some(pcomm), Some(panel), _) => format!("{}, {}", merge_geo::quote(&pcomm), merge_geo::quote(&panel)),
+            (_, _, Some(firm_pan)) => merge_geo::quote(&firm_pan),
+            _ => return Err(format!("{} has no PCOMM and PANEL or FIRM_PAN fields", PANEL_LAYER).into()),
+        };
+        let eff_date = field("EFF_DATE").ok_or_else(|| format!("{} has no EFF_DATE field", PANEL_LAYER))?;
+        (panel, merge_geo::quote(&eff_date), layer.feature_count())
+    };
+
+    let table = merge_geo::quote(PANEL_LAYER);
+    merged.execute_sql(
+        format!("DELETE FROM {table} WHERE fid NOT IN (SELECT fid FROM (SELECT fid, ROW_NUMBER() OVER (
+                     PARTITION BY {panel} ORDER BY {eff_date} DESC, effective_date DESC, fid) AS n FROM {table}) WHERE n = 1)"),
+        None,
+        Dialect::DEFAULT,
+    )?;
+    merged.execute_sql(
+        format!("CREATE INDEX IF NOT EXISTS panel_idx ON {table} ({panel})"),
+        None,
+        Dialect::DEFAULT,
+    )?;
+    let panels = merged.layer_by_name(PANEL_LAYER)?.feature_count();
+    Ok(PanelSummary { merge, panels, superseded: before - panels })
+}
@@ -0,0 +1,178 @@
+//! `--extract`: unzips each county's archive (or with `--extract-to`, converts it) as soon as its download lands,
+//! on a pool of worker threads, rather than after the run. Processing then overlaps with the network, and a national
+//! refresh is done processing roughly when its last download finishes.
+
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::{self, CancellationToken};
+use crate::convert::ConvertFormat;
+use crate::error::{NfhlError, Result};
+use crate::extract::{self, FrdGrid};
+use crate::publish::Event;
+
+/// What `--extract` does with each download.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Where the results go: `{dir}/{fips}/` for unzipped archives, or the layout of `convert --to` for
+    /// conversions.
+    pub dir: PathBuf,
+    /// Convert to this per-county format instead of unzipping. Needs the `gdal` feature.
+    pub convert: Option<ConvertFormat>,
+    /// Only unzip these grids, for Flood Risk Databases; see `extract::extract_frd_grids`.
+    pub grids: Vec<FrdGrid>,
+    /// How many counties to work on at once.
+    pub workers: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtractRecord {
+    pub fips: String,
+    pub file_name: String,
+    /// How many files were unzipped; 0 for conversions.
+    pub files: usize,
+    pub seconds: f64,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtractFailure {
+    pub fips: String,
+    pub file_name: String,
+    pub error: String,
+}
+
+/// What the pool did over a run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ExtractReport {
+    pub extracted: Vec<ExtractRecord>,
+    pub failures: Vec<ExtractFailure>,
+}
+
+struct Job {
+    fips: String,
+    file_name: String,
+    archive: PathBuf,
+}
+
+/// The worker pool. Downloads reach it through `listener`, and `finish` waits for it to catch up.
+pub struct ExtractPool {
+    sender: mpsc::Sender<Job>,
+    workers: Vec<JoinHandle<()>>,
+    report: Arc<Mutex<ExtractReport>>,
+    cache_dir: PathBuf,
+}
+
+impl ExtractPool {
+    /// Starts `opts.workers` threads working on archives in `cache_dir`.
+    pub fn start(cache_dir: &Path, opts: ExtractOptions) -> Result<ExtractPool> {
+        if let Some(format) = opts.convert {
+            if format.is_per_layer() {
+                return Err(NfhlError::Validation("--extract-to geoparquet combines counties, so it can't be done a \
+                    download at a time; run `convert` after the downloads instead".into()));
+            }
+            if cfg!(not(feature = "gdal")) {
+                return Err(NfhlError::Validation("--extract-to needs nfhl_util built with `--features gdal`".into()));
+            }
+        }
+        if opts.convert.is_some() && !opts.grids.is_empty() {
+            return Err(NfhlError::Validation("--frd-grids unzips the grids as they are, so it can't be combined with \
+                --extract-to".into()));
+        }
+        std::fs::create_dir_all(&opts.dir).map_err(NfhlError::io(&opts.dir))?;
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let report = Arc::new(Mutex::new(ExtractReport::default()));
+        let opts = Arc::new(opts);
+        let workers = (0..opts.workers.max(1))
+            .map(|_| {
+                let (receiver, report, opts) = (receiver.clone(), report.clone(), opts.clone());
+                std::thread::spawn(move || loop {
+                    // the lock is only held while waiting, so the other workers aren't held up while this one works
+                    let job = receiver.lock().unwrap().recv();
+                    let Ok(job) = job else {
+                        break;
+                    };
+                    // queued archives are left for `extract` or `convert` to finish, like a cancelled `convert`
+                    if cancel::requested(&CancellationToken::new()) {
+                        continue;
+                    }
+                    let start = Instant::now();
+                    let result = process(&job.fips, &job.archive, &opts);
+                    let mut report = report.lock().unwrap();
+                    match result {
+                        Ok(files) => report.extracted.push(ExtractRecord {
+                            fips: job.fips,
+                            file_name: job.file_name,
+                            files,
+                            seconds: start.elapsed().as_secs_f64(),
+                            finished_at: Utc::now(),
+                        }),
+                        Err(e) => {
+                            eprintln!("failed to extract {}: {}", job.fips, e);
+                            let error = e.to_string();
+                            report.failures.push(ExtractFailure { fips: job.fips, file_name: job.file_name, error });
+                        }
+                    }
+                })
+            })
+            .collect();
+        Ok(ExtractPool { sender, workers, report, cache_dir: cache_dir.to_path_buf() })
+    }
+
+    /// For `Publishers::listen`: queues every completed download. The pool can't finish while this is still
+    /// around, so the publishers given it have to be dropped first.
+    pub fn listener(&self) -> impl FnMut(&Event) + 'static {
+        let (sender, cache_dir) = (self.sender.clone(), self.cache_dir.clone());
+        move |event| {
+            if let Event::Download(record) = event {
+                let job = Job {
+                    fips: record.fips.clone(),
+                    file_name: record.file_name.clone(),
+                    archive: cache_dir.join(&record.file_name),
+                };
+                // the workers only go away in `finish`, after the downloads
+                let _ = sender.send(job);
+            }
+        }
+    }
+
+    /// Waits for the queued archives to be processed, and says how it went.
+    pub fn finish(self) -> ExtractReport {
+        drop(self.sender);
+        for worker in self.workers {
+            if let Err(panic) = worker.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+        std::mem::take(&mut *self.report.lock().unwrap())
+    }
+}
+
+/// Unzips (all of it, or just its grids) or converts one archive, returning how many files were unzipped.
+fn process(fips: &str, archive: &Path, opts: &ExtractOptions) -> std::result::Result<usize, Box<dyn std::error::Error>> {
+    match opts.convert {
+        None if !opts.grids.is_empty() => Ok(extract::extract_frd_grids(archive, &opts.dir.join(fips), &opts.grids)?.files),
+        None => Ok(extract::extract_archive(archive, &opts.dir.join(fips))?.files),
+        #[cfg(feature = "gdal")]
+        Some(format) => {
+            let convert = crate::convert::ConvertOptions {
+                format,
+                layers: Vec::new(),
+                partition_by_state: false,
+                translate: Default::default(),
+            };
+            let archives = [(fips.to_string(), archive.to_path_buf())];
+            crate::convert::convert(&archives, &opts.dir, &convert, &CancellationToken::new())?;
+            Ok(0)
+        }
+        #[cfg(not(feature = "gdal"))]
+        Some(_) => unreachable!("`start` refuses --extract-to without gdal"),
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::cache::{self, CacheManifest};
+use crate::diff::{self, Change, ChangeKind};
+use crate::error::{self, NfhlError};
+use crate::inventory::{format_file_date, FileRole, Fips, Inventory, ProductKind};
+use crate::recompress::Recompression;
+use crate::shard::Shard;
+
+/// Bumped whenever a plan file's meaning changes, so `apply` can refuse plans it would misread. Version 2 added
+/// `keep`, which version 1 plans don't have, so they're still read.
+pub const PLAN_VERSION: u32 = 2;
+
+/// What `download_all --product` mirrors. Each is kept in its own part of the cache.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Product {
+    /// The effective NFHL database, from the inventory.
+    #[default]
+    Effective,
+    /// The preliminary FIRM database, from the inventory.
+    Preliminary,
+    /// Flood Insurance Study reports, as listed by MSC.
+    Fis,
+    /// Letters of Map Change, as listed by MSC.
+    Lomc,
+    /// Superseded FIRMs and FIS reports, as listed by MSC.
+    Historic,
+    /// Risk MAP Flood Risk Databases, as listed by MSC.
+    Frd,
+    /// The scanned FIRM panels among the historic products, cached as `{fips}/{panel}_{YYYYMMDD}.tif` (or `.pdf`)
+    /// for georeferencing.
+    HistoricPanels,
+}
+
+impl Product {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Product::Effective => "effective",
+            Product::Preliminary => "preliminary",
+            Product::Fis => "fis",
+            Product::Lomc => "lomc",
+            Product::Historic => "historic",
+            Product::Frd => "frd",
+            Product::HistoricPanels => "historic-panels",
+        }
+    }
+
+    /// The inventory's file for the product, if the inventory has one; the others come from MSC's search, county by
+    /// county.
+    pub fn inventory_kind(&self) -> Option<ProductKind> {
+        match self {
+            Product::Effective => Some(ProductKind::Effective),
+            Product::Preliminary => Some(ProductKind::Preliminary),
+            _ => None,
+        }
+    }
+
+    /// Where the product is cached: in the cache directory itself for effective files, as it always was, and in a
+    /// subdirectory named for the product otherwise.
+    pub fn cache_dir(&self, cache_dir: &Path) -> PathBuf {
+        match self {
+            Product::Effective => cache_dir.to_path_buf(),
+            _ => cache_dir.join(self.as_str()),
+        }
+    }
+
+    /// Whether its files are zip archives, so a download that isn't one fails. Reports, letters and scans are PDFs
+    /// and images.
+    pub fn is_archive(&self) -> bool {
+        matches!(self, Product::Effective | Product::Preliminary | Product::Frd)
+    }
+
+    /// Whether its files are scans, so a download that isn't a TIFF or PDF fails.
+    pub fn is_scan(&self) -> bool {
+        matches!(self, Product::HistoricPanels)
+    }
+}
+
+/// A file MSC listed for a county, for the products the inventory doesn't have.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListedFile {
+    pub fips: String,
+    /// Relative to the product's cache directory: `{fips}/{MSC's file name}`, since a county has any number.
+    pub file_name: String,
+    pub url: String,
+    pub date: Option<NaiveDate>,
+}
+
+/// A cached file FEMA now serves different bytes for at the same url and date; see `download::find_republished`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Republished {
+    pub fips: String,
+    pub file_name: String,
+    pub url: String,
+    pub effective_date: String,
+    /// The size the manifest recorded when it was downloaded.
+    pub cached_size: u64,
+    /// The `Content-Length` FEMA answers a HEAD with now.
+    pub served_size: u64,
+}
+
+/// A cached file renamed before it's downloaded again, so `--keep-history` keeps the copy FEMA replaced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeptCopy {
+    pub file_name: String,
+    pub kept_as: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// The county has nothing in the cache yet.
+    Add,
+    /// The county has an older or stale file in the cache, which the download replaces.
+    Update,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlannedDownload {
+    pub fips: String,
+    pub action: Action,
+    pub file_name: String,
+    pub url: String,
+    pub effective_date: String,
+    /// Set for one of a county's other files (see `InventoryEntry::files`), which the manifest keeps by file name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<FileRole>,
+}
+
+/// Everything a `download_all` run would do to a cache, worked out up front so it can be saved, reviewed and
+/// applied later.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Plan {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    /// The product's cache directory; see `Product::cache_dir`.
+    pub cache_dir: PathBuf,
+    /// Missing from plans made before there was a choice, which were all effective files.
+    #[serde(default)]
+    pub product: Product,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+    /// Changes relative to the old inventory, if one was given.
+    pub changes: Vec<Change>,
+    pub downloads: Vec<PlannedDownload>,
+    /// Files to remove from the cache because they're no longer in the inventory.
+    pub deletions: Vec<String>,
+    /// Counties to drop from the cache manifest, for the same reason.
+    pub forget: Vec<String>,
+    /// How many files are already in the cache and will be left alone.
+    pub skipped: usize,
+    /// Files to rename before the downloads start; see `add_republished`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep: Vec<KeptCopy>,
+    /// How to recompress the downloads as they land; see `recompress`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recompress: Option<Recompression>,
+}
+
+/// Works out which files of `inv` need (re-)downloading into `cache_dir`, given what's cached and what changed
+/// since `old_inv`, and with `delete`, which cached files no longer belong. With `keep_history` too, the newest
+/// superseded file of each county still in the inventory is kept, for `diff-geo`. Nothing is touched.
+pub fn make_plan(
+    inv: &Inventory,
+    old_inv: Option<&Inventory>,
+    cache_dir: &Path,
+    delete: bool,
+    keep_history: bool,
+    shard: Option<Shard>,
+) -> error::Result<Plan> {
+    make_product_plan(inv, old_inv, cache_dir, Product::Effective, delete, keep_history, shard)
+}
+
+/// `make_plan` for one of the products the inventory has (see `Product::inventory_kind`), in its part of
+/// `cache_dir`.
+pub fn make_product_plan(
+    inv: &Inventory,
+    old_inv: Option<&Inventory>,
+    cache_dir: &Path,
+    product: Product,
+    delete: bool,
+    keep_history: bool,
+    shard: Option<Shard>,
+) -> error::Result<Plan> {
+    let kind = product.inventory_kind().ok_or_else(|| NfhlError::Validation(format!(
+        "the inventory doesn't list {} files; they're listed from MSC with `msc::list_product_files`", product.as_str())))?;
+    let changed_kind = match kind {
+        ProductKind::Effective => ChangeKind::Effective,
+        ProductKind::Preliminary => ChangeKind::Preliminary,
+    };
+    let cache_dir = &product.cache_dir(cache_dir);
+    let manifest = CacheManifest::load(cache_dir)?;
+    let in_shard = |fips: &str| match shard {
+        Some(shard) => shard.contains(fips),
+        None => true,
+    };
+    let mut changes = match old_inv {
+        Some(old_inv) => diff::diff_inventories(old_inv, inv),
+        None => Vec::new(),
+    };
+    changes.retain(|c| in_shard(&c.fips));
+    let changed: HashSet<&str> = changes.iter()
+        .filter(|c| c.kind == changed_kind)
+        .map(|c| c.fips.as_str())
+        .collect();
+
+    let mut fips_codes: Vec<&Fips> = inv.keys().filter(|fips| in_shard(fips)).collect();
+    fips_codes.sort();
+
+    let mut downloads = Vec::new();
+    let mut skipped = 0;
+    for fips in fips_codes {
+        let entry = &inv[fips];
+        let Some(url) = entry.url(kind) else {
+            continue;
+        };
+        let file_name = manifest.layout.relative_path(fips, &cache::product_file_name(fips, entry, kind));
+        let mut files = vec![(None, file_name, url, entry.date(kind))];
+        for (n, file) in entry.files(kind).enumerate() {
+            let file_name = manifest.layout.relative_path(fips, &cache::inventory_file_name(fips, file, n + 1));
+            files.push((Some(file.role), file_name, &file.url, file.date));
+        }
+        for (role, file_name, url, date) in files {
+            let effective_date = format_file_date(date);
+            let cached = cache_dir.join(&file_name).exists();
+            let recorded = match role {
+                None => manifest.entries.get(fips.as_str()),
+                Some(_) => manifest.files.get(&file_name),
+            };
+            // a changed county whose new file the manifest says we already fetched (e.g. by an earlier, interrupted
+            // run against the same inventories) doesn't need fetching again
+            let current = recorded
+                .is_some_and(|c| c.file_name == file_name && c.url == url.as_str() && c.effective_date == effective_date);
+            if cached && (current || !changed.contains(fips.as_str())) {
+                skipped += 1;
+                continue;
+            }
+            downloads.push(PlannedDownload {
+                fips: fips.to_string(),
+                action: if cached || recorded.is_some() { Action::Update } else { Action::Add },
+                file_name,
+                url: url.to_string(),
+                effective_date,
+                role,
+            });
+        }
+    }
+
+    let mut deletions = Vec::new();
+    let mut forget = Vec::new();
+    if delete && cache_dir.exists() {
+        // every county in the inventory, not just this shard's, so workers sharing a cache don't delete each other's
+        // files
+        let expected: HashSet<String> = inv.iter()
+            .filter(|(_, entry)| entry.has(kind))
+            .flat_map(|(fips, entry)| {
+                let others = entry.files(kind).enumerate().map(|(n, file)| cache::inventory_file_name(fips, file, n + 1));
+                std::iter::once(cache::product_file_name(fips, entry, kind)).chain(others)
+                    .map(|file_name| manifest.layout.relative_path(fips, &file_name))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut previous: HashSet<String> = HashSet::new();
+        if keep_history {
+            // sorted by fips then name, and the names end in YYYYMMDD, so the last superseded file per county wins
+            let mut newest: HashMap<String, String> = HashMap::new();
+            for (fips, path) in cache::cached_archives(cache_dir)? {
+                let file_name = cache::relative_name(cache_dir, &path);
+                if inv.contains_key(fips.as_str()) && !expected.contains(&file_name) {
+                    newest.insert(fips, file_name);
+                }
+            }
+            previous.extend(newest.into_values());
+        }
+        for path in cache::zip_files(cache_dir)? {
+            let file_name = cache::relative_name(cache_dir, &path);
+            if !expected.contains(&file_name) && !previous.contains(&file_name) {
+                deletions.push(file_name);
+            }
+        }
+        deletions.sort();
+        forget = manifest.entries.iter().chain(&manifest.files)
+            .filter(|(_, cached)| !expected.contains(&cached.file_name))
+            .map(|(key, _)| key.clone())
+            .collect();
+    }
+
+    Ok(Plan {
+        version: PLAN_VERSION,
+        created_at: Utc::now(),
+        cache_dir: cache_dir.to_path_buf(),
+        product,
+        shard,
+        changes,
+        downloads,
+        deletions,
+        forget,
+        skipped,
+        keep: Vec::new(),
+        recompress: None,
+    })
+}
+
+/// Adds re-downloading the `republished` files to `plan`, each flagged as a `Republished` change. With
+/// `keep_history`, the cached copy is first renamed to `{name}.replaced-{YYYYMMDD}.zip` (the plan's date), which
+/// `--delete --keep-history` then keeps as the county's previous file.
+pub fn add_republished(plan: &mut Plan, republished: &[Republished], keep_history: bool) {
+    for file in republished {
+        if plan.downloads.iter().any(|d| d.file_name == file.file_name) {
+            continue;
+        }
+        plan.changes.push(Change {
+            fips: file.fips.clone(),
+            kind: ChangeKind::Republished,
+            old_date: file.effective_date.clone(),
+            new_date: file.effective_date.clone(),
+            url: file.url.clone(),
+        });
+        plan.downloads.push(PlannedDownload {
+            fips: file.fips.clone(),
+            action: Action::Update,
+            file_name: file.file_name.clone(),
+            url: file.url.clone(),
+            effective_date: file.effective_date.clone(),
+            role: None,
+        });
+        if keep_history {
+            let (stem, ext) = file.file_name.rsplit_once('.').unwrap_or((&file.file_name, "zip"));
+            let kept_as = format!("{}.replaced-{}.{}", stem, plan.created_at.format("%Y%m%d"), ext);
+            plan.keep.push(KeptCopy { file_name: file.file_name.clone(), kept_as });
+        }
+        plan.skipped = plan.skipped.saturating_sub(1);
+    }
+    plan.changes.sort_by(|a, b| a.fips.cmp(&b.fips));
+    plan.downloads.sort_by(|a, b| a.fips.cmp(&b.fips));
+}
+
+/// The plan for files MSC listed (see `msc::list_product_files`), in `product`'s part of `cache_dir`: the ones not
+/// cached yet, or cached from a different url or date. With `delete`, files in the listed counties' directories
+/// that MSC no longer lists are deleted. They're only compared with the cache, since the inventory doesn't have them
+/// to diff.
+pub fn make_listed_plan(
+    files: &[ListedFile],
+    cache_dir: &Path,
+    product: Product,
+    delete: bool,
+    shard: Option<Shard>,
+) -> error::Result<Plan> {
+    let cache_dir = &product.cache_dir(cache_dir);
+    let manifest = CacheManifest::load(cache_dir)?;
+    let in_shard = |fips: &str| shard.is_none_or(|shard| shard.contains(fips));
+    let mut files: Vec<&ListedFile> = files.iter().filter(|file| in_shard(&file.fips)).collect();
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+    let mut downloads = Vec::new();
+    let mut skipped = 0;
+    for file in &files {
+        let effective_date = format_file_date(file.date);
+        let cached = cache_dir.join(&file.file_name).exists();
+        let recorded = manifest.entries.get(&file.file_name);
+        if cached && recorded.is_none_or(|c| c.url == file.url && c.effective_date == effective_date) {
+            skipped += 1;
+            continue;
+        }
+        downloads.push(PlannedDownload {
+            fips: file.fips.clone(),
+            action: if cached || recorded.is_some() { Action::Update } else { Action::Add },
+            file_name: file.file_name.clone(),
+            url: file.url.clone(),
+            effective_date,
+            role: None,
+        });
+    }
+
+    let mut deletions = Vec::new();
+    let mut forget = Vec::new();
+    if delete {
+        let expected: HashSet<&str> = files.iter().map(|file| file.file_name.as_str()).collect();
+        let counties: HashSet<&str> = files.iter().map(|file| file.fips.as_str()).collect();
+        for fips in &counties {
+            let dir = cache_dir.join(fips);
+            if !dir.exists() {
+                continue;
+            }
+            for dir_entry in std::fs::read_dir(&dir).map_err(NfhlError::io(&dir))? {
+                let name = dir_entry.map_err(NfhlError::io(&dir))?.file_name();
+                let file_name = format!("{}/{}", fips, name.to_string_lossy());
+                if !expected.contains(file_name.as_str()) && !file_name.ends_with(".part") {
+                    deletions.push(file_name);
+                }
+            }
+        }
+        deletions.sort();
+        forget = manifest.entries.keys()
+            .filter(|key| key.split_once('/').is_some_and(|(fips, _)| counties.contains(fips)))
+            .filter(|key| !expected.contains(key.as_str()))
+            .cloned()
+            .collect();
+    }
+
+    Ok(Plan {
+        version: PLAN_VERSION,
+        created_at: Utc::now(),
+        cache_dir: cache_dir.to_path_buf(),
+        product,
+        shard,
+        changes: Vec::new(),
+        downloads,
+        deletions,
+        forget,
+        skipped,
+        keep: Vec::new(),
+        recompress: None,
+    })
+}
+
+pub fn read_plan(path: &Path) -> error::Result<Plan> {
+    let f = File::open(path).map_err(NfhlError::io(path))?;
+    let plan: Plan = serde_json::from_reader(BufReader::new(f)).map_err(NfhlError::parse(path))?;
+    if !(1..=PLAN_VERSION).contains(&plan.version) {
+        return Err(NfhlError::Validation(format!("{} is a version {} plan, but this nfhl_util reads version {}; re-run plan",
+            path.display(), plan.version, PLAN_VERSION)));
+    }
+    Ok(plan)
+}
+
+/// Writes a reviewable, terraform-style listing of the plan: `+` for new files, `~` for replaced ones and `-` for
+/// deletions, followed by the totals.
+pub fn write_plan_summary(out: &mut dyn Write, plan: &Plan) -> Result<(), Box<dyn std::error::Error>> {
+    for d in &plan.downloads {
+        let sign = match d.action {
+            Action::Add => '+',
+            Action::Update => '~',
+        };
+        writeln!(out, "  {} {} {}", sign, d.fips, d.file_name)?;
+    }
+    for kept in &plan.keep {
+        writeln!(out, "  > {} kept as {}", kept.file_name, kept.kept_as)?;
+    }
+    for file_name in &plan.deletions {
+        writeln!(out, "  - {}", file_name)?;
+    }
+    let adds = plan.downloads.iter().filter(|d| d.action == Action::Add).count();
+    if !plan.downloads.is_empty() || !plan.deletions.is_empty() {
+        writeln!(out)?;
+    }
+    writeln!(out, "Plan: {} to add, {} to update, {} to delete, {} unchanged.",
+        adds, plan.downloads.len() - adds, plan.deletions.len(), plan.skipped)?;
+    Ok(())
+}
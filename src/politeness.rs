@@ -0,0 +1,105 @@
+//! Slowing down when FEMA pushes back. The download loop waits the `--politeness` delay between downloads, doubled
+//! for each recent download that FEMA pushed back on (429, 503, timeouts, refused connections; see
+//! `NfhlError::is_pushback`), and halved again after a run of clean ones. The state is saved in the cache directory
+//! after each download, so a run restarted after a bad patch starts out slow rather than hammering FEMA at full speed
+//! again.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::error::{NfhlError, Result};
+
+pub const STATE_FILE_NAME: &str = ".politeness.json";
+/// At most 2^6 times the politeness delay, ~2.7 minutes at the default.
+const MAX_LEVEL: u32 = 6;
+/// Clean downloads in a row that take a level off.
+const RECOVERY_STREAK: u32 = 5;
+/// A level comes off for each of these that passes between runs, since FEMA recovers whether we're running or not.
+const DECAY: Duration = Duration::from_secs(10 * 60);
+/// What's doubled when the politeness delay is 0.
+const MIN_BACKOFF_DELAY: Duration = Duration::from_secs(1);
+
+/// The saved state, as of `updated_at`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffState {
+    pub level: u32,
+    /// The delay it came to, for whoever reads the file.
+    pub delay_ms: u64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdaptiveDelay {
+    base: Duration,
+    level: u32,
+    streak: u32,
+}
+
+impl AdaptiveDelay {
+    pub fn new(base: Duration) -> AdaptiveDelay {
+        AdaptiveDelay { base, level: 0, streak: 0 }
+    }
+
+    /// Picks up where the last run in `cache_dir` left off, less what's worn off since. A missing or unreadable
+    /// state file starts at the politeness delay, since it's only advice.
+    pub fn restore(cache_dir: &Path, base: Duration) -> AdaptiveDelay {
+        let mut adaptive = AdaptiveDelay::new(base);
+        let path = cache_dir.join(STATE_FILE_NAME);
+        let state: Option<BackoffState> = File::open(&path).ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok());
+        if let Some(state) = state {
+            let idle = (Utc::now() - state.updated_at).to_std().unwrap_or_default();
+            let worn_off = (idle.as_secs() / DECAY.as_secs()).min(MAX_LEVEL as u64) as u32;
+            adaptive.level = state.level.min(MAX_LEVEL).saturating_sub(worn_off);
+            if adaptive.level > 0 {
+                eprintln!("starting slower, {:.1}s between downloads: the last run was backing off",
+                    adaptive.delay().as_secs_f64());
+            }
+        }
+        adaptive
+    }
+
+    pub fn delay(&self) -> Duration {
+        match self.level {
+            0 => self.base,
+            level => self.base.max(MIN_BACKOFF_DELAY) * 2u32.pow(level),
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// A download that finished without a retry.
+    pub fn succeeded(&mut self) {
+        self.streak += 1;
+        if self.streak >= RECOVERY_STREAK && self.level > 0 {
+            self.level -= 1;
+            self.streak = 0;
+        }
+    }
+
+    /// A download that failed on the network, or needed retrying to finish.
+    pub fn pushed_back(&mut self) {
+        self.streak = 0;
+        if self.level < MAX_LEVEL {
+            self.level += 1;
+            eprintln!("backing off, {:.1}s between downloads", self.delay().as_secs_f64());
+        }
+    }
+
+    /// Saves the state to `cache_dir`, replacing the file atomically like the manifest.
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        let state = BackoffState { level: self.level, delay_ms: self.delay().as_millis() as u64, updated_at: Utc::now() };
+        let path = cache_dir.join(STATE_FILE_NAME);
+        let tmp_path = cache_dir.join(format!("{}.tmp", STATE_FILE_NAME));
+        let f = File::create(&tmp_path).map_err(NfhlError::io(&tmp_path))?;
+        serde_json::to_writer_pretty(BufWriter::new(f), &state).map_err(|e| NfhlError::io(&tmp_path)(e.into()))?;
+        std::fs::rename(&tmp_path, &path).map_err(NfhlError::io(&path))
+    }
+}
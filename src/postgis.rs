@@ -0,0 +1,140 @@
+//! `load-postgis`: loads layers of the cached counties into PostGIS tables, one per layer, with `source_fips` and
+//! `effective_date` columns. Which version of each county is loaded is kept in `{schema}.nfhl_loaded_counties`, so
+//! an upsert only touches counties that are new or were re-issued.
+
+use std::collections::HashSet;
+
+use postgres::{Client, NoTls};
+
+use crate::merge_geo::{quote, Source};
+
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub schema: String,
+    pub layers: Vec<String>,
+    /// Replace only new and re-issued counties, rather than reloading everything.
+    pub upsert: bool,
+    pub translate: crate::convert::TranslateOptions,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadSummary {
+    pub loaded: usize,
+    pub unchanged: usize,
+    pub failed: Vec<String>,
+}
+
+/// Whether `name` can be used as a schema name unquoted, which is all we allow so it means the same thing to GDAL and
+/// to us.
+pub fn valid_schema(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Loads `sources` into PostGIS at `dsn` (a libpq connection string or `postgresql://` url). Each county's rows are
+/// deleted before it's (re-)loaded, so a county left half loaded by an interrupted run is cleaned up by the next one.
+#[cfg(feature = "gdal")]
+pub fn load(dsn: &str, sources: &[Source], opts: &LoadOptions) -> Result<LoadSummary, Box<dyn std::error::Error>> {
+    if !valid_schema(&opts.schema) {
+        return Err(format!("'{}' isn't a plain lowercase schema name", opts.schema).into());
+    }
+    opts.translate.check()?;
+    let mut client = Client::connect(dsn, NoTls)?;
+    let loaded_table = format!("{}.nfhl_loaded_counties", opts.schema);
+    client.batch_execute(&format!(
+        "CREATE SCHEMA IF NOT EXISTS {schema};
+         CREATE TABLE IF NOT EXISTS {table} (
+             fips            TEXT PRIMARY KEY,
+             file_name       TEXT NOT NULL,
+             effective_date  DATE,
+             loaded_at       TIMESTAMPTZ NOT NULL DEFAULT now()
+         );",
+        schema = opts.schema, table = loaded_table))?;
+    if !opts.upsert {
+        client.batch_execute(&format!("DELETE FROM {}", loaded_table))?;
+    }
+
+    let mut summary = LoadSummary::default();
+    // tables this run has created (or, without --upsert, replaced)
+    let mut created: HashSet<String> = HashSet::new();
+    for source in sources {
+        let file_name = source.archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        let current = client.query_opt(&format!("SELECT file_name FROM {} WHERE fips = $1", loaded_table), &[&source.fips])?
+            .is_some_and(|row| row.get::<_, String>(0) == file_name);
+        if opts.upsert && current {
+            summary.unchanged += 1;
+            continue;
+        }
+        match load_county(&mut client, dsn, source, opts, &mut created) {
+            Ok(()) => {
+                client.execute(
+                    &format!("INSERT INTO {} (fips, file_name, effective_date) VALUES ($1, $2, $3)
+                              ON CONFLICT (fips) DO UPDATE SET file_name = EXCLUDED.file_name,
+                                  effective_date = EXCLUDED.effective_date, loaded_at = now()", loaded_table),
+                    &[&source.fips, &file_name, &source.effective_date])?;
+                summary.loaded += 1;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", source.fips, e);
+                summary.failed.push(source.fips.clone());
+            }
+        }
+    }
+
+    for table in &created {
+        let name = table.rsplit('.').next().unwrap_or(table);
+        client.batch_execute(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} (source_fips); ANALYZE {};",
+            quote(&format!("{}_source_fips_idx", name)), table, table))?;
+    }
+    Ok(summary)
+}
+
+/// Replaces one county's rows in every layer table it has.
+#[cfg(feature = "gdal")]
+fn load_county(client: &mut Client, dsn: &str, source: &Source, opts: &LoadOptions, created: &mut HashSet<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let src = gdal::Dataset::open(&gdb)?;
+    let layers = crate::merge_geo::wanted_layers(&src, &opts.layers);
+    if layers.is_empty() {
+        return Err(format!("{} has none of the requested layers", gdb).into());
+    }
+
+    let mut tx = client.transaction()?;
+    tx.execute(&format!("DELETE FROM {}.nfhl_loaded_counties WHERE fips = $1", opts.schema), &[&source.fips])?;
+    for layer in &layers {
+        let table = format!("{}.{}", opts.schema, layer.to_ascii_lowercase());
+        let exists: bool = tx.query_one("SELECT to_regclass($1) IS NOT NULL", &[&table])?.get(0);
+        if exists && (opts.upsert || created.contains(&table)) {
+            tx.execute(&format!("DELETE FROM {} WHERE source_fips = $1", table), &[&source.fips])?;
+        }
+    }
+    tx.commit()?;
+
+    for layer in layers {
+        let table = format!("{}.{}", opts.schema, layer.to_ascii_lowercase());
+        let mut args: Vec<String> = ["-f", "PostgreSQL", "-lco", "GEOMETRY_NAME=geom", "-lco", "FID=fid"].map(String::from).to_vec();
+        if opts.upsert || created.contains(&table) {
+            args.push("-addfields".into());
+        } else {
+            // the first county of a full load replaces whatever was there
+            args.push("-overwrite".into());
+        }
+        if opts.translate.t_srs.is_none() {
+            // FEMA's data is NAD83, but the geodatabases' CRS doesn't always name its EPSG code, which would leave
+            // PostGIS without a proper SRID
+            args.extend(["-t_srs".to_string(), "EPSG:4269".to_string()]);
+        }
+        args.extend(crate::convert::TranslateOptions { where_clause: None, ..opts.translate.clone() }.args());
+        args.extend([
+            "-nln".to_string(), table.clone(),
+            "-nlt".to_string(), "PROMOTE_TO_MULTI".to_string(),
+            "-dialect".to_string(), "OGRSQL".to_string(),
+            "-sql".to_string(), crate::merge_geo::tagged_select(source, &layer, opts.translate.where_clause.as_deref()),
+        ]);
+        crate::convert::vector_translate(&src, std::path::Path::new(&format!("PG:{}", dsn)), &args)?;
+        created.insert(table);
+    }
+    Ok(())
+}
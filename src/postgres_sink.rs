@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use postgres::{Client, NoTls, Transaction};
+use reqwest::Url;
+
+use crate::diff::Change;
+use crate::download::RunReport;
+use crate::inventory::{non_empty, parse_file_date, Fips, Inventory};
+
+/// The schema, one migration per entry. Applied migrations are recorded in `nfhl_schema_migrations` by their
+/// 1-based position here, so never edit or reorder an entry once released; append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE nfhl_inventory_snapshots (
+        id          BIGSERIAL PRIMARY KEY,
+        kind        TEXT NOT NULL,
+        created_at  TIMESTAMPTZ NOT NULL
+    );
+    CREATE TABLE nfhl_inventory_entries (
+        snapshot_id             BIGINT NOT NULL REFERENCES nfhl_inventory_snapshots (id) ON DELETE CASCADE,
+        fips                    TEXT NOT NULL,
+        effective_file_url      TEXT,
+        effective_file_date     DATE,
+        preliminary_file_url    TEXT,
+        preliminary_file_date   DATE,
+        PRIMARY KEY (snapshot_id, fips)
+    );
+    CREATE TABLE nfhl_runs (
+        id              BIGSERIAL PRIMARY KEY,
+        snapshot_id     BIGINT REFERENCES nfhl_inventory_snapshots (id) ON DELETE SET NULL,
+        started_at      TIMESTAMPTZ NOT NULL,
+        finished_at     TIMESTAMPTZ NOT NULL,
+        skipped         INTEGER NOT NULL,
+        cache_files     INTEGER NOT NULL,
+        cache_bytes     BIGINT NOT NULL
+    );
+    CREATE TABLE nfhl_run_downloads (
+        run_id          BIGINT NOT NULL REFERENCES nfhl_runs (id) ON DELETE CASCADE,
+        fips            TEXT NOT NULL,
+        file_name       TEXT NOT NULL,
+        url             TEXT NOT NULL,
+        bytes           BIGINT NOT NULL,
+        seconds         DOUBLE PRECISION NOT NULL,
+        finished_at     TIMESTAMPTZ NOT NULL
+    );
+    CREATE TABLE nfhl_run_failures (
+        run_id          BIGINT NOT NULL REFERENCES nfhl_runs (id) ON DELETE CASCADE,
+        fips            TEXT NOT NULL,
+        url             TEXT NOT NULL,
+        error           TEXT NOT NULL
+    );
+    CREATE TABLE nfhl_run_deletions (
+        run_id          BIGINT NOT NULL REFERENCES nfhl_runs (id) ON DELETE CASCADE,
+        file_name       TEXT NOT NULL
+    );
+    CREATE TABLE nfhl_changes (
+        id              BIGSERIAL PRIMARY KEY,
+        run_id          BIGINT REFERENCES nfhl_runs (id) ON DELETE CASCADE,
+        observed_at     TIMESTAMPTZ NOT NULL,
+        fips            TEXT NOT NULL,
+        kind            TEXT NOT NULL,
+        old_date        DATE,
+        new_date        DATE,
+        url             TEXT
+    );
+    CREATE INDEX nfhl_changes_fips_idx ON nfhl_changes (fips, observed_at);
+    "#,
+    r#"
+    ALTER TABLE nfhl_run_downloads ADD COLUMN retries INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE nfhl_run_failures ADD COLUMN retries INTEGER NOT NULL DEFAULT 0;
+    "#,
+];
+
+/// Arbitrary, but fixed: serializes concurrent migrators on the same database.
+const MIGRATION_LOCK_KEY: i64 = 0x6e66_686c;
+
+/// A connection to the database behind `--format postgres` / `--report-postgres`, migrated to the current schema.
+pub struct PostgresSink {
+    client: Client,
+}
+
+impl PostgresSink {
+    /// Connects to a `postgresql://` url (or libpq-style `host=... dbname=...` string) and applies any pending
+    /// migrations.
+    pub fn connect(url: &str) -> Result<PostgresSink, Box<dyn std::error::Error>> {
+        let mut client = Client::connect(url, NoTls)?;
+        migrate(&mut client)?;
+        Ok(PostgresSink { client })
+    }
+
+    /// Saves an inventory as a new snapshot, returning its id. `kind` is `states` or `counties`.
+    pub fn write_inventory(&mut self, kind: &str, inv: &Inventory) -> Result<i64, Box<dyn std::error::Error>> {
+        let mut tx = self.client.transaction()?;
+        let snapshot_id = insert_inventory(&mut tx, kind, inv)?;
+        tx.commit()?;
+        Ok(snapshot_id)
+    }
+
+    /// Saves a `download_all` run with its downloads, failures, deletions and changes. If `snapshot_id` is given the
+    /// run is linked to the inventory snapshot it was made from.
+    pub fn write_run(&mut self, report: &RunReport, snapshot_id: Option<i64>) -> Result<i64, Box<dyn std::error::Error>> {
+        let mut tx = self.client.transaction()?;
+        let run_id: i64 = tx.query_one(
+            "INSERT INTO nfhl_runs (snapshot_id, started_at, finished_at, skipped, cache_files, cache_bytes)
+             VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+            &[&snapshot_id, &report.started_at, &report.finished_at, &(report.skipped as i32),
+                &(report.cache.files as i32), &(report.cache.total_bytes as i64)],
+        )?.get(0);
+
+        let download = tx.prepare(
+            "INSERT INTO nfhl_run_downloads (run_id, fips, file_name, url, bytes, seconds, finished_at, retries)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")?;
+        for d in &report.downloads {
+            tx.execute(&download, &[&run_id, &d.fips, &d.file_name, &d.url, &(d.bytes as i64), &d.seconds, &d.finished_at,
+                &(d.retries as i32)])?;
+        }
+        let failure = tx.prepare(
+            "INSERT INTO nfhl_run_failures (run_id, fips, url, error, retries) VALUES ($1, $2, $3, $4, $5)")?;
+        for f in &report.failures {
+            tx.execute(&failure, &[&run_id, &f.fips, &f.url, &f.error, &(f.retries as i32)])?;
+        }
+        let deletion = tx.prepare("INSERT INTO nfhl_run_deletions (run_id, file_name) VALUES ($1, $2)")?;
+        for file_name in &report.deleted {
+            tx.execute(&deletion, &[&run_id, file_name])?;
+        }
+        insert_changes(&mut tx, Some(run_id), &report.changes, report.started_at)?;
+        tx.commit()?;
+        Ok(run_id)
+    }
+}
+
+fn migrate(client: &mut Client) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tx = client.transaction()?;
+    tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])?;
+    tx.batch_execute(
+        "CREATE TABLE IF NOT EXISTS nfhl_schema_migrations (
+            version     INTEGER PRIMARY KEY,
+            applied_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+        )")?;
+    let current: i32 = tx.query_one("SELECT COALESCE(MAX(version), 0) FROM nfhl_schema_migrations", &[])?.get(0);
+    if current as usize > MIGRATIONS.len() {
+        return Err(format!("database schema is at version {}, but this nfhl_util only knows up to {}; upgrade nfhl_util",
+            current, MIGRATIONS.len()).into());
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = i as i32 + 1;
+        eprintln!("applying database migration {}", version);
+        tx.batch_execute(migration)?;
+        tx.execute("INSERT INTO nfhl_schema_migrations (version) VALUES ($1)", &[&version])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn insert_inventory(tx: &mut Transaction, kind: &str, inv: &Inventory) -> Result<i64, Box<dyn std::error::Error>> {
+    let snapshot_id: i64 = tx.query_one(
+        "INSERT INTO nfhl_inventory_snapshots (kind, created_at) VALUES ($1, $2) RETURNING id",
+        &[&kind, &Utc::now()],
+    )?.get(0);
+    let entry = tx.prepare(
+        "INSERT INTO nfhl_inventory_entries
+             (snapshot_id, fips, effective_file_url, effective_file_date, preliminary_file_url, preliminary_file_date)
+         VALUES ($1, $2, $3, $4, $5, $6)")?;
+    let mut fips_codes: Vec<&Fips> = inv.keys().collect();
+    fips_codes.sort();
+    for fips in fips_codes {
+        let e = &inv[fips];
+        tx.execute(&entry, &[
+            &snapshot_id, &fips.as_str(),
+            &e.effective_file_url.as_ref().map(Url::as_str), &e.effective_file_date,
+            &e.preliminary_file_url.as_ref().map(Url::as_str), &e.preliminary_file_date,
+        ])?;
+    }
+    Ok(snapshot_id)
+}
+
+fn insert_changes(tx: &mut Transaction, run_id: Option<i64>, changes: &[Change], observed_at: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>> {
+    let change = tx.prepare(
+        "INSERT INTO nfhl_changes (run_id, observed_at, fips, kind, old_date, new_date, url)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)")?;
+    for c in changes {
+        tx.execute(&change, &[
+            &run_id, &observed_at, &c.fips, &c.kind.as_str(),
+            &parse_file_date(&c.old_date), &parse_file_date(&c.new_date), &non_empty(&c.url),
+        ])?;
+    }
+    Ok(())
+}
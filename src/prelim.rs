@@ -0,0 +1,240 @@
+//! `compare-prelim`: a county's effective database against its preliminary one, for communities in the appeal
+//! period. The flood zones are overlaid the way `diff-geo` does it, and the base flood elevation lines of the two are
+//! paired up to show how far BFEs rise or fall.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::diff_geo::GeoDiff;
+use crate::inventory::{format_file_date, Inventory};
+use crate::merge_geo::Source;
+use crate::report::{self, ReportFormat};
+
+/// Where preliminary files are kept: a subdirectory, so they're never mistaken for a county's effective file.
+pub const PRELIMINARY_DIR: &str = "preliminary";
+
+/// Finds the county's preliminary file: `--prelim` if given, otherwise the inventory's, downloaded into the cache's
+/// preliminary directory the first time.
+pub fn preliminary_source(
+    cache_dir: &Path,
+    fips: &str,
+    prelim: Option<PathBuf>,
+    inv: Option<&Inventory>,
+) -> Result<Source, Box<dyn std::error::Error>> {
+    if let Some(archive) = prelim {
+        return Ok(Source { fips: fips.to_string(), effective_date: None, archive });
+    }
+    let (entry, url) = inv.and_then(|inv| inv.get(fips))
+        .and_then(|entry| Some((entry, entry.preliminary_file_url.as_ref()?)))
+        .ok_or_else(|| format!("the inventory has no preliminary file for {}; pass one with --prelim", fips))?;
+    let file_name = url.query_pairs()
+        .find(|(k, _)| k == "fileName")
+        .map(|(_, file_name)| file_name.into_owned())
+        .filter(|file_name| !file_name.is_empty() && !file_name.contains(['/', '\\']))
+        .unwrap_or_else(|| format!("{}C_prelim_{}.zip", fips, format_file_date(entry.preliminary_file_date)));
+
+    let dir = cache_dir.join(PRELIMINARY_DIR);
+    let archive = dir.join(&file_name);
+    if !archive.exists() {
+        std::fs::create_dir_all(&dir)?;
+        eprintln!("downloading {} ({})", fips, file_name);
+        crate::blocking::download_archive(url.as_str(), &archive)?;
+    }
+    Ok(Source { fips: fips.to_string(), effective_date: entry.preliminary_file_date, archive })
+}
+
+/// How the BFE lines changed. A preliminary line is paired with the nearest effective one within `BFE_MATCH_METRES`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BfeChanges {
+    pub effective_lines: u64,
+    pub preliminary_lines: u64,
+    pub raised: u64,
+    pub lowered: u64,
+    pub unchanged: u64,
+    /// Preliminary lines with no effective line near them.
+    pub new: u64,
+    /// Effective lines with no preliminary line near them.
+    pub removed: u64,
+    /// Pairs in different vertical datums or units (usually NGVD 29 restudied in NAVD 88), which aren't compared.
+    pub datum_changed: u64,
+    /// Over the compared pairs, in the elevations' own unit (nearly always feet).
+    pub mean_change: Option<f64>,
+    pub max_raise: Option<f64>,
+    pub max_lowering: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PrelimComparison {
+    pub zones: GeoDiff,
+    /// None when either database has no S_BFE.
+    pub bfes: Option<BfeChanges>,
+}
+
+#[cfg(feature = "gdal")]
+const BFE_MATCH_METRES: f64 = 30.0;
+
+/// BFEs within this much of each other count as unchanged.
+#[cfg(feature = "gdal")]
+const BFE_TOLERANCE: f64 = 0.05;
+
+#[cfg(feature = "gdal")]
+pub fn compare(effective: &Source, preliminary: &Source) -> Result<PrelimComparison, Box<dyn std::error::Error>> {
+    let zones = crate::diff_geo::diff_county(effective, preliminary)?;
+    let bfes = compare_bfes(effective, preliminary)?;
+    Ok(PrelimComparison { zones, bfes })
+}
+
+#[cfg(feature = "gdal")]
+fn compare_bfes(effective: &Source, preliminary: &Source) -> Result<Option<BfeChanges>, Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::{Envelope, Geometry, LayerAccess};
+    use crate::stats::{equal_area_srs, layer_srs};
+
+    struct Bfe {
+        elevation: f64,
+        /// The unit and vertical datum, which have to match for elevations to be compared.
+        reference: (String, String),
+        envelope: Envelope,
+        geometry: Geometry,
+        matched: bool,
+    }
+
+    /// The lines, and the projection they were put in.
+    type Lines = Option<(Vec<Bfe>, SpatialRef)>;
+
+    fn read(source: &Source, equal_area: Option<&SpatialRef>) -> Result<Lines, Box<dyn std::error::Error>> {
+        let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+        let dataset = gdal::Dataset::open(&gdb)?;
+        let mut layer = match dataset.layer_by_name("S_BFE") {
+            Ok(layer) => layer,
+            Err(_) => return Ok(None),
+        };
+        let equal_area = match equal_area {
+            Some(srs) => srs.clone(),
+            None => equal_area_srs(&layer)?,
+        };
+        let to_equal_area = CoordTransform::new(&layer_srs(&layer)?, &equal_area)?;
+        let mut lines = Vec::new();
+        for feature in layer.features() {
+            let (geometry, elevation) = match (feature.geometry(), feature.field_as_double_by_name("ELEV")?) {
+                (Some(geometry), Some(elevation)) if !geometry.is_empty() => (geometry.transform(&to_equal_area)?, elevation),
+                _ => continue,
+            };
+            let text = |field: &str| -> Result<String, Box<dyn std::error::Error>> {
+                Ok(feature.field_as_string_by_name(field)?.unwrap_or_default().trim().to_ascii_uppercase())
+            };
+            lines.push(Bfe {
+                elevation,
+                reference: (text("LEN_UNIT")?, text("V_DATUM")?),
+                envelope: geometry.envelope(),
+                geometry,
+                matched: false,
+            });
+        }
+        Ok(Some((lines, equal_area)))
+    }
+
+    let (prelim_lines, equal_area) = match read(preliminary, None)? {
+        Some(read) => read,
+        None => return Ok(None),
+    };
+    let mut effective_lines = match read(effective, Some(&equal_area))? {
+        Some((lines, _)) => lines,
+        None => return Ok(None),
+    };
+
+    let mut changes = BfeChanges {
+        effective_lines: effective_lines.len() as u64,
+        preliminary_lines: prelim_lines.len() as u64,
+        ..Default::default()
+    };
+    let mut deltas = Vec::new();
+    for prelim in &prelim_lines {
+        let bounds = &prelim.envelope;
+        let mut nearest: Option<(f64, usize)> = None;
+        for (i, line) in effective_lines.iter().enumerate() {
+            let e = &line.envelope;
+            if e.MinX > bounds.MaxX + BFE_MATCH_METRES || e.MaxX < bounds.MinX - BFE_MATCH_METRES
+                || e.MinY > bounds.MaxY + BFE_MATCH_METRES || e.MaxY < bounds.MinY - BFE_MATCH_METRES {
+                continue;
+            }
+            // SAFETY: both handles stay owned by their geometries for the duration of the call
+            let distance = unsafe { gdal_sys::OGR_G_Distance(prelim.geometry.c_geometry(), line.geometry.c_geometry()) };
+            if (0.0..=BFE_MATCH_METRES).contains(&distance) && nearest.is_none_or(|(d, _)| distance < d) {
+                nearest = Some((distance, i));
+            }
+        }
+        let line = match nearest {
+            Some((_, i)) => &mut effective_lines[i],
+            None => {
+                changes.new += 1;
+                continue;
+            }
+        };
+        line.matched = true;
+        if line.reference != prelim.reference {
+            changes.datum_changed += 1;
+            continue;
+        }
+        let delta = prelim.elevation - line.elevation;
+        if delta > BFE_TOLERANCE {
+            changes.raised += 1;
+        } else if delta < -BFE_TOLERANCE {
+            changes.lowered += 1;
+        } else {
+            changes.unchanged += 1;
+        }
+        deltas.push(delta);
+    }
+    changes.removed = effective_lines.iter().filter(|line| !line.matched).count() as u64;
+    if !deltas.is_empty() {
+        changes.mean_change = Some(deltas.iter().sum::<f64>() / deltas.len() as f64);
+        changes.max_raise = deltas.iter().copied().filter(|d| *d > 0.0).reduce(f64::max);
+        changes.max_lowering = deltas.iter().copied().filter(|d| *d < 0.0).map(|d| -d).reduce(f64::max);
+    }
+    Ok(Some(changes))
+}
+
+/// The zone transitions as `diff-geo` writes them, then the BFE changes.
+pub fn write_comparison(out: &mut dyn Write, comparison: &PrelimComparison, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, comparison)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    crate::diff_geo::write_geo_diff(out, &comparison.zones, format)?;
+    let bfes = match &comparison.bfes {
+        Some(bfes) => bfes,
+        None => {
+            if let ReportFormat::Table = format {
+                writeln!(out, "\nno BFE lines to compare")?;
+            }
+            return Ok(());
+        }
+    };
+    let value = |v: Option<f64>| v.map(|v| format!("{:.2}", v)).unwrap_or_default();
+    let headers: Vec<String> = ["bfe_lines", "value"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = [
+        ("effective lines", bfes.effective_lines.to_string()),
+        ("preliminary lines", bfes.preliminary_lines.to_string()),
+        ("raised", bfes.raised.to_string()),
+        ("lowered", bfes.lowered.to_string()),
+        ("unchanged", bfes.unchanged.to_string()),
+        ("new", bfes.new.to_string()),
+        ("removed", bfes.removed.to_string()),
+        ("datum changed", bfes.datum_changed.to_string()),
+        ("mean change", value(bfes.mean_change)),
+        ("max raise", value(bfes.max_raise)),
+        ("max lowering", value(bfes.max_lowering)),
+    ].into_iter().map(|(name, value)| vec![name.to_string(), value]).collect();
+    writeln!(out)?;
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
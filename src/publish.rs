@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::diff::Change;
+use crate::download::DownloadRecord;
+
+/// Something downstream pipelines may want to react to.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A county's inventory entry changed relative to the previous inventory.
+    Change(Change),
+    /// A county's file finished downloading into the cache.
+    Download(DownloadRecord),
+}
+
+impl Event {
+    /// The county the event is about, used as the message key so a county's events stay ordered within a partition.
+    pub fn fips(&self) -> &str {
+        match self {
+            Event::Change(change) => &change.fips,
+            Event::Download(record) => &record.fips,
+        }
+    }
+}
+
+/// What actually goes on the wire: the event plus when it was emitted.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    emitted_at: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+trait Publisher {
+    fn publish(&mut self, key: &str, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+type Listener = Box<dyn FnMut(&Event)>;
+
+/// The message buses given with `--publish`. Publishing is best-effort: a broker being down is logged but doesn't
+/// fail the run, since the cache and the change log remain the source of truth.
+#[derive(Default)]
+pub struct Publishers {
+    publishers: Vec<(String, Box<dyn Publisher>)>,
+    /// In-process reactions to events, like `--extract`'s pool.
+    listeners: Vec<Listener>,
+}
+
+impl Publishers {
+    /// Connects to every url, which must look like `kafka://host:9092[,host:9092...]/topic` or
+    /// `nats://[user:pass@]host:4222/subject`.
+    pub fn connect_all(urls: &[String]) -> Result<Publishers, Box<dyn std::error::Error>> {
+        let mut publishers = Publishers::default();
+        for url in urls {
+            publishers.publishers.push((url.clone(), connect(url)?));
+        }
+        Ok(publishers)
+    }
+
+    /// Also calls `listener` with every event, before it's sent anywhere.
+    pub fn listen(&mut self, listener: impl FnMut(&Event) + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn publish(&mut self, event: &Event) {
+        for listener in &mut self.listeners {
+            listener(event);
+        }
+        if self.publishers.is_empty() {
+            return;
+        }
+        let payload = match serde_json::to_vec(&Envelope { emitted_at: Utc::now(), event }) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("failed to serialize event: {}", e);
+                return;
+            }
+        };
+        for (url, publisher) in &mut self.publishers {
+            if let Err(e) = publisher.publish(event.fips(), &payload) {
+                eprintln!("failed to publish to {}: {}", url, e);
+            }
+        }
+    }
+}
+
+/// Splits `scheme://hosts/path` into its hosts and path (topic or subject).
+#[cfg(any(feature = "kafka", feature = "nats"))]
+fn split_url<'a>(url: &'a str, scheme: &str) -> Result<(&'a str, &'a str), Box<dyn std::error::Error>> {
+    let rest = &url[scheme.len() + 3..];
+    match rest.split_once('/') {
+        Some((hosts, path)) if !hosts.is_empty() && !path.is_empty() => Ok((hosts, path)),
+        _ => Err(format!("'{}' should look like {}://host:port/{}", url, scheme,
+            if scheme == "kafka" { "topic" } else { "subject" }).into()),
+    }
+}
+
+fn connect(url: &str) -> Result<Box<dyn Publisher>, Box<dyn std::error::Error>> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme).unwrap_or_default();
+    match scheme {
+        "kafka" => connect_kafka(url),
+        "nats" => connect_nats(url),
+        _ => Err(format!("unsupported publish url '{}' (expected kafka:// or nats://)", url).into()),
+    }
+}
+
+#[cfg(feature = "kafka")]
+struct KafkaPublisher {
+    producer: kafka::producer::Producer,
+    topic: String,
+}
+
+#[cfg(feature = "kafka")]
+impl Publisher for KafkaPublisher {
+    fn publish(&mut self, key: &str, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.producer.send(&kafka::producer::Record::from_key_value(&self.topic, key, payload))?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn connect_kafka(url: &str) -> Result<Box<dyn Publisher>, Box<dyn std::error::Error>> {
+    let (hosts, topic) = split_url(url, "kafka")?;
+    let producer = kafka::producer::Producer::from_hosts(hosts.split(',').map(String::from).collect())
+        .with_ack_timeout(std::time::Duration::from_secs(10))
+        .with_required_acks(kafka::producer::RequiredAcks::One)
+        .with_client_id("nfhl_util".to_string())
+        .create()?;
+    Ok(Box::new(KafkaPublisher { producer, topic: topic.to_string() }))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn connect_kafka(url: &str) -> Result<Box<dyn Publisher>, Box<dyn std::error::Error>> {
+    Err(format!("cannot publish to '{}': nfhl_util was built without kafka support (enable the `kafka` feature)", url).into())
+}
+
+#[cfg(feature = "nats")]
+struct NatsPublisher {
+    connection: nats::Connection,
+    subject: String,
+}
+
+#[cfg(feature = "nats")]
+impl Publisher for NatsPublisher {
+    fn publish(&mut self, _key: &str, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.connection.publish(&self.subject, payload)?;
+        self.connection.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nats")]
+fn connect_nats(url: &str) -> Result<Box<dyn Publisher>, Box<dyn std::error::Error>> {
+    let (server, subject) = split_url(url, "nats")?;
+    let connection = nats::connect(format!("nats://{}", server).as_str())?;
+    Ok(Box::new(NatsPublisher { connection, subject: subject.to_string() }))
+}
+
+#[cfg(not(feature = "nats"))]
+fn connect_nats(url: &str) -> Result<Box<dyn Publisher>, Box<dyn std::error::Error>> {
+    Err(format!("cannot publish to '{}': nfhl_util was built without nats support (enable the `nats` feature)", url).into())
+}
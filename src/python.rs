@@ -0,0 +1,145 @@
+//! The `nfhl_util` Python module, behind the `python` feature and built with maturin (`maturin build --release`,
+//! set up in `pyproject.toml`). Inventories, changes, run reports and zone determinations come back as the dicts
+//! and lists the CLI's JSON output parses to, so scripts that ran the CLI and parsed its output can call these instead
+//! without changing what they do with the results:
+//!
+//! ```python
+//! import nfhl_util
+//!
+//! inv = nfhl_util.counties_inventory()
+//! changes = nfhl_util.diff(nfhl_util.read_inventory("counties.json"), inv)
+//! report = nfhl_util.download(inv, "/data/nfhl", politeness=100)
+//! zone = nfhl_util.query_point(29.7604, -95.3698, cache_dir="/data/nfhl")
+//! ```
+//!
+//! The GIL is released while FEMA's sites and the cache are being worked on. Failures raise `nfhl_util.NfhlError`,
+//! or its subclass `nfhl_util.NetworkError` for the ones worth retrying.
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::cancel::CancellationToken;
+use crate::inventory::{format, Inventory};
+use crate::publish::Publishers;
+use crate::{blocking, diff, inventory, query};
+
+create_exception!(nfhl_util, NfhlError, PyException, "An nfhl_util operation failed.");
+create_exception!(nfhl_util, NetworkError, NfhlError, "A request to FEMA failed in a way that's worth retrying.");
+
+/// The exception for an error. Made without the GIL, so it can be called inside `allow_threads`.
+fn error(e: impl Into<Box<dyn std::error::Error>>) -> PyErr {
+    let e = e.into();
+    match e.downcast_ref::<crate::error::NfhlError>() {
+        Some(nfhl) if nfhl.is_retryable() => NetworkError::new_err(e.to_string()),
+        _ => NfhlError::new_err(e.to_string()),
+    }
+}
+
+/// A value as the Python object its JSON parses to.
+fn to_py<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(error)?;
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// An inventory dict from `counties_inventory` or `json.load`, which may have the `version` of one saved to a file.
+fn inventory_from_py(value: &Bound<'_, PyAny>) -> PyResult<Inventory> {
+    let json: String = value.py().import("json")?.call_method1("dumps", (value,))?.extract()?;
+    format::read(json.as_bytes()).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// The current county inventory from the NFHL portal, a dict of entries by county fips.
+#[pyfunction]
+fn counties_inventory(py: Python<'_>) -> PyResult<PyObject> {
+    let inv = py.allow_threads(|| blocking::get_effective_county_products().map_err(error))?;
+    to_py(py, &inv)
+}
+
+/// The current state inventory from the Map Service Center, a dict of entries by state fips.
+#[pyfunction]
+fn states_inventory(py: Python<'_>) -> PyResult<PyObject> {
+    let inv = py.allow_threads(|| blocking::get_effective_state_products().map_err(error))?;
+    to_py(py, &inv)
+}
+
+/// An inventory saved by `nfhl_util counties_inventory` or `states_inventory`.
+#[pyfunction]
+fn read_inventory(py: Python<'_>, path: PathBuf) -> PyResult<PyObject> {
+    let inv = inventory::read_inventory(&path).map_err(error)?;
+    to_py(py, &inv)
+}
+
+/// What changed from one inventory to the next, as a list of changes like `nfhl_util diff --format json` prints.
+#[pyfunction]
+#[pyo3(name = "diff")]
+fn diff_inventories(py: Python<'_>, old: &Bound<'_, PyAny>, new: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let old = inventory_from_py(old)?;
+    let new = inventory_from_py(new)?;
+    to_py(py, &diff::diff_inventories(&old, &new))
+}
+
+/// Brings `cache_dir` up to date with `inventory`, like `nfhl_util download_all`, one download at a time. Returns the
+/// run report.
+#[pyfunction]
+#[pyo3(signature = (inventory, cache_dir, old_inventory = None, delete = false, politeness = 255))]
+fn download(
+    py: Python<'_>,
+    inventory: &Bound<'_, PyAny>,
+    cache_dir: PathBuf,
+    old_inventory: Option<&Bound<'_, PyAny>>,
+    delete: bool,
+    politeness: u8,
+) -> PyResult<PyObject> {
+    let inv = inventory_from_py(inventory)?;
+    let old_inv = old_inventory.map(inventory_from_py).transpose()?;
+    let report = py.allow_threads(|| {
+        let mut publishers = Publishers::default();
+        blocking::download_all(
+            &inv,
+            old_inv.as_ref(),
+            &cache_dir,
+            delete,
+            politeness,
+            None,
+            &mut publishers,
+            &CancellationToken::new(),
+        )
+        .map_err(error)
+    })?;
+    to_py(py, &report)
+}
+
+/// The flood zone at a point, like `nfhl_util query point`: from the cache if `cache_dir` is given (which needs
+/// nfhl_util built with gdal), otherwise from the NFHL map service.
+#[pyfunction]
+#[pyo3(signature = (lat, lon, cache_dir = None, fips = None))]
+fn query_point(
+    py: Python<'_>,
+    lat: f64,
+    lon: f64,
+    cache_dir: Option<PathBuf>,
+    fips: Option<String>,
+) -> PyResult<PyObject> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(PyValueError::new_err(format!("{}, {} isn't a latitude and longitude", lat, lon)));
+    }
+    let determination =
+        py.allow_threads(|| query::determine(cache_dir.as_deref(), fips.as_deref(), lon, lat).map_err(error))?;
+    to_py(py, &determination)
+}
+
+#[pymodule]
+fn nfhl_util(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("NfhlError", m.py().get_type::<NfhlError>())?;
+    m.add("NetworkError", m.py().get_type::<NetworkError>())?;
+    m.add_function(wrap_pyfunction!(counties_inventory, m)?)?;
+    m.add_function(wrap_pyfunction!(states_inventory, m)?)?;
+    m.add_function(wrap_pyfunction!(read_inventory, m)?)?;
+    m.add_function(wrap_pyfunction!(diff_inventories, m)?)?;
+    m.add_function(wrap_pyfunction!(download, m)?)?;
+    m.add_function(wrap_pyfunction!(query_point, m)?)?;
+    Ok(())
+}
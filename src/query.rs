@@ -0,0 +1,772 @@
+//! `query`: flood zone lookups against the cache. A point is matched to the county containing it (cached counties'
+//! bboxes are kept in `county_index.json` in the cache, so only the few candidates are opened), then to the flood
+//! hazard polygon and FIRM panel it falls in. `--online` asks FEMA's NFHL map service instead.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::merge_geo::Source;
+use crate::report::{self, ReportFormat};
+
+pub const COUNTY_INDEX_FILE_NAME: &str = "county_index.json";
+
+/// FEMA's stand-in for "no value" in numeric fields such as `STATIC_BFE`.
+const NO_VALUE: f64 = -9999.0;
+
+/// FEMA's NFHL map service, and the ids of its FIRM panel and flood hazard zone layers.
+pub const NFHL_MAPSERVER: &str = "https://hazards.fema.gov/arcgis/rest/services/public/NFHL/MapServer";
+const PANELS_LAYER_ID: i64 = 3;
+const POLITICAL_LAYER_ID: i64 = 22;
+const HAZARDS_LAYER_ID: i64 = 28;
+
+/// What a lookup found at a point. The shape is the same whichever way the answer was found.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ZoneDetermination {
+    pub lat: f64,
+    pub lon: f64,
+    /// `cache`, for an answer from the cached databases, otherwise the url of the service asked.
+    pub source: String,
+    pub fips: Option<String>,
+    /// The cached file the answer came from.
+    pub file: Option<String>,
+    pub effective_date: Option<NaiveDate>,
+    /// None if the point is in the county but outside its mapped area.
+    pub flood_zone: Option<String>,
+    pub zone_subtype: Option<String>,
+    /// Whether the point is in the Special Flood Hazard Area.
+    pub sfha: Option<bool>,
+    pub static_bfe: Option<f64>,
+    pub v_datum: Option<String>,
+    pub panel: Option<String>,
+    pub panel_effective_date: Option<NaiveDate>,
+}
+
+/// The lon/lat bbox of each cached file, by file name. File names carry the effective date, so an entry never goes
+/// stale; entries for files no longer cached are dropped when the index is updated.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CountyIndex {
+    pub files: BTreeMap<String, Option<[f64; 4]>>,
+}
+
+impl CountyIndex {
+    pub fn load(cache_dir: &Path) -> Result<CountyIndex, Box<dyn std::error::Error>> {
+        let path = cache_dir.join(COUNTY_INDEX_FILE_NAME);
+        if !path.exists() {
+            return Ok(CountyIndex::default());
+        }
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Saves the index to `cache_dir`, replacing it atomically the way the manifest is.
+    pub fn save(&self, cache_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let path = cache_dir.join(COUNTY_INDEX_FILE_NAME);
+        let tmp_path = cache_dir.join(format!("{}.tmp", COUNTY_INDEX_FILE_NAME));
+        serde_json::to_writer(BufWriter::new(File::create(&tmp_path)?), self)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+fn file_name(source: &Source) -> String {
+    source.archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// A cached county and its lon/lat bbox, if it could be read.
+pub type IndexedSource = (Source, Option<[f64; 4]>);
+
+/// Every cached county (its newest file) with its bbox, indexing the files the index doesn't have yet.
+#[cfg(feature = "gdal")]
+pub fn indexed_sources(cache_dir: &Path) -> Result<Vec<IndexedSource>, Box<dyn std::error::Error>> {
+    let sources = crate::merge_geo::sources(cache_dir, |_| true)?;
+    let mut index = CountyIndex::load(cache_dir)?;
+    let cached: std::collections::HashSet<String> = sources.iter().map(file_name).collect();
+    let mut changed = false;
+    index.files.retain(|name, _| {
+        let keep = cached.contains(name);
+        changed |= !keep;
+        keep
+    });
+    for source in &sources {
+        if let std::collections::btree_map::Entry::Vacant(entry) = index.files.entry(file_name(source)) {
+            let bbox = county_bbox(source).unwrap_or_else(|e| {
+                eprintln!("{}: can't index {}: {}", source.fips, entry.key(), e);
+                None
+            });
+            entry.insert(bbox);
+            changed = true;
+        }
+    }
+    if changed {
+        index.save(cache_dir)?;
+    }
+    Ok(sources.into_iter()
+        .map(|source| {
+            let bbox = index.files.get(&file_name(&source)).copied().flatten();
+            (source, bbox)
+        })
+        .collect())
+}
+
+pub fn in_bbox(bbox: Option<[f64; 4]>, lon: f64, lat: f64) -> bool {
+    bbox.is_some_and(|[min_x, min_y, max_x, max_y]| (min_x..=max_x).contains(&lon) && (min_y..=max_y).contains(&lat))
+}
+
+/// The cached counties whose bbox contains the point.
+#[cfg(feature = "gdal")]
+pub fn candidates(cache_dir: &Path, lon: f64, lat: f64) -> Result<Vec<Source>, Box<dyn std::error::Error>> {
+    Ok(indexed_sources(cache_dir)?.into_iter()
+        .filter(|(_, bbox)| in_bbox(*bbox, lon, lat))
+        .map(|(source, _)| source)
+        .collect())
+}
+
+/// The lon/lat bbox of the county's political areas (or failing them, its flood hazard areas).
+#[cfg(feature = "gdal")]
+fn county_bbox(source: &Source) -> Result<Option<[f64; 4]>, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let layer = match dataset.layer_by_name("S_Pol_Ar").or_else(|_| dataset.layer_by_name("S_Fld_Haz_Ar")) {
+        Ok(layer) => layer,
+        Err(_) => return Ok(None),
+    };
+    let extent = layer.get_extent()?;
+    let to_lon_lat = gdal::spatial_ref::CoordTransform::new(&crate::stats::layer_srs(&layer)?, &lon_lat()?)?;
+    Ok(Some(to_lon_lat.transform_bounds(&[extent.MinX, extent.MinY, extent.MaxX, extent.MaxY], 21)?))
+}
+
+#[cfg(feature = "gdal")]
+fn lon_lat() -> Result<gdal::spatial_ref::SpatialRef, Box<dyn std::error::Error>> {
+    let lon_lat = gdal::spatial_ref::SpatialRef::from_epsg(4326)?;
+    lon_lat.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    Ok(lon_lat)
+}
+
+/// A county's database, opened for any number of lookups.
+#[cfg(feature = "gdal")]
+pub struct CountyLookup {
+    pub source: Source,
+    dataset: gdal::Dataset,
+    /// From lon/lat into the layers' projection. The layers of a FIRM database all share one.
+    to_layers: gdal::spatial_ref::CoordTransform,
+}
+
+#[cfg(feature = "gdal")]
+impl CountyLookup {
+    pub fn open(source: &Source) -> Result<CountyLookup, Box<dyn std::error::Error>> {
+        use gdal::vector::LayerAccess;
+
+        let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+        let dataset = gdal::Dataset::open(&gdb)?;
+        let srs = crate::stats::layer_srs(&dataset.layer_by_name("S_Fld_Haz_Ar")
+            .map_err(|_| format!("{} has no S_Fld_Haz_Ar layer", gdb))?)?;
+        let to_layers = gdal::spatial_ref::CoordTransform::new(&lon_lat()?, &srs)?;
+        Ok(CountyLookup { source: source.clone(), dataset, to_layers })
+    }
+
+    fn point(&self, lon: f64, lat: f64) -> Result<gdal::vector::Geometry, Box<dyn std::error::Error>> {
+        Ok(gdal::vector::Geometry::from_wkt(&format!("POINT ({} {})", lon, lat))?.transform(&self.to_layers)?)
+    }
+
+    /// Whether the point is inside the county's political areas. Counties whose database has none are taken to
+    /// contain every point in their bbox.
+    pub fn contains(&self, lon: f64, lat: f64) -> Result<bool, Box<dyn std::error::Error>> {
+        use gdal::vector::LayerAccess;
+
+        let mut layer = match self.dataset.layer_by_name("S_Pol_Ar") {
+            Ok(layer) => layer,
+            Err(_) => return Ok(true),
+        };
+        let point = self.point(lon, lat)?;
+        layer.set_spatial_filter(&point);
+        let contains = layer.features().any(|feature| feature.geometry().is_some_and(|g| g.intersects(&point)));
+        Ok(contains)
+    }
+
+    pub fn determine(&self, lon: f64, lat: f64) -> Result<ZoneDetermination, Box<dyn std::error::Error>> {
+        use gdal::vector::LayerAccess;
+
+        let point = self.point(lon, lat)?;
+        let mut determination = ZoneDetermination {
+            lat,
+            lon,
+            source: "cache".to_string(),
+            fips: Some(self.source.fips.clone()),
+            file: Some(file_name(&self.source)),
+            effective_date: self.source.effective_date,
+            ..Default::default()
+        };
+        let text = |feature: &gdal::vector::Feature, field: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+            Ok(feature.field_as_string_by_name(field)?.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))
+        };
+
+        let mut hazards = self.dataset.layer_by_name("S_Fld_Haz_Ar")?;
+        hazards.set_spatial_filter(&point);
+        for feature in hazards.features() {
+            if !feature.geometry().is_some_and(|g| g.intersects(&point)) {
+                continue;
+            }
+            determination.flood_zone = text(&feature, "FLD_ZONE")?;
+            determination.zone_subtype = text(&feature, "ZONE_SUBTY")?;
+            determination.sfha = text(&feature, "SFHA_TF")?.map(|tf| tf.eq_ignore_ascii_case("T"));
+            determination.static_bfe = feature.field_as_double_by_name("STATIC_BFE")?.filter(|bfe| *bfe != NO_VALUE);
+            determination.v_datum = text(&feature, "V_DATUM")?.filter(|_| determination.static_bfe.is_some());
+            break;
+        }
+
+        if let Some(panel) = self.panel_at(&point)? {
+            determination.panel = panel.firm_pan;
+            determination.panel_effective_date = panel.effective_date;
+        }
+        Ok(determination)
+    }
+
+    /// The NFIP community the point is in, from the political areas.
+    pub fn community(&self, lon: f64, lat: f64) -> Result<Option<Community>, Box<dyn std::error::Error>> {
+        use gdal::vector::LayerAccess;
+
+        let mut layer = match self.dataset.layer_by_name("S_Pol_Ar") {
+            Ok(layer) => layer,
+            Err(_) => return Ok(None),
+        };
+        let point = self.point(lon, lat)?;
+        layer.set_spatial_filter(&point);
+        for feature in layer.features() {
+            if !feature.geometry().is_some_and(|g| g.intersects(&point)) {
+                continue;
+            }
+            let text = |field: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+                Ok(feature.field_as_string_by_name(field)?.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))
+            };
+            return Ok(Some(Community { cid: text("CID")?, name: text("COMM_NAME")? }));
+        }
+        Ok(None)
+    }
+
+    /// Whether the database has a FIRM panel layer to search.
+    pub fn has_panels(&self) -> bool {
+        self.dataset.layer_by_name("S_FIRM_Pan").is_ok()
+    }
+
+    /// The FIRM panel the point is on.
+    pub fn panel(&self, lon: f64, lat: f64) -> Result<Option<FirmPanel>, Box<dyn std::error::Error>> {
+        self.panel_at(&self.point(lon, lat)?)
+    }
+
+    fn panel_at(&self, point: &gdal::vector::Geometry) -> Result<Option<FirmPanel>, Box<dyn std::error::Error>> {
+        use gdal::vector::LayerAccess;
+
+        let mut panels = match self.dataset.layer_by_name("S_FIRM_Pan") {
+            Ok(panels) => panels,
+            Err(_) => return Ok(None),
+        };
+        panels.set_spatial_filter(point);
+        let mut found: Option<FirmPanel> = None;
+        // panel indexes at two scales can overlap; the latest panel is the one to cite
+        for feature in panels.features() {
+            if !feature.geometry().is_some_and(|g| g.intersects(point)) {
+                continue;
+            }
+            let text = |field: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+                Ok(feature.field_as_string_by_name(field)?.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))
+            };
+            let panel = FirmPanel {
+                firm_pan: text("FIRM_PAN")?,
+                pcomm: text("PCOMM")?,
+                panel: text("PANEL")?,
+                suffix: text("SUFFIX")?,
+                effective_date: feature.field_as_datetime_by_name("EFF_DATE")?.map(|date| date.date_naive()),
+                panel_type: text("PANEL_TYP")?,
+            };
+            if found.as_ref().is_none_or(|found| panel.effective_date > found.effective_date) {
+                found = Some(panel);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Finds the cached county containing the point, or with `fips`, just opens that county's database.
+#[cfg(feature = "gdal")]
+pub fn locate(cache_dir: &Path, fips: Option<&str>, lon: f64, lat: f64) -> Result<CountyLookup, Box<dyn std::error::Error>> {
+    if let Some(fips) = fips {
+        let source = crate::merge_geo::sources(cache_dir, |cached| cached == fips)?
+            .pop()
+            .ok_or_else(|| format!("no cached archive for {} in {}", fips, cache_dir.display()))?;
+        return CountyLookup::open(&source);
+    }
+    for source in candidates(cache_dir, lon, lat)? {
+        let lookup = match CountyLookup::open(&source) {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                eprintln!("{}: skipped: {}", source.fips, e);
+                continue;
+            }
+        };
+        if lookup.contains(lon, lat)? {
+            return Ok(lookup);
+        }
+    }
+    Err(format!("no cached county in {} contains {}, {}", cache_dir.display(), lat, lon).into())
+}
+
+/// The part of a polygon in one kind of flood hazard area.
+#[derive(Serialize, Debug, Clone)]
+pub struct ZoneShare {
+    pub flood_zone: String,
+    pub zone_subtype: Option<String>,
+    pub sfha: bool,
+    pub acres: f64,
+    /// Of the whole polygon.
+    pub fraction: f64,
+}
+
+/// The flood hazard areas a polygon (e.g. a parcel) intersects.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PolygonDetermination {
+    pub acres: f64,
+    /// The counties the intersected areas are in.
+    pub counties: Vec<String>,
+    /// Whether any part of the polygon is in the Special Flood Hazard Area.
+    pub in_sfha: bool,
+    pub sfha_fraction: f64,
+    /// Largest first.
+    pub zones: Vec<ZoneShare>,
+    /// The part outside the mapped area of every cached county.
+    pub unmapped_fraction: f64,
+}
+
+/// Intersects the polygons of a GeoJSON file (dissolved into one) with the flood hazard areas of the cached counties
+/// it overlaps, or with `fips`, just that county's. Areas are measured in an equal-area projection centred on it.
+#[cfg(feature = "gdal")]
+pub fn determine_polygon(cache_dir: &Path, geojson: &Path, fips: Option<&str>) -> Result<PolygonDetermination, Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::CoordTransform;
+    use gdal::vector::LayerAccess;
+    use crate::stats::{equal_area_srs, layer_srs, SQ_M_PER_ACRE};
+
+    let input = gdal::Dataset::open(geojson)?;
+    let mut input_layer = input.layer(0)?;
+    let input_srs = layer_srs(&input_layer)?;
+    let equal_area = equal_area_srs(&input_layer)?;
+    let extent = input_layer.get_extent()?;
+    let [min_x, min_y, max_x, max_y] = CoordTransform::new(&input_srs, &lon_lat()?)?
+        .transform_bounds(&[extent.MinX, extent.MinY, extent.MaxX, extent.MaxY], 21)?;
+    let to_equal_area = CoordTransform::new(&input_srs, &equal_area)?;
+    let mut polygons = Vec::new();
+    for feature in input_layer.features() {
+        if let Some(geometry) = feature.geometry().filter(|g| !g.is_empty()) {
+            polygons.push(geometry.transform(&to_equal_area)?);
+        }
+    }
+    let polygon = crate::validate::geometry::dissolve(polygons.into_iter())?;
+    let area = polygon.area();
+    if area <= 0.0 {
+        return Err(format!("{} has no polygons", geojson.display()).into());
+    }
+
+    let sources: Vec<Source> = match fips {
+        Some(fips) => crate::merge_geo::sources(cache_dir, |cached| cached == fips)?,
+        None => indexed_sources(cache_dir)?.into_iter()
+            .filter(|(_, bbox)| bbox.is_some_and(|b| b[0] <= max_x && b[2] >= min_x && b[1] <= max_y && b[3] >= min_y))
+            .map(|(source, _)| source)
+            .collect(),
+    };
+    let mut areas: BTreeMap<(String, Option<String>, bool), f64> = BTreeMap::new();
+    let mut counties = Vec::new();
+    for source in &sources {
+        let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+        let dataset = gdal::Dataset::open(&gdb)?;
+        let mut hazards = dataset.layer_by_name("S_Fld_Haz_Ar").map_err(|_| format!("{} has no S_Fld_Haz_Ar layer", gdb))?;
+        let srs = layer_srs(&hazards)?;
+        hazards.set_spatial_filter(&polygon.transform(&CoordTransform::new(&equal_area, &srs)?)?);
+        let to_equal_area = CoordTransform::new(&srs, &equal_area)?;
+        let mut intersected = false;
+        for feature in hazards.features() {
+            let geometry = match feature.geometry() {
+                Some(geometry) if !geometry.is_empty() => geometry.transform(&to_equal_area)?,
+                _ => continue,
+            };
+            let geometry = if geometry.is_valid() { geometry } else { geometry.buffer(0.0, 8)? };
+            let piece = match geometry.intersection(&polygon) {
+                Some(piece) if piece.area() > 0.0 => piece.area(),
+                _ => continue,
+            };
+            let text = |field: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+                Ok(feature.field_as_string_by_name(field)?.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))
+            };
+            let sfha = text("SFHA_TF")?.is_some_and(|tf| tf.eq_ignore_ascii_case("T"));
+            *areas.entry((text("FLD_ZONE")?.unwrap_or_default(), text("ZONE_SUBTY")?, sfha)).or_default() += piece;
+            intersected = true;
+        }
+        if intersected {
+            counties.push(source.fips.clone());
+        }
+    }
+
+    let mut zones: Vec<ZoneShare> = areas.into_iter()
+        .map(|((flood_zone, zone_subtype, sfha), zone_area)| ZoneShare {
+            flood_zone,
+            zone_subtype,
+            sfha,
+            acres: zone_area / SQ_M_PER_ACRE,
+            fraction: zone_area / area,
+        })
+        .collect();
+    zones.sort_by(|a, b| b.acres.total_cmp(&a.acres));
+    let sfha_fraction: f64 = zones.iter().filter(|zone| zone.sfha).map(|zone| zone.fraction).sum();
+    let mapped_fraction: f64 = zones.iter().map(|zone| zone.fraction).sum();
+    Ok(PolygonDetermination {
+        acres: area / SQ_M_PER_ACRE,
+        counties,
+        in_sfha: sfha_fraction > 0.0,
+        sfha_fraction,
+        zones,
+        unmapped_fraction: (1.0 - mapped_fraction).max(0.0),
+    })
+}
+
+/// A row per zone, or for JSON the whole determination.
+pub fn write_polygon(out: &mut dyn std::io::Write, determination: &PolygonDetermination, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, determination)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["flood_zone", "zone_subtype", "sfha", "acres", "fraction"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = determination.zones.iter()
+        .map(|zone| vec![
+            zone.flood_zone.clone(),
+            zone.zone_subtype.clone().unwrap_or_default(),
+            if zone.sfha { "T" } else { "F" }.to_string(),
+            format!("{:.3}", zone.acres),
+            format!("{:.4}", zone.fraction),
+        ])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows)?,
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+    if let ReportFormat::Table = format {
+        writeln!(out, "\n{:.3} acres, {:.1}% in the SFHA, {:.1}% unmapped", determination.acres,
+            determination.sfha_fraction * 100.0, determination.unmapped_fraction * 100.0)?;
+    }
+    Ok(())
+}
+
+/// A FIRM panel, as it's cited: `firm_pan` is the community or county number (`pcomm`), the panel number and the
+/// suffix letter of its latest revision.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FirmPanel {
+    pub firm_pan: Option<String>,
+    pub pcomm: Option<String>,
+    pub panel: Option<String>,
+    pub suffix: Option<String>,
+    pub effective_date: Option<NaiveDate>,
+    pub panel_type: Option<String>,
+}
+
+/// What `query panel` found at a point.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PanelDetermination {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_address: Option<String>,
+    /// `cache`, or the url of the service asked.
+    pub source: String,
+    pub fips: Option<String>,
+    /// None where the point isn't on any panel, e.g. in a county without a FIRM.
+    pub panel: Option<FirmPanel>,
+}
+
+/// The panel at the point, from the cache if there's one (finding its county unless `fips` is given), otherwise
+/// online.
+pub fn determine_panel(cache_dir: Option<&Path>, fips: Option<&str>, lon: f64, lat: f64) -> Result<PanelDetermination, Box<dyn std::error::Error>> {
+    match cache_dir {
+        #[cfg(feature = "gdal")]
+        Some(cache_dir) => {
+            let lookup = locate(cache_dir, fips, lon, lat)?;
+            let panel = lookup.panel(lon, lat)?;
+            Ok(PanelDetermination { lat, lon, source: "cache".to_string(), fips: Some(lookup.source.fips), panel, ..Default::default() })
+        }
+        #[cfg(not(feature = "gdal"))]
+        Some(_) => {
+            let _ = fips;
+            Err("looking up in the cache needs nfhl_util built with `--features gdal`; use `--online` instead".into())
+        }
+        None => {
+            let panel = online_panel(&identify(lon, lat, &[PANELS_LAYER_ID])?);
+            let fips = panel.as_ref().and_then(|panel| panel.firm_pan.as_deref()).and_then(countywide_fips);
+            Ok(PanelDetermination { lat, lon, source: NFHL_MAPSERVER.to_string(), fips, panel, ..Default::default() })
+        }
+    }
+}
+
+/// An NFIP community, as the political areas have it.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Community {
+    /// The 6-digit community number, e.g. `480287`.
+    pub cid: Option<String>,
+    pub name: Option<String>,
+}
+
+/// `query point --determination`: the lookup laid out as section II of FEMA's Standard Flood Hazard Determination
+/// Form (FEMA Form 086-0-32), so a lending workflow can fill the form in from it.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct FormDetermination {
+    pub lat: f64,
+    pub lon: f64,
+    /// `cache`, or the url of the service asked.
+    pub source: String,
+    pub fips: Option<String>,
+    /// Box A: the NFIP community, its number and its state's postal code.
+    pub community_name: Option<String>,
+    pub community_number: Option<String>,
+    pub state: Option<String>,
+    /// Box B: the NFIP map number (the FIRM panel's, e.g. `22071C0230F`), its panel and suffix apart, and the date
+    /// the panel took effect or was last revised.
+    pub map_number: Option<String>,
+    pub panel: Option<String>,
+    pub suffix: Option<String>,
+    pub map_panel_effective_date: Option<NaiveDate>,
+    /// Box B's "no NFIP map": true only when the county's FIRM panels were searched and none is at the point, false
+    /// when one is, and None when that can't be said, e.g. a database without a panel layer. The map service's
+    /// `identify` doesn't say which layers it searched, so an online answer is never true.
+    pub no_nfip_map: Option<bool>,
+    pub flood_zone: Option<String>,
+    /// Box D: whether the building is in the Special Flood Hazard Area.
+    pub in_sfha: Option<bool>,
+}
+
+impl FormDetermination {
+    /// `panels_searched` is whether a panel layer was searched, so that finding no `panel` means there's no map.
+    fn new(determination: ZoneDetermination, panel: Option<FirmPanel>, panels_searched: bool, community: Option<Community>) -> FormDetermination {
+        let community = community.unwrap_or_default();
+        // a community number starts with its state's fips, for when the county isn't known
+        let state_fips = determination.fips.as_deref().or(community.cid.as_deref()).and_then(|fips| fips.get(..2));
+        let no_nfip_map = match (&panel, panels_searched) {
+            (Some(_), _) => Some(false),
+            (None, true) => Some(true),
+            (None, false) => None,
+        };
+        let panel = panel.unwrap_or_default();
+        FormDetermination {
+            lat: determination.lat,
+            lon: determination.lon,
+            source: determination.source,
+            state: state_fips.and_then(crate::fips::state).map(|(_, postal, _)| postal.to_string()),
+            fips: determination.fips,
+            community_name: community.name,
+            community_number: community.cid,
+            no_nfip_map,
+            map_number: panel.firm_pan,
+            panel: panel.panel,
+            suffix: panel.suffix,
+            map_panel_effective_date: panel.effective_date,
+            flood_zone: determination.flood_zone,
+            in_sfha: determination.sfha,
+        }
+    }
+}
+
+/// `determine`, with the panel and community the form needs too.
+pub fn determine_form(cache_dir: Option<&Path>, fips: Option<&str>, lon: f64, lat: f64) -> Result<FormDetermination, Box<dyn std::error::Error>> {
+    match cache_dir {
+        #[cfg(feature = "gdal")]
+        Some(cache_dir) => {
+            let lookup = locate(cache_dir, fips, lon, lat)?;
+            let (panel, panels_searched) = (lookup.panel(lon, lat)?, lookup.has_panels());
+            Ok(FormDetermination::new(lookup.determine(lon, lat)?, panel, panels_searched, lookup.community(lon, lat)?))
+        }
+        #[cfg(not(feature = "gdal"))]
+        Some(_) => {
+            let _ = fips;
+            Err("looking up in the cache needs nfhl_util built with `--features gdal`; use `--online` instead".into())
+        }
+        None => {
+            let results = identify(lon, lat, &[PANELS_LAYER_ID, POLITICAL_LAYER_ID, HAZARDS_LAYER_ID])?;
+            let determination = online_determination(lon, lat, &results);
+            Ok(FormDetermination::new(determination, online_panel(&results), false, online_community(&results)))
+        }
+    }
+}
+
+/// A lookup at a geocoded address.
+#[derive(Serialize, Debug, Clone)]
+pub struct AddressDetermination {
+    pub address: String,
+    pub matched_address: String,
+    #[serde(flatten)]
+    pub determination: ZoneDetermination,
+}
+
+/// Looks the point up in the cache if there's one (finding its county unless `fips` is given), otherwise online.
+pub fn determine(cache_dir: Option<&Path>, fips: Option<&str>, lon: f64, lat: f64) -> Result<ZoneDetermination, Box<dyn std::error::Error>> {
+    match cache_dir {
+        #[cfg(feature = "gdal")]
+        Some(cache_dir) => locate(cache_dir, fips, lon, lat)?.determine(lon, lat),
+        #[cfg(not(feature = "gdal"))]
+        Some(_) => {
+            let _ = fips;
+            Err("looking up in the cache needs nfhl_util built with `--features gdal`; use `--online` instead".into())
+        }
+        None => determine_online(lon, lat),
+    }
+}
+
+/// What `query zone-history` found: the point looked up in the version of its county in effect `as_of`.
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoricalDetermination {
+    pub as_of: NaiveDate,
+    /// When the next cached version took over from the one looked up; None if it's still the current one.
+    pub superseded_date: Option<NaiveDate>,
+    #[serde(flatten)]
+    pub determination: ZoneDetermination,
+}
+
+/// The cached version of the county that was in effect `as_of`, and when the next one superseded it. Versions whose
+/// effective date isn't known can't be placed, and are passed over.
+pub fn version_as_of(cache_dir: &Path, fips: &str, as_of: NaiveDate) -> Result<(Source, Option<NaiveDate>), Box<dyn std::error::Error>> {
+    let versions: Vec<Source> = crate::merge_geo::county_versions(cache_dir, fips)?.into_iter()
+        .filter(|version| version.effective_date.is_some())
+        .collect();
+    let Some(oldest) = versions.first() else {
+        return Err(format!("{} has no cached version of {} with a known effective date", cache_dir.display(), fips).into());
+    };
+    let Some(at) = versions.iter().rposition(|version| version.effective_date <= Some(as_of)) else {
+        return Err(format!(
+            "the oldest cached version of {} is effective {}, after {}; older versions are only kept by `--keep-history`",
+            fips, oldest.effective_date.expect("filtered above"), as_of).into());
+    };
+    Ok((versions[at].clone(), versions.get(at + 1).and_then(|next| next.effective_date)))
+}
+
+/// Looks the point up in the version of its county in effect `as_of`. Without `fips`, the county is the cached one
+/// containing the point now.
+#[cfg(feature = "gdal")]
+pub fn determine_as_of(cache_dir: &Path, fips: Option<&str>, lon: f64, lat: f64, as_of: NaiveDate) -> Result<HistoricalDetermination, Box<dyn std::error::Error>> {
+    let fips = match fips {
+        Some(fips) => fips.to_string(),
+        None => locate(cache_dir, None, lon, lat)?.source.fips,
+    };
+    let (version, superseded_date) = version_as_of(cache_dir, &fips, as_of)?;
+    let determination = CountyLookup::open(&version)?.determine(lon, lat)?;
+    Ok(HistoricalDetermination { as_of, superseded_date, determination })
+}
+
+/// Asks the NFHL map service's `identify` what's on `layers` at the point.
+fn identify(lon: f64, lat: f64, layers: &[i64]) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()?;
+    let url = format!("{}/identify", NFHL_MAPSERVER);
+    let layers: Vec<String> = layers.iter().map(|id| id.to_string()).collect();
+    // the map extent and image only matter to a tolerance, and this has none
+    let extent = format!("{},{},{},{}", lon - 0.001, lat - 0.001, lon + 0.001, lat + 0.001);
+    let response: Value = client.get(&url)
+        .query(&[
+            ("geometry", format!("{},{}", lon, lat).as_str()),
+            ("geometryType", "esriGeometryPoint"),
+            ("sr", "4326"),
+            ("layers", &format!("all:{}", layers.join(","))),
+            ("tolerance", "0"),
+            ("mapExtent", &extent),
+            ("imageDisplay", "100,100,96"),
+            ("returnGeometry", "false"),
+            ("returnFieldName", "true"),
+            ("returnUnformattedValues", "true"),
+            ("f", "json"),
+        ])
+        .send()?
+        .error_for_status()?
+        .json()?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("{} failed: {}", url, error).into());
+    }
+    Ok(response.get("results").and_then(Value::as_array).cloned().unwrap_or_default())
+}
+
+/// The county of a countywide study's `DFIRM_ID` or panel number, which start with its fips and a `C`. Community
+/// studies' start with the community number instead.
+fn countywide_fips(id: &str) -> Option<String> {
+    match id.get(..6) {
+        Some(prefix) if prefix.ends_with('C') && prefix[..5].bytes().all(|b| b.is_ascii_digit()) => Some(prefix[..5].to_string()),
+        _ => None,
+    }
+}
+
+fn attribute_text(attributes: &Value, field: &str) -> Option<String> {
+    match &attributes[field] {
+        Value::String(v) if !v.trim().is_empty() && !v.eq_ignore_ascii_case("null") => Some(v.trim().to_string()),
+        Value::Number(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+/// The latest of the panels in `identify` results.
+fn online_panel(results: &[Value]) -> Option<FirmPanel> {
+    let mut found: Option<FirmPanel> = None;
+    for result in results.iter().filter(|result| result.get("layerId").and_then(Value::as_i64) == Some(PANELS_LAYER_ID)) {
+        let attributes = &result["attributes"];
+        let panel = FirmPanel {
+            firm_pan: attribute_text(attributes, "FIRM_PAN"),
+            pcomm: attribute_text(attributes, "PCOMM"),
+            panel: attribute_text(attributes, "PANEL"),
+            suffix: attribute_text(attributes, "SUFFIX"),
+            // unformatted dates are epoch milliseconds
+            effective_date: attributes["EFF_DATE"].as_i64()
+                .and_then(chrono::DateTime::from_timestamp_millis)
+                .map(|date| date.date_naive()),
+            panel_type: attribute_text(attributes, "PANEL_TYP"),
+        };
+        if found.as_ref().is_none_or(|found| panel.effective_date > found.effective_date) {
+            found = Some(panel);
+        }
+    }
+    found
+}
+
+/// The community in `identify` results.
+fn online_community(results: &[Value]) -> Option<Community> {
+    let attributes = &results.iter()
+        .find(|result| result.get("layerId").and_then(Value::as_i64) == Some(POLITICAL_LAYER_ID))?["attributes"];
+    Some(Community { cid: attribute_text(attributes, "CID"), name: attribute_text(attributes, "COMM_NAME") })
+}
+
+/// Asks the NFHL map service what's at the point, with the answer put in the same shape as the cache's. The service
+/// has no notion of the file a county was published in, so `file` and `effective_date` are left empty, and `fips` is
+/// only known for countywide studies (whose `DFIRM_ID` is the county's fips and a `C`).
+pub fn determine_online(lon: f64, lat: f64) -> Result<ZoneDetermination, Box<dyn std::error::Error>> {
+    Ok(online_determination(lon, lat, &identify(lon, lat, &[PANELS_LAYER_ID, HAZARDS_LAYER_ID])?))
+}
+
+/// `determine_online`'s answer from `identify` results.
+fn online_determination(lon: f64, lat: f64, results: &[Value]) -> ZoneDetermination {
+    let mut determination = ZoneDetermination { lat, lon, source: NFHL_MAPSERVER.to_string(), ..Default::default() };
+    if let Some(hazard) = results.iter().find(|result| result.get("layerId").and_then(Value::as_i64) == Some(HAZARDS_LAYER_ID)) {
+        let text = |field: &str| attribute_text(&hazard["attributes"], field);
+        determination.flood_zone = text("FLD_ZONE");
+        determination.zone_subtype = text("ZONE_SUBTY");
+        determination.sfha = text("SFHA_TF").map(|tf| tf.eq_ignore_ascii_case("T"));
+        determination.static_bfe = text("STATIC_BFE").and_then(|bfe| bfe.replace(',', "").parse().ok()).filter(|bfe| *bfe != NO_VALUE);
+        determination.v_datum = text("V_DATUM").filter(|_| determination.static_bfe.is_some());
+        determination.fips = text("DFIRM_ID").as_deref().and_then(countywide_fips);
+    }
+    if let Some(panel) = online_panel(results) {
+        determination.panel = panel.firm_pan;
+        determination.panel_effective_date = panel.effective_date;
+    }
+    determination
+}
@@ -0,0 +1,203 @@
+//! `query batch`: `query point` for every row of a CSV. Rows are grouped by the counties their point could be in, so
+//! each county's database is opened once per worker, and the groups are shared out between worker threads. Results
+//! are appended to the output a group at a time, so an interrupted run picks up where it stopped.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The columns added to each input row.
+pub const RESULT_COLUMNS: &[&str] = &[
+    "input_row", "fips", "flood_zone", "zone_subtype", "sfha", "static_bfe", "v_datum", "panel", "firm_effective_date", "error",
+];
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// The latitude and longitude columns. Found by name (`lat`/`latitude`, `lon`/`lng`/`longitude`) when not given.
+    pub lat_column: Option<String>,
+    pub lon_column: Option<String>,
+    pub jobs: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub looked_up: usize,
+    /// Rows already in the output from an earlier run.
+    pub already_done: usize,
+    /// Points outside every cached county.
+    pub outside: usize,
+    pub failed: usize,
+}
+
+fn column(headers: &csv::StringRecord, given: Option<&str>, names: &[&str]) -> Result<usize, Box<dyn std::error::Error>> {
+    let wanted: Vec<&str> = match given {
+        Some(given) => vec![given],
+        None => names.to_vec(),
+    };
+    headers.iter()
+        .position(|header| wanted.iter().any(|name| header.trim().eq_ignore_ascii_case(name)))
+        .ok_or_else(|| format!("the input has no {} column", wanted.join(" or ")).into())
+}
+
+/// The `input_row` of every row already in `out`.
+fn done_rows(out: &Path) -> Result<HashSet<usize>, Box<dyn std::error::Error>> {
+    let mut done = HashSet::new();
+    if !out.exists() {
+        return Ok(done);
+    }
+    let mut reader = csv::Reader::from_path(out)?;
+    let input_row = column(reader.headers()?, Some(RESULT_COLUMNS[0]), &[])?;
+    for record in reader.records() {
+        if let Some(row) = record?.get(input_row).and_then(|row| row.parse().ok()) {
+            done.insert(row);
+        }
+    }
+    Ok(done)
+}
+
+/// Looks up every row of `input` against the cache and appends them, with `RESULT_COLUMNS`, to `out`. Rows come out
+/// grouped by county rather than in input order; `input_row` (from 1) is their position in the input.
+#[cfg(feature = "gdal")]
+pub fn query_batch(cache_dir: &Path, input: &Path, out: &PathBuf, opts: &BatchOptions) -> Result<BatchSummary, Box<dyn std::error::Error>> {
+    use std::collections::HashMap;
+    use std::sync::{mpsc, Mutex};
+    use crate::query::{self, CountyLookup, ZoneDetermination};
+
+    /// Fewer than this many open databases per worker, so a national batch doesn't run out of file handles.
+    const OPEN_COUNTIES: usize = 16;
+
+    /// An input row to look up: its number, the record, and its latitude and longitude.
+    type Point = (usize, csv::StringRecord, f64, f64);
+
+    let mut reader = csv::Reader::from_path(input)?;
+    let headers = reader.headers()?.clone();
+    let lat_column = column(&headers, opts.lat_column.as_deref(), &["lat", "latitude"])?;
+    let lon_column = column(&headers, opts.lon_column.as_deref(), &["lon", "lng", "long", "longitude"])?;
+
+    let done = done_rows(out)?;
+    let mut summary = BatchSummary { already_done: done.len(), ..Default::default() };
+    let resuming = out.exists();
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut writer = csv::Writer::from_writer(std::fs::OpenOptions::new().create(true).append(true).open(out)?);
+    if !resuming {
+        let mut out_headers = headers.clone();
+        out_headers.extend(RESULT_COLUMNS);
+        writer.write_record(&out_headers)?;
+        writer.flush()?;
+    }
+
+    let indexed = query::indexed_sources(cache_dir)?;
+    // the rows of each set of candidate counties, by the candidates' indexes into `indexed`
+    let mut groups: BTreeMap<Vec<usize>, Vec<Point>> = BTreeMap::new();
+    let result_row = |record: &csv::StringRecord, row: usize, determination: Option<&ZoneDetermination>, error: &str| {
+        let mut record = record.clone();
+        let d = determination.cloned().unwrap_or_default();
+        record.extend([
+            row.to_string(),
+            d.fips.unwrap_or_default(),
+            d.flood_zone.unwrap_or_default(),
+            d.zone_subtype.unwrap_or_default(),
+            d.sfha.map(|sfha| if sfha { "T" } else { "F" }.to_string()).unwrap_or_default(),
+            d.static_bfe.map(|bfe| bfe.to_string()).unwrap_or_default(),
+            d.v_datum.unwrap_or_default(),
+            d.panel.unwrap_or_default(),
+            d.panel_effective_date.or(d.effective_date).map(|date| date.to_string()).unwrap_or_default(),
+            error.to_string(),
+        ]);
+        record
+    };
+    for (i, record) in reader.records().enumerate() {
+        let row = i + 1;
+        let record = record?;
+        if done.contains(&row) {
+            continue;
+        }
+        let coordinate = |column: usize| record.get(column).and_then(|v| v.trim().parse::<f64>().ok());
+        let (lat, lon) = match (coordinate(lat_column), coordinate(lon_column)) {
+            (Some(lat), Some(lon)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => (lat, lon),
+            _ => {
+                writer.write_record(&result_row(&record, row, None, "not a latitude and longitude"))?;
+                summary.failed += 1;
+                continue;
+            }
+        };
+        let candidates: Vec<usize> = indexed.iter().enumerate()
+            .filter(|(_, (_, bbox))| query::in_bbox(*bbox, lon, lat))
+            .map(|(i, _)| i)
+            .collect();
+        groups.entry(candidates).or_default().push((row, record, lat, lon));
+    }
+    writer.flush()?;
+
+    // neighbouring groups share counties, so each worker takes them in order
+    let queue = Mutex::new(groups.into_iter().collect::<std::collections::VecDeque<_>>());
+    type Results = Vec<(usize, csv::StringRecord, Result<Option<ZoneDetermination>, String>)>;
+    let (sender, receiver) = mpsc::channel::<Results>();
+    std::thread::scope(|scope| -> Result<(), Box<dyn std::error::Error>> {
+        for _ in 0..opts.jobs.max(1) {
+            let sender = sender.clone();
+            let (queue, indexed) = (&queue, &indexed);
+            scope.spawn(move || {
+                let mut open: HashMap<usize, CountyLookup> = HashMap::new();
+                loop {
+                    let (candidates, rows) = match queue.lock().unwrap().pop_front() {
+                        Some(group) => group,
+                        None => break,
+                    };
+                    if open.len() + candidates.len() > OPEN_COUNTIES {
+                        open.clear();
+                    }
+                    let mut results = Vec::with_capacity(rows.len());
+                    for (row, record, lat, lon) in rows {
+                        let mut result = Ok(None);
+                        for &candidate in &candidates {
+                            let lookup = match open.entry(candidate) {
+                                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                                std::collections::hash_map::Entry::Vacant(entry) => match CountyLookup::open(&indexed[candidate].0) {
+                                    Ok(lookup) => entry.insert(lookup),
+                                    Err(e) => {
+                                        result = Err(e.to_string());
+                                        continue;
+                                    }
+                                },
+                            };
+                            match lookup.contains(lon, lat).and_then(|contains| contains.then(|| lookup.determine(lon, lat)).transpose()) {
+                                Ok(Some(determination)) => {
+                                    result = Ok(Some(determination));
+                                    break;
+                                }
+                                Ok(None) => {}
+                                Err(e) => result = Err(e.to_string()),
+                            }
+                        }
+                        results.push((row, record, result));
+                    }
+                    if sender.send(results).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for results in receiver {
+            for (row, record, result) in results {
+                let record = match &result {
+                    Ok(Some(determination)) => result_row(&record, row, Some(determination), ""),
+                    Ok(None) => result_row(&record, row, None, "outside the cached counties"),
+                    Err(e) => result_row(&record, row, None, e),
+                };
+                match result {
+                    Ok(Some(_)) => summary.looked_up += 1,
+                    Ok(None) => summary.outside += 1,
+                    Err(_) => summary.failed += 1,
+                }
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    })?;
+    Ok(summary)
+}
@@ -0,0 +1,116 @@
+//! Recompressing cached archives to take less space. FEMA's zips are deflated; with `--recompress zstd` every member
+//! of a downloaded zip is rewritten with zstd, which typically saves a fifth to a third of a mirror. The zip crate
+//! reads zstd members like any other, so `extract`, `verify` and the rest don't notice. GDAL and most unzip tools
+//! can't, so the geo commands and `serve` get a deflated copy (see `encryption::deflated`).
+//!
+//! The manifest keeps the size and SHA-256 of the file FEMA served, so republish checks still compare like with like,
+//! and records the stored file's next to them, for `verify`.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::{NfhlError, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Recompression {
+    Zstd,
+}
+
+impl Recompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Recompression::Zstd => "zstd",
+        }
+    }
+
+    fn compression_method(&self) -> CompressionMethod {
+        match self {
+            Recompression::Zstd => CompressionMethod::Zstd,
+        }
+    }
+
+    /// Slow to write, which happens once, and as fast as any other level to read.
+    fn level(&self) -> i32 {
+        match self {
+            Recompression::Zstd => 19,
+        }
+    }
+}
+
+/// How a cached file was recompressed, and its size and SHA-256 as stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recompressed {
+    pub method: Recompression,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Rewrites the zip at `path` with its members compressed by `method`. The new zip is written beside it and renamed
+/// over it, but only if it's smaller; None if it wasn't, and the file is left as it was.
+pub fn recompress_file(path: &Path, method: Recompression) -> Result<Option<Recompressed>> {
+    let part = PathBuf::from(format!("{}.recompress.part", path.display()));
+    if let Err(e) = rewrite(path, &part, method.compression_method(), Some(method.level())) {
+        let _ = std::fs::remove_file(&part);
+        return Err(NfhlError::Validation(format!("can't recompress {}: {}", path.display(), e)));
+    }
+    let size = std::fs::metadata(&part).map_err(NfhlError::io(&part))?.len();
+    if size >= std::fs::metadata(path).map_err(NfhlError::io(path))?.len() {
+        std::fs::remove_file(&part).map_err(NfhlError::io(&part))?;
+        return Ok(None);
+    }
+    let sha256 = crate::info::sha256_file(&part)
+        .map_err(|e| NfhlError::Validation(format!("{}: {}", part.display(), e)))?;
+    std::fs::rename(&part, path).map_err(NfhlError::io(path))?;
+    Ok(Some(Recompressed { method, size, sha256 }))
+}
+
+/// Writes a copy of the zip at `path` to `dest` with its members deflated, for readers that don't know zstd.
+pub fn deflate_file(path: &Path, dest: &Path) -> Result<()> {
+    rewrite(path, dest, CompressionMethod::Deflated, None)
+        .map_err(|e| NfhlError::Validation(format!("can't deflate {}: {}", path.display(), e)))
+}
+
+/// Whether any member of the zip at `path` is compressed with something other than deflate; false for files that
+/// aren't zips.
+pub fn is_recompressed(path: &Path) -> Result<bool> {
+    let file = File::open(path).map_err(NfhlError::io(path))?;
+    let Ok(mut zip) = ZipArchive::new(BufReader::new(file)) else {
+        return Ok(false);
+    };
+    for i in 0..zip.len() {
+        if let Ok(CompressionMethod::Zstd) = zip.by_index_raw(i).map(|entry| entry.compression()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn rewrite(path: &Path, dest: &Path, method: CompressionMethod, level: Option<i32>) -> zip::result::ZipResult<()> {
+    let mut zip = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut out = ZipWriter::new(BufWriter::new(File::create(dest)?));
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let mut options = FileOptions::default()
+            .compression_method(method)
+            .compression_level(level)
+            .last_modified_time(entry.last_modified())
+            .large_file(entry.size() >= u32::MAX as u64);
+        if let Some(mode) = entry.unix_mode() {
+            options = options.unix_permissions(mode);
+        }
+        if entry.is_dir() {
+            out.add_directory(entry.name(), options)?;
+            continue;
+        }
+        out.start_file(entry.name(), options)?;
+        std::io::copy(&mut entry, &mut out)?;
+    }
+    out.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+
+use crate::inventory::{Inventory, ProductKind};
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Table,
+    Csv,
+    Json,
+    Markdown,
+}
+
+/// Counts of counties per age bucket, keyed by 2-digit state fips.
+pub struct AgeReport {
+    pub bucket_labels: Vec<String>,
+    pub rows: BTreeMap<String, Vec<usize>>,
+}
+
+/// Buckets every county in the inventory by how many whole years old its effective file is, as of `today`.
+/// `boundaries` are the bucket edges in years, each starting the bucket it names, e.g. [5, 15] gives <5y, 5-<15y
+/// and >=15y, so a county exactly 15 years old is in >=15y. Counties with no
+/// (or an unparseable) effective date land in a trailing "unknown" bucket.
+pub fn age_report(inv: &Inventory, boundaries: &[u32], today: NaiveDate) -> AgeReport {
+    let mut boundaries = boundaries.to_vec();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut bucket_labels = Vec::with_capacity(boundaries.len() + 2);
+    for (i, &b) in boundaries.iter().enumerate() {
+        if i == 0 {
+            bucket_labels.push(format!("<{}y", b));
+        } else {
+            bucket_labels.push(format!("{}-<{}y", boundaries[i - 1], b));
+        }
+    }
+    match boundaries.last() {
+        Some(&b) => bucket_labels.push(format!(">={}y", b)),
+        None => bucket_labels.push("all".to_string()),
+    }
+    bucket_labels.push("unknown".to_string());
+
+    let mut rows = BTreeMap::<String, Vec<usize>>::new();
+    for (fips, entry) in inv.iter() {
+        let state_fips = fips.get(..2).unwrap_or(fips).to_string();
+        let counts = rows.entry(state_fips).or_insert_with(|| vec![0; bucket_labels.len()]);
+        let bucket = match entry.effective_date() {
+            Some(date) => {
+                let age = years_between(date, today);
+                boundaries.iter().position(|&b| age < b as i32).unwrap_or(boundaries.len())
+            }
+            None => bucket_labels.len() - 1,
+        };
+        counts[bucket] += 1;
+    }
+
+    AgeReport { bucket_labels, rows }
+}
+
+/// Whole years elapsed from `from` to `to` (negative if `from` is in the future).
+fn years_between(from: NaiveDate, to: NaiveDate) -> i32 {
+    let mut years = to.year() - from.year();
+    if (to.month(), to.day()) < (from.month(), from.day()) {
+        years -= 1;
+    }
+    years
+}
+
+pub fn write_age_report(out: &mut dyn Write, report: &AgeReport, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let mut headers = vec!["state_fips".to_string()];
+    headers.extend(report.bucket_labels.iter().cloned());
+    headers.push("total".to_string());
+
+    let mut rows = Vec::with_capacity(report.rows.len() + 1);
+    let mut totals = vec![0; report.bucket_labels.len()];
+    for (state, counts) in report.rows.iter() {
+        let mut row = vec![state.clone()];
+        row.extend(counts.iter().map(|c| c.to_string()));
+        row.push(counts.iter().sum::<usize>().to_string());
+        rows.push(row);
+        for (t, c) in totals.iter_mut().zip(counts) {
+            *t += c;
+        }
+    }
+    match format {
+        ReportFormat::Table => {
+            let mut total_row = vec!["total".to_string()];
+            total_row.extend(totals.iter().map(|c| c.to_string()));
+            total_row.push(totals.iter().sum::<usize>().to_string());
+            rows.push(total_row);
+            write_table(out, &headers, &rows)
+        }
+        ReportFormat::Csv => write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => write_markdown_table(out, &headers, &rows),
+        ReportFormat::Json => {
+            let states: serde_json::Map<String, serde_json::Value> = report.rows.iter()
+                .map(|(state, counts)| {
+                    let buckets = report.bucket_labels.iter().cloned().zip(counts.iter().map(|&c| c.into())).collect();
+                    (state.clone(), serde_json::Value::Object(buckets))
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut *out, &serde_json::json!({
+                "buckets": report.bucket_labels,
+                "states": states,
+            }))?;
+            writeln!(out)?;
+            Ok(())
+        }
+    }
+}
+
+#[derive(Serialize, Default, Debug, Clone, Copy)]
+pub struct SummaryCounts {
+    pub effective_only: usize,
+    pub preliminary_only: usize,
+    pub both: usize,
+    pub neither: usize,
+    pub total: usize,
+}
+
+/// Counts counties by which products they have, keyed by 2-digit state fips.
+pub fn summary_report(inv: &Inventory) -> BTreeMap<String, SummaryCounts> {
+    let mut rows = BTreeMap::<String, SummaryCounts>::new();
+    for (fips, entry) in inv.iter() {
+        let state_fips = fips.get(..2).unwrap_or(fips).to_string();
+        let counts = rows.entry(state_fips).or_default();
+        match (entry.has(ProductKind::Effective), entry.has(ProductKind::Preliminary)) {
+            (true, false) => counts.effective_only += 1,
+            (false, true) => counts.preliminary_only += 1,
+            (true, true) => counts.both += 1,
+            (false, false) => counts.neither += 1,
+        }
+        counts.total += 1;
+    }
+    rows
+}
+
+pub fn write_summary_report(out: &mut dyn Write, report: &BTreeMap<String, SummaryCounts>, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, report)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["state_fips", "effective_only", "preliminary_only", "both", "neither", "total"]
+        .iter().map(|h| h.to_string()).collect();
+    let to_row = |label: &str, c: &SummaryCounts| vec![
+        label.to_string(),
+        c.effective_only.to_string(),
+        c.preliminary_only.to_string(),
+        c.both.to_string(),
+        c.neither.to_string(),
+        c.total.to_string(),
+    ];
+    let mut rows: Vec<Vec<String>> = report.iter().map(|(state, c)| to_row(state, c)).collect();
+    match format {
+        ReportFormat::Table => {
+            let mut totals = SummaryCounts::default();
+            for c in report.values() {
+                totals.effective_only += c.effective_only;
+                totals.preliminary_only += c.preliminary_only;
+                totals.both += c.both;
+                totals.neither += c.neither;
+                totals.total += c.total;
+            }
+            rows.push(to_row("total", &totals));
+            write_table(out, &headers, &rows)
+        }
+        ReportFormat::Markdown => write_markdown_table(out, &headers, &rows),
+        _ => write_csv(out, &headers, &rows),
+    }
+}
+
+/// Writes rows as a plain, space-aligned text table. Columns holding only numbers are right-aligned.
+pub fn write_table(out: &mut dyn Write, headers: &[String], rows: &[Vec<String>]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    let mut numeric = vec![!rows.is_empty(); headers.len()];
+    for row in rows {
+        for (i, cell) in row.iter().enumerate().take(headers.len()) {
+            widths[i] = widths[i].max(cell.chars().count());
+            numeric[i] &= cell.parse::<f64>().is_ok();
+        }
+    }
+    let format_row = |row: &[String]| -> String {
+        row.iter()
+            .zip(widths.iter().zip(&numeric))
+            .map(|(cell, (&w, &numeric))| if numeric { format!("{:>w$}", cell, w = w) } else { format!("{:<w$}", cell, w = w) })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    writeln!(out, "{}", format_row(headers))?;
+    writeln!(out, "{}", widths.iter().map(|&w| "-".repeat(w)).collect::<Vec<_>>().join("  "))?;
+    for row in rows {
+        writeln!(out, "{}", format_row(row))?;
+    }
+    Ok(())
+}
+
+/// Writes rows as a GitHub-flavored Markdown table.
+pub fn write_markdown_table(out: &mut dyn Write, headers: &[String], rows: &[Vec<String>]) -> Result<(), Box<dyn std::error::Error>> {
+    let format_row = |row: &[String]| format!("| {} |", row.iter().map(|cell| cell.replace('|', "\\|")).collect::<Vec<_>>().join(" | "));
+    writeln!(out, "{}", format_row(headers))?;
+    writeln!(out, "|{}", headers.iter().map(|_| " --- |").collect::<String>())?;
+    for row in rows {
+        writeln!(out, "{}", format_row(row))?;
+    }
+    Ok(())
+}
+
+pub fn write_csv(out: &mut dyn Write, headers: &[String], rows: &[Vec<String>]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(headers)?;
+    for row in rows {
+        wtr.write_record(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::{Fips, InventoryEntry};
+
+    fn inventory(counties: &[(&str, Option<NaiveDate>)]) -> Inventory {
+        counties.iter()
+            .map(|&(fips, date)| {
+                let entry = InventoryEntry { effective_file_date: date, ..Default::default() };
+                (Fips::new(fips).unwrap(), entry)
+            })
+            .collect()
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn buckets_start_at_their_boundary() {
+        let today = date(2024, 6, 1);
+        let inv = inventory(&[
+            ("48001", Some(date(2024, 1, 1))),
+            // exactly 5 years old
+            ("48003", Some(date(2019, 6, 1))),
+            // a day short of 5
+            ("48005", Some(date(2019, 6, 2))),
+            // exactly 15
+            ("48007", Some(date(2009, 6, 1))),
+            // a day short of 15
+            ("48009", Some(date(2009, 6, 2))),
+            ("01001", Some(date(1990, 1, 1))),
+        ]);
+        let report = age_report(&inv, &[15, 5, 5], today);
+        assert_eq!(report.bucket_labels, ["<5y", "5-<15y", ">=15y", "unknown"]);
+        assert_eq!(report.rows["48"], [2, 2, 1, 0]);
+        assert_eq!(report.rows["01"], [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn missing_dates_are_unknown() {
+        let inv = inventory(&[("48001", None), ("48003", Some(date(2030, 1, 1)))]);
+        let report = age_report(&inv, &[5], date(2024, 6, 1));
+        assert_eq!(report.bucket_labels, ["<5y", ">=5y", "unknown"]);
+        // a date in the future is under any boundary
+        assert_eq!(report.rows["48"], [1, 0, 1]);
+
+        let report = age_report(&inv, &[], date(2024, 6, 1));
+        assert_eq!(report.bucket_labels, ["all", "unknown"]);
+        assert_eq!(report.rows["48"], [1, 1]);
+    }
+}
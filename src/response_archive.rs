@@ -0,0 +1,113 @@
+//! `--archive-responses`: a gzipped copy of every portal page and MSC response a run read, so what FEMA's servers
+//! said at the time can be shown later. Each response is `{time}-{n}-{host}.{html|json|txt}.gz` in the directory, and
+//! `index.jsonl` says what was asked for and what came back, with the SHA-256 of the uncompressed body. Downloads
+//! aren't archived; the cache and its manifest already keep those.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{NfhlError, Result};
+
+pub const INDEX_FILE_NAME: &str = "index.jsonl";
+
+/// One line of `index.jsonl`.
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchivedResponse {
+    /// The body's file, relative to the archive directory.
+    pub file: String,
+    pub received_at: DateTime<Utc>,
+    pub method: String,
+    pub url: String,
+    /// The form posted, for MSC's searches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// The directory responses are archived to, shared by a client's clones.
+#[derive(Debug)]
+pub struct ResponseArchive {
+    dir: PathBuf,
+    next: AtomicUsize,
+    index: Mutex<File>,
+}
+
+impl ResponseArchive {
+    /// Archives into `dir`, adding to an index already there.
+    pub fn open(dir: &Path) -> Result<ResponseArchive> {
+        std::fs::create_dir_all(dir).map_err(NfhlError::io(dir))?;
+        let index_path = dir.join(INDEX_FILE_NAME);
+        let index = OpenOptions::new().create(true).append(true).open(&index_path).map_err(NfhlError::io(&index_path))?;
+        Ok(ResponseArchive { dir: dir.to_path_buf(), next: AtomicUsize::new(1), index: Mutex::new(index) })
+    }
+
+    /// Starts archiving a response to `request`, whose body is then written a chunk at a time.
+    pub fn start(&self, request: &reqwest::Request, status: u16, headers: &HeaderMap) -> Result<ArchivingBody<'_>> {
+        let received_at = Utc::now();
+        let content_type = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(String::from);
+        let extension = match content_type.as_deref() {
+            Some(t) if t.contains("json") => "json",
+            Some(t) if t.contains("html") => "html",
+            _ => "txt",
+        };
+        let file = format!("{}-{:05}-{}.{}.gz", received_at.format("%Y%m%dT%H%M%S%.3fZ"),
+            self.next.fetch_add(1, Ordering::Relaxed), request.url().host_str().unwrap_or("unknown"), extension);
+        let path = self.dir.join(&file);
+        let body = GzEncoder::new(File::create(&path).map_err(NfhlError::io(&path))?, Compression::default());
+        let record = ArchivedResponse {
+            file,
+            received_at,
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            request_body: request.body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+            status,
+            content_type,
+            bytes: 0,
+            sha256: String::new(),
+        };
+        Ok(ArchivingBody { archive: self, path, body, hasher: Sha256::new(), record })
+    }
+}
+
+/// A response's body on its way into the archive. It's only indexed once `finish`ed, so a read that fails part way
+/// leaves a partial file but no claim about it.
+pub struct ArchivingBody<'a> {
+    archive: &'a ResponseArchive,
+    path: PathBuf,
+    body: GzEncoder<File>,
+    hasher: Sha256,
+    record: ArchivedResponse,
+}
+
+impl ArchivingBody<'_> {
+    pub fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.body.write_all(chunk).map_err(NfhlError::io(&self.path))?;
+        self.hasher.update(chunk);
+        self.record.bytes += chunk.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.body.finish().and_then(|f| f.sync_all()).map_err(NfhlError::io(&self.path))?;
+        self.record.sha256 = format!("{:x}", self.hasher.finalize());
+        let line = serde_json::to_string(&self.record).expect("a record always serializes");
+        let mut index = self.archive.index.lock().unwrap();
+        writeln!(index, "{}", line).map_err(NfhlError::io(&self.archive.dir.join(INDEX_FILE_NAME)))
+    }
+}
@@ -0,0 +1,198 @@
+//! robots.txt, for `--respect-robots`. With it, the client fetches each host's robots.txt before its first request
+//! there, refuses requests for paths it disallows, and spaces requests to the host by its `Crawl-delay`, on top of
+//! the politeness delay. Rules are read as RFC 9309 has them: the group for `nfhl_util` if there is one, otherwise
+//! the one for `*`; the longest matching `Allow` or `Disallow` wins, `Allow` on a tie; `*` and `$` in paths.
+
+use std::time::Duration;
+
+/// Set (to anything but `false` or `0`) to respect robots.txt; what `--respect-robots` and the config file's
+/// `respect_robots` set.
+pub const RESPECT_ROBOTS_ENV: &str = "NFHL_UTIL_RESPECT_ROBOTS";
+/// The product token robots.txt can address this tool by.
+pub const USER_AGENT_TOKEN: &str = "nfhl_util";
+/// The longest `Crawl-delay` waited out between requests; longer ones are taken as this.
+pub const MAX_CRAWL_DELAY: Duration = Duration::from_secs(3600);
+
+pub fn from_env() -> bool {
+    std::env::var(RESPECT_ROBOTS_ENV).is_ok_and(|value| !matches!(value.trim(), "" | "0" | "false"))
+}
+
+/// The rules of one robots.txt group.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Robots {
+    pub allow: Vec<String>,
+    pub disallow: Vec<String>,
+    pub crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    /// The rules in `text` for `agent`. A robots.txt without a group for it, or for `*`, allows everything.
+    pub fn parse(text: &str, agent: &str) -> Robots {
+        // (user agents, rules) of each group
+        let mut groups: Vec<(Vec<String>, Robots)> = Vec::new();
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            if key == "user-agent" {
+                if !in_agents {
+                    groups.push((Vec::new(), Robots::default()));
+                }
+                in_agents = true;
+                groups.last_mut().expect("pushed above").0.push(value.to_ascii_lowercase());
+                continue;
+            }
+            in_agents = false;
+            // rules before any user-agent line belong to no group
+            let Some((_, rules)) = groups.last_mut() else {
+                continue;
+            };
+            match key.as_str() {
+                "allow" if !value.is_empty() => rules.allow.push(value.to_string()),
+                // an empty Disallow disallows nothing
+                "disallow" if !value.is_empty() => rules.disallow.push(value.to_string()),
+                // a delay too long for a `Duration` is still a long delay
+                "crawl-delay" => rules.crawl_delay = value.parse::<f64>().ok()
+                    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                    .map(|secs| Duration::try_from_secs_f64(secs).ok().map_or(MAX_CRAWL_DELAY, |delay| {
+                        delay.min(MAX_CRAWL_DELAY)
+                    })),
+                _ => {}
+            }
+        }
+
+        let agent = agent.to_ascii_lowercase();
+        let mut named = Robots::default();
+        let mut any = Robots::default();
+        let (mut found_named, mut found_any) = (false, false);
+        // several groups for the same agent are combined
+        for (agents, rules) in groups {
+            let target = if agents.iter().any(|a| *a == agent) {
+                found_named = true;
+                &mut named
+            } else if agents.iter().any(|a| a == "*") {
+                found_any = true;
+                &mut any
+            } else {
+                continue;
+            };
+            target.allow.extend(rules.allow);
+            target.disallow.extend(rules.disallow);
+            target.crawl_delay = target.crawl_delay.or(rules.crawl_delay);
+        }
+        match (found_named, found_any) {
+            (true, _) => named,
+            (false, true) => any,
+            (false, false) => Robots::default(),
+        }
+    }
+
+    /// Whether `path` (with its query) may be fetched.
+    pub fn allows(&self, path: &str) -> bool {
+        let longest = |patterns: &[String]| patterns.iter()
+            .filter(|pattern| matches(pattern, path))
+            .map(|pattern| pattern.len())
+            .max();
+        match (longest(&self.allow), longest(&self.disallow)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+}
+
+/// Whether a robots.txt path pattern matches the start of `path`: `*` is any run of characters, and a trailing `$`
+/// anchors it to the end.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        if last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        let robots = Robots::parse("User-agent: *\nDisallow: /femaportal/*/Download*.zip\n", USER_AGENT_TOKEN);
+        assert!(!robots.allows("/femaportal/NFHL/Download/ProductsDownLoadServlet?fileName=48201C_20220915.zip"));
+        assert!(!robots.allows("/femaportal/NFHL/Download.zip.bak"));
+        assert!(robots.allows("/femaportal/NFHL/searchResult"));
+        assert!(robots.allows("/portal/femaportal/NFHL/Download.zip"));
+    }
+
+    #[test]
+    fn dollar_anchors_to_the_end() {
+        let robots = Robots::parse("User-agent: *\nDisallow: /*.zip$\nDisallow: /private$\n", USER_AGENT_TOKEN);
+        assert!(!robots.allows("/files/48201C.zip"));
+        assert!(robots.allows("/files/48201C.zip?download=1"));
+        assert!(!robots.allows("/private"));
+        assert!(robots.allows("/private/page"));
+    }
+
+    #[test]
+    fn longest_match_wins_and_allow_wins_a_tie() {
+        let robots = Robots::parse("User-agent: *\nDisallow: /portal\nAllow: /portal/search\nAllow: /data\nDisallow: /data\n", USER_AGENT_TOKEN);
+        assert!(!robots.allows("/portal/download"));
+        assert!(robots.allows("/portal/search?q=48201"));
+        assert!(robots.allows("/data/48201"));
+        // an empty Disallow disallows nothing
+        assert!(Robots::parse("User-agent: *\nDisallow:\n", USER_AGENT_TOKEN).allows("/anything"));
+    }
+
+    #[test]
+    fn a_named_group_beats_the_star_group() {
+        let text = "User-agent: *\nDisallow: /\n\nUser-agent: other-bot\nAllow: /\n\nUser-agent: NFHL_UTIL\nDisallow: /msc\n";
+        let robots = Robots::parse(text, USER_AGENT_TOKEN);
+        assert!(robots.allows("/portal"));
+        assert!(!robots.allows("/msc/search"));
+        // without one, the `*` group applies
+        assert!(!Robots::parse(text, "someone-else").allows("/portal"));
+        // and without either, everything is allowed
+        assert_eq!(Robots::parse("User-agent: other-bot\nDisallow: /\n", USER_AGENT_TOKEN), Robots::default());
+    }
+
+    #[test]
+    fn groups_for_the_same_agent_are_merged() {
+        let text = "User-agent: nfhl_util\nDisallow: /a\n\nUser-agent: other-bot\nUser-agent: nfhl_util\nDisallow: /b\nCrawl-delay: 2\n";
+        let robots = Robots::parse(text, USER_AGENT_TOKEN);
+        assert_eq!(robots.disallow, ["/a", "/b"]);
+        assert_eq!(robots.crawl_delay, Some(Duration::from_secs(2)));
+        // rules before any user-agent line belong to no group
+        assert!(Robots::parse("Disallow: /\nUser-agent: *\nAllow: /x\n", USER_AGENT_TOKEN).allows("/y"));
+    }
+
+    #[test]
+    fn crawl_delays() {
+        let delay = |value: &str| Robots::parse(&format!("User-agent: *\nCrawl-delay: {} # seconds\n", value), USER_AGENT_TOKEN).crawl_delay;
+        assert_eq!(delay("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(delay("10"), Some(Duration::from_secs(10)));
+        assert_eq!(delay("-1"), None);
+        assert_eq!(delay("soon"), None);
+        assert_eq!(delay("NaN"), None);
+        assert_eq!(delay("7200"), Some(MAX_CRAWL_DELAY));
+        assert_eq!(delay("1e300"), Some(MAX_CRAWL_DELAY));
+    }
+}
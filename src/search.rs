@@ -0,0 +1,237 @@
+//! `search`: counties and communities by name, so nobody has to know fips codes. The inventory has no names, so they
+//! come from the cached databases' political areas (`S_Pol_Ar`, with each community's `CID`), indexed once per file
+//! in `community_index.json` in the cache.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::inventory::{format_file_date, Inventory};
+use crate::report::{self, ReportFormat};
+
+pub const COMMUNITY_INDEX_FILE_NAME: &str = "community_index.json";
+
+/// Matches scoring lower than this are left out.
+const MIN_SCORE: f64 = 0.85;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Community {
+    pub name: String,
+    /// FEMA's 6-digit community id; None for areas that aren't a participating community.
+    pub cid: Option<String>,
+}
+
+/// The communities in each cached file, by file name.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CommunityIndex {
+    pub files: BTreeMap<String, Vec<Community>>,
+}
+
+impl CommunityIndex {
+    pub fn load(cache_dir: &Path) -> Result<CommunityIndex, Box<dyn std::error::Error>> {
+        let path = cache_dir.join(COMMUNITY_INDEX_FILE_NAME);
+        if !path.exists() {
+            return Ok(CommunityIndex::default());
+        }
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Saves the index to `cache_dir`, replacing it atomically the way the manifest is.
+    pub fn save(&self, cache_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let path = cache_dir.join(COMMUNITY_INDEX_FILE_NAME);
+        let tmp_path = cache_dir.join(format!("{}.tmp", COMMUNITY_INDEX_FILE_NAME));
+        serde_json::to_writer(BufWriter::new(File::create(&tmp_path)?), self)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchMatch {
+    pub fips: String,
+    pub name: String,
+    pub cid: Option<String>,
+    /// 1 for a name containing the search, otherwise the Jaro-Winkler similarity of the closest words.
+    pub score: f64,
+    pub effective_date: Option<String>,
+    pub preliminary_date: Option<String>,
+    pub cached_file: Option<String>,
+}
+
+/// Upper case, with punctuation dropped and "SAINT" shortened the way FEMA spells it, so "St. Louis", "ST LOUIS" and
+/// "Saint Louis" all come out the same.
+fn normalize(name: &str) -> Vec<String> {
+    name.to_uppercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| if word == "SAINT" { "ST".to_string() } else { word.to_string() })
+        .collect()
+}
+
+fn score(query: &[String], name: &[String]) -> f64 {
+    if query.is_empty() || name.len() < query.len() {
+        return if name.is_empty() { 0.0 } else { strsim::jaro_winkler(&query.join(" "), &name.join(" ")) };
+    }
+    // the best run of as many words as the search has
+    name.windows(query.len())
+        .map(|window| if window == query { 1.0 } else { strsim::jaro_winkler(&query.join(" "), &window.join(" ")) })
+        .fold(0.0, f64::max)
+}
+
+/// The fips and file name of each county's newest cached file.
+type CachedFiles = Vec<(String, String)>;
+
+/// Indexes the communities of cached files the index doesn't have yet (which needs `gdal`; without it the index is
+/// used as it is), and drops files no longer cached.
+fn update_index(cache_dir: &Path) -> Result<(CommunityIndex, CachedFiles), Box<dyn std::error::Error>> {
+    let mut index = CommunityIndex::load(cache_dir)?;
+    let cached: CachedFiles = crate::merge_geo::sources(cache_dir, |_| true)?.into_iter()
+        .map(|source| (source.fips, crate::cache::relative_name(cache_dir, &source.archive)))
+        .collect();
+    let names: HashSet<&String> = cached.iter().map(|(_, file_name)| file_name).collect();
+    let before = index.files.len();
+    index.files.retain(|file_name, _| names.contains(file_name));
+    let removed = index.files.len() != before;
+    let added = index_new_files(cache_dir, &cached, &mut index);
+    let changed = removed || added;
+    if changed {
+        index.save(cache_dir)?;
+    }
+    Ok((index, cached))
+}
+
+/// Adds the files missing from the index, returning whether there were any.
+#[cfg(feature = "gdal")]
+fn index_new_files(cache_dir: &Path, cached: &[(String, String)], index: &mut CommunityIndex) -> bool {
+    let mut added = false;
+    for (fips, file_name) in cached {
+        if index.files.contains_key(file_name) {
+            continue;
+        }
+        match communities(&cache_dir.join(file_name)) {
+            Ok(communities) => {
+                index.files.insert(file_name.clone(), communities);
+                added = true;
+            }
+            Err(e) => eprintln!("{}: can't index {}: {}", fips, file_name, e),
+        }
+    }
+    added
+}
+
+#[cfg(not(feature = "gdal"))]
+fn index_new_files(_cache_dir: &Path, _cached: &[(String, String)], _index: &mut CommunityIndex) -> bool {
+    false
+}
+
+/// The distinct names (and community ids) of the archive's political areas.
+#[cfg(feature = "gdal")]
+fn communities(archive: &Path) -> Result<Vec<Community>, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let gdb = crate::extract::require_archive_gdb_path(archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let mut layer = match dataset.layer_by_name("S_Pol_Ar") {
+        Ok(layer) => layer,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut communities = std::collections::BTreeSet::new();
+    for feature in layer.features() {
+        let text = |field: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+            Ok(feature.field_as_string_by_name(field)?.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))
+        };
+        if let Some(name) = text("POL_NAME1")? {
+            communities.insert(Community { name, cid: text("CID")? });
+        }
+    }
+    Ok(communities.into_iter().collect())
+}
+
+/// Matches `query` against the names of the cached counties and communities (and, for digits, against fips codes
+/// and community ids), best first.
+pub fn search(
+    cache_dir: &Path,
+    inv: Option<&Inventory>,
+    query: &str,
+) -> Result<Vec<SearchMatch>, Box<dyn std::error::Error>> {
+    let (index, cached) = update_index(cache_dir)?;
+    let wanted = normalize(query);
+    let digits = query.trim();
+    let by_digits = !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+
+    let make_match = |fips: &str, name: String, cid: Option<String>, score: f64, cached_file: Option<String>| {
+        let entry = inv.and_then(|inv| inv.get(fips));
+        SearchMatch {
+            fips: fips.to_string(),
+            name,
+            cid,
+            score,
+            effective_date: entry.and_then(|e| e.effective_file_date).map(|d| format_file_date(Some(d))),
+            preliminary_date: entry.and_then(|e| e.preliminary_file_date).map(|d| format_file_date(Some(d))),
+            cached_file,
+        }
+    };
+    let mut matches = Vec::new();
+    let mut seen = HashSet::new();
+    for (fips, file_name) in &cached {
+        for community in index.files.get(file_name).into_iter().flatten() {
+            let score = if by_digits {
+                let cid_matches = community.cid.as_deref().is_some_and(|cid| cid.starts_with(digits));
+                if fips.starts_with(digits) || cid_matches { 1.0 } else { 0.0 }
+            } else {
+                score(&wanted, &normalize(&community.name))
+            };
+            if score >= MIN_SCORE && seen.insert((fips.clone(), community.clone())) {
+                matches.push(make_match(fips, community.name.clone(), community.cid.clone(), score, Some(file_name.clone())));
+            }
+        }
+    }
+    // counties not cached (or not indexed) have no names, and can only be found by fips
+    if by_digits {
+        let matched: HashSet<String> = matches.iter().map(|m| m.fips.clone()).collect();
+        let mut unnamed: BTreeMap<&str, Option<String>> = BTreeMap::new();
+        for fips in inv.into_iter().flat_map(|inv| inv.keys()) {
+            unnamed.insert(fips, None);
+        }
+        for (fips, file_name) in &cached {
+            unnamed.insert(fips.as_str(), Some(file_name.clone()));
+        }
+        for (fips, cached_file) in unnamed {
+            if fips.starts_with(digits) && !matched.contains(fips) {
+                matches.push(make_match(fips, String::new(), None, 1.0, cached_file));
+            }
+        }
+    }
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| (&a.fips, &a.name).cmp(&(&b.fips, &b.name))));
+    Ok(matches)
+}
+
+pub fn write_matches(out: &mut dyn Write, matches: &[SearchMatch], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, matches)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["fips", "cid", "name", "score", "effective", "preliminary", "cached"]
+        .iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = matches.iter()
+        .map(|m| vec![
+            m.fips.clone(),
+            m.cid.clone().unwrap_or_default(),
+            m.name.clone(),
+            format!("{:.2}", m.score),
+            m.effective_date.clone().unwrap_or_default(),
+            m.preliminary_date.clone().unwrap_or_default(),
+            m.cached_file.clone().unwrap_or_default(),
+        ])
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
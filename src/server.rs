@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::cache::{self, CacheManifest};
+use crate::encryption;
+use crate::cancel::CancellationToken;
+use crate::download::RunReport;
+use crate::inventory::{format, read_inventory, Fips, Inventory, InventoryEntry};
+use crate::publish::Publishers;
+use crate::{blocking, history, systemd};
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// The inventory to serve. Re-read on every request, so it can be swapped out (e.g. by `watch`) while serving.
+    pub inventory: PathBuf,
+    pub cache_dir: PathBuf,
+    pub changelog: Option<PathBuf>,
+    pub threads: usize,
+    /// The bearer token `POST /refresh` requires. Refreshing over HTTP is disabled without one.
+    pub refresh_token: Option<String>,
+    pub politeness: u8,
+}
+
+/// The state of the most recent `POST /refresh`.
+#[derive(Serialize, Default, Debug, Clone)]
+struct RefreshStatus {
+    running: bool,
+    /// The county being refreshed, or none for a full refresh.
+    fips: Option<String>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    error: Option<String>,
+    changes: usize,
+    downloaded: usize,
+    failed: usize,
+}
+
+struct Context {
+    opts: ServeOptions,
+    refresh: Mutex<RefreshStatus>,
+}
+
+/// Serves the inventory, changelog and cache over HTTP until a shutdown is requested:
+///
+/// - `GET /counties` and `GET /counties/{fips}`: inventory entries, with their cache status
+/// - `GET /changes?since=2024-01-01`: changelog records observed since a date or RFC 3339 time
+/// - `GET /cache/status`: cache size and how much of the inventory it holds
+/// - `GET /files/{fips}`: the cached zip itself
+/// - `POST /refresh[?fips=12086]`: re-scrape the inventory and download what changed (or just the one county) in the
+///   background; needs `Authorization: Bearer <token>`. `GET /refresh/status` reports on the latest one.
+pub fn serve(addr: &str, opts: ServeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Arc::new(Server::http(addr).map_err(|e| format!("couldn't listen on {}: {}", addr, e))?);
+    eprintln!("listening on http://{}", addr);
+    systemd::notify_ready();
+
+    let threads = opts.threads;
+    let ctx = Arc::new(Context { opts, refresh: Mutex::new(RefreshStatus::default()) });
+    run_workers(server, threads, move |request| handle(request, &ctx));
+    systemd::notify_stopping();
+    Ok(())
+}
+
+/// Answers requests with `handle` on `threads` worker threads, until a shutdown is requested.
+pub fn run_workers(server: Arc<Server>, threads: usize, handle: impl Fn(Request) + Send + Sync + 'static) {
+    let handle = Arc::new(handle);
+    let workers: Vec<_> = (0..threads.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let handle = Arc::clone(&handle);
+            std::thread::spawn(move || {
+                while !systemd::shutdown_requested() {
+                    match server.recv_timeout(Duration::from_secs(1)) {
+                        Ok(Some(request)) => handle(request),
+                        Ok(None) => {}
+                        Err(e) => eprintln!("failed to receive request: {}", e),
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle(mut request: Request, ctx: &Arc<Context>) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    let response = match route(&mut request, ctx) {
+        Ok(response) => response,
+        Err(e) => json_response(500, &serde_json::json!({ "error": e.to_string() })),
+    };
+    eprintln!("{} {} {}", method, url, response.status_code().0);
+    if let Err(e) = request.respond(response) {
+        eprintln!("failed to respond to {} {}: {}", method, url, e);
+    }
+}
+
+fn route(request: &mut Request, ctx: &Arc<Context>) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+    let opts = &ctx.opts;
+    let parsed = reqwest::Url::parse(&format!("http://localhost{}", request.url()))?;
+    let query: HashMap<String, String> = parsed.query_pairs().into_owned().collect();
+    let segments: Vec<&str> = parsed.path().trim_matches('/').split('/').collect();
+
+    if let ["refresh"] = segments.as_slice() {
+        if *request.method() != Method::Post {
+            return Ok(error_response(405, "method not allowed"));
+        }
+        return start_refresh(request, query.get("fips").cloned(), ctx);
+    }
+    if *request.method() != Method::Get && *request.method() != Method::Head {
+        return Ok(error_response(405, "method not allowed"));
+    }
+    match segments.as_slice() {
+        ["refresh", "status"] => Ok(json_response(200, &*ctx.refresh.lock().unwrap())),
+        ["counties"] => {
+            let inv = read_inventory(&opts.inventory)?;
+            let manifest = CacheManifest::load(&opts.cache_dir)?;
+            let mut fips_codes: Vec<&Fips> = inv.keys().collect();
+            fips_codes.sort();
+            let counties: Vec<CountyStatus> = fips_codes.into_iter()
+                .map(|fips| county_status(fips, &inv[fips], &manifest))
+                .collect();
+            Ok(json_response(200, &counties))
+        }
+        ["counties", fips] => {
+            let inv = read_inventory(&opts.inventory)?;
+            match inv.get(*fips) {
+                Some(entry) => {
+                    let manifest = CacheManifest::load(&opts.cache_dir)?;
+                    Ok(json_response(200, &county_status(fips, entry, &manifest)))
+                }
+                None => Ok(error_response(404, &format!("no inventory entry for {}", fips))),
+            }
+        }
+        ["changes"] => {
+            let changelog = match &opts.changelog {
+                Some(changelog) => changelog,
+                None => return Ok(error_response(404, "no changelog configured")),
+            };
+            let since = match query.get("since").map(|s| parse_since(s)) {
+                Some(Some(since)) => Some(since),
+                Some(None) => return Ok(error_response(400, "since must be a date (YYYY-MM-DD) or RFC 3339 time")),
+                None => None,
+            };
+            let mut records = history::read_changelog(changelog)?;
+            if let Some(since) = since {
+                records.retain(|r| r.observed_at >= since);
+            }
+            if let Some(fips) = query.get("fips") {
+                records.retain(|r| r.change.fips.starts_with(fips.as_str()));
+            }
+            Ok(json_response(200, &records))
+        }
+        ["cache", "status"] => {
+            let inv = read_inventory(&opts.inventory)?;
+            let manifest = CacheManifest::load(&opts.cache_dir)?;
+            let stats = cache::cache_stats(&opts.cache_dir)?;
+            let wanted = inv.values().filter(|e| e.effective_file_url.is_some()).count();
+            let mut missing: Vec<&Fips> = inv.iter()
+                .filter(|(fips, entry)| entry.effective_file_url.is_some() && !is_cached(fips, entry, &manifest))
+                .map(|(fips, _)| fips)
+                .collect();
+            missing.sort();
+            let last_download = manifest.entries.values().map(|e| e.downloaded_at).max();
+            Ok(json_response(200, &serde_json::json!({
+                "files": stats.files,
+                "total_bytes": stats.total_bytes,
+                "inventory_entries": wanted,
+                "cached_entries": wanted - missing.len(),
+                "missing": missing,
+                "last_download": last_download,
+            })))
+        }
+        ["files", fips] => {
+            let manifest = CacheManifest::load(&opts.cache_dir)?;
+            let cached = match manifest.entries.get(*fips) {
+                Some(cached) => cached,
+                None => return Ok(error_response(404, &format!("{} isn't cached", fips))),
+            };
+            let path = opts.cache_dir.join(&cached.file_name);
+            let f = match File::open(&path) {
+                Ok(f) => f,
+                Err(_) => return Ok(error_response(404, &format!("{} is missing from the cache", cached.file_name))),
+            };
+            // served decrypted and deflated, as FEMA served it
+            let response = match (cached.recompressed.is_some(), encryption::is_encrypted(&path)?) {
+                // an open file outlives the copy's removal
+                (true, _) => Response::from_file(File::open(encryption::deflated(&path)?.path())?).boxed(),
+                (false, true) => Response::new(200.into(), Vec::new(), encryption::open(&path)?, Some(cached.size as usize), None),
+                (false, false) => Response::from_file(f).boxed(),
+            };
+            Ok(response
+                .with_header(header("Content-Type", "application/zip"))
+                .with_header(header("Content-Disposition", &format!("attachment; filename=\"{}\"",
+                    cached.file_name.rsplit('/').next().unwrap_or_default()))))
+        }
+        _ => Ok(error_response(404, "not found")),
+    }
+}
+
+/// Checks the request's bearer token, then kicks off a refresh on its own thread. Only one runs at a time.
+fn start_refresh(request: &mut Request, fips: Option<String>, ctx: &Arc<Context>) -> Result<ResponseBox, Box<dyn std::error::Error>> {
+    let token = match &ctx.opts.refresh_token {
+        Some(token) => token,
+        None => return Ok(error_response(403, "refresh is disabled; start serve with --refresh-token")),
+    };
+    let authorized = request.headers().iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Ok(error_response(401, "missing or wrong bearer token"));
+    }
+
+    // the county can also come in a JSON body, for webhook senders that can't set query strings
+    let fips = match fips {
+        Some(fips) => Some(fips),
+        None => {
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body)?;
+            serde_json::from_str::<serde_json::Value>(&body).ok()
+                .and_then(|v| v.get("fips").and_then(|f| f.as_str()).map(str::to_string))
+        }
+    };
+
+    {
+        let mut status = ctx.refresh.lock().unwrap();
+        if status.running {
+            return Ok(json_response(409, &*status));
+        }
+        *status = RefreshStatus { running: true, fips: fips.clone(), started_at: Some(Utc::now()), ..Default::default() };
+    }
+
+    let worker_ctx = Arc::clone(ctx);
+    std::thread::spawn(move || {
+        let result = refresh(&worker_ctx.opts, fips.as_deref());
+        let mut status = worker_ctx.refresh.lock().unwrap();
+        status.running = false;
+        status.finished_at = Some(Utc::now());
+        match result {
+            Ok(report) => {
+                status.changes = report.changes.len();
+                status.downloaded = report.downloads.len();
+                status.failed = report.failures.len();
+            }
+            Err(e) => {
+                eprintln!("refresh failed: {}", e);
+                status.error = Some(e.to_string());
+            }
+        }
+    });
+    Ok(json_response(202, &*ctx.refresh.lock().unwrap()))
+}
+
+/// Re-scrapes the county inventory and downloads what changed against the served inventory, then saves the new
+/// inventory in its place. With `fips`, only that county is downloaded and updated in the served inventory.
+fn refresh(opts: &ServeOptions, fips: Option<&str>) -> Result<RunReport, Box<dyn std::error::Error>> {
+    let mut fresh = blocking::get_effective_county_products()?;
+    let mut served = if opts.inventory.exists() { read_inventory(&opts.inventory)? } else { HashMap::new() };
+
+    let (inv, old_inv) = match fips {
+        Some(fips) => {
+            let (fips, entry) = fresh.remove_entry(fips).ok_or_else(|| format!("{} isn't in the current inventory", fips))?;
+            let old: Inventory = served.remove_entry(&*fips).into_iter().collect();
+            served.insert(fips.clone(), entry.clone());
+            (HashMap::from([(fips, entry)]), old)
+        }
+        None => {
+            let old = std::mem::replace(&mut served, fresh.clone());
+            (fresh, old)
+        }
+    };
+
+    let cancel = CancellationToken::new();
+    let report = blocking::download_all(&inv, Some(&old_inv), &opts.cache_dir, false, opts.politeness, None, &mut Publishers::default(), &cancel)?;
+
+    let tmp_path = opts.inventory.with_extension("json.tmp");
+    format::write(File::create(&tmp_path)?, &served)?;
+    std::fs::rename(tmp_path, &opts.inventory)?;
+    if let Some(changelog) = &opts.changelog {
+        history::append_changes(changelog, &report.changes, report.started_at)?;
+    }
+    Ok(report)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Serialize)]
+struct CountyStatus<'a> {
+    fips: &'a str,
+    #[serde(flatten)]
+    entry: &'a InventoryEntry,
+    cached: bool,
+    cached_file: Option<&'a cache::CacheEntry>,
+}
+
+fn county_status<'a>(fips: &'a str, entry: &'a InventoryEntry, manifest: &'a CacheManifest) -> CountyStatus<'a> {
+    CountyStatus {
+        fips,
+        entry,
+        cached: is_cached(fips, entry, manifest),
+        cached_file: manifest.entries.get(fips),
+    }
+}
+
+/// Whether the cache holds the inventory's current effective file for this county.
+fn is_cached(fips: &str, entry: &InventoryEntry, manifest: &CacheManifest) -> bool {
+    let file_name = manifest.layout.relative_path(fips, &cache::cache_file_name(fips, entry));
+    manifest.entries.get(fips).is_some_and(|cached| cached.file_name == file_name)
+}
+
+fn parse_since(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc())
+}
+
+pub fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("invalid header")
+}
+
+pub fn json_response<T: Serialize>(status: u16, body: &T) -> ResponseBox {
+    let body = serde_json::to_string(body).unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e));
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .boxed()
+}
+
+pub fn error_response(status: u16, message: &str) -> ResponseBox {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+
+use crate::cache::CacheStats;
+use crate::download::{RunReport, RunSummary};
+
+/// One of `count` deterministic slices of the inventory, written `index/count` with `index` counting from 1. Every
+/// worker given the same inventory and a different index gets a disjoint set of counties, and together they cover
+/// all of them, with no coordinator needed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Whether `fips` belongs to this shard.
+    pub fn contains(&self, fips: &str) -> bool {
+        fnv1a(fips.as_bytes()) % self.count as u64 == (self.index - 1) as u64
+    }
+}
+
+/// 64-bit FNV-1a. Used rather than std's hasher wherever a hash is compared across processes or persisted, since
+/// std's output may change between Rust releases and not every machine runs the same build.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("'{}' should look like 2/8 (the 2nd of 8 shards)", s);
+        let (index, count) = s.split_once('/').ok_or_else(err)?;
+        let index: u32 = index.trim().parse().map_err(|_| err())?;
+        let count: u32 = count.trim().parse().map_err(|_| err())?;
+        if count == 0 || index == 0 || index > count {
+            return Err(format!("shard {}/{} is out of range: the index counts from 1 to {}", index, count, count));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// Combines the reports of a sharded run into one, as if a single worker had done it all. The shards must all be
+/// from the same split; a missing shard is only warned about, since a partial picture is still useful.
+pub fn merge_reports(reports: Vec<RunReport>) -> Result<RunReport, Box<dyn std::error::Error>> {
+    let mut seen: Vec<Shard> = Vec::new();
+    for report in &reports {
+        match report.shard {
+            Some(shard) => {
+                if seen.contains(&shard) {
+                    return Err(format!("shard {} appears more than once", shard).into());
+                }
+                if let Some(first) = seen.first() {
+                    if first.count != shard.count {
+                        return Err(format!("can't merge shard {} with shard {}: they split the inventory differently", first, shard).into());
+                    }
+                }
+                seen.push(shard);
+            }
+            None if reports.len() > 1 => return Err("can't merge a report from an unsharded run".into()),
+            None => {}
+        }
+    }
+    if let Some(first) = seen.first() {
+        for index in 1..=first.count {
+            if !seen.iter().any(|s| s.index == index) {
+                eprintln!("warning: no report for shard {}/{}", index, first.count);
+            }
+        }
+    }
+
+    let mut reports = reports.into_iter();
+    let mut merged = reports.next().ok_or("no reports to merge")?;
+    for report in reports {
+        merged.started_at = merged.started_at.min(report.started_at);
+        merged.finished_at = merged.finished_at.max(report.finished_at);
+        merged.changes.extend(report.changes);
+        merged.downloads.extend(report.downloads);
+        merged.failures.extend(report.failures);
+        merged.skipped += report.skipped;
+        merged.deleted.extend(report.deleted);
+        // the usual setup is a cache per worker; workers sharing one cache get it counted once per shard
+        merged.cache = CacheStats {
+            files: merged.cache.files + report.cache.files,
+            total_bytes: merged.cache.total_bytes + report.cache.total_bytes,
+        };
+        if let Some(extraction) = report.extraction {
+            let merged = merged.extraction.get_or_insert_with(Default::default);
+            merged.extracted.extend(extraction.extracted);
+            merged.failures.extend(extraction.failures);
+        }
+    }
+    merged.shard = None;
+    merged.changes.sort_by(|a, b| a.fips.cmp(&b.fips));
+    merged.downloads.sort_by_key(|d| d.finished_at);
+    merged.failures.sort_by(|a, b| a.fips.cmp(&b.fips));
+    if let Some(extraction) = &mut merged.extraction {
+        extraction.extracted.sort_by_key(|e| e.finished_at);
+        extraction.failures.sort_by(|a, b| a.fips.cmp(&b.fips));
+    }
+    // counties removed from the inventory are deleted by every shard
+    merged.deleted.sort();
+    merged.deleted.dedup();
+    // over the span of all the shards, which ran side by side
+    merged.summary = RunSummary::of(&merged);
+    Ok(merged)
+}
@@ -0,0 +1,41 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use minisign::{PublicKey, SecretKey, SignatureBox};
+
+/// Password for an encrypted minisign secret key. If unset, the password is prompted for interactively; set it to
+/// an empty string for keys generated without one (`minisign -G -W`).
+pub const SIGN_KEY_PASSWORD_ENV: &str = "NFHL_UTIL_SIGN_KEY_PASSWORD";
+
+/// Where the detached signature for `path` lives, following minisign's convention of appending `.minisig`.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut s = OsString::from(path.as_os_str());
+    s.push(".minisig");
+    PathBuf::from(s)
+}
+
+/// Signs `path` with the minisign secret key at `secret_key_path`, writing the signature next to it. The signature
+/// can be checked with `verify-signature` or the stock `minisign -V`.
+pub fn sign_file(path: &Path, secret_key_path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let password = std::env::var(SIGN_KEY_PASSWORD_ENV).ok();
+    let sk = SecretKey::from_file(secret_key_path, password)?;
+
+    let file_name = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+    let trusted_comment = format!("timestamp:{}\tfile:{}", chrono::Utc::now().timestamp(), file_name);
+    let signature = minisign::sign(None, &sk, File::open(path)?, Some(&trusted_comment), Some("signed by nfhl_util"))?;
+
+    let sig_path = signature_path(path);
+    std::fs::write(&sig_path, signature.into_string())?;
+    Ok(sig_path)
+}
+
+/// Verifies `path` against its detached signature (by default `<path>.minisig`), returning the signature's trusted
+/// comment on success.
+pub fn verify_file(path: &Path, public_key_path: &Path, sig_path: Option<&Path>) -> Result<String, Box<dyn std::error::Error>> {
+    let pk = PublicKey::from_file(public_key_path)?;
+    let sig_path = sig_path.map(Path::to_path_buf).unwrap_or_else(|| signature_path(path));
+    let signature = SignatureBox::from_file(&sig_path)?;
+    minisign::verify(&pk, &signature, File::open(path)?, true, false, false)?;
+    Ok(signature.trusted_comment()?)
+}
@@ -0,0 +1,200 @@
+//! `report sizes`: how much space the cache takes and where, for capacity planning. Sizes are of the files as they
+//! sit on disk, recompressed or encrypted or not, since that's what the disk has to hold.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::cache::{self, CacheManifest};
+use crate::download::RunReport;
+use crate::error::{NfhlError, Result};
+use crate::html_report::format_bytes;
+use crate::inventory::Inventory;
+use crate::plan;
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct StateSizes {
+    pub state: String,
+    pub counties: usize,
+    pub files: usize,
+    pub bytes: u64,
+    /// Of the files downloaded since the last run's report; 0 without one.
+    pub new_bytes: u64,
+    /// What the inventory has that the cache doesn't; 0 without an inventory.
+    pub pending_downloads: usize,
+    pub projected_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CountySize {
+    pub fips: String,
+    /// All of the county's files, superseded ones kept for history included.
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// The cache compared with a run's report.
+#[derive(Serialize, Debug, Clone)]
+pub struct Growth {
+    pub since: DateTime<Utc>,
+    pub bytes_then: u64,
+    /// Can be negative, when deletions outweighed downloads.
+    pub bytes_change: i64,
+    pub files_change: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SizesReport {
+    pub generated_at: DateTime<Utc>,
+    pub files: usize,
+    pub bytes: u64,
+    pub states: Vec<StateSizes>,
+    /// The counties taking the most space, largest first.
+    pub largest: Vec<CountySize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub growth: Option<Growth>,
+    /// The cache's size plus what its pending downloads are projected to add: an upper bound, since an update's
+    /// old file may be deleted. None without an inventory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projected_bytes: Option<u64>,
+}
+
+/// Sizes up `cache_dir` by state and county, with the `largest` counties. With `last_run`, how it's grown since
+/// that run finished; with `inv`, how much its pending downloads will add. A pending download is projected at the
+/// size of the county's cached file it replaces, or for a county not cached yet, the average for its state's (or
+/// failing that, all) cached files.
+pub fn sizes_report(cache_dir: &Path, inv: Option<&Inventory>, last_run: Option<&RunReport>, largest: usize) -> Result<SizesReport> {
+    let manifest = CacheManifest::load(cache_dir)?;
+    let mut states: BTreeMap<String, StateSizes> = BTreeMap::new();
+    let mut counties: BTreeMap<String, CountySize> = BTreeMap::new();
+    // the newest file of each county, for projecting its next one
+    let mut newest: HashMap<String, u64> = HashMap::new();
+    for (fips, archive) in cache::cached_archives(cache_dir)? {
+        let bytes = std::fs::metadata(&archive).map_err(NfhlError::io(&archive))?.len();
+        let state = states.entry(fips.get(..2).unwrap_or(&fips).to_string()).or_default();
+        state.files += 1;
+        state.bytes += bytes;
+        let downloaded_at = manifest.entries.get(&fips)
+            .filter(|cached| archive.ends_with(&cached.file_name))
+            .map(|cached| cached.downloaded_at);
+        if let (Some(last_run), Some(downloaded_at)) = (last_run, downloaded_at) {
+            if downloaded_at > last_run.finished_at {
+                state.new_bytes += bytes;
+            }
+        }
+        let county = counties.entry(fips.clone()).or_insert_with(|| CountySize { fips: fips.clone(), files: 0, bytes: 0 });
+        county.files += 1;
+        county.bytes += bytes;
+        // cached_archives is sorted oldest first within a county
+        newest.insert(fips, bytes);
+    }
+    for county in counties.values() {
+        states.get_mut(county.fips.get(..2).unwrap_or(&county.fips)).expect("counted above").counties += 1;
+    }
+    let files: usize = states.values().map(|state| state.files).sum();
+    let bytes: u64 = states.values().map(|state| state.bytes).sum();
+
+    let mut projected_bytes = None;
+    if let Some(inv) = inv {
+        let average = |files: usize, bytes: u64| if files == 0 { 0 } else { bytes / files as u64 };
+        let overall = average(files, bytes);
+        let mut added = 0;
+        let plan = plan::make_plan(inv, None, cache_dir, false, false, None)?;
+        for download in &plan.downloads {
+            let state_fips = download.fips.get(..2).unwrap_or(&download.fips).to_string();
+            let projected = match (newest.get(&download.fips), states.get(&state_fips)) {
+                (Some(&bytes), _) => bytes,
+                (None, Some(state)) => average(state.files, state.bytes),
+                (None, None) => overall,
+            };
+            let state = states.entry(state_fips.clone()).or_insert_with(|| StateSizes { state: state_fips, ..Default::default() });
+            state.pending_downloads += 1;
+            state.projected_bytes += projected;
+            added += projected;
+        }
+        projected_bytes = Some(bytes + added);
+    }
+
+    let mut largest_counties: Vec<CountySize> = counties.into_values().collect();
+    largest_counties.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.fips.cmp(&b.fips)));
+    largest_counties.truncate(largest);
+
+    Ok(SizesReport {
+        generated_at: Utc::now(),
+        files,
+        bytes,
+        states: states.into_iter().map(|(state, sizes)| StateSizes { state, ..sizes }).collect(),
+        largest: largest_counties,
+        growth: last_run.map(|run| Growth {
+            since: run.finished_at,
+            bytes_then: run.cache.total_bytes,
+            bytes_change: bytes as i64 - run.cache.total_bytes as i64,
+            files_change: files as i64 - run.cache.files as i64,
+        }),
+        projected_bytes,
+    })
+}
+
+/// The per-state table, then the largest counties. CSV has just the per-state rows, for a spreadsheet; JSON has it
+/// all.
+pub fn write_sizes_report(out: &mut dyn Write, report: &SizesReport, format: ReportFormat) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, report)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let size = |bytes: u64| match format {
+        ReportFormat::Csv => bytes.to_string(),
+        _ => format_bytes(bytes),
+    };
+    let share = |bytes: u64| match report.bytes {
+        0 => "0.0".to_string(),
+        total => format!("{:.1}", bytes as f64 * 100.0 / total as f64),
+    };
+    let headers: Vec<String> = ["state_fips", "counties", "files", "size", "percent", "new", "pending", "projected"]
+        .iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = report.states.iter()
+        .map(|state| vec![
+            state.state.clone(),
+            state.counties.to_string(),
+            state.files.to_string(),
+            size(state.bytes),
+            share(state.bytes),
+            size(state.new_bytes),
+            state.pending_downloads.to_string(),
+            size(state.projected_bytes),
+        ])
+        .collect();
+    match format {
+        ReportFormat::Csv => return report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+
+    writeln!(out)?;
+    let headers: Vec<String> = ["fips", "files", "size", "percent"].iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = report.largest.iter()
+        .map(|county| vec![county.fips.clone(), county.files.to_string(), size(county.bytes), share(county.bytes)])
+        .collect();
+    match format {
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+
+    writeln!(out, "\n{} files, {}", report.files, format_bytes(report.bytes))?;
+    if let Some(growth) = &report.growth {
+        let sign = if growth.bytes_change < 0 { "-" } else { "+" };
+        writeln!(out, "{}{} ({:+} files) since {}", sign, format_bytes(growth.bytes_change.unsigned_abs()), growth.files_change,
+            growth.since.format("%Y-%m-%d %H:%M UTC"))?;
+    }
+    if let Some(projected) = report.projected_bytes {
+        let pending: usize = report.states.iter().map(|state| state.pending_downloads).sum();
+        writeln!(out, "at most {} once the inventory's {} pending downloads are done", format_bytes(projected), pending)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! A store of inventory snapshots as deltas: a JSONL file whose first line is the first snapshot in full and whose
+//! later lines hold only what each snapshot changed. A nightly inventory changes a handful of its ~3000 entries, so
+//! this is a tiny fraction of keeping every night's file, and any of them can still be rebuilt with `materialize`.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::inventory::{Fips, Inventory, InventoryEntry};
+
+/// One line of the store: what changed as of a snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Delta {
+    pub taken_at: DateTime<Utc>,
+    /// Entries that are new or different, in full.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<Fips, InventoryEntry>,
+    /// Entries that are gone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<Fips>,
+}
+
+impl Delta {
+    /// What turns `old` into `new`.
+    pub fn between(old: &Inventory, new: &Inventory, taken_at: DateTime<Utc>) -> Delta {
+        let set = new.iter()
+            .filter(|(fips, entry)| old.get(*fips) != Some(*entry))
+            .map(|(fips, entry)| (fips.clone(), entry.clone()))
+            .collect();
+        let mut removed: Vec<Fips> = old.keys().filter(|fips| !new.contains_key(*fips)).cloned().collect();
+        removed.sort();
+        Delta { taken_at, set, removed }
+    }
+
+    pub fn apply(&self, inv: &mut Inventory) {
+        for fips in &self.removed {
+            inv.remove(fips);
+        }
+        for (fips, entry) in &self.set {
+            inv.insert(fips.clone(), entry.clone());
+        }
+    }
+}
+
+/// Every delta in the store, oldest first. A missing store has none.
+pub fn read_store(path: &Path) -> Result<Vec<Delta>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut deltas = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let delta = serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+        deltas.push(delta);
+    }
+    Ok(deltas)
+}
+
+/// The inventory as it was at `as_of`, or as of the latest snapshot; None if the store has nothing that old.
+pub fn materialize(deltas: &[Delta], as_of: Option<DateTime<Utc>>) -> Option<Inventory> {
+    let mut taken = deltas.iter().filter(|delta| as_of.is_none_or(|as_of| delta.taken_at <= as_of)).peekable();
+    taken.peek()?;
+    let mut inv = Inventory::new();
+    for delta in taken {
+        delta.apply(&mut inv);
+    }
+    Some(inv)
+}
+
+/// Adds `inv` to the store as a snapshot taken at `taken_at`: in full if it's the first, otherwise as what changed
+/// since the latest. Snapshots go in order, so one older than the latest is refused. Returns the delta written.
+pub fn append_snapshot(path: &Path, inv: &Inventory, taken_at: DateTime<Utc>) -> Result<Delta, Box<dyn std::error::Error>> {
+    let deltas = read_store(path)?;
+    if let Some(latest) = deltas.last().filter(|latest| latest.taken_at > taken_at) {
+        return Err(format!("{} already has a snapshot from {}, after {}; snapshots have to be added in order",
+            path.display(), latest.taken_at, taken_at).into());
+    }
+    let previous = materialize(&deltas, None).unwrap_or_default();
+    let delta = Delta::between(&previous, inv, taken_at);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(f, "{}", serde_json::to_string(&delta)?)?;
+    f.sync_all()?;
+    Ok(delta)
+}
+
+/// A `--as-of`: an RFC 3339 time, or a YYYY-MM-DD date meaning the end of that day (UTC), so a snapshot taken any
+/// time that day is included.
+pub fn parse_as_of(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_nano_opt(23, 59, 59, 999_999_999).expect("a valid time").and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("'{}' should be a date like 2024-05-01 or a time like 2024-05-01T06:00:00Z", s))
+}
@@ -0,0 +1,103 @@
+//! `ProductSource`: anything that can list FEMA products, so the inventory can come from the NFHL portal
+//! (`nfhl_portal::NfhlPortal`), MSC's search (`msc::Msc`), both (`Combined`) or canned data (`MockSource`) without the
+//! code using it caring which.
+
+use std::future::Future;
+
+use crate::error::{NfhlError, Result};
+use crate::inventory::{Fips, Inventory, ProductKind};
+
+/// What to list products for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Jurisdiction {
+    Nation,
+    /// By 2-digit fips.
+    State(String),
+    /// By 5-digit fips.
+    County(String),
+}
+
+impl Jurisdiction {
+    /// A state for 2 digits, a county for 5.
+    pub fn from_fips(fips: &str) -> Result<Jurisdiction> {
+        let fips = Fips::new(fips)?;
+        if fips.is_state() {
+            Ok(Jurisdiction::State(fips.into()))
+        } else {
+            Ok(Jurisdiction::County(fips.into()))
+        }
+    }
+
+    /// Whether a product keyed by `fips` (a state's or a county's) is in the jurisdiction.
+    pub fn contains(&self, fips: &str) -> bool {
+        match self {
+            Jurisdiction::Nation => true,
+            Jurisdiction::State(state) => fips.starts_with(state.as_str()),
+            Jurisdiction::County(county) => fips == county,
+        }
+    }
+}
+
+pub trait ProductSource: Sync {
+    /// The products the source lists in the jurisdiction, as inventory entries by fips.
+    fn list_products(&self, jurisdiction: &Jurisdiction) -> impl Future<Output = Result<Inventory>> + Send;
+
+    /// Where to download the current product of one kind for a state or county, or None if the source has none.
+    fn resolve_download_url(&self, fips: &str, kind: ProductKind) -> impl Future<Output = Result<Option<String>>> + Send {
+        async move {
+            let inv = self.list_products(&Jurisdiction::from_fips(fips)?).await?;
+            Ok(inv.get(fips).and_then(|entry| entry.url(kind)).map(|url| url.to_string()))
+        }
+    }
+}
+
+/// Two sources as one. Entries from `primary` win; `secondary` adds the counties it lacks and fills in the products
+/// it has no file for, e.g. the portal's effective files with MSC's preliminary ones.
+pub struct Combined<A, B> {
+    pub primary: A,
+    pub secondary: B,
+}
+
+impl<A: ProductSource, B: ProductSource> ProductSource for Combined<A, B> {
+    async fn list_products(&self, jurisdiction: &Jurisdiction) -> Result<Inventory> {
+        let mut inv = self.primary.list_products(jurisdiction).await?;
+        for (fips, other) in self.secondary.list_products(jurisdiction).await? {
+            let entry = inv.entry(fips).or_insert_with(|| other.clone());
+            if entry.effective_file_url.is_none() {
+                entry.effective_file_url = other.effective_file_url;
+                entry.effective_file_date = other.effective_file_date;
+            }
+            if entry.preliminary_file_url.is_none() {
+                entry.preliminary_file_url = other.preliminary_file_url;
+                entry.preliminary_file_date = other.preliminary_file_date;
+            }
+        }
+        Ok(inv)
+    }
+}
+
+/// A source serving a fixed inventory, or failing with `fail_with`'s error if it's set (e.g. a `Network` one, to
+/// exercise retries), for code that takes a source to be run without going near FEMA.
+#[derive(Debug, Default, Clone)]
+pub struct MockSource {
+    pub inventory: Inventory,
+    pub fail_with: Option<fn() -> NfhlError>,
+}
+
+impl MockSource {
+    pub fn new(inventory: Inventory) -> MockSource {
+        MockSource { inventory, fail_with: None }
+    }
+}
+
+impl ProductSource for MockSource {
+    async fn list_products(&self, jurisdiction: &Jurisdiction) -> Result<Inventory> {
+        if let Some(fail_with) = self.fail_with {
+            return Err(fail_with());
+        }
+        Ok(self.inventory.iter()
+            .filter(|(fips, _)| jurisdiction.contains(fips))
+            .map(|(fips, entry)| (fips.clone(), entry.clone()))
+            .collect())
+    }
+}
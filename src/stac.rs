@@ -0,0 +1,221 @@
+//! `publish-stac`: a static STAC catalog of the cache, so the mirror can be harvested by the geospatial data catalogs
+//! that already speak STAC. The layout is a self-contained catalog with relative links:
+//!
+//! - `catalog.json`, linking to
+//! - `nfhl-counties/collection.json`, with the overall extent (and the GeoParquet outputs as collection assets), and
+//! - `nfhl-counties/{id}/{id}.json`, an item per cached county file, `id` being the file's name without `.zip`.
+
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+
+use crate::cache::{self, CacheManifest};
+
+pub const STAC_VERSION: &str = "1.0.0";
+pub const COLLECTION_ID: &str = "nfhl-counties";
+const FILE_EXTENSION: &str = "https://stac-extensions.github.io/file/v2.1.0/schema.json";
+
+#[derive(Debug, Clone)]
+pub struct StacOptions {
+    /// The url the cache directory is served at, for the zips' hrefs. Without it they're local paths.
+    pub base_url: Option<String>,
+    /// GeoParquet written by `convert` or `merge-geo`, added as collection assets.
+    pub geoparquet_dir: Option<PathBuf>,
+    /// The url `geoparquet_dir` is served at.
+    pub geoparquet_base_url: Option<String>,
+}
+
+/// What went into the catalog.
+#[derive(Debug, Default)]
+pub struct StacSummary {
+    pub items: usize,
+    /// Items written without a geometry, because the file couldn't be read (or without `gdal`, all of them).
+    pub without_geometry: usize,
+}
+
+/// Writes the catalog into `out_dir`, replacing the items from an earlier run.
+pub fn publish_stac(cache_dir: &Path, out_dir: &Path, opts: &StacOptions) -> Result<StacSummary, Box<dyn std::error::Error>> {
+    let manifest = CacheManifest::load(cache_dir)?;
+    let collection_dir = out_dir.join(COLLECTION_ID);
+    if collection_dir.exists() {
+        std::fs::remove_dir_all(&collection_dir)?;
+    }
+    std::fs::create_dir_all(&collection_dir)?;
+
+    let mut summary = StacSummary::default();
+    let mut item_links = Vec::new();
+    let mut bounds: Option<[f64; 4]> = None;
+    let mut dates: Vec<NaiveDate> = Vec::new();
+    for (fips, archive) in cache::cached_archives(cache_dir)? {
+        let file_name = archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        let id = file_name.trim_end_matches(".zip").to_string();
+        let effective_date = cache::archive_effective_date(&manifest, &fips, &archive);
+        let extent = county_extent(&archive).unwrap_or_else(|e| {
+            eprintln!("{}: no geometry for {}: {}", fips, file_name, e);
+            None
+        });
+
+        let mut item = json!({
+            "type": "Feature",
+            "stac_version": STAC_VERSION,
+            "stac_extensions": [FILE_EXTENSION],
+            "id": id,
+            "collection": COLLECTION_ID,
+            "geometry": null,
+            "properties": {
+                "datetime": effective_date.map(|d| format!("{}T00:00:00Z", d)),
+                "title": format!("NFHL county {} effective {}", fips, effective_date.map(|d| d.to_string()).unwrap_or_else(|| "(undated)".to_string())),
+                "nfhl:fips": fips,
+            },
+            "links": [
+                { "rel": "root", "href": "../../catalog.json", "type": "application/json" },
+                { "rel": "parent", "href": "../collection.json", "type": "application/json" },
+                { "rel": "collection", "href": "../collection.json", "type": "application/json" },
+            ],
+            "assets": {
+                "archive": {
+                    "href": href(opts.base_url.as_deref(), &archive, &file_name)?,
+                    "type": "application/zip",
+                    "title": "FEMA's county file geodatabase, as downloaded",
+                    "roles": ["data"],
+                    "file:size": std::fs::metadata(&archive)?.len(),
+                },
+            },
+        });
+        match extent {
+            Some((bbox, geometry)) => {
+                item["bbox"] = json!(bbox);
+                item["geometry"] = geometry;
+                bounds = Some(match bounds {
+                    Some(b) => [b[0].min(bbox[0]), b[1].min(bbox[1]), b[2].max(bbox[2]), b[3].max(bbox[3])],
+                    None => bbox,
+                });
+            }
+            None => summary.without_geometry += 1,
+        }
+        if let Some(date) = effective_date {
+            dates.push(date);
+        }
+
+        let item_dir = collection_dir.join(&id);
+        std::fs::create_dir_all(&item_dir)?;
+        std::fs::write(item_dir.join(format!("{}.json", id)), serde_json::to_vec_pretty(&item)?)?;
+        item_links.push(json!({ "rel": "item", "href": format!("./{0}/{0}.json", id), "type": "application/geo+json" }));
+        summary.items += 1;
+    }
+
+    let mut links = vec![
+        json!({ "rel": "root", "href": "../catalog.json", "type": "application/json" }),
+        json!({ "rel": "parent", "href": "../catalog.json", "type": "application/json" }),
+    ];
+    links.extend(item_links);
+    let interval = [dates.iter().min().map(|d| format!("{}T00:00:00Z", d)), dates.iter().max().map(|d| format!("{}T00:00:00Z", d))];
+    let mut collection = json!({
+        "type": "Collection",
+        "stac_version": STAC_VERSION,
+        "stac_extensions": [FILE_EXTENSION],
+        "id": COLLECTION_ID,
+        "title": "FEMA National Flood Hazard Layer, by county",
+        "description": "The effective NFHL file geodatabase of each county, as published on FEMA's Map Service Center.",
+        "license": "other",
+        "providers": [
+            { "name": "FEMA", "roles": ["producer", "licensor"], "url": "https://msc.fema.gov" },
+        ],
+        "extent": {
+            "spatial": { "bbox": [bounds.unwrap_or([-180.0, -90.0, 180.0, 90.0])] },
+            "temporal": { "interval": [interval] },
+        },
+        "links": links,
+    });
+    if let Some(dir) = &opts.geoparquet_dir {
+        let mut assets = serde_json::Map::new();
+        for file in parquet_files(dir)? {
+            let relative = file.strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+            assets.insert(relative.trim_end_matches(".parquet").replace('/', "-"), json!({
+                "href": href(opts.geoparquet_base_url.as_deref(), &file, &relative)?,
+                "type": "application/vnd.apache.parquet",
+                "title": relative,
+                "roles": ["data"],
+                "file:size": std::fs::metadata(&file)?.len(),
+            }));
+        }
+        collection["assets"] = Value::Object(assets);
+    }
+    std::fs::write(collection_dir.join("collection.json"), serde_json::to_vec_pretty(&collection)?)?;
+
+    let catalog = json!({
+        "type": "Catalog",
+        "stac_version": STAC_VERSION,
+        "id": "nfhl",
+        "title": "NFHL mirror",
+        "description": "A mirror of FEMA's National Flood Hazard Layer.",
+        "links": [
+            { "rel": "root", "href": "./catalog.json", "type": "application/json" },
+            { "rel": "child", "href": format!("./{}/collection.json", COLLECTION_ID), "type": "application/json" },
+        ],
+    });
+    std::fs::write(out_dir.join("catalog.json"), serde_json::to_vec_pretty(&catalog)?)?;
+    Ok(summary)
+}
+
+/// `base_url` joined with `relative`, or failing that the file's absolute path.
+fn href(base_url: Option<&str>, path: &Path, relative: &str) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(match base_url {
+        Some(base_url) => format!("{}/{}", base_url.trim_end_matches('/'), relative),
+        None => std::fs::canonicalize(path)?.to_string_lossy().into_owned(),
+    })
+}
+
+/// Every `.parquet` under `dir`, sorted.
+fn parquet_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let path = dir_entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("parquet")) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// A bbox, and the geometry inside it as GeoJSON.
+type Extent = ([f64; 4], Value);
+
+/// The county's boundary, from its `S_Pol_Ar` (dissolved and lightly simplified), in lon/lat, and its bbox.
+#[cfg(feature = "gdal")]
+fn county_extent(archive: &Path) -> Result<Option<Extent>, Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::LayerAccess;
+
+    let gdb = crate::extract::require_archive_gdb_path(archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let mut layer = dataset.layer_by_name("S_Pol_Ar").map_err(|_| format!("{} has no S_Pol_Ar layer", gdb))?;
+    let lon_lat = SpatialRef::from_epsg(4326)?;
+    lon_lat.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    let to_lon_lat = CoordTransform::new(&crate::stats::layer_srs(&layer)?, &lon_lat)?;
+    let mut polygons = Vec::new();
+    for feature in layer.features() {
+        if let Some(geometry) = feature.geometry().filter(|g| !g.is_empty() && g.is_valid()) {
+            polygons.push(geometry.transform(&to_lon_lat)?);
+        }
+    }
+    if polygons.is_empty() {
+        return Ok(None);
+    }
+    // about 10 m: plenty for finding the county, and it keeps the item small
+    let boundary = crate::validate::geometry::dissolve(polygons.into_iter())?.simplify_preserve_topology(0.0001)?;
+    let envelope = boundary.envelope();
+    Ok(Some(([envelope.MinX, envelope.MinY, envelope.MaxX, envelope.MaxY], serde_json::from_str(&boundary.json()?)?)))
+}
+
+#[cfg(not(feature = "gdal"))]
+fn county_extent(_archive: &Path) -> Result<Option<Extent>, Box<dyn std::error::Error>> {
+    Ok(None)
+}
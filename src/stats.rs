@@ -0,0 +1,277 @@
+//! `stats`: flood zone areas of the cached counties, from their `S_Fld_Haz_Ar` layer: the figures hazard mitigation
+//! plans and grant applications ask for. With `--by-tract`, the Special Flood Hazard Area is also broken down by census
+//! tract (or block group), for social vulnerability analyses.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::report::{self, ReportFormat};
+
+pub const SQ_M_PER_SQ_MI: f64 = 2_589_988.110_336;
+pub const SQ_M_PER_ACRE: f64 = 4_046.856_422_4;
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ZoneArea {
+    pub features: u64,
+    pub area_sq_mi: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CountyStats {
+    pub fips: String,
+    pub effective_date: Option<NaiveDate>,
+    /// The area the flood hazard polygons cover, Zone X included; usually the whole county.
+    pub mapped_area_sq_mi: f64,
+    /// The Special Flood Hazard Area (`SFHA_TF = 'T'`): zones A and V, the 1% annual chance floodplain.
+    pub sfha_area_sq_mi: f64,
+    pub sfha_pct: f64,
+    /// Polygons whose `ZONE_SUBTY` is a floodway.
+    pub floodway_area_sq_mi: f64,
+    /// By `FLD_ZONE`.
+    pub zones: BTreeMap<String, ZoneArea>,
+}
+
+/// Works out a county's flood zone areas. Areas are measured in a Lambert azimuthal equal-area projection centred on
+/// the county, which keeps them accurate from Guam to Maine without choosing a projection per state.
+#[cfg(feature = "gdal")]
+pub fn county_stats(source: &crate::merge_geo::Source) -> Result<CountyStats, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let mut layer = dataset.layer_by_name("S_Fld_Haz_Ar")
+        .map_err(|_| format!("{} has no S_Fld_Haz_Ar layer", gdb))?;
+    let to_equal_area = gdal::spatial_ref::CoordTransform::new(&layer_srs(&layer)?, &equal_area_srs(&layer)?)?;
+
+    let mut stats = CountyStats {
+        fips: source.fips.clone(),
+        effective_date: source.effective_date,
+        mapped_area_sq_mi: 0.0,
+        sfha_area_sq_mi: 0.0,
+        sfha_pct: 0.0,
+        floodway_area_sq_mi: 0.0,
+        zones: BTreeMap::new(),
+    };
+    for feature in layer.features() {
+        let area = match feature.geometry() {
+            Some(geometry) => geometry.transform(&to_equal_area)?.area() / SQ_M_PER_SQ_MI,
+            None => continue,
+        };
+        let zone = feature.field_as_string_by_name("FLD_ZONE")?.unwrap_or_default();
+        let sfha = feature.field_as_string_by_name("SFHA_TF")?.is_some_and(|tf| tf.eq_ignore_ascii_case("T"));
+        let floodway = feature.field_as_string_by_name("ZONE_SUBTY")?
+            .is_some_and(|subtype| subtype.to_ascii_uppercase().contains("FLOODWAY"));
+
+        stats.mapped_area_sq_mi += area;
+        if sfha {
+            stats.sfha_area_sq_mi += area;
+        }
+        if floodway {
+            stats.floodway_area_sq_mi += area;
+        }
+        let zone = stats.zones.entry(zone).or_default();
+        zone.features += 1;
+        zone.area_sq_mi += area;
+    }
+    if stats.mapped_area_sq_mi > 0.0 {
+        stats.sfha_pct = 100.0 * stats.sfha_area_sq_mi / stats.mapped_area_sq_mi;
+    }
+    Ok(stats)
+}
+
+/// The layer's CRS, with x as the longitude or easting whatever the CRS says.
+#[cfg(feature = "gdal")]
+pub fn layer_srs(layer: &gdal::vector::Layer) -> Result<gdal::spatial_ref::SpatialRef, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let srs = layer.spatial_ref().ok_or("the layer has no CRS")?;
+    srs.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    Ok(srs)
+}
+
+/// An equal-area projection, in metres, centred on the layer's extent.
+#[cfg(feature = "gdal")]
+pub fn equal_area_srs(layer: &gdal::vector::Layer) -> Result<gdal::spatial_ref::SpatialRef, Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::LayerAccess;
+
+    let lon_lat = SpatialRef::from_epsg(4269)?;
+    lon_lat.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    let extent = layer.get_extent()?;
+    let mut center_x = [(extent.MinX + extent.MaxX) / 2.0];
+    let mut center_y = [(extent.MinY + extent.MaxY) / 2.0];
+    CoordTransform::new(&layer_srs(layer)?, &lon_lat)?.transform_coords(&mut center_x, &mut center_y, &mut [0.0])?;
+    let laea = SpatialRef::from_proj4(&format!(
+        "+proj=laea +lat_0={} +lon_0={} +datum=NAD83 +units=m +no_defs", center_y[0], center_x[0]))?;
+    laea.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    Ok(laea)
+}
+
+/// Where `--by-tract` gets its census geographies.
+#[derive(Debug, Clone)]
+pub struct TractOptions {
+    /// Any vector file of tracts or block groups, e.g. TIGER/Line `tl_2023_22_tract.shp` or a GeoPackage of them.
+    pub tiger: std::path::PathBuf,
+    /// The field with the full GEOID, whose first 5 digits are the county's fips.
+    pub geoid_field: String,
+    /// A population field, for population-weighted exposure.
+    pub population_field: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TractExposure {
+    pub geoid: String,
+    pub county_fips: String,
+    pub area_sq_mi: f64,
+    pub sfha_area_sq_mi: f64,
+    pub sfha_pct: f64,
+    pub population: Option<f64>,
+    /// The population living in the SFHA, assuming it's spread evenly over the tract.
+    pub population_in_sfha: Option<f64>,
+}
+
+/// Intersects a county's SFHA polygons with its census geographies (those whose GEOID starts with its fips).
+#[cfg(feature = "gdal")]
+pub fn tract_exposure(source: &crate::merge_geo::Source, opts: &TractOptions) -> Result<Vec<TractExposure>, Box<dyn std::error::Error>> {
+    use gdal::spatial_ref::CoordTransform;
+    use gdal::vector::LayerAccess;
+
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let mut hazards = dataset.layer_by_name("S_Fld_Haz_Ar")
+        .map_err(|_| format!("{} has no S_Fld_Haz_Ar layer", gdb))?;
+    let equal_area = equal_area_srs(&hazards)?;
+    let to_equal_area = CoordTransform::new(&layer_srs(&hazards)?, &equal_area)?;
+    hazards.set_attribute_filter("SFHA_TF = 'T'")?;
+    let mut sfha = Vec::new();
+    for feature in hazards.features() {
+        if let Some(geometry) = feature.geometry() {
+            let geometry = geometry.transform(&to_equal_area)?;
+            sfha.push((geometry.envelope(), geometry));
+        }
+    }
+
+    let tiger = gdal::Dataset::open(&opts.tiger)?;
+    let mut tracts = tiger.layer(0)?;
+    let tracts_to_equal_area = CoordTransform::new(&layer_srs(&tracts)?, &equal_area)?;
+    tracts.set_attribute_filter(&format!("{} LIKE '{}%'", crate::merge_geo::quote(&opts.geoid_field), source.fips))?;
+
+    let mut exposures = Vec::new();
+    for feature in tracts.features() {
+        let geoid = feature.field_as_string_by_name(&opts.geoid_field)?.unwrap_or_default();
+        let tract = match feature.geometry() {
+            Some(geometry) => geometry.transform(&tracts_to_equal_area)?,
+            None => continue,
+        };
+        let bounds = tract.envelope();
+        let mut sfha_area = 0.0;
+        for (envelope, polygon) in &sfha {
+            let overlaps = envelope.MinX <= bounds.MaxX && envelope.MaxX >= bounds.MinX
+                && envelope.MinY <= bounds.MaxY && envelope.MaxY >= bounds.MinY;
+            if !overlaps || !polygon.intersects(&tract) {
+                continue;
+            }
+            // a polygon GEOS can't intersect as is usually just needs a zero-width buffer to become valid
+            let piece = polygon.intersection(&tract)
+                .or_else(|| polygon.buffer(0.0, 8).ok()?.intersection(&tract));
+            match piece {
+                Some(piece) => sfha_area += piece.area(),
+                None => eprintln!("warning: {}: couldn't intersect a flood hazard polygon with tract {}", source.fips, geoid),
+            }
+        }
+        let area = tract.area();
+        let population = match &opts.population_field {
+            Some(field) => feature.field_as_double_by_name(field)?,
+            None => None,
+        };
+        let share = if area > 0.0 { (sfha_area / area).min(1.0) } else { 0.0 };
+        exposures.push(TractExposure {
+            geoid,
+            county_fips: source.fips.clone(),
+            area_sq_mi: area / SQ_M_PER_SQ_MI,
+            sfha_area_sq_mi: sfha_area / SQ_M_PER_SQ_MI,
+            sfha_pct: 100.0 * share,
+            population,
+            population_in_sfha: population.map(|p| p * share),
+        });
+    }
+    if exposures.is_empty() {
+        return Err(format!("no geographies in {} have a {} starting with {}", opts.tiger.display(), opts.geoid_field, source.fips).into());
+    }
+    Ok(exposures)
+}
+
+pub fn write_tract_exposure(out: &mut dyn Write, exposures: &[TractExposure], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, exposures)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let weighted = exposures.iter().any(|e| e.population.is_some());
+    let mut headers: Vec<String> = ["geoid", "county_fips", "area_sq_mi", "sfha_sq_mi", "sfha_pct"]
+        .iter().map(|h| h.to_string()).collect();
+    if weighted {
+        headers.extend(["population".to_string(), "population_in_sfha".to_string()]);
+    }
+    let rows: Vec<Vec<String>> = exposures.iter()
+        .map(|e| {
+            let mut row = vec![
+                e.geoid.clone(),
+                e.county_fips.clone(),
+                format!("{:.3}", e.area_sq_mi),
+                format!("{:.3}", e.sfha_area_sq_mi),
+                format!("{:.2}", e.sfha_pct),
+            ];
+            if weighted {
+                row.push(e.population.map(|p| format!("{:.0}", p)).unwrap_or_default());
+                row.push(e.population_in_sfha.map(|p| format!("{:.1}", p)).unwrap_or_default());
+            }
+            row
+        })
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
+
+/// Writes one row per county, with a column per flood zone found in any of them.
+pub fn write_stats(out: &mut dyn Write, stats: &[CountyStats], format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, stats)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let zones: BTreeSet<&String> = stats.iter().flat_map(|s| s.zones.keys()).collect();
+    let mut headers: Vec<String> = ["fips", "effective_date", "mapped_sq_mi", "sfha_sq_mi", "sfha_pct", "floodway_sq_mi"]
+        .iter().map(|h| h.to_string()).collect();
+    headers.extend(zones.iter().map(|zone| match zone.as_str() {
+        "" => "zone_unknown_sq_mi".to_string(),
+        zone => format!("zone_{}_sq_mi", zone.replace(' ', "_")),
+    }));
+    let rows: Vec<Vec<String>> = stats.iter()
+        .map(|s| {
+            let mut row = vec![
+                s.fips.clone(),
+                s.effective_date.map(|d| d.to_string()).unwrap_or_default(),
+                format!("{:.3}", s.mapped_area_sq_mi),
+                format!("{:.3}", s.sfha_area_sq_mi),
+                format!("{:.2}", s.sfha_pct),
+                format!("{:.3}", s.floodway_area_sq_mi),
+            ];
+            row.extend(zones.iter().map(|zone| format!("{:.3}", s.zones.get(*zone).map(|z| z.area_sq_mi).unwrap_or(0.0))));
+            row
+        })
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows),
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows),
+        _ => report::write_table(out, &headers, &rows),
+    }
+}
@@ -0,0 +1,109 @@
+//! Service supervision: systemd readiness/status/watchdog notifications and SIGTERM/SIGINT handling.
+//! The notifications are no-ops unless we were started by systemd with `NOTIFY_SOCKET` set.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+fn shutdown_flag() -> &'static Arc<AtomicBool> {
+    static FLAG: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    FLAG.get_or_init(|| Arc::new(AtomicBool::new(false)))
+}
+
+/// Makes SIGTERM and SIGINT request a clean shutdown instead of killing the process. Long-running loops should poll
+/// `shutdown_requested()` and wind down.
+pub fn install_signal_handlers() -> Result<(), Box<dyn std::error::Error>> {
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(shutdown_flag()))?;
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(shutdown_flag()))?;
+    Ok(())
+}
+
+pub fn shutdown_requested() -> bool {
+    shutdown_flag().load(Ordering::Relaxed)
+}
+
+/// Sleeps for `duration`, waking early if a shutdown is requested and keeping the watchdog fed meanwhile.
+/// Returns false if the sleep was cut short.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if shutdown_requested() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        watchdog_ping();
+        std::thread::sleep((deadline - now).min(Duration::from_secs(1)));
+    }
+}
+
+/// `sleep` for async code.
+pub async fn sleep_async(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if shutdown_requested() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        watchdog_ping();
+        tokio::time::sleep((deadline - now).min(Duration::from_secs(1))).await;
+    }
+}
+
+pub fn notify_ready() {
+    #[cfg(unix)]
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
+}
+
+pub fn notify_stopping() {
+    #[cfg(unix)]
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Stopping]);
+}
+
+/// Sets the one-line status shown by `systemctl status`.
+pub fn notify_status(status: &str) {
+    #[cfg(unix)]
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Status(status)]);
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// The service's `WatchdogSec`, if systemd is watching us.
+fn watchdog_interval() -> Option<Duration> {
+    static INTERVAL: OnceLock<Option<Duration>> = OnceLock::new();
+    *INTERVAL.get_or_init(|| {
+        #[cfg(unix)]
+        {
+            let mut usec = 0;
+            if sd_notify::watchdog_enabled(false, &mut usec) {
+                return Some(Duration::from_micros(usec));
+            }
+        }
+        None
+    })
+}
+
+/// Tells the watchdog we're still making progress. Cheap enough to call per chunk of a download: pings are only
+/// actually sent a few times per watchdog interval.
+pub fn watchdog_ping() {
+    static LAST_PING: Mutex<Option<Instant>> = Mutex::new(None);
+    let interval = match watchdog_interval() {
+        Some(interval) => interval,
+        None => return,
+    };
+    let mut last_ping = LAST_PING.lock().unwrap();
+    let due = match *last_ping {
+        Some(t) => t.elapsed() >= interval / 4,
+        None => true,
+    };
+    if due {
+        #[cfg(unix)]
+        let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]);
+        *last_ping = Some(Instant::now());
+    }
+}
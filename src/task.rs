@@ -0,0 +1,76 @@
+//! `--task-mode`: exit codes and a state file for workflow orchestrators (Airflow, Prefect, ...), which retry a task
+//! until it succeeds. Re-running a task is safe, since each run only does what the previous ones didn't finish.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::download::RunReport;
+use crate::plan::Plan;
+
+/// The exit code when the run went fine but left work to do (failed or interrupted downloads), so the task should be
+/// retried. `EX_TEMPFAIL` from sysexits.h. Other errors exit with 1 as usual.
+pub const EXIT_WORK_REMAINS: i32 = 75;
+
+/// The default state file name, in the cache directory.
+pub const STATE_FILE_NAME: &str = "task_state.json";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// The cache matches the inventory.
+    Complete,
+    /// Some planned downloads didn't happen; run again.
+    Incomplete,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TaskState {
+    pub status: TaskStatus,
+    pub finished_at: DateTime<Utc>,
+    pub planned: usize,
+    pub downloaded: usize,
+    pub failed: usize,
+    pub deleted: usize,
+    /// Counties whose planned download didn't complete, whether it failed or was never attempted.
+    pub remaining: Vec<String>,
+}
+
+impl TaskState {
+    pub fn new(plan: &Plan, report: &RunReport) -> TaskState {
+        let downloaded: HashSet<&str> = report.downloads.iter().map(|d| d.fips.as_str()).collect();
+        let remaining: Vec<String> = plan.downloads.iter()
+            .filter(|d| !downloaded.contains(d.fips.as_str()))
+            .map(|d| d.fips.clone())
+            .collect();
+        TaskState {
+            status: if remaining.is_empty() { TaskStatus::Complete } else { TaskStatus::Incomplete },
+            finished_at: report.finished_at,
+            planned: plan.downloads.len(),
+            downloaded: report.downloads.len(),
+            failed: report.failures.len(),
+            deleted: report.deleted.len(),
+            remaining,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.status {
+            TaskStatus::Complete => 0,
+            TaskStatus::Incomplete => EXIT_WORK_REMAINS,
+        }
+    }
+
+    /// Written atomically, so a sensor polling the file never sees half of it.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        serde_json::to_writer_pretty(std::fs::File::create(&tmp_path)?, self)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
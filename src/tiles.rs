@@ -0,0 +1,102 @@
+//! `tiles`: Mapbox vector tiles, in a single PMTiles or MBTiles file, made from the cache or from a merged dataset so
+//! a web map can be served straight from the mirror.
+
+use std::path::{Path, PathBuf};
+
+/// Where the features come from.
+#[derive(Debug, Clone)]
+pub enum TileSource {
+    /// Every cached county, newest file each.
+    Cache(PathBuf),
+    /// Any vector dataset GDAL can read, typically a `merge-geo` GeoPackage.
+    Dataset(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct TileOptions {
+    pub layers: Vec<String>,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+}
+
+/// The GDAL driver for a tile file, from its extension.
+pub fn driver(out: &Path) -> Result<&'static str, Box<dyn std::error::Error>> {
+    match out.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()).as_deref() {
+        Some("pmtiles") => Ok("PMTiles"),
+        Some("mbtiles") => Ok("MBTiles"),
+        _ => Err(format!("don't know what kind of tiles {} is: name it .pmtiles or .mbtiles", out.display()).into()),
+    }
+}
+
+/// Writes the tiles to `out`, replacing it. Geometries are simplified less as the zoom goes up, so low zooms stay
+/// small while the top zoom keeps the full detail of the flood zone boundaries.
+#[cfg(feature = "gdal")]
+pub fn write_tiles(source: &TileSource, out: &Path, opts: &TileOptions) -> Result<(), Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    if opts.min_zoom > opts.max_zoom {
+        return Err(format!("--min-zoom {} is above --max-zoom {}", opts.min_zoom, opts.max_zoom).into());
+    }
+    let driver = driver(out)?;
+    let src = match source {
+        TileSource::Dataset(path) => gdal::Dataset::open(path)?,
+        TileSource::Cache(cache_dir) => {
+            let mut gdbs = Vec::new();
+            for source in crate::merge_geo::sources(cache_dir, |_| true)? {
+                match crate::extract::require_archive_gdb_path(&source.archive) {
+                    Ok(gdb) => gdbs.push((source.fips, gdb)),
+                    Err(e) => eprintln!("{}: {}", source.fips, e),
+                }
+            }
+            let mut vrt = String::from("<OGRVRTDataSource>");
+            for layer in &opts.layers {
+                let mut counties = Vec::new();
+                for (fips, gdb) in &gdbs {
+                    if gdal::Dataset::open(gdb)?.layer_by_name(layer).is_ok() {
+                        counties.push((fips.as_str(), gdb.as_str()));
+                    }
+                }
+                if counties.is_empty() {
+                    eprintln!("warning: no cached county has a {} layer", layer);
+                    continue;
+                }
+                vrt.push_str(&crate::convert::union_layer_vrt(layer, &counties));
+            }
+            vrt.push_str("</OGRVRTDataSource>");
+            gdal::Dataset::open(&vrt)?
+        }
+    };
+    let layers: Vec<String> = src.layers()
+        .map(|layer| layer.name())
+        .filter(|name| opts.layers.iter().any(|l| l.eq_ignore_ascii_case(name)))
+        .collect();
+    if layers.is_empty() {
+        return Err("none of the requested layers are there to tile".into());
+    }
+
+    if out.exists() {
+        std::fs::remove_file(out)?;
+    }
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut args: Vec<String> = vec!["-f".into(), driver.into()];
+    for option in [
+        format!("MINZOOM={}", opts.min_zoom),
+        format!("MAXZOOM={}", opts.max_zoom),
+        // in tile units (4096 to a tile): only what's invisible at each zoom goes, and just a little at the top zoom,
+        // since overzooming keeps showing it
+        "SIMPLIFICATION=4".to_string(),
+        "SIMPLIFICATION_MAX_ZOOM=1".to_string(),
+        "NAME=NFHL".to_string(),
+        "DESCRIPTION=FEMA National Flood Hazard Layer".to_string(),
+    ] {
+        args.extend(["-dsco".to_string(), option]);
+    }
+    args.extend(layers.iter().cloned());
+    crate::convert::vector_translate(&src, out, &args)?;
+    for layer in layers {
+        eprintln!("tiled {}", layer);
+    }
+    Ok(())
+}
@@ -0,0 +1,488 @@
+//! `validate-gdb`: QC of cached county geodatabases against FEMA's database specification (see `gdb_spec`), so a bad
+//! delivery is caught before it reaches anything downstream.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::report::{self, ReportFormat};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One problem found, for one layer (and field) of one county.
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    /// What was checked, e.g. `missing_field` or `domain`.
+    pub check: &'static str,
+    pub layer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// How many features have the problem, where that applies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CountyValidation {
+    pub fips: String,
+    pub file_name: String,
+    pub findings: Vec<Finding>,
+}
+
+impl CountyValidation {
+    pub fn errors(&self) -> usize {
+        self.findings.iter().filter(|f| f.severity == Severity::Error).count()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ValidationReport {
+    pub generated_at: DateTime<Utc>,
+    pub counties: Vec<CountyValidation>,
+    /// Archives that couldn't be opened at all, by fips.
+    pub failures: BTreeMap<String, String>,
+}
+
+/// What to check beyond the specification's tables and fields.
+#[derive(Debug, Clone, Default)]
+pub struct ValidateOptions {
+    /// Also check the geometries: null and invalid ones in every layer, overlapping flood hazard polygons, and gaps
+    /// between them and the county boundary.
+    pub geometry: bool,
+    /// Where to write the features failing the geometry checks, as `{fips}_geometry.geojson`.
+    pub geojson_dir: Option<PathBuf>,
+}
+
+/// How many offending values to quote in a finding's detail.
+#[cfg(feature = "gdal")]
+const SAMPLE_VALUES: usize = 5;
+
+/// Checks one county's geodatabase against the specification.
+#[cfg(feature = "gdal")]
+pub fn validate_county(source: &crate::merge_geo::Source, opts: &ValidateOptions) -> Result<CountyValidation, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+    use crate::gdb_spec::TABLES;
+    use crate::merge_geo::quote;
+
+    let gdb = crate::extract::require_archive_gdb_path(&source.archive)?;
+    let dataset = gdal::Dataset::open(&gdb)?;
+    let layer_names: Vec<String> = dataset.layers().map(|layer| layer.name()).collect();
+    let mut findings = Vec::new();
+
+    for table in TABLES {
+        let name = match layer_names.iter().find(|name| name.eq_ignore_ascii_case(table.name)) {
+            Some(name) => name,
+            None => {
+                if table.required {
+                    findings.push(Finding {
+                        severity: Severity::Error,
+                        check: "missing_table",
+                        layer: table.name.to_string(),
+                        field: None,
+                        count: None,
+                        detail: format!("required table {} is missing", table.name),
+                    });
+                }
+                continue;
+            }
+        };
+        let mut layer = dataset.layer_by_name(name)?;
+        let fields: Vec<String> = layer.defn().fields().map(|field| field.name()).collect();
+        let has_field = |wanted: &str| fields.iter().find(|f| f.eq_ignore_ascii_case(wanted)).cloned();
+
+        for wanted in table.fields {
+            if has_field(wanted).is_none() {
+                findings.push(Finding {
+                    severity: Severity::Error,
+                    check: "missing_field",
+                    layer: name.clone(),
+                    field: Some(wanted.to_string()),
+                    count: None,
+                    detail: format!("{} has no {} field", name, wanted),
+                });
+            }
+        }
+        for wanted in table.not_null {
+            let field = match has_field(wanted) {
+                Some(field) => field,
+                None => continue,
+            };
+            let condition = format!("{0} IS NULL OR CAST({0} AS CHARACTER) = ''", quote(&field));
+            let count = count_matching(&mut layer, &condition)?;
+            if count > 0 {
+                findings.push(Finding {
+                    severity: Severity::Error,
+                    check: "null_value",
+                    layer: name.clone(),
+                    field: Some(field.clone()),
+                    count: Some(count),
+                    detail: format!("{} features leave {} empty", count, field),
+                });
+            }
+        }
+        for (wanted, allowed) in table.domains {
+            let field = match has_field(wanted) {
+                Some(field) => field,
+                None => continue,
+            };
+            let allowed_list: Vec<String> = allowed.iter().map(|v| format!("'{}'", v.replace('\'', "''"))).collect();
+            let condition = format!("{0} IS NOT NULL AND {0} <> '' AND {0} NOT IN ({1})", quote(&field), allowed_list.join(", "));
+            let count = count_matching(&mut layer, &condition)?;
+            if count > 0 {
+                layer.set_attribute_filter(&condition)?;
+                let mut samples: Vec<String> = Vec::new();
+                for feature in layer.features() {
+                    let value = feature.field_as_string_by_name(&field)?.unwrap_or_default();
+                    if !samples.contains(&value) {
+                        samples.push(value);
+                    }
+                    if samples.len() == SAMPLE_VALUES {
+                        break;
+                    }
+                }
+                layer.clear_attribute_filter();
+                findings.push(Finding {
+                    severity: Severity::Error,
+                    check: "domain",
+                    layer: name.clone(),
+                    field: Some(field.clone()),
+                    count: Some(count),
+                    detail: format!("{} features have a {} outside its domain, e.g. {}", count, field, samples.join(", ")),
+                });
+            }
+        }
+    }
+    findings.extend(check_effective_date(&dataset, source)?);
+    if opts.geometry {
+        findings.extend(geometry::check(&dataset, &source.fips, opts.geojson_dir.as_deref())?);
+    }
+
+    Ok(CountyValidation {
+        fips: source.fips.clone(),
+        file_name: source.archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default(),
+        findings,
+    })
+}
+
+/// Tables whose `EFF_DATE` says when the county's mapping last changed.
+#[cfg(feature = "gdal")]
+const DATED_TABLES: &[&str] = &["Study_Info", "S_FIRM_Pan", "S_LOMR"];
+
+/// Compares the date a county's file is named for (from its download URL) with the dates inside it: the metadata's
+/// publication date, and the latest effective date of its study, panels and LOMRs. FEMA has posted files whose name
+/// doesn't match what's in them, and those break anything keyed on the date.
+#[cfg(feature = "gdal")]
+fn check_effective_date(dataset: &gdal::Dataset, source: &crate::merge_geo::Source) -> Result<Vec<Finding>, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    let file_date = match source.effective_date {
+        Some(date) => date,
+        None => return Ok(Vec::new()),
+    };
+    let mut findings = Vec::new();
+    if let Some(pubdate) = crate::extract::archive_metadata_date(&source.archive)?.filter(|date| *date != file_date) {
+        findings.push(Finding {
+            severity: Severity::Warning,
+            check: "effective_date",
+            layer: "metadata".to_string(),
+            field: Some("pubdate".to_string()),
+            count: None,
+            detail: format!("the metadata was published {} but the file is dated {}", pubdate, file_date),
+        });
+    }
+
+    let mut latest: Option<(chrono::NaiveDate, String)> = None;
+    for table in DATED_TABLES {
+        let mut layer = match dataset.layer_by_name(table) {
+            Ok(layer) => layer,
+            Err(_) => continue,
+        };
+        let field = match layer.defn().fields().map(|field| field.name()).find(|name| name.eq_ignore_ascii_case("EFF_DATE")) {
+            Some(field) => field,
+            None => continue,
+        };
+        let name = layer.name();
+        for feature in layer.features() {
+            if let Some(date) = feature.field_as_datetime_by_name(&field)?.map(|date| date.date_naive()) {
+                if latest.as_ref().is_none_or(|(l, _)| *l < date) {
+                    latest = Some((date, name.clone()));
+                }
+            }
+        }
+    }
+    if let Some((date, layer)) = latest.filter(|(date, _)| *date != file_date) {
+        findings.push(Finding {
+            severity: Severity::Warning,
+            check: "effective_date",
+            layer,
+            field: Some("EFF_DATE".to_string()),
+            count: None,
+            detail: format!("the latest effective date inside is {} but the file is dated {}", date, file_date),
+        });
+    }
+    Ok(findings)
+}
+
+/// How many features of `layer` match an OGR SQL condition. The layer's attribute filter is cleared afterwards.
+#[cfg(feature = "gdal")]
+pub fn count_matching(layer: &mut gdal::vector::Layer, condition: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    use gdal::vector::LayerAccess;
+
+    layer.set_attribute_filter(condition)?;
+    let count = layer.feature_count();
+    layer.clear_attribute_filter();
+    Ok(count)
+}
+
+/// The geometry checks. Overlaps and gaps are measured in the same equal-area projection as `stats`, and only
+/// reported above a sliver's size, since snapping leaves slivers in most deliveries.
+#[cfg(feature = "gdal")]
+pub mod geometry {
+    use std::path::Path;
+
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    use gdal::vector::{Geometry, Layer, LayerAccess};
+    use serde_json::{json, Value};
+
+    use super::{Finding, Severity};
+    use crate::stats::{equal_area_srs, layer_srs, SQ_M_PER_ACRE};
+
+    /// In square metres.
+    const SLIVER_AREA: f64 = 10.0;
+
+    pub fn check(dataset: &gdal::Dataset, fips: &str, geojson_dir: Option<&Path>) -> Result<Vec<Finding>, Box<dyn std::error::Error>> {
+        let mut findings = Vec::new();
+        let mut offending: Vec<Value> = Vec::new();
+
+        for mut layer in dataset.layers() {
+            if layer.defn().geom_fields().count() == 0 {
+                continue;
+            }
+            let name = layer.name();
+            let to_lon_lat = lon_lat_transform(&layer)?;
+            let (mut nulls, mut invalid) = (0, 0);
+            for feature in layer.features() {
+                let (check, geometry) = match feature.geometry() {
+                    Some(geometry) if geometry.is_empty() => ("null_geometry", None),
+                    Some(geometry) if !geometry.is_valid() => ("invalid_geometry", Some(geometry)),
+                    Some(_) => continue,
+                    None => ("null_geometry", None),
+                };
+                if geometry.is_some() {
+                    invalid += 1;
+                } else {
+                    nulls += 1;
+                }
+                offending.push(geojson_feature(geometry, to_lon_lat.as_ref(), json!({
+                    "layer": name, "fid": feature.fid(), "check": check,
+                })));
+            }
+            if nulls > 0 {
+                findings.push(finding(Severity::Error, "null_geometry", &name, nulls, format!("{} features have no geometry", nulls)));
+            }
+            if invalid > 0 {
+                findings.push(finding(Severity::Error, "invalid_geometry", &name, invalid,
+                    format!("{} features have invalid geometries, e.g. self-intersecting rings", invalid)));
+            }
+        }
+
+        if let Ok(mut hazards) = dataset.layer_by_name("S_Fld_Haz_Ar") {
+            let equal_area = equal_area_srs(&hazards)?;
+            let to_equal_area = CoordTransform::new(&layer_srs(&hazards)?, &equal_area)?;
+            let from_equal_area = lon_lat_transform_from(&equal_area)?;
+            let mut polygons = Vec::new();
+            for feature in hazards.features() {
+                if let Some(geometry) = feature.geometry().filter(|g| !g.is_empty() && g.is_valid()) {
+                    let geometry = geometry.transform(&to_equal_area)?;
+                    polygons.push((feature.fid(), geometry.envelope(), geometry));
+                }
+            }
+
+            // sweep along x so only polygons whose envelopes overlap get intersected
+            polygons.sort_by(|a, b| a.1.MinX.total_cmp(&b.1.MinX));
+            let (mut overlaps, mut overlap_area) = (0, 0.0);
+            for (i, (fid, envelope, polygon)) in polygons.iter().enumerate() {
+                for (other_fid, other_envelope, other) in &polygons[i + 1..] {
+                    if other_envelope.MinX > envelope.MaxX {
+                        break;
+                    }
+                    if other_envelope.MinY > envelope.MaxY || other_envelope.MaxY < envelope.MinY || !polygon.intersects(other) {
+                        continue;
+                    }
+                    let piece = match polygon.intersection(other) {
+                        Some(piece) if piece.area() > SLIVER_AREA => piece,
+                        _ => continue,
+                    };
+                    overlaps += 1;
+                    overlap_area += piece.area();
+                    offending.push(geojson_feature(Some(&piece), Some(&from_equal_area), json!({
+                        "layer": "S_Fld_Haz_Ar", "fid": fid, "other_fid": other_fid, "check": "overlap",
+                        "acres": piece.area() / SQ_M_PER_ACRE,
+                    })));
+                }
+            }
+            if overlaps > 0 {
+                findings.push(finding(Severity::Warning, "overlap", "S_Fld_Haz_Ar", overlaps, format!(
+                    "{} pairs of flood hazard polygons overlap, over {:.2} acres", overlaps, overlap_area / SQ_M_PER_ACRE)));
+            }
+
+            if let Ok(mut political) = dataset.layer_by_name("S_Pol_Ar") {
+                let to_equal_area = CoordTransform::new(&layer_srs(&political)?, &equal_area)?;
+                let mut boundary = Vec::new();
+                for feature in political.features() {
+                    if let Some(geometry) = feature.geometry().filter(|g| !g.is_empty() && g.is_valid()) {
+                        boundary.push(geometry.transform(&to_equal_area)?);
+                    }
+                }
+                let covered = dissolve(polygons.into_iter().map(|(_, _, polygon)| polygon))?;
+                let boundary = dissolve(boundary.into_iter())?;
+                let mut gaps = Vec::new();
+                if let Some(uncovered) = difference(&boundary, &covered) {
+                    for piece in parts(uncovered) {
+                        if piece.area() > SLIVER_AREA {
+                            gaps.push(piece);
+                        }
+                    }
+                }
+                if !gaps.is_empty() {
+                    let gap_area: f64 = gaps.iter().map(|gap| gap.area()).sum();
+                    findings.push(finding(Severity::Warning, "boundary_gap", "S_Fld_Haz_Ar", gaps.len() as u64, format!(
+                        "{} areas inside S_Pol_Ar have no flood hazard polygon, {:.2} acres in all", gaps.len(), gap_area / SQ_M_PER_ACRE)));
+                    for gap in &gaps {
+                        offending.push(geojson_feature(Some(gap), Some(&from_equal_area), json!({
+                            "layer": "S_Fld_Haz_Ar", "check": "boundary_gap", "acres": gap.area() / SQ_M_PER_ACRE,
+                        })));
+                    }
+                }
+            }
+        }
+
+        if let (Some(dir), false) = (geojson_dir, offending.is_empty()) {
+            std::fs::create_dir_all(dir)?;
+            let path = dir.join(format!("{}_geometry.geojson", fips));
+            let collection = json!({ "type": "FeatureCollection", "features": offending });
+            std::fs::write(&path, serde_json::to_vec(&collection)?)?;
+            eprintln!("{}: wrote {} offending features to {}", fips, offending.len(), path.display());
+        }
+        Ok(findings)
+    }
+
+    fn finding(severity: Severity, check: &'static str, layer: &str, count: u64, detail: String) -> Finding {
+        Finding { severity, check, layer: layer.to_string(), field: None, count: Some(count), detail }
+    }
+
+    fn lon_lat_transform(layer: &Layer) -> Result<Option<CoordTransform>, Box<dyn std::error::Error>> {
+        match layer.spatial_ref() {
+            Some(_) => Ok(Some(lon_lat_transform_from(&layer_srs(layer)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn lon_lat_transform_from(srs: &SpatialRef) -> Result<CoordTransform, Box<dyn std::error::Error>> {
+        let lon_lat = SpatialRef::from_epsg(4326)?;
+        lon_lat.set_axis_mapping_strategy(gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+        Ok(CoordTransform::new(srs, &lon_lat)?)
+    }
+
+    /// A GeoJSON feature, in lon/lat when there's a transform. An invalid geometry that won't transform is left out
+    /// rather than losing the feature.
+    fn geojson_feature(geometry: Option<&Geometry>, to_lon_lat: Option<&CoordTransform>, properties: Value) -> Value {
+        let geometry = geometry
+            .and_then(|geometry| match to_lon_lat {
+                Some(transform) => geometry.transform(transform).ok()?.json().ok(),
+                None => geometry.json().ok(),
+            })
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or(Value::Null);
+        json!({ "type": "Feature", "geometry": geometry, "properties": properties })
+    }
+
+    /// The polygons of a geometry, on their own.
+    fn parts(geometry: Geometry) -> Vec<Geometry> {
+        match geometry.geometry_name().as_str() {
+            "POLYGON" => vec![geometry],
+            "MULTIPOLYGON" | "GEOMETRYCOLLECTION" => (0..geometry.geometry_count())
+                .flat_map(|i| parts(geometry.get_geometry(i).clone()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The union of some polygons: a zero-width buffer of them as one multipolygon, which GEOS does in one go rather
+    /// than a union at a time.
+    pub fn dissolve(polygons: impl Iterator<Item = Geometry>) -> Result<Geometry, Box<dyn std::error::Error>> {
+        let mut multi = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbMultiPolygon)?;
+        for polygon in polygons.flat_map(parts) {
+            multi.add_geometry(polygon)?;
+        }
+        Ok(multi.buffer(0.0, 8)?)
+    }
+
+    /// `a` minus `b`, which the gdal crate doesn't wrap.
+    fn difference(a: &Geometry, b: &Geometry) -> Option<Geometry> {
+        // SAFETY: both handles stay owned by their geometries; the difference is copied out as WKB and then destroyed.
+        let wkb = unsafe {
+            let c_difference = gdal_sys::OGR_G_Difference(a.c_geometry(), b.c_geometry());
+            if c_difference.is_null() {
+                return None;
+            }
+            let mut wkb = vec![0u8; gdal_sys::OGR_G_WkbSize(c_difference) as usize];
+            let error = gdal_sys::OGR_G_ExportToWkb(c_difference, gdal_sys::OGRwkbByteOrder::wkbNDR, wkb.as_mut_ptr());
+            gdal_sys::OGR_G_DestroyGeometry(c_difference);
+            if error != gdal_sys::OGRErr::OGRERR_NONE {
+                return None;
+            }
+            wkb
+        };
+        Geometry::from_wkb(&wkb).ok()
+    }
+}
+
+/// Writes a line per finding, then a count of the counties that passed.
+pub fn write_validation_report(out: &mut dyn Write, report: &ValidationReport, format: ReportFormat) -> Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, report)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["fips", "severity", "check", "layer", "field", "count", "detail"]
+        .iter().map(|h| h.to_string()).collect();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for county in &report.counties {
+        for f in &county.findings {
+            rows.push(vec![
+                county.fips.clone(),
+                format!("{:?}", f.severity).to_lowercase(),
+                f.check.to_string(),
+                f.layer.clone(),
+                f.field.clone().unwrap_or_default(),
+                f.count.map(|c| c.to_string()).unwrap_or_default(),
+                f.detail.clone(),
+            ]);
+        }
+    }
+    for (fips, error) in &report.failures {
+        rows.push(vec![fips.clone(), "error".into(), "unreadable".into(), String::new(), String::new(), String::new(), error.clone()]);
+    }
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows)?,
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+    if let ReportFormat::Table = format {
+        let passed = report.counties.iter().filter(|c| c.errors() == 0).count();
+        writeln!(out, "\n{} of {} counties passed", passed, report.counties.len() + report.failures.len())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Record and replay of the scrapers' HTTP traffic, behind the `vcr` feature. A `Cassette` is a JSON file of
+//! requests and the responses they got. Recording sends requests for real and keeps what comes back; replaying answers
+//! them from the file without touching the network. That lets the parsing of FEMA's HTML and JSON be tested against
+//! real responses, in `tests/vcr.rs`.
+//!
+//! Only requests made through `http::text` (the portal and MSC scrapers) are taken; downloads are never recorded.
+
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::Client;
+use crate::error::{NfhlError, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    /// The form the request posted, if any; replays match on it along with the method and url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+tokio::task_local! {
+    static CASSETTE: Arc<Cassette>;
+}
+
+/// Runs `future` with its scraper requests going through `cassette`.
+pub async fn with_cassette<F: Future>(cassette: Arc<Cassette>, future: F) -> F::Output {
+    CASSETTE.scope(cassette, future).await
+}
+
+/// The cassette the current task is running with, if any.
+pub fn current() -> Option<Arc<Cassette>> {
+    CASSETTE.try_with(Arc::clone).ok()
+}
+
+impl Cassette {
+    /// A cassette that records to `path` when saved.
+    pub fn record(path: &Path) -> Arc<Cassette> {
+        Arc::new(Cassette { path: path.to_path_buf(), mode: Mode::Record, interactions: Mutex::new(Vec::new()) })
+    }
+
+    /// A cassette answering from the recording at `path`.
+    pub fn replay(path: &Path) -> Result<Arc<Cassette>> {
+        let f = File::open(path).map_err(NfhlError::io(path))?;
+        let file: CassetteFile = serde_json::from_reader(BufReader::new(f)).map_err(NfhlError::parse(path))?;
+        Ok(Arc::new(Cassette { path: path.to_path_buf(), mode: Mode::Replay, interactions: Mutex::new(file.interactions) }))
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().unwrap().clone()
+    }
+
+    /// Writes what was recorded to the cassette's file.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(NfhlError::io(dir))?;
+        }
+        let file = CassetteFile { interactions: self.interactions() };
+        let f = File::create(&self.path).map_err(NfhlError::io(&self.path))?;
+        serde_json::to_writer_pretty(BufWriter::new(f), &file).map_err(|e| NfhlError::io(&self.path)(e.into()))
+    }
+
+    pub async fn send(&self, client: &Client, request: reqwest::Request) -> Result<String> {
+        let method = request.method().to_string();
+        let url = request.url().to_string();
+        let request_body = request.body()
+            .and_then(|body| body.as_bytes())
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+
+        let (status, body) = match self.mode {
+            Mode::Replay => {
+                let interactions = self.interactions.lock().unwrap();
+                let recorded = interactions.iter()
+                    .find(|i| i.method == method && i.url == url && i.request_body == request_body)
+                    .ok_or_else(|| NfhlError::Validation(format!("{} has no recorded response for {} {}", self.path.display(), method, url)))?;
+                (recorded.status, recorded.body.clone())
+            }
+            Mode::Record => {
+                let response = client.execute(request).await?;
+                let status = response.status().as_u16();
+                let body = response.text().await?;
+                self.interactions.lock().unwrap().push(Interaction {
+                    method: method.clone(),
+                    url: url.clone(),
+                    request_body,
+                    status,
+                    body: body.clone(),
+                });
+                (status, body)
+            }
+        };
+        if !(200..300).contains(&status) {
+            let status = reqwest::StatusCode::from_u16(status)
+                .map_err(|_| NfhlError::Validation(format!("{} isn't an HTTP status", status)))?;
+            return Err(NfhlError::status(&url, status));
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,275 @@
+//! `verify`: checks that the cached archives are still what was downloaded. Each file is hashed and compared with
+//! the size and SHA-256 the manifest recorded as it was downloaded, then read through as a zip so every member's CRC
+//! is checked. A national cache is thousands of independent multi-GB reads, so files are checked in parallel on a
+//! rayon pool. The counties' other files the manifest has under `files` are checked the same way.
+
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::cache::{self, CacheEntry, CacheManifest};
+use crate::encryption;
+use crate::error::{NfhlError, Result};
+use crate::info::sha256_file;
+use crate::report::{self, ReportFormat};
+
+/// One cached file's checks.
+#[derive(Serialize, Debug, Clone)]
+pub struct FileCheck {
+    pub fips: String,
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// The SHA-256 the manifest has for the file; None for files it didn't download or downloaded before hashing.
+    pub recorded_sha256: Option<String>,
+    /// What's wrong with the file, if anything.
+    pub problems: Vec<String>,
+    /// Whether it was moved to the quarantine; see `quarantine_failed`.
+    pub quarantined: bool,
+}
+
+impl FileCheck {
+    pub fn ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct VerifyReport {
+    pub generated_at: DateTime<Utc>,
+    pub files: Vec<FileCheck>,
+}
+
+/// Checks every cached archive of the counties whose fips starts with `prefix` (all of them for ""), and their other
+/// files, `workers` at a time.
+pub fn verify_cache(cache_dir: &Path, prefix: &str, workers: usize) -> Result<VerifyReport> {
+    let manifest = CacheManifest::load(cache_dir)?;
+    let mut files: Vec<(String, PathBuf, Option<&CacheEntry>)> = cache::cached_archives(cache_dir)?.into_iter()
+        .filter(|(fips, _)| fips.starts_with(prefix))
+        .map(|(fips, archive)| {
+            let file_name = cache::relative_name(cache_dir, &archive);
+            let recorded = manifest.entries.get(&fips).filter(|cached| cached.file_name == file_name);
+            (fips, archive, recorded)
+        })
+        .collect();
+    // a quarantined file is gone until the next download brings it back
+    for (file_name, cached) in &manifest.files {
+        let fips = other_file_fips(file_name);
+        let path = cache_dir.join(file_name);
+        if fips.starts_with(prefix) && path.exists() {
+            files.push((fips, path, Some(cached)));
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(workers.max(1)).build()
+        .map_err(|e| NfhlError::Validation(format!("can't start {} verify workers: {}", workers, e)))?;
+    let files = pool.install(|| {
+        files.par_iter()
+            .map(|(fips, path, recorded)| {
+                let file_name = cache::relative_name(cache_dir, path);
+                FileCheck { file_name, ..check_file(fips, path, *recorded) }
+            })
+            .collect()
+    });
+    Ok(VerifyReport { generated_at: Utc::now(), files })
+}
+
+/// The county one of the manifest's other files is for, from the DFIRM id its name starts with like an archive's, or
+/// "" if it doesn't have one. Those are only checked when all the counties are.
+fn other_file_fips(file_name: &str) -> String {
+    let name = file_name.rsplit('/').next().unwrap_or(file_name);
+    crate::inventory::file_name_fips(name).map(|fips| fips.as_str().to_string()).unwrap_or_default()
+}
+
+/// Checks one file against what the manifest recorded for it, if anything, and reads it through if it's a zip.
+pub fn check_file(fips: &str, archive: &Path, recorded: Option<&CacheEntry>) -> FileCheck {
+    let mut check = FileCheck {
+        fips: fips.to_string(),
+        file_name: archive.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default(),
+        size: 0,
+        sha256: String::new(),
+        recorded_sha256: recorded.and_then(|cached| cached.stored_sha256().map(str::to_string)),
+        problems: Vec::new(),
+        quarantined: false,
+    };
+    let is_zip = archive.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+    // an encrypted file is checked as what was downloaded, before it was encrypted
+    let plain = match encryption::plain(archive) {
+        Ok(plain) => plain,
+        Err(e) => {
+            check.problems.push(format!("can't read it: {}", e));
+            return check;
+        }
+    };
+    let archive = plain.path();
+    match std::fs::metadata(archive) {
+        Ok(metadata) => check.size = metadata.len(),
+        Err(e) => {
+            check.problems.push(format!("can't read it: {}", e));
+            return check;
+        }
+    }
+    if let Some(cached) = recorded.filter(|cached| cached.stored_size() != check.size) {
+        check.problems.push(format!("it's {} bytes, but {} were downloaded", check.size, cached.stored_size()));
+    }
+    match sha256_file(archive) {
+        Ok(sha256) => check.sha256 = sha256,
+        Err(e) => check.problems.push(format!("can't read it: {}", e)),
+    }
+    if check.recorded_sha256.as_ref().is_some_and(|recorded| !check.sha256.is_empty() && *recorded != check.sha256) {
+        check.problems.push("its SHA-256 isn't the one it was downloaded with".to_string());
+    }
+    if is_zip {
+        check.problems.extend(zip_problems(archive));
+    }
+    check
+}
+
+/// Moves the files that failed to `.quarantine/` in the cache (see `cache::quarantine_file`), so the next
+/// `download_all` fetches them again, and returns how many there were. Files that couldn't be read at all are left
+/// where they are, since that's more likely the disk or their permissions than their contents.
+pub fn quarantine_failed(cache_dir: &Path, report: &mut VerifyReport) -> Result<usize> {
+    let mut manifest = CacheManifest::load(cache_dir)?;
+    let mut quarantined = 0;
+    for file in report.files.iter_mut().filter(|file| !file.ok() && !file.sha256.is_empty()) {
+        let path = cache::quarantine_file(cache_dir, &mut manifest, &file.fips, &file.file_name, &file.problems)?;
+        eprintln!("quarantined {} to {}", file.file_name, path.display());
+        file.quarantined = true;
+        quarantined += 1;
+    }
+    if quarantined > 0 {
+        manifest.save(cache_dir)?;
+    }
+    Ok(quarantined)
+}
+
+/// Reads every member of the zip, which checks their CRCs.
+fn zip_problems(archive: &Path) -> Vec<String> {
+    let file = match File::open(archive) {
+        Ok(file) => file,
+        Err(e) => return vec![format!("can't read it: {}", e)],
+    };
+    let mut zip = match zip::ZipArchive::new(BufReader::new(file)) {
+        Ok(zip) => zip,
+        Err(e) => return vec![format!("isn't a readable zip: {}", e)],
+    };
+    let mut problems = Vec::new();
+    for i in 0..zip.len() {
+        match zip.by_index(i) {
+            Ok(mut member) => {
+                if let Err(e) = std::io::copy(&mut member, &mut std::io::sink()) {
+                    problems.push(format!("{}: {}", member.name(), e));
+                }
+            }
+            Err(e) => problems.push(format!("member {}: {}", i, e)),
+        }
+    }
+    problems
+}
+
+/// The files' SHA-256s in `sha256sum`'s format, so a copy of the cache can be checked with `sha256sum -c` from
+/// inside it.
+pub fn write_checksums(out: &mut dyn Write, report: &VerifyReport) -> std::io::Result<()> {
+    for file in report.files.iter().filter(|file| !file.sha256.is_empty() && !file.quarantined) {
+        writeln!(out, "{}  {}", file.sha256, file.file_name)?;
+    }
+    Ok(())
+}
+
+pub fn write_verify_report(out: &mut dyn Write, report: &VerifyReport, format: ReportFormat) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if let ReportFormat::Json = format {
+        serde_json::to_writer_pretty(&mut *out, report)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let headers: Vec<String> = ["fips", "file", "size", "sha256", "status", "problems"]
+        .iter().map(|h| h.to_string()).collect();
+    let rows: Vec<Vec<String>> = report.files.iter()
+        .map(|file| {
+            let status = match (&file.recorded_sha256, file.ok()) {
+                (_, false) if file.quarantined => "failed (quarantined)",
+                (_, false) => "failed",
+                (Some(_), true) => "ok",
+                (None, true) => "ok (unrecorded)",
+            };
+            vec![
+                file.fips.clone(),
+                file.file_name.clone(),
+                file.size.to_string(),
+                file.sha256.clone(),
+                status.to_string(),
+                file.problems.join("; "),
+            ]
+        })
+        .collect();
+    match format {
+        ReportFormat::Csv => report::write_csv(out, &headers, &rows)?,
+        ReportFormat::Markdown => report::write_markdown_table(out, &headers, &rows)?,
+        _ => report::write_table(out, &headers, &rows)?,
+    }
+    if let ReportFormat::Table = format {
+        let passed = report.files.iter().filter(|file| file.ok()).count();
+        writeln!(out, "\n{} of {} files passed", passed, report.files.len())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_zip(path: &Path, contents: &[u8]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        zip.start_file("S_FLD_HAZ_AR.dbf", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(contents).unwrap();
+        zip.finish().unwrap();
+    }
+
+    fn recorded(cache_dir: &Path, file_name: &str) -> CacheEntry {
+        let path = cache_dir.join(file_name);
+        CacheEntry {
+            file_name: file_name.to_string(),
+            url: format!("https://hazards.fema.gov/{}", file_name),
+            effective_date: "20220915".to_string(),
+            size: std::fs::metadata(&path).unwrap().len(),
+            sha256: Some(sha256_file(&path).unwrap()),
+            downloaded_at: Utc::now(),
+            quarantined_at: None,
+            recompressed: None,
+        }
+    }
+
+    #[test]
+    fn a_countys_corrupt_part_fails_and_is_quarantined() {
+        let cache_dir = std::env::temp_dir().join(format!("nfhl_util-verify-{}", std::process::id()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let (main, part) = ("48201C_20220915.zip", "48201C_20220915_2.zip");
+        write_zip(&cache_dir.join(main), b"the main file");
+        write_zip(&cache_dir.join(part), b"the second part");
+        let mut manifest = CacheManifest::default();
+        manifest.entries.insert("48201".to_string(), recorded(&cache_dir, main));
+        manifest.files.insert(part.to_string(), recorded(&cache_dir, part));
+        manifest.save(&cache_dir).unwrap();
+        write_zip(&cache_dir.join(part), b"the second pact");
+
+        let mut report = verify_cache(&cache_dir, "48", 1).unwrap();
+        let checks: Vec<(&str, &str, bool)> = report.files.iter()
+            .map(|file| (file.fips.as_str(), file.file_name.as_str(), file.ok()))
+            .collect();
+        assert_eq!(checks, [("48201", main, true), ("48201", part, false)]);
+        assert!(report.files[1].problems.iter().any(|problem| problem.contains("SHA-256")));
+
+        assert_eq!(quarantine_failed(&cache_dir, &mut report).unwrap(), 1);
+        assert!(!cache_dir.join(part).exists());
+        assert!(CacheManifest::load(&cache_dir).unwrap().files[part].quarantined_at.is_some());
+        let mut checksums = Vec::new();
+        write_checksums(&mut checksums, &report).unwrap();
+        assert_eq!(String::from_utf8(checksums).unwrap(), format!("{}  {}\n", report.files[0].sha256, main));
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
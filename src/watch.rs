@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use chrono_tz::Tz;
+
+use crate::cancel::CancellationToken;
+use crate::download::RunReport;
+use crate::inventory::read_inventory;
+use crate::plan;
+use crate::postgres_sink::PostgresSink;
+use crate::publish::Publishers;
+use crate::{blocking, history, systemd};
+
+/// The snapshot in the inventory directory that the next cycle diffs against.
+pub const LATEST_INVENTORY_FILE_NAME: &str = "latest.json";
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub cache_dir: PathBuf,
+    /// Where timestamped inventory snapshots and run reports are kept.
+    pub inventory_dir: PathBuf,
+    pub delete: bool,
+    /// With `delete`, keeps each county's previous file rather than deleting it.
+    pub keep_history: bool,
+    pub politeness: u8,
+    /// Urls to POST a JSON summary to whenever a cycle finds changes or failures.
+    pub notify_urls: Vec<String>,
+    pub changelog: Option<PathBuf>,
+    /// Message buses (`kafka://...`, `nats://...`) that get an event per change and completed download.
+    pub publish_urls: Vec<String>,
+    /// A database to record every cycle's inventory snapshot and run in.
+    pub postgres_url: Option<String>,
+}
+
+/// When the watch loop refreshes.
+#[derive(Debug, Clone)]
+pub enum Cadence {
+    /// Every so often, measured start to start.
+    Interval(Duration),
+    /// Whenever the cron schedule fires, evaluated in the given time zone.
+    Schedule(Box<cron::Schedule>, Tz),
+}
+
+impl Cadence {
+    /// How long from now until the next refresh should start, given when the previous one started.
+    fn next_wait(&self, last_started: Instant) -> Option<Duration> {
+        match self {
+            Cadence::Interval(interval) => Some(interval.saturating_sub(last_started.elapsed())),
+            Cadence::Schedule(schedule, tz) => {
+                let now = Utc::now().with_timezone(tz);
+                let next = schedule.after(&now).next()?;
+                eprintln!("next refresh at {}", next.to_rfc3339());
+                Some((next - now).to_std().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Parses a cron expression. The usual 5-field form (`min hour dom month dow`) is accepted as well as the 6/7-field
+/// form with seconds (and years). Day-of-week is safest spelled out (`SUN`), as numbers count from Sunday = 1.
+pub fn parse_schedule(expr: &str) -> Result<cron::Schedule, Box<dyn std::error::Error>> {
+    let expr = expr.trim();
+    let expr = if expr.split_whitespace().count() == 5 { format!("0 {}", expr) } else { expr.to_string() };
+    Ok(cron::Schedule::from_str(&expr).map_err(|e| format!("invalid schedule '{}': {}", expr, e))?)
+}
+
+/// Runs a refresh cycle whenever `cadence` says to, until the process is killed or asked to shut down (SIGTERM).
+/// A failed cycle is logged and retried at the next opportunity rather than ending the loop.
+pub fn watch(opts: &WatchOptions, cadence: &Cadence) -> Result<(), Box<dyn std::error::Error>> {
+    // connected once up front, so a bad url fails at startup rather than every cycle
+    let mut publishers = Publishers::connect_all(&opts.publish_urls)?;
+    systemd::notify_ready();
+    // a schedule waits for its first slot; an interval refreshes right away
+    let mut wait = match cadence {
+        Cadence::Schedule(..) => cadence.next_wait(Instant::now()).ok_or("schedule never fires")?,
+        Cadence::Interval(_) => Duration::ZERO,
+    };
+    loop {
+        if !wait.is_zero() {
+            let next = humantime::format_duration(Duration::from_secs(wait.as_secs()));
+            eprintln!("next refresh in {}", next);
+            systemd::notify_status(&format!("idle, next refresh in {}", next));
+        }
+        if !systemd::sleep(wait) {
+            break;
+        }
+        let started = Instant::now();
+        systemd::notify_status("refreshing inventory");
+        run_cycle(opts, &mut publishers);
+        if systemd::shutdown_requested() {
+            break;
+        }
+        wait = cadence.next_wait(started).ok_or("schedule never fires again")?;
+    }
+    eprintln!("shutting down");
+    systemd::notify_stopping();
+    Ok(())
+}
+
+/// One refresh, with errors logged rather than returned, as the daemon loops want it.
+pub fn run_cycle(opts: &WatchOptions, publishers: &mut Publishers) -> Option<RunReport> {
+    match refresh(opts, publishers) {
+        Ok(report) => {
+            eprintln!("refresh finished: {} changes, {} downloaded, {} failed",
+                report.changes.len(), report.downloads.len(), report.failures.len());
+            Some(report)
+        }
+        Err(e) => {
+            eprintln!("refresh failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Fetches a fresh county inventory, diffs it against the previous snapshot, downloads whatever changed, and
+/// sends notifications and events. The new inventory only becomes the `latest.json` baseline once the downloads have run.
+pub fn refresh(opts: &WatchOptions, publishers: &mut Publishers) -> Result<RunReport, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(&opts.inventory_dir)?;
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+
+    let inv = blocking::get_effective_county_products()?;
+    let snapshot_path = opts.inventory_dir.join(format!("counties_{}.json", timestamp));
+    serde_json::to_writer(File::create(&snapshot_path)?, &inv)?;
+
+    let latest_path = opts.inventory_dir.join(LATEST_INVENTORY_FILE_NAME);
+    let old_inv = if latest_path.exists() { Some(read_inventory(&latest_path)?) } else { None };
+
+    let plan = plan::make_plan(&inv, old_inv.as_ref(), &opts.cache_dir, opts.delete, opts.keep_history, None)?;
+    let report = blocking::apply_plan(&plan, opts.politeness, publishers, &CancellationToken::new())?;
+    serde_json::to_writer_pretty(File::create(opts.inventory_dir.join(format!("report_{}.json", timestamp)))?, &report)?;
+    std::fs::copy(&snapshot_path, &latest_path)?;
+
+    if let Some(changelog) = &opts.changelog {
+        history::append_changes(changelog, &report.changes, report.started_at)?;
+    }
+    if let Some(url) = &opts.postgres_url {
+        // reconnected each cycle: the loop may idle for days, longer than a connection reliably survives
+        let mut postgres = PostgresSink::connect(url)?;
+        let snapshot_id = postgres.write_inventory("counties", &inv)?;
+        postgres.write_run(&report, Some(snapshot_id))?;
+    }
+    if !report.changes.is_empty() || !report.failures.is_empty() {
+        for url in &opts.notify_urls {
+            if let Err(e) = notify(url, &report) {
+                eprintln!("failed to notify {}: {}", url, e);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// POSTs a JSON summary of the run (its changes and failures in full) to a webhook.
+fn notify(url: &str, report: &RunReport) -> Result<(), Box<dyn std::error::Error>> {
+    let body = serde_json::json!({
+        "started_at": report.started_at,
+        "finished_at": report.finished_at,
+        "changes": report.changes,
+        "failures": report.failures,
+        "downloaded": report.downloads.len(),
+        "deleted": report.deleted,
+    });
+    reqwest::blocking::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(30))
+        .json(&body)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
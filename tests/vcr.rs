@@ -0,0 +1,230 @@
+//! The scrapers against recorded FEMA responses, replayed from `tests/fixtures/vcr`. Needs the `vcr` feature:
+//! `cargo test --features vcr`. `cargo test --features vcr -- --ignored` re-records the fixtures from the live sites.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+
+use nfhl_util::cancel::CancellationToken;
+use nfhl_util::client::Client;
+use nfhl_util::error::NfhlError;
+use nfhl_util::inventory::{format, FileRole, Inventory, InventoryEntry, InventoryFile, ProductKind};
+use nfhl_util::msc::{self, Msc};
+use nfhl_util::nfhl_portal::{self, NfhlPortal};
+use nfhl_util::plan::Product;
+use nfhl_util::source::{Jurisdiction, ProductSource};
+use nfhl_util::vcr::{self, Cassette};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vcr").join(name)
+}
+
+fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+}
+
+fn portal() -> NfhlPortal {
+    NfhlPortal { client: Client::builder().build().unwrap() }
+}
+
+fn msc() -> Msc {
+    Msc { client: Client::builder().build().unwrap(), diagnostics_dir: None }
+}
+
+#[tokio::test]
+async fn portal_lists_every_county_with_a_download() {
+    let cassette = Cassette::replay(&fixture("nfhl_portal.json")).unwrap();
+    let inv = vcr::with_cassette(cassette, portal().list_products(&Jurisdiction::Nation)).await.unwrap();
+
+    let mut fips: Vec<&str> = inv.keys().map(|fips| fips.as_str()).collect();
+    fips.sort();
+    assert_eq!(fips, ["01003", "01101", "48201"]);
+    let harris = &inv["48201"];
+    assert_eq!(harris.effective_file_date, Some(date(2022, 9, 15)));
+    assert_eq!(harris.effective_file_url.as_ref().unwrap().as_str(), "https://hazards.fema.gov/femaportal/NFHL/Download/ProductsDownLoadServlet\
+        ?DFIRMID=48201C&state=TEXAS&county=HARRIS%20COUNTY&fileName=48201C_20220915.zip");
+    assert_eq!(harris.url(ProductKind::Preliminary), None);
+    assert_eq!(harris.county_name.as_deref(), Some("HARRIS COUNTY"));
+    assert_eq!((harris.state_fips.as_deref(), harris.state_abbrev.as_deref()), (Some("48"), Some("TX")));
+}
+
+#[tokio::test]
+async fn portal_filters_by_jurisdiction() {
+    let cassette = Cassette::replay(&fixture("nfhl_portal.json")).unwrap();
+    let alabama = vcr::with_cassette(cassette.clone(), portal().list_products(&Jurisdiction::State("01".to_string()))).await.unwrap();
+    assert_eq!(alabama.len(), 2);
+    assert!(alabama.keys().all(|fips| fips.starts_with("01")));
+
+    let url = vcr::with_cassette(cassette, portal().resolve_download_url("01101", ProductKind::Effective)).await.unwrap();
+    assert!(url.unwrap().ends_with("fileName=01101C_20190110.zip"));
+}
+
+#[tokio::test]
+async fn portal_lists_county_equivalents_mapped_by_community() {
+    let cassette = Cassette::replay(&fixture("nfhl_portal_county_equivalents.json")).unwrap();
+    let scrape = vcr::with_cassette(cassette, nfhl_portal::scrape_county_products(&portal().client)).await.unwrap();
+
+    // Virginia Beach, DC and Anchorage are named by community id; Falls Church's isn't one we know
+    let mut fips: Vec<&str> = scrape.inventory.keys().map(|fips| fips.as_str()).collect();
+    fips.sort();
+    assert_eq!(fips, ["02020", "11001", "51013", "51810"]);
+    assert_eq!(scrape.inventory["51810"].effective_file_date, Some(date(2015, 1, 16)));
+    assert_eq!(scrape.report.unmatched_files, ["515543_20090218.zip"]);
+}
+
+#[tokio::test]
+async fn portal_keeps_the_newest_of_a_countys_rows() {
+    let cassette = Cassette::replay(&fixture("nfhl_portal_reissued.json")).unwrap();
+    let scrape = vcr::with_cassette(cassette, nfhl_portal::scrape_county_products(&portal().client)).await.unwrap();
+
+    // the stale row comes last, which used to win
+    assert_eq!(scrape.inventory["48201"].effective_file_date, Some(date(2022, 9, 15)));
+    assert!(scrape.report.consistent);
+    let collision = &scrape.report.collisions[..];
+    assert_eq!(collision.len(), 1);
+    assert_eq!((collision[0].fips.as_str(), collision[0].kept.as_str()), ("48201", "48201C_20220915.zip"));
+    assert_eq!(collision[0].dropped, ["48201C_20170106.zip"]);
+}
+
+#[tokio::test]
+async fn portal_page_without_downloads_is_a_format_change() {
+    let cassette = Cassette::replay(&fixture("nfhl_portal_maintenance.json")).unwrap();
+    let err = vcr::with_cassette(cassette, portal().list_products(&Jurisdiction::Nation)).await.unwrap_err();
+    assert!(matches!(err, NfhlError::PortalFormat { .. }), "{:?}", err);
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+async fn msc_lists_a_countys_newest_effective_and_preliminary_products() {
+    let cassette = Cassette::replay(&fixture("msc_48201.json")).unwrap();
+    let inv = vcr::with_cassette(cassette, msc().list_products(&Jurisdiction::County("48201".to_string()))).await.unwrap();
+
+    let harris = &inv["48201"];
+    assert_eq!(harris.effective_file_date, Some(date(2022, 9, 15)));
+    assert_eq!(harris.effective_file_url.as_ref().unwrap().as_str(), "https://msc.fema.gov/portal/downloadProduct\
+        ?filepath=NFHL_48201C_20220915.zip&productTypeID=NFHL&productSubTypeID=NFHL_COUNTY_DATA&productID=NFHL_48201C");
+    // the preliminary file's name has no date, so it's MSC's displayed one
+    assert_eq!(harris.preliminary_file_date, Some(date(2024, 3, 1)));
+    assert!(harris.url(ProductKind::Preliminary).unwrap().as_str().contains("productID=48201C_PRELIM_FIRM_DB"));
+}
+
+#[tokio::test]
+async fn msc_lists_a_countys_files_of_each_product() {
+    let cassette = Cassette::replay(&fixture("msc_48201_products.json")).unwrap();
+    let (msc, cancel) = (msc(), CancellationToken::new());
+    let list = |product| {
+        let files = msc::list_product_files(&msc.client, &["48201"], product, Duration::ZERO, &cancel);
+        vcr::with_cassette(cassette.clone(), files)
+    };
+
+    let fis = list(Product::Fis).await.unwrap();
+    let names: Vec<&str> = fis.iter().map(|file| file.file_name.as_str()).collect();
+    assert_eq!(names, ["48201/48201CV001D.pdf", "48201/48201CV002D.pdf"]);
+    assert_eq!(fis[0].date, Some(date(2022, 9, 15)));
+    assert!(fis[0].url.contains("filepath=48201CV001D.pdf"));
+    // listed under EFFECTIVE and LOMC both
+    assert_eq!(list(Product::Lomc).await.unwrap().len(), 1);
+    let historic: Vec<String> = list(Product::Historic).await.unwrap().into_iter().map(|file| file.file_name).collect();
+    assert_eq!(historic, ["48201/48201C0010L.tif", "48201/48201CV001C.pdf"]);
+    let frd = list(Product::Frd).await.unwrap();
+    assert_eq!((frd.len(), frd[0].date), (1, Some(date(2023, 1, 20))));
+    let panels: Vec<String> = list(Product::HistoricPanels).await.unwrap().into_iter().map(|file| file.file_name).collect();
+    assert_eq!(panels, ["48201/48201C0010L_20070618.tif"]);
+}
+
+#[tokio::test]
+async fn msc_html_instead_of_json_is_a_format_change() {
+    let cassette = Cassette::replay(&fixture("msc_maintenance.json")).unwrap();
+    let err = vcr::with_cassette(cassette, msc().list_products(&Jurisdiction::County("48201".to_string()))).await.unwrap_err();
+    assert!(matches!(err, NfhlError::PortalFormat { site: "the Map Service Center", .. }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn msc_state_with_unreadable_results_is_quarantined_and_skipped() {
+    let diagnostics = std::env::temp_dir().join(format!("nfhl_util-vcr-{}", std::process::id()));
+    let msc = Msc { diagnostics_dir: Some(diagnostics.clone()), ..msc() };
+    let cassette = Cassette::replay(&fixture("msc_states_quarantine.json")).unwrap();
+    let inv = vcr::with_cassette(cassette, msc.list_products(&Jurisdiction::Nation)).await.unwrap();
+
+    let fips: Vec<&str> = inv.keys().map(|fips| fips.as_str()).collect();
+    assert_eq!(fips, ["01"]);
+    let saved: Vec<_> = std::fs::read_dir(&diagnostics).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(saved.len(), 1);
+    assert!(saved[0].to_string_lossy().starts_with("msc_AR_"));
+    std::fs::remove_dir_all(&diagnostics).unwrap();
+}
+
+#[tokio::test]
+async fn resumed_state_search_only_searches_the_states_left() {
+    let checkpoint = std::env::temp_dir().join(format!("nfhl_util-vcr-checkpoint-{}.json", std::process::id()));
+    std::fs::write(&checkpoint, r#"{"searched": ["AR"], "inventory": {"05": {"effective_file_url": "https://example.com/05.zip",
+        "effective_file_date": "20200101", "preliminary_file_url": "", "preliminary_file_date": ""}}}"#).unwrap();
+    // AR's recorded results don't parse, so searching it again would quarantine it
+    let cassette = Cassette::replay(&fixture("msc_states_quarantine.json")).unwrap();
+    let (msc, cancel) = (msc(), CancellationToken::new());
+    let diagnostics = std::env::temp_dir().join(format!("nfhl_util-vcr-resumed-{}", std::process::id()));
+    let products = msc::get_checkpointed_state_products(&msc.client, &checkpoint, true, msc::DEFAULT_REGION_CONCURRENCY,
+        &cancel, &diagnostics);
+    let products = vcr::with_cassette(cassette, products).await.unwrap();
+
+    let mut fips: Vec<&str> = products.inventory.keys().map(|fips| fips.as_str()).collect();
+    fips.sort();
+    assert_eq!(fips, ["01", "05"]);
+    assert_eq!(products.inventory["05"].effective_file_date, Some(date(2020, 1, 1)));
+    assert!(products.quarantined.is_empty());
+    assert!(products.remaining.is_empty());
+    assert!(!checkpoint.exists());
+    assert!(!diagnostics.exists());
+}
+
+#[test]
+fn inventory_with_other_files_says_its_version() {
+    let entry = InventoryEntry {
+        effective_file_url: Some("https://hazards.fema.gov/48201C_20220915.zip".parse().unwrap()),
+        effective_file_date: Some(date(2022, 9, 15)),
+        ..Default::default()
+    };
+    let mut inv: Inventory = [("48201".parse().unwrap(), entry)].into_iter().collect();
+    assert!(!format::to_string(&inv).contains("\"version\""));
+
+    inv.get_mut("48201").unwrap().files.push(InventoryFile {
+        kind: ProductKind::Effective,
+        role: FileRole::Part,
+        url: "https://hazards.fema.gov/48201C_20220915_2.zip".parse().unwrap(),
+        date: Some(date(2022, 9, 15)),
+    });
+    let json = format::to_string(&inv);
+    assert!(json.contains("\"version\":2"));
+    let read = format::read(json.as_bytes()).unwrap();
+    assert_eq!(read["48201"].files, inv["48201"].files);
+
+    let newer = json.replace("\"version\":2", "\"version\":3");
+    assert!(format::read(newer.as_bytes()).unwrap_err().to_string().contains("version 3"));
+}
+
+#[tokio::test]
+async fn replaying_an_unrecorded_request_fails() {
+    let cassette = Cassette::replay(&fixture("msc_48201.json")).unwrap();
+    let err = vcr::with_cassette(cassette, msc().list_products(&Jurisdiction::County("01101".to_string()))).await.unwrap_err();
+    assert!(matches!(err, NfhlError::Validation(_)), "{:?}", err);
+    assert!(err.to_string().contains("no recorded response"));
+}
+
+/// Re-records `nfhl_portal.json` and `msc_48201.json` from the live sites. The maintenance fixtures can't be recorded
+/// on demand and are left alone.
+#[tokio::test]
+#[ignore]
+async fn record() {
+    let client = Client::builder().build().unwrap();
+
+    let cassette = Cassette::record(&fixture("nfhl_portal.json"));
+    let portal = NfhlPortal { client: client.clone() };
+    vcr::with_cassette(cassette.clone(), portal.list_products(&Jurisdiction::Nation)).await.unwrap();
+    cassette.save().unwrap();
+
+    let cassette = Cassette::record(&fixture("msc_48201.json"));
+    let msc = Msc { client, diagnostics_dir: None };
+    vcr::with_cassette(cassette.clone(), msc.list_products(&Jurisdiction::County("48201".to_string()))).await.unwrap();
+    cassette.save().unwrap();
+}